@@ -0,0 +1,35 @@
+/**
+ * Glob matching, run against the key patterns of ACL users on every command
+ * and by CONFIG GET.
+ */
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use redis_starter_rust::glob::glob_match;
+
+fn matching(c: &mut Criterion) {
+  let mut group = c.benchmark_group("glob");
+  let cases = [
+    ("literal", "user:1000:name", "user:1000:name"),
+    ("star", "*", "user:1000:name"),
+    ("prefix", "user:*", "user:1000:name"),
+    ("suffix", "*:name", "user:1000:name"),
+    ("class", "user:[0-9][0-9][0-9][0-9]:*", "user:1000:name"),
+    ("miss", "session:*", "user:1000:name"),
+    // Backtracking: several stars that only fail at the very end
+    (
+      "backtrack",
+      "*a*a*a*a*b",
+      "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+    ),
+  ];
+  for (name, pattern, string) in cases {
+    group.bench_with_input(
+      BenchmarkId::from_parameter(name),
+      &(pattern, string),
+      |b, &(pattern, string)| b.iter(|| glob_match(black_box(pattern), black_box(string))),
+    );
+  }
+  group.finish();
+}
+
+criterion_group!(benches, matching);
+criterion_main!(benches);
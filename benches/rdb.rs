@@ -0,0 +1,59 @@
+/**
+ * RDB snapshots: encoding the keyspace for SAVE/BGSAVE and parsing it back
+ * as done at startup.
+ */
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use redis_starter_rust::database::{self, RDBParser};
+use redis_starter_rust::rdb::{self, RdbOptions};
+use redis_starter_rust::Storage;
+
+fn dataset(keys: usize) -> Storage {
+  let storage = Storage::new();
+  for i in 0..keys {
+    let options = if i % 4 == 0 {
+      vec![("EX".to_string(), "3600".to_string())]
+    } else {
+      Vec::new()
+    };
+    storage.set(format!("key:{}", i), format!("value:{}", i), options);
+  }
+  storage
+}
+
+fn snapshots(c: &mut Criterion) {
+  let mut group = c.benchmark_group("rdb");
+  group.sample_size(20);
+  for keys in [1_000, 100_000] {
+    let storage = dataset(keys);
+    let snapshot = rdb::encode(&storage, RdbOptions::default());
+    group.throughput(Throughput::Elements(keys as u64));
+    group.bench_with_input(BenchmarkId::new("encode", keys), &storage, |b, storage| {
+      b.iter(|| rdb::encode(black_box(storage), RdbOptions::default()))
+    });
+    group.bench_with_input(BenchmarkId::new("load", keys), &snapshot, |b, snapshot| {
+      b.iter(|| {
+        let mut parser = RDBParser::new(snapshot.clone());
+        parser.parse().unwrap();
+        parser
+      })
+    });
+    let threads = database::load_threads();
+    group.bench_with_input(
+      BenchmarkId::new(format!("load-{}-threads", threads), keys),
+      &snapshot,
+      |b, snapshot| {
+        b.iter(|| {
+          let storage = Storage::new();
+          RDBParser::new(snapshot.clone())
+            .load(&storage, threads)
+            .unwrap();
+          storage
+        })
+      },
+    );
+  }
+  group.finish();
+}
+
+criterion_group!(benches, snapshots);
+criterion_main!(benches);
@@ -0,0 +1,83 @@
+/**
+ * RESP parsing and serialization: the cost paid on every request and reply.
+ */
+use criterion::{
+  black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput,
+};
+use redis_starter_rust::parser::{frame_length, parse_command, serialize_response, RedisValue};
+
+/// A command as clients send it, as a RESP array of bulk strings
+fn encode(argv: &[&str]) -> Vec<u8> {
+  let mut command = format!("*{}\r\n", argv.len());
+  for argument in argv {
+    command.push_str(&format!("${}\r\n{}\r\n", argument.len(), argument));
+  }
+  command.into_bytes()
+}
+
+fn parsing(c: &mut Criterion) {
+  let mut group = c.benchmark_group("parse");
+  let value = "x".repeat(1024);
+  let commands = [
+    ("ping", encode(&["PING"])),
+    ("get", encode(&["GET", "user:1000:name"])),
+    ("set", encode(&["SET", "user:1000:name", "alice"])),
+    (
+      "set_ex",
+      encode(&["SET", "session:42", "token", "EX", "3600"]),
+    ),
+    ("set_1k", encode(&["SET", "blob", &value])),
+    (
+      "del_10",
+      encode(&["DEL", "a", "b", "c", "d", "e", "f", "g", "h", "i", "j"]),
+    ),
+  ];
+  for (name, command) in &commands {
+    group.throughput(Throughput::Bytes(command.len() as u64));
+    group.bench_with_input(BenchmarkId::from_parameter(name), command, |b, command| {
+      b.iter(|| parse_command(black_box(command)))
+    });
+  }
+  group.finish();
+
+  // Splitting a pipeline into commands
+  let mut group = c.benchmark_group("frame");
+  let pipeline: Vec<u8> = (0..100)
+    .flat_map(|i| encode(&["SET", &format!("key:{}", i), "value"]))
+    .collect();
+  group.throughput(Throughput::Elements(100));
+  group.bench_function("pipeline_100", |b| {
+    b.iter(|| {
+      let mut position = 0;
+      while let Ok(Some(length)) = frame_length(black_box(&pipeline[position..])) {
+        position += length;
+      }
+      position
+    })
+  });
+  group.finish();
+}
+
+fn serialization(c: &mut Criterion) {
+  let mut group = c.benchmark_group("serialize");
+  let replies = [
+    ("ok", RedisValue::SimpleString("OK".to_string())),
+    ("integer", RedisValue::Integer(123_456)),
+    ("bulk", RedisValue::BulkString(Some("alice".to_string()))),
+    ("bulk_1k", RedisValue::BulkString(Some("x".repeat(1024)))),
+    ("nil", RedisValue::BulkString(None)),
+    (
+      "array_100",
+      RedisValue::Array((0..100).map(|i| format!("key:{}", i)).collect()),
+    ),
+  ];
+  for (name, reply) in &replies {
+    group.bench_with_input(BenchmarkId::from_parameter(name), reply, |b, reply| {
+      b.iter_batched(|| reply.clone(), serialize_response, BatchSize::SmallInput)
+    });
+  }
+  group.finish();
+}
+
+criterion_group!(benches, parsing, serialization);
+criterion_main!(benches);
@@ -0,0 +1,103 @@
+/**
+ * The keyspace: SET and GET alone, then from many tasks at once through the
+ * lock connections share, which is where contention shows. Each task locks
+ * its key as commands do (see `storagelock`).
+ */
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use redis_starter_rust::{Storage, StorageLock};
+use std::sync::Arc;
+
+const KEYS: usize = 10_000;
+
+/// Operations each task runs per iteration of the contended benchmarks
+const OPERATIONS_PER_TASK: usize = 100;
+
+fn populated() -> Storage {
+  let storage = Storage::new();
+  for i in 0..KEYS {
+    storage.set(format!("key:{}", i), format!("value:{}", i), Vec::new());
+  }
+  storage
+}
+
+fn single_threaded(c: &mut Criterion) {
+  let mut group = c.benchmark_group("storage");
+  let storage = populated();
+  let mut i = 0;
+  group.bench_function("set", |b| {
+    b.iter(|| {
+      i = (i + 1) % KEYS;
+      storage.set(format!("key:{}", i), "updated".to_string(), Vec::new())
+    })
+  });
+  group.bench_function("set_ex", |b| {
+    b.iter(|| {
+      i = (i + 1) % KEYS;
+      storage.set(
+        format!("key:{}", i),
+        "updated".to_string(),
+        vec![("EX".to_string(), "3600".to_string())],
+      )
+    })
+  });
+  group.bench_function("get_hit", |b| {
+    b.iter(|| {
+      i = (i + 1) % KEYS;
+      storage.get(black_box(&format!("key:{}", i)))
+    })
+  });
+  group.bench_function("get_miss", |b| {
+    b.iter(|| {
+      i = (i + 1) % KEYS;
+      storage.get(black_box(&format!("missing:{}", i)))
+    })
+  });
+  group.finish();
+}
+
+fn contended(c: &mut Criterion) {
+  let runtime = tokio::runtime::Builder::new_multi_thread()
+    .enable_all()
+    .build()
+    .unwrap();
+  let storage = Arc::new(StorageLock::new(populated()));
+
+  let mut group = c.benchmark_group("storage_contended");
+  for tasks in [1, 4, 16, 64] {
+    group.throughput(Throughput::Elements((tasks * OPERATIONS_PER_TASK) as u64));
+    group.bench_with_input(BenchmarkId::from_parameter(tasks), &tasks, |b, &tasks| {
+      b.to_async(&runtime).iter(|| {
+        let storage = storage.clone();
+        async move {
+          let handles: Vec<_> = (0..tasks)
+            .map(|task| {
+              let storage = storage.clone();
+              tokio::spawn(async move {
+                for i in 0..OPERATIONS_PER_TASK {
+                  let key = format!("key:{}", (task * OPERATIONS_PER_TASK + i) % KEYS);
+                  let _key = storage.lock_keys(&[&key]).await;
+                  // Nine reads for a write, like a cache
+                  if i % 10 == 0 {
+                    storage
+                      .share()
+                      .await
+                      .set(key.clone(), "updated".to_string(), Vec::new());
+                  } else {
+                    black_box(storage.share().await.get(&key));
+                  }
+                }
+              })
+            })
+            .collect();
+          for handle in handles {
+            handle.await.unwrap();
+          }
+        }
+      })
+    });
+  }
+  group.finish();
+}
+
+criterion_group!(benches, single_threaded, contended);
+criterion_main!(benches);
@@ -0,0 +1,767 @@
+/**
+ * Access control lists.
+ *
+ * Users carry a set of SHA-256 password hashes, an ordered list of command rules
+ * (`+@read`, `-flushall`, `+client|list`, ...) and the key and channel patterns
+ * they may touch. Command rules are evaluated last match wins, starting from
+ * "nothing allowed", which gives the same results as Redis's rule compaction.
+ *
+ * Every command is checked against the user of the connection before it is
+ * dispatched. Users are managed with ACL SETUSER and friends and can be loaded
+ * from the file given with `--aclfile`, which uses the ACL LIST format.
+ */
+use crate::acllog::{AclLog, DenialReason};
+use crate::clients::ClientRegistry;
+use crate::glob::glob_match;
+use crate::parser::{Command, RedisValue};
+use dashmap::DashMap;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+use std::fs;
+
+/// User connections are authenticated as when they don't send AUTH
+pub const DEFAULT_USER: &str = "default";
+
+const NO_ACLFILE: &str = "ERR This Redis instance is not configured to use an ACL file. You may want to specify users via the ACL SETUSER command and then issue a CONFIG REWRITE (assuming you have a Redis configuration file set) in order to store users in the Redis configuration.";
+
+/// Command categories, in the order ACL CAT lists them
+pub const CATEGORIES: [&str; 21] = [
+  "keyspace",
+  "read",
+  "write",
+  "set",
+  "sortedset",
+  "list",
+  "hash",
+  "string",
+  "bitmap",
+  "hyperloglog",
+  "geo",
+  "stream",
+  "pubsub",
+  "admin",
+  "fast",
+  "slow",
+  "blocking",
+  "dangerous",
+  "connection",
+  "transaction",
+  "scripting",
+];
+
+/// Categories of the commands known to the server. Subcommands can override the
+/// categories of their container command with a `container|subcommand` entry.
+const COMMAND_CATEGORIES: &[(&str, &[&str])] = &[
+  ("acl", &["admin", "slow", "dangerous"]),
+  ("acl|cat", &["slow"]),
+  ("acl|log", &["admin", "slow", "dangerous"]),
+  ("acl|whoami", &["slow"]),
+  ("asking", &["fast"]),
+  ("auth", &["fast", "connection"]),
+  ("bgsave", &["admin", "slow", "dangerous"]),
+  ("client", &["admin", "slow", "dangerous", "connection"]),
+  ("client|caching", &["slow", "connection"]),
+  ("client|getname", &["slow", "connection"]),
+  ("client|getredir", &["slow", "connection"]),
+  ("client|id", &["slow", "connection"]),
+  ("client|info", &["slow", "connection"]),
+  (
+    "client|no-evict",
+    &["admin", "slow", "dangerous", "connection"],
+  ),
+  ("client|no-touch", &["slow", "connection"]),
+  ("client|setinfo", &["slow", "connection"]),
+  ("client|setname", &["slow", "connection"]),
+  ("client|tracking", &["slow", "connection"]),
+  ("client|trackinginfo", &["slow", "connection"]),
+  ("cluster", &["slow"]),
+  ("config", &["admin", "slow", "dangerous"]),
+  ("debug", &["admin", "slow", "dangerous"]),
+  ("decr", &["write", "string", "fast"]),
+  ("decrby", &["write", "string", "fast"]),
+  ("del", &["keyspace", "write", "slow"]),
+  ("dump", &["keyspace", "read", "slow"]),
+  ("echo", &["fast", "connection"]),
+  ("expire", &["keyspace", "write", "fast"]),
+  ("flushall", &["keyspace", "write", "slow", "dangerous"]),
+  ("get", &["read", "string", "fast"]),
+  ("hello", &["fast", "connection"]),
+  ("incr", &["write", "string", "fast"]),
+  ("incrby", &["write", "string", "fast"]),
+  ("info", &["slow", "dangerous"]),
+  ("keys", &["keyspace", "read", "slow", "dangerous"]),
+  ("lolwut", &["read", "fast"]),
+  ("memory", &["read", "slow"]),
+  ("monitor", &["admin", "slow", "dangerous"]),
+  ("object", &["keyspace", "read", "slow"]),
+  ("persist", &["keyspace", "write", "fast"]),
+  ("pexpire", &["keyspace", "write", "fast"]),
+  ("ping", &["fast", "connection"]),
+  ("pttl", &["keyspace", "read", "fast"]),
+  ("reset", &["fast", "connection"]),
+  ("restore", &["keyspace", "write", "slow", "dangerous"]),
+  ("scan", &["keyspace", "read", "slow"]),
+  ("set", &["write", "string", "slow"]),
+  ("shutdown", &["admin", "slow", "dangerous"]),
+  ("slowlog", &["admin", "slow", "dangerous"]),
+  ("time", &["fast"]),
+  ("ttl", &["keyspace", "read", "fast"]),
+  ("unlink", &["keyspace", "write", "fast"]),
+];
+
+/// Categories of a command, given its `container|subcommand` name
+fn categories_of(name: &str) -> &'static [&'static str] {
+  let base = name.split('|').next().unwrap_or(name);
+  COMMAND_CATEGORIES
+    .iter()
+    .find(|(command, _)| *command == name)
+    .or_else(|| {
+      COMMAND_CATEGORIES
+        .iter()
+        .find(|(command, _)| *command == base)
+    })
+    .map(|(_, categories)| *categories)
+    .unwrap_or(&[])
+}
+
+/// Whether `name` is a built-in command, as opposed to a custom one
+pub fn is_builtin_command(name: &str) -> bool {
+  COMMAND_CATEGORIES
+    .iter()
+    .any(|(command, _)| command.split('|').next() == Some(name))
+}
+
+fn hash_password(password: &str) -> String {
+  hex::encode(Sha256::digest(password.as_bytes()))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RuleTarget {
+  Category(String),
+  Command(String),
+}
+
+/// A single `+...` or `-...` command rule
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CommandRule {
+  allow: bool,
+  target: RuleTarget,
+}
+
+impl CommandRule {
+  fn matches(&self, name: &str) -> bool {
+    match &self.target {
+      RuleTarget::Category(category) if category == "all" => true,
+      RuleTarget::Category(category) => categories_of(name).contains(&category.as_str()),
+      // `+client` covers every subcommand, `+client|list` only that one
+      RuleTarget::Command(command) => {
+        command == name || name.split('|').next() == Some(command.as_str())
+      }
+    }
+  }
+
+  fn describe(&self) -> String {
+    let sign = if self.allow { '+' } else { '-' };
+    match &self.target {
+      RuleTarget::Category(category) => format!("{}@{}", sign, category),
+      RuleTarget::Command(command) => format!("{}{}", sign, command),
+    }
+  }
+}
+
+/// A `~pattern` rule, optionally restricted with `%R~` or `%W~`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct KeyPattern {
+  pattern: String,
+  read: bool,
+  write: bool,
+}
+
+impl KeyPattern {
+  fn describe(&self) -> String {
+    match (self.read, self.write) {
+      (true, false) => format!("%R~{}", self.pattern),
+      (false, true) => format!("%W~{}", self.pattern),
+      _ => format!("~{}", self.pattern),
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct User {
+  pub name: String,
+  pub enabled: bool,
+  pub nopass: bool,
+  /// SHA-256 hashes of the accepted passwords, hex encoded
+  passwords: BTreeSet<String>,
+  commands: Vec<CommandRule>,
+  keys: Vec<KeyPattern>,
+  channels: Vec<String>,
+}
+
+impl User {
+  /// New users start disabled, without passwords and without any permission
+  pub fn new(name: &str) -> Self {
+    Self {
+      name: name.to_string(),
+      enabled: false,
+      nopass: false,
+      passwords: BTreeSet::new(),
+      commands: Vec::new(),
+      keys: Vec::new(),
+      channels: Vec::new(),
+    }
+  }
+
+  /// Applies a single ACL SETUSER rule
+  pub fn apply(&mut self, rule: &str) -> Result<(), String> {
+    let lowercase = rule.to_lowercase();
+    match lowercase.as_str() {
+      "on" => self.enabled = true,
+      "off" => self.enabled = false,
+      "nopass" => {
+        self.nopass = true;
+        self.passwords.clear();
+      }
+      "resetpass" => {
+        self.nopass = false;
+        self.passwords.clear();
+      }
+      "allkeys" => self.keys = vec![Self::key_pattern("*", true, true)],
+      "resetkeys" => self.keys.clear(),
+      "allchannels" => self.channels = vec!["*".to_string()],
+      "resetchannels" => self.channels.clear(),
+      "allcommands" => self.add_command_rule(true, RuleTarget::Category("all".to_string())),
+      "nocommands" => self.add_command_rule(false, RuleTarget::Category("all".to_string())),
+      "reset" => *self = User::new(&self.name),
+      _ => return self.apply_prefixed(rule),
+    }
+    Ok(())
+  }
+
+  fn apply_prefixed(&mut self, rule: &str) -> Result<(), String> {
+    let syntax_error = || "Syntax error".to_string();
+
+    if let Some(password) = rule.strip_prefix('>') {
+      self.passwords.insert(hash_password(password));
+      self.nopass = false;
+    } else if let Some(password) = rule.strip_prefix('<') {
+      self.passwords.remove(&hash_password(password));
+    } else if let Some(hash) = rule.strip_prefix('#') {
+      Self::validate_hash(hash)?;
+      self.passwords.insert(hash.to_string());
+      self.nopass = false;
+    } else if let Some(hash) = rule.strip_prefix('!') {
+      Self::validate_hash(hash)?;
+      self.passwords.remove(hash);
+    } else if let Some(pattern) = rule.strip_prefix('~') {
+      self.keys.push(Self::key_pattern(pattern, true, true));
+    } else if let Some(rest) = rule.strip_prefix('%') {
+      let (permissions, pattern) = rest.split_once('~').ok_or_else(syntax_error)?;
+      let permissions = permissions.to_uppercase();
+      if permissions.is_empty() || permissions.chars().any(|c| c != 'R' && c != 'W') {
+        return Err(syntax_error());
+      }
+      self.keys.push(Self::key_pattern(
+        pattern,
+        permissions.contains('R'),
+        permissions.contains('W'),
+      ));
+    } else if let Some(pattern) = rule.strip_prefix('&') {
+      self.channels.push(pattern.to_string());
+    } else if let Some(target) = rule.strip_prefix('+') {
+      self.add_command_rule(true, Self::rule_target(target)?);
+    } else if let Some(target) = rule.strip_prefix('-') {
+      self.add_command_rule(false, Self::rule_target(target)?);
+    } else {
+      return Err(syntax_error());
+    }
+    Ok(())
+  }
+
+  fn validate_hash(hash: &str) -> Result<(), String> {
+    if hash.len() == 64 && hash.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f')) {
+      Ok(())
+    } else {
+      Err(
+        "The password hash must be exactly 64 characters and contain only lowercase hexadecimal characters"
+          .to_string(),
+      )
+    }
+  }
+
+  fn key_pattern(pattern: &str, read: bool, write: bool) -> KeyPattern {
+    KeyPattern {
+      pattern: pattern.to_string(),
+      read,
+      write,
+    }
+  }
+
+  fn rule_target(target: &str) -> Result<RuleTarget, String> {
+    let unknown = || "Unknown command or category name in ACL".to_string();
+    let target = target.to_lowercase();
+
+    if let Some(category) = target.strip_prefix('@') {
+      if category == "all" || CATEGORIES.contains(&category) {
+        return Ok(RuleTarget::Category(category.to_string()));
+      }
+      return Err(unknown());
+    }
+
+    let base = target.split('|').next().unwrap_or(&target);
+    if COMMAND_CATEGORIES
+      .iter()
+      .any(|(command, _)| *command == base)
+    {
+      Ok(RuleTarget::Command(target))
+    } else {
+      Err(unknown())
+    }
+  }
+
+  /// Appends a command rule, dropping the earlier rules it makes irrelevant
+  fn add_command_rule(&mut self, allow: bool, target: RuleTarget) {
+    if target == RuleTarget::Category("all".to_string()) {
+      self.commands.clear();
+    } else {
+      self.commands.retain(|rule| rule.target != target);
+    }
+    if !allow && self.commands.is_empty() {
+      // Nothing is allowed to begin with
+      return;
+    }
+    self.commands.push(CommandRule { allow, target });
+  }
+
+  /// Whether the password is accepted for this user
+  pub fn check_password(&self, password: &str) -> bool {
+    self.nopass || self.passwords.contains(&hash_password(password))
+  }
+
+  /// Whether the user may run the command with the given `container|subcommand` name
+  pub fn can_execute(&self, name: &str) -> bool {
+    self
+      .commands
+      .iter()
+      .rev()
+      .find(|rule| rule.matches(name))
+      .map(|rule| rule.allow)
+      .unwrap_or(false)
+  }
+
+  /// Whether the user may access `key` with the requested permissions
+  pub fn can_access_key(&self, key: &str, read: bool, write: bool) -> bool {
+    self.keys.iter().any(|pattern| {
+      (!read || pattern.read) && (!write || pattern.write) && glob_match(&pattern.pattern, key)
+    })
+  }
+
+  /// Whether the user may publish or subscribe to `channel`
+  pub fn can_access_channel(&self, channel: &str) -> bool {
+    self
+      .channels
+      .iter()
+      .any(|pattern| glob_match(pattern, channel))
+  }
+
+  fn describe_commands(&self) -> String {
+    let mut rules: Vec<String> = self.commands.iter().map(CommandRule::describe).collect();
+    if self.commands.first().map(|rule| rule.describe()) != Some("+@all".to_string()) {
+      rules.insert(0, "-@all".to_string());
+    }
+    rules.join(" ")
+  }
+
+  fn describe_keys(&self) -> String {
+    self
+      .keys
+      .iter()
+      .map(KeyPattern::describe)
+      .collect::<Vec<String>>()
+      .join(" ")
+  }
+
+  fn describe_channels(&self) -> String {
+    if self.channels.is_empty() {
+      return "resetchannels".to_string();
+    }
+    self
+      .channels
+      .iter()
+      .map(|pattern| format!("&{}", pattern))
+      .collect::<Vec<String>>()
+      .join(" ")
+  }
+
+  /// The user in ACL LIST (and aclfile) format
+  pub fn describe(&self) -> String {
+    let mut parts = vec![
+      "user".to_string(),
+      self.name.clone(),
+      if self.enabled { "on" } else { "off" }.to_string(),
+    ];
+    if self.nopass {
+      parts.push("nopass".to_string());
+    }
+    parts.extend(self.passwords.iter().map(|hash| format!("#{}", hash)));
+    if !self.keys.is_empty() {
+      parts.push(self.describe_keys());
+    }
+    parts.push(self.describe_channels());
+    parts.push(self.describe_commands());
+    parts.join(" ")
+  }
+
+  /// The user as returned by ACL GETUSER
+  fn to_value(&self) -> RedisValue {
+    let mut flags = vec![if self.enabled { "on" } else { "off" }.to_string()];
+    if self.nopass {
+      flags.push("nopass".to_string());
+    }
+
+    let bulk = |s: String| RedisValue::BulkString(Some(s));
+    RedisValue::Nested(vec![
+      bulk("flags".to_string()),
+      RedisValue::Array(flags),
+      bulk("passwords".to_string()),
+      RedisValue::Array(self.passwords.iter().cloned().collect()),
+      bulk("commands".to_string()),
+      bulk(self.describe_commands()),
+      bulk("keys".to_string()),
+      bulk(self.describe_keys()),
+      bulk("channels".to_string()),
+      bulk(
+        self
+          .channels
+          .iter()
+          .map(|pattern| format!("&{}", pattern))
+          .collect::<Vec<String>>()
+          .join(" "),
+      ),
+      bulk("selectors".to_string()),
+      RedisValue::Array(vec![]),
+    ])
+  }
+}
+
+pub struct Acl {
+  users: DashMap<String, User>,
+  /// aclfile the users are loaded from and saved to
+  file: Option<String>,
+  /// Denied commands and failed authentications
+  pub log: AclLog,
+}
+
+impl Acl {
+  pub fn new(file: Option<String>) -> Self {
+    let acl = Self {
+      users: DashMap::new(),
+      file,
+      log: AclLog::new(),
+    };
+    acl
+      .users
+      .insert(DEFAULT_USER.to_string(), Self::default_user());
+    acl
+  }
+
+  /// The default user can run everything without a password, like in Redis
+  fn default_user() -> User {
+    let mut user = User::new(DEFAULT_USER);
+    for rule in ["on", "nopass", "allkeys", "allchannels", "allcommands"] {
+      user
+        .apply(rule)
+        .expect("default user rules are always valid");
+    }
+    user
+  }
+
+  /// Replaces the users with the content of the aclfile, if one is configured.
+  /// Nothing is changed when the file contains an error.
+  pub fn load(&self) -> Result<(), String> {
+    let Some(path) = &self.file else {
+      return Ok(());
+    };
+    let content = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+
+    let mut users = vec![];
+    for (number, line) in content.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+
+      let error = |message: String| format!("{}:{}: {}", path, number + 1, message);
+      let mut words = line.split_whitespace();
+      if words.next() != Some("user") {
+        return Err(error("should start with user keyword".to_string()));
+      }
+      let name = words
+        .next()
+        .ok_or_else(|| error("missing user name".to_string()))?;
+
+      let mut user = User::new(name);
+      for rule in words {
+        user
+          .apply(rule)
+          .map_err(|e| error(format!("Error in user declaration '{}': {}", rule, e)))?;
+      }
+      users.push(user);
+    }
+
+    self.users.clear();
+    self
+      .users
+      .insert(DEFAULT_USER.to_string(), Self::default_user());
+    for user in users {
+      self.users.insert(user.name.clone(), user);
+    }
+    Ok(())
+  }
+
+  /// Writes every user to the aclfile
+  fn save(&self) -> Result<(), String> {
+    let Some(path) = &self.file else {
+      return Err(NO_ACLFILE.to_string());
+    };
+    let content: String = self
+      .users_sorted()
+      .iter()
+      .map(|user| format!("{}\n", user.describe()))
+      .collect();
+    fs::write(path, content).map_err(|e| format!("ERR There was an error trying to save the ACLs. Please check the server logs for more information: {}", e))
+  }
+
+  fn users_sorted(&self) -> Vec<User> {
+    let mut users: Vec<User> = self.users.iter().map(|user| user.clone()).collect();
+    users.sort_by(|a, b| a.name.cmp(&b.name));
+    users
+  }
+
+  /// Whether `user` exists, is enabled and accepts `password`. A missing
+  /// password only works for `nopass` users.
+  pub fn authenticate(&self, user: &str, password: Option<&str>) -> bool {
+    self.users.get(user).is_some_and(|user| {
+      user.enabled
+        && match password {
+          Some(password) => user.check_password(password),
+          None => user.nopass,
+        }
+    })
+  }
+
+  /// Whether `user` exists and is enabled
+  pub fn user_enabled(&self, user: &str) -> bool {
+    self.users.get(user).is_some_and(|user| user.enabled)
+  }
+
+  /// Whether the default user logs in without a password, which is what
+  /// protected-mode looks at
+  pub fn default_user_nopass(&self) -> bool {
+    self.users.get(DEFAULT_USER).is_some_and(|user| user.nopass)
+  }
+
+  /// Verifies that the client may run `command`, returning the error to reply
+  /// with when it can't. Denials are recorded in the ACL log.
+  pub fn check(
+    &self,
+    clients: &ClientRegistry,
+    client_id: u64,
+    command: &Command,
+  ) -> Result<(), String> {
+    if matches!(
+      command,
+      Command::AUTH(_) | Command::HELLO(_) | Command::RESET
+    ) {
+      return Ok(());
+    }
+    let (user, authenticated) = clients.session(client_id);
+    if !authenticated {
+      return Err("NOAUTH Authentication required.".to_string());
+    }
+    let Some(user) = self.users.get(&user) else {
+      return Err("NOAUTH Authentication required.".to_string());
+    };
+
+    let name = command.name();
+    if !user.can_execute(&name) {
+      self.log_denial(clients, client_id, DenialReason::Command, &name, &user.name);
+      return Err(format!(
+        "NOPERM User {} has no permissions to run the '{}' command",
+        user.name, name
+      ));
+    }
+
+    let (read, write) = match command {
+      Command::INCR(_) | Command::DECR(_) | Command::INCRBY(..) | Command::DECRBY(..) => {
+        (true, true)
+      }
+      command if command.is_write() => (false, true),
+      _ => (true, false),
+    };
+    let denied_key = command
+      .keys()
+      .into_iter()
+      .find(|key| !user.can_access_key(key, read, write));
+    if let Some(key) = denied_key {
+      self.log_denial(clients, client_id, DenialReason::Key, key, &user.name);
+      return Err("NOPERM No permissions to access a key".to_string());
+    }
+
+    Ok(())
+  }
+
+  fn log_denial(
+    &self,
+    clients: &ClientRegistry,
+    client_id: u64,
+    reason: DenialReason,
+    object: &str,
+    username: &str,
+  ) {
+    let client_info = clients
+      .get(client_id)
+      .map(|client| client.describe())
+      .unwrap_or_default();
+    self.log.record(reason, object, username, &client_info);
+  }
+
+  /// AUTH [username] password
+  pub fn auth(&self, clients: &ClientRegistry, client_id: u64, args: &[String]) -> RedisValue {
+    let (user, password) = match args {
+      [password] => (DEFAULT_USER, password),
+      [user, password] => (user.as_str(), password),
+      _ => {
+        return RedisValue::Error("ERR wrong number of arguments for 'auth' command".to_string())
+      }
+    };
+
+    if self.authenticate(user, Some(password)) {
+      clients.set_user(client_id, user);
+      RedisValue::SimpleString("OK".to_string())
+    } else {
+      self.log_denial(clients, client_id, DenialReason::Auth, "AUTH", user);
+      RedisValue::Error("WRONGPASS invalid username-password pair or user is disabled.".to_string())
+    }
+  }
+
+  /// Executes an ACL subcommand on behalf of `user`
+  pub fn handle_command(
+    &self,
+    clients: &ClientRegistry,
+    user: &str,
+    subcommand: &str,
+    args: &[String],
+  ) -> RedisValue {
+    match subcommand {
+      "SETUSER" => match args.split_first() {
+        Some((name, rules)) => self.setuser(name, rules),
+        None => wrong_arguments("acl|setuser"),
+      },
+      "GETUSER" => match args {
+        [name] => match self.users.get(name) {
+          Some(user) => user.to_value(),
+          None => RedisValue::BulkString(None),
+        },
+        _ => wrong_arguments("acl|getuser"),
+      },
+      "DELUSER" if !args.is_empty() => self.deluser(clients, args),
+      "DELUSER" => wrong_arguments("acl|deluser"),
+      "LIST" => RedisValue::Array(self.users_sorted().iter().map(User::describe).collect()),
+      "USERS" => RedisValue::Array(
+        self
+          .users_sorted()
+          .into_iter()
+          .map(|user| user.name)
+          .collect(),
+      ),
+      "CAT" => self.cat(args),
+      "WHOAMI" => RedisValue::BulkString(Some(user.to_string())),
+      "LOG" => self.log.handle_command(args),
+      "LOAD" => {
+        if self.file.is_none() {
+          return RedisValue::Error(NO_ACLFILE.to_string());
+        }
+        match self.load() {
+          Ok(()) => RedisValue::SimpleString("OK".to_string()),
+          Err(e) => RedisValue::Error(format!("ERR {}", e)),
+        }
+      }
+      "SAVE" => match self.save() {
+        Ok(()) => RedisValue::SimpleString("OK".to_string()),
+        Err(e) => RedisValue::Error(e),
+      },
+      _ => RedisValue::Error(format!(
+        "ERR unknown subcommand '{}'. Try ACL HELP.",
+        subcommand.to_lowercase()
+      )),
+    }
+  }
+
+  /// ACL SETUSER name [rule ...]. Rules are applied all or nothing.
+  fn setuser(&self, name: &str, rules: &[String]) -> RedisValue {
+    let mut user = self
+      .users
+      .get(name)
+      .map(|user| user.clone())
+      .unwrap_or_else(|| User::new(name));
+
+    for rule in rules {
+      if let Err(e) = user.apply(rule) {
+        return RedisValue::Error(format!(
+          "ERR Error in ACL SETUSER modifier '{}': {}",
+          rule, e
+        ));
+      }
+    }
+
+    self.users.insert(name.to_string(), user);
+    RedisValue::SimpleString("OK".to_string())
+  }
+
+  /// ACL DELUSER name [name ...]. Connections authenticated as a deleted user
+  /// are killed.
+  fn deluser(&self, clients: &ClientRegistry, names: &[String]) -> RedisValue {
+    if names.iter().any(|name| name == DEFAULT_USER) {
+      return RedisValue::Error("ERR The 'default' user cannot be removed".to_string());
+    }
+
+    let mut deleted = 0;
+    for name in names {
+      if self.users.remove(name).is_some() {
+        clients.kill_user(name);
+        deleted += 1;
+      }
+    }
+    RedisValue::Integer(deleted)
+  }
+
+  /// ACL CAT [category]
+  fn cat(&self, args: &[String]) -> RedisValue {
+    match args {
+      [] => RedisValue::Array(CATEGORIES.iter().map(|c| c.to_string()).collect()),
+      [category] => {
+        let category = category.to_lowercase();
+        if !CATEGORIES.contains(&category.as_str()) {
+          return RedisValue::Error(format!("ERR Unknown category '{}'", category));
+        }
+        RedisValue::Array(
+          COMMAND_CATEGORIES
+            .iter()
+            .filter(|(_, categories)| categories.contains(&category.as_str()))
+            .map(|(command, _)| command.to_string())
+            .collect(),
+        )
+      }
+      _ => wrong_arguments("acl|cat"),
+    }
+  }
+}
+
+fn wrong_arguments(command: &str) -> RedisValue {
+  RedisValue::Error(format!(
+    "ERR wrong number of arguments for '{}' command",
+    command
+  ))
+}
@@ -0,0 +1,207 @@
+/**
+ * ACL LOG: a bounded, in-memory audit trail of denied commands and failed
+ * authentications.
+ *
+ * Like Redis, repeated denials with the same reason, object and user that happen
+ * within `ACL_LOG_GROUPING_WINDOW` of each other are folded into a single entry
+ * whose count is bumped, so a misbehaving client can't flush the interesting
+ * entries out of the log.
+ */
+use crate::parser::RedisValue;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::Instant;
+
+/// Default number of entries kept, same as Redis's `acllog-max-len`
+pub const ACL_LOG_MAX_LEN: usize = 128;
+
+/// Similar denials closer than this are grouped in the same entry
+const ACL_LOG_GROUPING_WINDOW: Duration = Duration::from_secs(60);
+
+/// Why an entry was logged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DenialReason {
+  Command,
+  Key,
+  Channel,
+  Auth,
+}
+
+impl DenialReason {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      DenialReason::Command => "command",
+      DenialReason::Key => "key",
+      DenialReason::Channel => "channel",
+      DenialReason::Auth => "auth",
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct AclLogEntry {
+  pub entry_id: u64,
+  pub count: u64,
+  pub reason: DenialReason,
+  /// Command, key or channel that was denied. `AUTH` for failed authentications.
+  pub object: String,
+  pub username: String,
+  /// CLIENT INFO line of the last client that triggered the entry
+  pub client_info: String,
+  created: Instant,
+  updated: Instant,
+  created_ms: u128,
+  updated_ms: u128,
+}
+
+impl AclLogEntry {
+  fn to_value(&self) -> RedisValue {
+    let bulk = |s: &str| RedisValue::BulkString(Some(s.to_string()));
+    RedisValue::Nested(vec![
+      bulk("count"),
+      RedisValue::Integer(self.count as i64),
+      bulk("reason"),
+      bulk(self.reason.as_str()),
+      bulk("context"),
+      bulk("toplevel"),
+      bulk("object"),
+      bulk(&self.object),
+      bulk("username"),
+      bulk(&self.username),
+      bulk("age-seconds"),
+      bulk(&format!("{:.3}", self.created.elapsed().as_secs_f64())),
+      bulk("client-info"),
+      bulk(&self.client_info),
+      bulk("entry-id"),
+      RedisValue::Integer(self.entry_id as i64),
+      bulk("timestamp-created"),
+      RedisValue::Integer(self.created_ms as i64),
+      bulk("timestamp-last-updated"),
+      RedisValue::Integer(self.updated_ms as i64),
+    ])
+  }
+}
+
+struct LogState {
+  /// Newest entries first
+  entries: VecDeque<AclLogEntry>,
+  next_entry_id: u64,
+}
+
+pub struct AclLog {
+  state: Mutex<LogState>,
+  max_len: AtomicUsize,
+}
+
+impl AclLog {
+  pub fn new() -> Self {
+    Self {
+      state: Mutex::new(LogState {
+        entries: VecDeque::new(),
+        next_entry_id: 0,
+      }),
+      max_len: AtomicUsize::new(ACL_LOG_MAX_LEN),
+    }
+  }
+
+  /// Changes how many entries are kept, trimming the oldest ones right away
+  pub fn set_max_len(&self, max_len: usize) {
+    self.max_len.store(max_len, Ordering::Relaxed);
+    self.state.lock().unwrap().entries.truncate(max_len);
+  }
+
+  /// Records a denial, folding it into a recent similar entry when there is one
+  pub fn record(&self, reason: DenialReason, object: &str, username: &str, client_info: &str) {
+    let now = Instant::now();
+    let now_ms = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map(|elapsed| elapsed.as_millis())
+      .unwrap_or(0);
+
+    let mut state = self.state.lock().unwrap();
+    let similar = state.entries.iter().position(|entry| {
+      entry.reason == reason
+        && entry.object == object
+        && entry.username == username
+        && now.duration_since(entry.updated) < ACL_LOG_GROUPING_WINDOW
+    });
+
+    if let Some(position) = similar {
+      let mut entry = state.entries.remove(position).unwrap();
+      entry.count += 1;
+      entry.updated = now;
+      entry.updated_ms = now_ms;
+      entry.client_info = client_info.to_string();
+      state.entries.push_front(entry);
+      return;
+    }
+
+    let entry_id = state.next_entry_id;
+    state.next_entry_id += 1;
+    state.entries.push_front(AclLogEntry {
+      entry_id,
+      count: 1,
+      reason,
+      object: object.to_string(),
+      username: username.to_string(),
+      client_info: client_info.to_string(),
+      created: now,
+      updated: now,
+      created_ms: now_ms,
+      updated_ms: now_ms,
+    });
+    let max_len = self.max_len.load(Ordering::Relaxed);
+    state.entries.truncate(max_len);
+  }
+
+  /// Number of entries currently kept
+  pub fn len(&self) -> usize {
+    self.state.lock().unwrap().entries.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  pub fn reset(&self) {
+    self.state.lock().unwrap().entries.clear();
+  }
+
+  /// ACL LOG [count|RESET]
+  pub fn handle_command(&self, args: &[String]) -> RedisValue {
+    let count = match args {
+      [] => 10,
+      [argument] if argument.eq_ignore_ascii_case("reset") => {
+        self.reset();
+        return RedisValue::SimpleString("OK".to_string());
+      }
+      [argument] => match argument.parse::<usize>() {
+        Ok(count) => count,
+        Err(_) => {
+          return RedisValue::Error("ERR value is out of range, must be positive".to_string())
+        }
+      },
+      _ => {
+        return RedisValue::Error("ERR wrong number of arguments for 'acl|log' command".to_string())
+      }
+    };
+
+    let state = self.state.lock().unwrap();
+    RedisValue::Nested(
+      state
+        .entries
+        .iter()
+        .take(count)
+        .map(AclLogEntry::to_value)
+        .collect(),
+    )
+  }
+}
+
+impl Default for AclLog {
+  fn default() -> Self {
+    Self::new()
+  }
+}
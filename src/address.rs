@@ -0,0 +1,60 @@
+/**
+ * Host and port notation, for IPv4 and IPv6 alike.
+ *
+ * Hosts are given the way Redis users write them: `replicaof ::1 6379`,
+ * `replicaof [::1] 6379` or `replicaof [::1]:6379`. Addresses are rendered
+ * the way Redis renders peers, IPv6 ones in brackets (`[::1]:6379`), and
+ * IPv4-mapped IPv6 clients as the IPv4 address they really are.
+ */
+use std::net::{IpAddr, SocketAddr};
+
+/// Drops the brackets around an IPv6 host
+pub fn unbracket(host: &str) -> &str {
+  host
+    .strip_prefix('[')
+    .and_then(|host| host.strip_suffix(']'))
+    .unwrap_or(host)
+}
+
+/// `host:port`, with IPv6 hosts in brackets
+pub fn format_host_port(host: &str, port: u16) -> String {
+  let host = unbracket(host);
+  if host.contains(':') {
+    format!("[{}]:{}", host, port)
+  } else {
+    format!("{}:{}", host, port)
+  }
+}
+
+/// Parses a host and port, given as two values (`::1 6379`, `[::1] 6379`) or
+/// as one (`[::1]:6379`, `127.0.0.1:6379`)
+pub fn parse_host_port(value: &str) -> Result<(String, u16), String> {
+  let (host, port) = match value.split_whitespace().collect::<Vec<&str>>()[..] {
+    [host, port] => (host, port),
+    [address] => match address.rsplit_once(':') {
+      // A bare IPv6 address has colons but no port
+      Some((host, port)) if !host.contains(':') || host.ends_with(']') => (host, port),
+      _ => return Err("expected \"<host> <port>\"".to_string()),
+    },
+    _ => return Err("expected \"<host> <port>\"".to_string()),
+  };
+  let port = port
+    .parse::<u16>()
+    .map_err(|_| format!("'{}' is not a valid port", port))?;
+  let host = unbracket(host);
+  if host.is_empty() {
+    return Err("the host is empty".to_string());
+  }
+  Ok((host.to_string(), port))
+}
+
+/// The address a peer is shown with: IPv4-mapped IPv6 addresses as IPv4
+pub fn canonical(addr: SocketAddr) -> SocketAddr {
+  match addr.ip() {
+    IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+      Some(ip) => SocketAddr::new(IpAddr::V4(ip), addr.port()),
+      None => addr,
+    },
+    IpAddr::V4(_) => addr,
+  }
+}
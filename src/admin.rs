@@ -0,0 +1,577 @@
+/**
+ * HTTP admin API, for dashboards and tools that can't speak RESP.
+ *
+ * Off unless `http-admin-port` is set; it listens on `http-admin-bind`
+ * (loopback by default) and answers in JSON:
+ *
+ * - `GET /info[?section=<name>]`: INFO, as `{"<section>": {"<field>": "<value>"}}`
+ * - `GET /clients`: CLIENT LIST, one object per client
+ * - `GET /slowlog[?count=<n>]`: SLOWLOG GET, newest first
+ * - `GET /config`: CONFIG GET *, secrets such as `http-admin-token` redacted
+ * - `GET /keys/<key>`: the value, encoding, TTL, idle time and memory of a key
+ * - `GET /metrics`: the TTL histogram (see `ttlhistogram`) in the Prometheus
+ *   text format, for scraping
+ * - `POST /bgsave`: BGSAVE
+ * - `POST /config/<parameter>`: CONFIG SET, the request body being the value
+ *
+ * POST endpoints change the server, so they need an
+ * `Authorization: Bearer <http-admin-token>` header and are refused while no
+ * token is configured. The server speaks just enough HTTP/1.1 for curl and
+ * dashboards: one request per connection, no chunked bodies.
+ */
+use crate::acl::Acl;
+use crate::clients::ClientRegistry;
+use crate::config::Config;
+use crate::configset;
+use crate::encryption;
+use crate::parser::RedisValue;
+use crate::ratelimit::RateLimiter;
+use crate::rdb::{self, RdbOptions};
+use crate::state::ServerState;
+use crate::storagelock::StorageLock;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex as AsyncMutex;
+use tokio::task::{JoinHandle, JoinSet};
+use tracing::{debug, info, warn};
+
+/// Longest request line and headers accepted
+const MAX_HEADER_SIZE: usize = 8 * 1024;
+
+/// Longest request body accepted
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+/// How long a client has to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Entries returned by /slowlog without a count
+const DEFAULT_SLOWLOG_COUNT: usize = 10;
+
+/// The state the endpoints read and change
+#[derive(Clone)]
+pub struct AdminState {
+  pub storage: Arc<StorageLock>,
+  pub config: Arc<AsyncMutex<Config>>,
+  pub clients: Arc<ClientRegistry>,
+  pub acl: Arc<Acl>,
+  pub rate_limiter: Arc<RateLimiter>,
+  /// Counters and logs of the server
+  pub server: Arc<ServerState>,
+}
+
+struct Request {
+  method: String,
+  path: String,
+  query: Vec<(String, String)>,
+  authorization: Option<String>,
+  body: String,
+}
+
+/// Content type of the JSON endpoints
+const JSON: &str = "application/json";
+
+/// Content type of the Prometheus text format
+const PROMETHEUS: &str = "text/plain; version=0.0.4";
+
+struct Response {
+  status: u16,
+  content_type: &'static str,
+  body: String,
+}
+
+impl Response {
+  fn ok(body: String) -> Self {
+    Self {
+      status: 200,
+      content_type: JSON,
+      body,
+    }
+  }
+
+  fn error(status: u16, message: &str) -> Self {
+    Self {
+      status,
+      content_type: JSON,
+      body: object(&[("error", string(message))]),
+    }
+  }
+
+  fn reason(&self) -> &'static str {
+    match self.status {
+      200 => "OK",
+      202 => "Accepted",
+      400 => "Bad Request",
+      401 => "Unauthorized",
+      403 => "Forbidden",
+      404 => "Not Found",
+      405 => "Method Not Allowed",
+      408 => "Request Timeout",
+      409 => "Conflict",
+      413 => "Payload Too Large",
+      _ => "Internal Server Error",
+    }
+  }
+}
+
+/// Starts the API when `http-admin-port` is set, returning the listener task.
+/// Aborting it stops the requests being served too.
+pub async fn spawn(state: AdminState) -> Result<Option<JoinHandle<()>>, String> {
+  let (bind, port) = {
+    let config = state.config.lock().await;
+    (
+      config
+        .get("http-admin-bind")
+        .unwrap_or_else(|| "127.0.0.1".to_string()),
+      config
+        .get("http-admin-port")
+        .and_then(|port| port.parse::<u16>().ok())
+        .unwrap_or(0),
+    )
+  };
+  if port == 0 {
+    return Ok(None);
+  }
+  let address = crate::address::format_host_port(&bind, port);
+  let listener = TcpListener::bind(&address)
+    .await
+    .map_err(|e| format!("Can't bind the HTTP admin API to {}: {}", address, e))?;
+  info!("HTTP admin API listening on {}", address);
+
+  Ok(Some(tokio::spawn(async move {
+    let mut requests = JoinSet::new();
+    loop {
+      tokio::select! {
+        accepted = listener.accept() => match accepted {
+          Ok((stream, addr)) => {
+            let state = state.clone();
+            requests.spawn(async move {
+              if let Err(e) = serve_connection(stream, addr, &state).await {
+                debug!("HTTP admin request from {} failed: {}", addr, e);
+              }
+            });
+          }
+          Err(e) => warn!("Failed to accept an HTTP admin connection: {}", e),
+        },
+        // Reaps the requests served
+        Some(_) = requests.join_next() => {}
+      }
+    }
+  })))
+}
+
+async fn serve_connection(
+  mut stream: TcpStream,
+  addr: SocketAddr,
+  state: &AdminState,
+) -> std::io::Result<()> {
+  let response = match tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut stream)).await {
+    Ok(Ok(Ok(request))) => {
+      debug!(
+        "HTTP admin {} {} from {}",
+        request.method, request.path, addr
+      );
+      route(&request, state).await
+    }
+    Ok(Ok(Err(response))) => response,
+    Ok(Err(e)) => return Err(e),
+    Err(_) => Response::error(408, "request timed out"),
+  };
+  let head = format!(
+    "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+    response.status,
+    response.reason(),
+    response.content_type,
+    response.body.len()
+  );
+  stream.write_all(head.as_bytes()).await?;
+  stream.write_all(response.body.as_bytes()).await?;
+  stream.shutdown().await
+}
+
+/// Reads the request, or the error response for a malformed one
+async fn read_request(stream: &mut TcpStream) -> std::io::Result<Result<Request, Response>> {
+  let mut data = Vec::new();
+  let mut chunk = [0u8; 4096];
+  let header_end = loop {
+    if let Some(end) = data.windows(4).position(|window| window == b"\r\n\r\n") {
+      break end;
+    }
+    if data.len() > MAX_HEADER_SIZE {
+      return Ok(Err(Response::error(413, "headers too large")));
+    }
+    let n = stream.read(&mut chunk).await?;
+    if n == 0 {
+      return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    data.extend_from_slice(&chunk[..n]);
+  };
+
+  let Ok(head) = std::str::from_utf8(&data[..header_end]) else {
+    return Ok(Err(Response::error(400, "headers are not UTF-8")));
+  };
+  let mut lines = head.split("\r\n");
+  let (method, target) = match lines
+    .next()
+    .unwrap_or_default()
+    .split(' ')
+    .collect::<Vec<&str>>()[..]
+  {
+    [method, target, version] if version.starts_with("HTTP/1.") => (method, target),
+    _ => return Ok(Err(Response::error(400, "malformed request line"))),
+  };
+  let mut content_length = 0;
+  let mut authorization = None;
+  for line in lines {
+    let Some((name, value)) = line.split_once(':') else {
+      continue;
+    };
+    let value = value.trim();
+    if name.eq_ignore_ascii_case("content-length") {
+      match value.parse::<usize>() {
+        Ok(length) if length <= MAX_BODY_SIZE => content_length = length,
+        Ok(_) => return Ok(Err(Response::error(413, "body too large"))),
+        Err(_) => return Ok(Err(Response::error(400, "invalid Content-Length"))),
+      }
+    } else if name.eq_ignore_ascii_case("authorization") {
+      authorization = value.strip_prefix("Bearer ").map(str::to_string);
+    }
+  }
+
+  let mut body = data[header_end + 4..].to_vec();
+  while body.len() < content_length {
+    let n = stream.read(&mut chunk).await?;
+    if n == 0 {
+      return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    body.extend_from_slice(&chunk[..n]);
+  }
+  body.truncate(content_length);
+  let Ok(body) = String::from_utf8(body) else {
+    return Ok(Err(Response::error(400, "body is not UTF-8")));
+  };
+
+  let (path, query) = target.split_once('?').unwrap_or((target, ""));
+  Ok(Ok(Request {
+    method: method.to_string(),
+    path: percent_decode(path),
+    query: query
+      .split('&')
+      .filter(|pair| !pair.is_empty())
+      .map(|pair| {
+        // Forms encode spaces as `+` in query strings
+        let pair = pair.replace('+', " ");
+        let (name, value) = pair.split_once('=').unwrap_or((&pair, ""));
+        (percent_decode(name), percent_decode(value))
+      })
+      .collect(),
+    authorization,
+    body,
+  }))
+}
+
+async fn route(request: &Request, state: &AdminState) -> Response {
+  match (request.method.as_str(), request.path.as_str()) {
+    ("GET", "/info") => info_endpoint(request, state).await,
+    ("GET", "/clients") => clients_endpoint(state),
+    ("GET", "/slowlog") => slowlog_endpoint(request, state),
+    ("GET", "/config") => config_endpoint(state).await,
+    ("GET", "/metrics") => metrics_endpoint(state).await,
+    ("GET", path) if path.starts_with("/keys/") => {
+      key_endpoint(&path["/keys/".len()..], state).await
+    }
+    ("POST", "/bgsave") => match authorize(request, state).await {
+      Ok(()) => bgsave_endpoint(state).await,
+      Err(response) => response,
+    },
+    ("POST", path) if path.starts_with("/config/") => match authorize(request, state).await {
+      Ok(()) => config_set_endpoint(&path["/config/".len()..], &request.body, state).await,
+      Err(response) => response,
+    },
+    (_, "/info" | "/clients" | "/slowlog" | "/config" | "/metrics" | "/bgsave") => {
+      Response::error(405, "method not allowed")
+    }
+    (_, path) if path.starts_with("/keys/") || path.starts_with("/config/") => {
+      Response::error(405, "method not allowed")
+    }
+    _ => Response::error(404, "not found"),
+  }
+}
+
+/// Checks the bearer token of a POST request against `http-admin-token`
+async fn authorize(request: &Request, state: &AdminState) -> Result<(), Response> {
+  let token = state
+    .config
+    .lock()
+    .await
+    .get("http-admin-token")
+    .unwrap_or_default();
+  if token.is_empty() {
+    return Err(Response::error(
+      403,
+      "POST endpoints are disabled until http-admin-token is set",
+    ));
+  }
+  match &request.authorization {
+    Some(given) if constant_time_eq(given.as_bytes(), token.as_bytes()) => Ok(()),
+    _ => Err(Response::error(401, "invalid or missing bearer token")),
+  }
+}
+
+/// Compares secrets without leaking through timing how much of them matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+  a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+async fn info_endpoint(request: &Request, state: &AdminState) -> Response {
+  let sections: Vec<String> = request
+    .query
+    .iter()
+    .filter(|(name, _)| name == "section")
+    .map(|(_, section)| section.clone())
+    .collect();
+  let report = crate::info::info(
+    &sections,
+    &state.storage,
+    &state.config,
+    &state.clients,
+    &state.server,
+  )
+  .await;
+
+  let mut sections: Vec<(String, Vec<(String, String)>)> = Vec::new();
+  for line in report.lines() {
+    if let Some(title) = line.strip_prefix("# ") {
+      sections.push((title.to_lowercase(), Vec::new()));
+    } else if let (Some((name, value)), Some((_, fields))) =
+      (line.split_once(':'), sections.last_mut())
+    {
+      fields.push((name.to_string(), string(value)));
+    }
+  }
+  Response::ok(object(
+    &sections
+      .iter()
+      .map(|(title, fields)| (title.as_str(), fields_object(fields)))
+      .collect::<Vec<(&str, String)>>(),
+  ))
+}
+
+fn clients_endpoint(state: &AdminState) -> Response {
+  let clients = state
+    .clients
+    .all()
+    .iter()
+    .map(|client| {
+      let fields: Vec<(String, String)> = client
+        .describe()
+        .split(' ')
+        .filter_map(|field| field.split_once('='))
+        .map(|(name, value)| (name.to_string(), string(value)))
+        .collect();
+      fields_object(&fields)
+    })
+    .collect();
+  Response::ok(array(clients))
+}
+
+fn slowlog_endpoint(request: &Request, state: &AdminState) -> Response {
+  let count = match request.query.iter().find(|(name, _)| name == "count") {
+    Some((_, count)) => match count.parse::<usize>() {
+      Ok(count) => count,
+      Err(_) => return Response::error(400, "count must be a positive integer"),
+    },
+    None => DEFAULT_SLOWLOG_COUNT,
+  };
+  let entries = state
+    .server
+    .slowlog
+    .entries(count)
+    .iter()
+    .map(|entry| {
+      object(&[
+        ("id", entry.id.to_string()),
+        ("timestamp", entry.timestamp.to_string()),
+        ("duration_us", entry.duration.to_string()),
+        (
+          "args",
+          array(entry.argv.iter().map(|argument| string(argument)).collect()),
+        ),
+        ("addr", string(&entry.addr)),
+        ("name", string(&entry.name)),
+      ])
+    })
+    .collect();
+  Response::ok(array(entries))
+}
+
+async fn config_endpoint(state: &AdminState) -> Response {
+  let parameters = state.config.lock().await.parameters(&["*".to_string()]);
+  let fields: Vec<(String, String)> = parameters
+    .chunks(2)
+    .map(|pair| (pair[0].clone(), string(&pair[1])))
+    .collect();
+  Response::ok(fields_object(&fields))
+}
+
+async fn metrics_endpoint(state: &AdminState) -> Response {
+  Response {
+    status: 200,
+    content_type: PROMETHEUS,
+    body: state
+      .storage
+      .lock()
+      .await
+      .ttl_histogram()
+      .latest()
+      .prometheus(),
+  }
+}
+
+async fn key_endpoint(key: &str, state: &AdminState) -> Response {
+  let storage = state.storage.lock().await;
+  let Some(value) = storage.peek(key) else {
+    return Response::error(404, "no such key");
+  };
+  let ttl = match storage.ttl(key) {
+    Some(Some(remaining)) => remaining.as_millis().to_string(),
+    _ => "-1".to_string(),
+  };
+  Response::ok(object(&[
+    ("key", string(key)),
+    ("type", string("string")),
+    (
+      "encoding",
+      string(storage.encoding(key).unwrap_or_default()),
+    ),
+    ("value", string(&value)),
+    ("length", value.len().to_string()),
+    ("ttl_ms", ttl),
+    (
+      "idle_seconds",
+      storage
+        .idle_time(key)
+        .map(|idle| idle.as_secs())
+        .unwrap_or_default()
+        .to_string(),
+    ),
+    (
+      "memory_bytes",
+      storage.memory_usage(key).unwrap_or_default().to_string(),
+    ),
+  ]))
+}
+
+async fn bgsave_endpoint(state: &AdminState) -> Response {
+  let (path, key, options) = {
+    let config = state.config.lock().await;
+    (
+      rdb::snapshot_path(&config),
+      encryption::key(&config),
+      RdbOptions::from_config(&config),
+    )
+  };
+  let key = match key {
+    Ok(key) => key,
+    Err(e) => return Response::error(500, &e),
+  };
+  match rdb::bgsave(
+    state.server.clone(),
+    state.storage.clone(),
+    path,
+    key,
+    options,
+  ) {
+    Ok(()) => Response {
+      status: 202,
+      content_type: JSON,
+      body: object(&[("status", string("Background saving started"))]),
+    },
+    Err(e) => Response::error(409, &e),
+  }
+}
+
+async fn config_set_endpoint(parameter: &str, value: &str, state: &AdminState) -> Response {
+  let arguments = [parameter.to_string(), value.trim_end().to_string()];
+  match configset::config_set(
+    &arguments,
+    &state.storage,
+    &state.config,
+    &state.clients,
+    &state.acl,
+    &state.rate_limiter,
+    &state.server,
+  )
+  .await
+  {
+    RedisValue::Error(e) => Response::error(400, &e),
+    _ => Response::ok(object(&[("status", string("OK"))])),
+  }
+}
+
+/// Decodes `%XX` escapes
+fn percent_decode(value: &str) -> String {
+  let bytes = value.as_bytes();
+  let mut decoded = Vec::with_capacity(bytes.len());
+  let mut i = 0;
+  while i < bytes.len() {
+    let escaped = bytes
+      .get(i + 1..i + 3)
+      .and_then(|hex| std::str::from_utf8(hex).ok())
+      .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+    match (bytes[i], escaped) {
+      (b'%', Some(byte)) => {
+        decoded.push(byte);
+        i += 3;
+        continue;
+      }
+      (byte, _) => decoded.push(byte),
+    }
+    i += 1;
+  }
+  String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// `value` as a JSON string
+fn string(value: &str) -> String {
+  let mut json = String::with_capacity(value.len() + 2);
+  json.push('"');
+  for c in value.chars() {
+    match c {
+      '"' => json.push_str("\\\""),
+      '\\' => json.push_str("\\\\"),
+      '\n' => json.push_str("\\n"),
+      '\r' => json.push_str("\\r"),
+      '\t' => json.push_str("\\t"),
+      c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+      c => json.push(c),
+    }
+  }
+  json.push('"');
+  json
+}
+
+/// A JSON object of already encoded values
+fn object(fields: &[(&str, String)]) -> String {
+  let fields: Vec<String> = fields
+    .iter()
+    .map(|(name, value)| format!("{}:{}", string(name), value))
+    .collect();
+  format!("{{{}}}", fields.join(","))
+}
+
+fn fields_object(fields: &[(String, String)]) -> String {
+  object(
+    &fields
+      .iter()
+      .map(|(name, value)| (name.as_str(), value.clone()))
+      .collect::<Vec<(&str, String)>>(),
+  )
+}
+
+/// A JSON array of already encoded values
+fn array(items: Vec<String>) -> String {
+  format!("[{}]", items.join(","))
+}
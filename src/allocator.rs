@@ -0,0 +1,380 @@
+/**
+ * Allocator level memory statistics.
+ *
+ * `CountingAllocator` wraps the allocator so that every allocation is counted,
+ * which gives the "allocated" side of the picture. The binary installs it as
+ * the global allocator; applications embedding the server choose their own,
+ * and unless they pick this one "allocated" stays at 0. The "resident" side comes from the kernel
+ * (VmRSS). A background sampler periodically snapshots both so that INFO and
+ * MEMORY STATS can report fragmentation without touching /proc on every call.
+ *
+ * The wrapped allocator is the system one unless the `jemalloc` or `mimalloc`
+ * feature is enabled. jemalloc adds its own figures to INFO and MEMORY STATS
+ * (active, resident and retained bytes). MEMORY PURGE asks whichever allocator
+ * runs to return the pages it keeps cached to the kernel.
+ */
+use crate::cron::Scheduler;
+use crate::storage::Storage;
+#[cfg(not(any(feature = "jemalloc", feature = "mimalloc")))]
+use std::alloc::System;
+use std::alloc::{GlobalAlloc, Layout};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+use tracing::warn;
+
+/// How often the sampler refreshes the resident set size
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Time a sample may take before it counts as an overrun
+const SAMPLE_BUDGET: Duration = Duration::from_millis(10);
+
+/// Fragmentation ratio above which the sampler starts warning
+const FRAGMENTATION_WARN_RATIO: f64 = 1.5;
+
+/// Resident memory below which fragmentation is not worth warning about
+const FRAGMENTATION_WARN_MIN_RSS: usize = 64 * 1024 * 1024;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK_ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static SAMPLED_ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static SAMPLED_RESIDENT: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(all(feature = "jemalloc", feature = "mimalloc"))]
+compile_error!("The jemalloc and mimalloc features can't be enabled together");
+
+/// Name of the underlying allocator, as reported by `mem_allocator`
+pub const ALLOCATOR_NAME: &str = if cfg!(feature = "jemalloc") {
+  "jemalloc"
+} else if cfg!(feature = "mimalloc") {
+  "mimalloc"
+} else {
+  "libc"
+};
+
+/// The allocator doing the work
+#[cfg(feature = "jemalloc")]
+static INNER: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+#[cfg(feature = "mimalloc")]
+static INNER: mimalloc::MiMalloc = mimalloc::MiMalloc;
+#[cfg(not(any(feature = "jemalloc", feature = "mimalloc")))]
+static INNER: System = System;
+
+/// Global allocator that delegates to the selected allocator while keeping
+/// track of the bytes currently allocated.
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    let ptr = INNER.alloc(layout);
+    if !ptr.is_null() {
+      ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+    }
+    ptr
+  }
+
+  unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+    let ptr = INNER.alloc_zeroed(layout);
+    if !ptr.is_null() {
+      ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+    }
+    ptr
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    INNER.dealloc(ptr, layout);
+    ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+  }
+
+  unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+    let new_ptr = INNER.realloc(ptr, layout, new_size);
+    if !new_ptr.is_null() {
+      ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+      ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+    new_ptr
+  }
+}
+
+/// Point-in-time view of the allocator statistics
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AllocatorStats {
+  /// Bytes currently handed out by the allocator
+  pub allocated: usize,
+  /// Highest value `allocated` has reached
+  pub peak_allocated: usize,
+  /// Resident set size of the process, as of the last sample
+  pub resident: usize,
+}
+
+impl AllocatorStats {
+  /// Resident memory over allocated memory. Values well above 1 mean the
+  /// allocator is holding on to pages it can't reuse.
+  pub fn fragmentation_ratio(&self) -> f64 {
+    if self.allocated == 0 {
+      return 0.0;
+    }
+    self.resident as f64 / self.allocated as f64
+  }
+
+  /// Resident bytes not backing live allocations
+  pub fn fragmentation_bytes(&self) -> i64 {
+    self.resident as i64 - self.allocated as i64
+  }
+}
+
+/// Bytes currently allocated, updated on every allocation
+pub fn allocated() -> usize {
+  ALLOCATED.load(Ordering::Relaxed)
+}
+
+/// Latest statistics. `allocated` and `resident` come from the same sample so
+/// the ratio between them is meaningful.
+pub fn stats() -> AllocatorStats {
+  let current = allocated();
+  PEAK_ALLOCATED.fetch_max(current, Ordering::Relaxed);
+
+  let mut sampled_allocated = SAMPLED_ALLOCATED.load(Ordering::Relaxed);
+  let mut resident = SAMPLED_RESIDENT.load(Ordering::Relaxed);
+  if resident == 0 {
+    // The sampler hasn't run yet
+    sampled_allocated = current;
+    resident = read_resident_set_size().unwrap_or(0);
+  }
+
+  AllocatorStats {
+    allocated: sampled_allocated,
+    peak_allocated: PEAK_ALLOCATED.load(Ordering::Relaxed),
+    resident,
+  }
+}
+
+/// Figures jemalloc keeps about its own pages
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JemallocStats {
+  /// Bytes in pages holding allocations, fragmentation within them included
+  pub active: usize,
+  /// Bytes in pages jemalloc has resident
+  pub resident: usize,
+  /// Bytes unmapped but kept as address space for reuse
+  pub retained: usize,
+}
+
+/// jemalloc's own statistics, `None` with another allocator
+#[cfg(feature = "jemalloc")]
+pub fn jemalloc_stats() -> Option<JemallocStats> {
+  use tikv_jemalloc_ctl::{epoch, stats};
+  // The statistics are cached until the epoch advances
+  epoch::advance().ok()?;
+  Some(JemallocStats {
+    active: stats::active::read().ok()?,
+    resident: stats::resident::read().ok()?,
+    retained: stats::retained::read().ok()?,
+  })
+}
+
+#[cfg(not(feature = "jemalloc"))]
+pub fn jemalloc_stats() -> Option<JemallocStats> {
+  None
+}
+
+/// MEMORY PURGE: returns the free pages the allocator holds on to to the
+/// kernel, then refreshes the statistics
+pub fn purge() -> Result<(), String> {
+  #[cfg(feature = "jemalloc")]
+  {
+    // Every arena, MALLCTL_ARENAS_ALL
+    let name = b"arena.4096.purge\0";
+    let result = unsafe {
+      tikv_jemalloc_sys::mallctl(
+        name.as_ptr() as *const _,
+        std::ptr::null_mut(),
+        std::ptr::null_mut(),
+        std::ptr::null_mut(),
+        0,
+      )
+    };
+    if result != 0 {
+      return Err(format!("ERR Error purging dirty pages: {}", result));
+    }
+  }
+  #[cfg(feature = "mimalloc")]
+  unsafe {
+    libmimalloc_sys::mi_collect(true);
+  }
+  #[cfg(all(
+    not(any(feature = "jemalloc", feature = "mimalloc")),
+    target_os = "linux",
+    target_env = "gnu"
+  ))]
+  unsafe {
+    libc::malloc_trim(0);
+  }
+  sample();
+  Ok(())
+}
+
+/// Reads the resident set size of the process from procfs
+fn read_resident_set_size() -> Option<usize> {
+  let status = std::fs::read_to_string("/proc/self/status").ok()?;
+  let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+  let kilobytes = line
+    .trim_start_matches("VmRSS:")
+    .trim()
+    .trim_end_matches("kB")
+    .trim()
+    .parse::<usize>()
+    .ok()?;
+  Some(kilobytes * 1024)
+}
+
+/// Takes a new sample of the allocator statistics
+pub fn sample() -> AllocatorStats {
+  let current = allocated();
+  PEAK_ALLOCATED.fetch_max(current, Ordering::Relaxed);
+  SAMPLED_ALLOCATED.store(current, Ordering::Relaxed);
+  if let Some(resident) = read_resident_set_size() {
+    SAMPLED_RESIDENT.store(resident, Ordering::Relaxed);
+  }
+  stats()
+}
+
+/// Whether the last sample warned about fragmentation
+static FRAGMENTATION_WARNED: AtomicBool = AtomicBool::new(false);
+
+/// Schedules the job that keeps the allocator statistics fresh and warns when
+/// the process looks bloated
+pub fn register_memory_sampler(scheduler: &mut Scheduler) {
+  scheduler.every_in_process("memory-sampler", SAMPLE_INTERVAL, SAMPLE_BUDGET, || async {
+    let stats = sample();
+    let fragmented = stats.resident >= FRAGMENTATION_WARN_MIN_RSS
+      && stats.fragmentation_ratio() > FRAGMENTATION_WARN_RATIO;
+
+    let warned = FRAGMENTATION_WARNED.swap(fragmented, Ordering::Relaxed);
+    if fragmented && !warned {
+      warn!(
+        "High memory fragmentation: ratio {:.2}, {} bytes resident for {} bytes allocated",
+        stats.fragmentation_ratio(),
+        stats.resident,
+        stats.allocated
+      );
+    }
+  });
+}
+
+/// Formats a byte count the way Redis does in INFO (`1.50M`)
+pub fn human_bytes(bytes: usize) -> String {
+  const UNITS: [&str; 5] = ["B", "K", "M", "G", "T"];
+  let mut value = bytes as f64;
+  let mut unit = 0;
+  while value >= 1024.0 && unit < UNITS.len() - 1 {
+    value /= 1024.0;
+    unit += 1;
+  }
+  if unit == 0 {
+    format!("{}B", bytes)
+  } else {
+    format!("{:.2}{}", value, UNITS[unit])
+  }
+}
+
+/// Lines of the `# Memory` INFO section
+pub fn memory_info(storage: &Storage) -> Vec<String> {
+  let stats = stats();
+  let jemalloc = jemalloc_stats();
+  let mut lines = vec![
+    format!("used_memory:{}", stats.allocated),
+    format!("used_memory_human:{}", human_bytes(stats.allocated)),
+    format!("used_memory_rss:{}", stats.resident),
+    format!("used_memory_rss_human:{}", human_bytes(stats.resident)),
+    format!("used_memory_peak:{}", stats.peak_allocated),
+    format!(
+      "used_memory_peak_human:{}",
+      human_bytes(stats.peak_allocated)
+    ),
+    format!("used_memory_dataset:{}", storage.used_memory()),
+    format!("used_memory_scan_index:{}", storage.scan_index_memory()),
+    format!("allocator_allocated:{}", stats.allocated),
+    format!(
+      "allocator_resident:{}",
+      jemalloc.map_or(stats.resident, |jemalloc| jemalloc.resident)
+    ),
+    format!("allocator_frag_ratio:{:.2}", stats.fragmentation_ratio()),
+    format!("allocator_frag_bytes:{}", stats.fragmentation_bytes()),
+    format!("mem_fragmentation_ratio:{:.2}", stats.fragmentation_ratio()),
+    format!("mem_fragmentation_bytes:{}", stats.fragmentation_bytes()),
+    format!("mem_allocator:{}", ALLOCATOR_NAME),
+    "active_defrag_running:0".to_string(),
+    format!(
+      "lazyfree_pending_objects:{}",
+      storage.lazyfree().pending_objects()
+    ),
+    format!("lazyfreed_objects:{}", storage.lazyfree().freed_objects()),
+  ];
+  lines.extend(storage.eviction().info());
+  if let Some(jemalloc) = jemalloc {
+    lines.push(format!("allocator_active:{}", jemalloc.active));
+    lines.push(format!("allocator_retained:{}", jemalloc.retained));
+  }
+  lines
+}
+
+/// Flat name/value pairs returned by MEMORY STATS
+pub fn memory_stats(storage: &Storage, keys: usize) -> Vec<String> {
+  let stats = stats();
+  let dataset = storage.used_memory();
+  let jemalloc = jemalloc_stats();
+  let mut pairs = vec![
+    ("peak.allocated", stats.peak_allocated.to_string()),
+    ("total.allocated", stats.allocated.to_string()),
+    ("keys.count", keys.to_string()),
+    (
+      "keys.bytes-per-key",
+      dataset.checked_div(keys).unwrap_or(0).to_string(),
+    ),
+    ("dataset.bytes", dataset.to_string()),
+    (
+      "overhead.scan-index",
+      storage.scan_index_memory().to_string(),
+    ),
+    (
+      "dataset.percentage",
+      if stats.allocated == 0 {
+        "0".to_string()
+      } else {
+        format!("{:.2}", dataset as f64 * 100.0 / stats.allocated as f64)
+      },
+    ),
+    ("allocator.allocated", stats.allocated.to_string()),
+    (
+      "allocator.resident",
+      jemalloc
+        .map_or(stats.resident, |jemalloc| jemalloc.resident)
+        .to_string(),
+    ),
+    (
+      "allocator-fragmentation.ratio",
+      format!("{:.2}", stats.fragmentation_ratio()),
+    ),
+    (
+      "allocator-fragmentation.bytes",
+      stats.fragmentation_bytes().to_string(),
+    ),
+    (
+      "fragmentation",
+      format!("{:.2}", stats.fragmentation_ratio()),
+    ),
+    (
+      "fragmentation.bytes",
+      stats.fragmentation_bytes().to_string(),
+    ),
+  ];
+  if let Some(jemalloc) = jemalloc {
+    pairs.push(("allocator.active", jemalloc.active.to_string()));
+    pairs.push(("allocator.retained", jemalloc.retained.to_string()));
+  }
+
+  pairs
+    .into_iter()
+    .flat_map(|(name, value)| [name.to_string(), value])
+    .collect()
+}
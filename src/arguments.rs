@@ -1,11 +1,17 @@
-use crate::config::Config;
-use log::info;
+use crate::address::{format_host_port, parse_host_port};
+use crate::config::{parse_memory, Config};
+use crate::eviction;
+use crate::syslog;
+use clap::builder::PossibleValuesParser;
+use clap::error::ErrorKind;
+use clap::{value_parser, Arg, Command};
 use nanoid::nanoid;
 use std::fs::create_dir_all;
 use std::fs::File;
 use std::path::Path;
 use std::sync::Arc;
 use tokio::sync::Mutex as AsyncMutex;
+use tracing::info;
 
 const ALPHABET: [char; 62] = [
   '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', 'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i',
@@ -14,23 +20,477 @@ const ALPHABET: [char; 62] = [
   'V', 'W', 'X', 'Y', 'Z',
 ];
 
-pub type CLIArguments = Vec<(String, String)>;
+/// Directives given on the command line with their values
+pub type CLIArguments = Vec<(String, Vec<String>)>;
 
-/// Parses CLI arguments into tuple
-pub fn parse_cli_arguments(options: Vec<String>) -> CLIArguments {
-  options
+/// Random 40 character replication id
+pub fn generate_replication_id() -> String {
+  nanoid!(40, &ALPHABET)
+}
+
+/// `--dir`: a directory, created at startup when missing
+fn directory(value: &str) -> Result<String, String> {
+  let path = Path::new(value);
+  if path.exists() && !path.is_dir() {
+    return Err(format!("'{}' is not a directory", value));
+  }
+  Ok(value.to_string())
+}
+
+/// `--dbfilename`: a file name inside `dir`, not a path
+fn file_name(value: &str) -> Result<String, String> {
+  if value.is_empty() || value.contains('/') {
+    return Err("dbfilename can't be a path, just a filename".to_string());
+  }
+  Ok(value.to_string())
+}
+
+/// Files the server reads at startup (ACLs, certificates...)
+fn existing_file(value: &str) -> Result<String, String> {
+  if !Path::new(value).is_file() {
+    return Err(format!("'{}' is not a readable file", value));
+  }
+  Ok(value.to_string())
+}
+
+/// `--replicaof "<host> <port>"`, `--replicaof <host> <port>` or
+/// `--replicaof [<ipv6>]:<port>`
+fn replica_of(values: &[String]) -> Result<(), String> {
+  parse_host_port(&values.join(" ")).map(|_| ())
+}
+
+/// Memory amounts like `100mb`
+fn memory(value: &str) -> Result<String, String> {
+  match parse_memory(value) {
+    Some(_) => Ok(value.to_string()),
+    None => Err(format!("'{}' is not a memory amount", value)),
+  }
+}
+
+/// Memory amounts or percentages of `maxmemory`, for `--maxmemory-clients`
+fn memory_or_percentage(value: &str) -> Result<String, String> {
+  match value.strip_suffix('%') {
+    Some(percentage) if percentage.parse::<u64>().is_ok() => Ok(value.to_string()),
+    Some(_) => Err(format!("'{}' is not a percentage", value)),
+    None => memory(value),
+  }
+}
+
+/// Non-negative numbers, possibly fractional
+fn rate(value: &str) -> Result<String, String> {
+  match value.parse::<f64>() {
+    Ok(rate) if rate.is_finite() && rate >= 0.0 => Ok(value.to_string()),
+    _ => Err(format!("'{}' is not a positive number", value)),
+  }
+}
+
+fn directive(name: &'static str, value_name: &'static str, help: &'static str) -> Arg {
+  Arg::new(name).long(name).value_name(value_name).help(help)
+}
+
+/// A directive taking any number of values, e.g. `--save 900 1 300 10`
+fn multi_value_directive(name: &'static str, value_name: &'static str, help: &'static str) -> Arg {
+  directive(name, value_name, help).num_args(1..)
+}
+
+fn yes_no(name: &'static str, help: &'static str) -> Arg {
+  directive(name, "yes|no", help).value_parser(PossibleValuesParser::new(["yes", "no"]))
+}
+
+/// Configuration directives accepted on the command line, in the order they
+/// are applied
+fn directives() -> Vec<Arg> {
+  vec![
+    directive(
+      "port",
+      "PORT",
+      "TCP port to listen on, 0 to disable plain TCP",
+    )
+    .value_parser(value_parser!(u16)),
+    multi_value_directive("bind", "ADDRESS", "Addresses to listen on"),
+    yes_no("daemonize", "Run in the background"),
+    yes_no("cluster-enabled", "Run as a Redis Cluster node"),
+    directive(
+      "cluster-config-file",
+      "FILE",
+      "Where a cluster node saves its node table, relative to dir",
+    ),
+    directive("pidfile", "FILE", "Where to write the PID of the server"),
+    directive(
+      "logfile",
+      "FILE",
+      "Write logs to this file instead of stderr",
+    ),
+    yes_no("syslog-enabled", "Also send logs to the syslog daemon"),
+    directive("syslog-ident", "IDENT", "Program name of syslog messages"),
+    directive(
+      "syslog-facility",
+      "FACILITY",
+      "Syslog facility, user or local0-7",
+    )
+    .value_parser(PossibleValuesParser::new(syslog::FACILITIES)),
+    directive("loglevel", "LEVEL", "Log verbosity").value_parser(PossibleValuesParser::new([
+      "debug", "verbose", "notice", "warning", "nothing",
+    ])),
+    directive(
+      "otlp-endpoint",
+      "URL",
+      "OpenTelemetry collector to export command spans to",
+    ),
+    yes_no(
+      "protected-mode",
+      "Only accept loopback connections while the default user has no password",
+    ),
+    yes_no(
+      "proxy-protocol",
+      "Expect a PROXY protocol header on plain connections",
+    ),
+    directive("dir", "DIR", "Working directory of the RDB file").value_parser(directory),
+    directive("dbfilename", "FILE", "Name of the RDB file").value_parser(file_name),
+    yes_no(
+      "rdbcompression",
+      "Compress the strings of the RDB file with LZF",
+    ),
+    yes_no("rdbchecksum", "End the RDB file with a CRC64 checksum"),
+    directive(
+      "encryption-key",
+      "HEX",
+      "AES-256 key encrypting the RDB file, as 64 hex digits",
+    ),
+    multi_value_directive(
+      "encryption-key-command",
+      "PROGRAM",
+      "Program printing the key encrypting the RDB file",
+    ),
+    directive(
+      "import-csv",
+      "FILE",
+      "Load string keys from the rows of this CSV file after the RDB file",
+    )
+    .value_parser(existing_file),
+    directive(
+      "import-csv-columns",
+      "\"KEY VALUE [TTL]\"",
+      "Header names of the key, value and TTL columns of the CSV file",
+    ),
+    directive(
+      "import-json",
+      "FILE",
+      "Load the keys of this JSON export after the RDB file",
+    )
+    .value_parser(existing_file),
+    directive(
+      "import-protocol",
+      "FILE",
+      "Run the commands of this Redis protocol file before accepting clients",
+    )
+    .value_parser(existing_file),
+    directive(
+      "replay-file",
+      "FILE",
+      "Replay this recording of client commands before accepting clients",
+    )
+    .value_parser(existing_file),
+    yes_no(
+      "replay-timing",
+      "Keep the recorded gaps between commands when replaying",
+    ),
+    directive(
+      "io-threads",
+      "THREADS",
+      "Threads serving the connections, each with its own runtime",
+    )
+    .value_parser(value_parser!(u64).range(1..=128)),
+    multi_value_directive("save", "RULE", "Snapshot rules, <seconds> <changes> ..."),
+    directive(
+      "sanitize-dump-payload",
+      "no|yes|clients",
+      "Deeply check RESTORE payloads before restoring them",
+    )
+    .value_parser(PossibleValuesParser::new(["no", "yes", "clients"])),
+    directive("replicaof", "HOST PORT", "Master to replicate").num_args(1..=2),
+    directive("aclfile", "FILE", "File holding the ACL users").value_parser(existing_file),
+    directive("acllog-max-len", "N", "Entries kept in the ACL log")
+      .value_parser(value_parser!(u64)),
+    directive(
+      "slowlog-log-slower-than",
+      "MICROSECONDS",
+      "Log commands slower than this, -1 to disable",
+    )
+    .value_parser(value_parser!(i64).range(-1..)),
+    directive("slowlog-max-len", "N", "Entries kept in the slow log")
+      .value_parser(value_parser!(u64)),
+    directive(
+      "audit-log",
+      "FILE",
+      "Append every write command to this file",
+    ),
+    directive(
+      "audit-log-max-size",
+      "BYTES",
+      "Rotate the audit log at this size, 0 to never rotate",
+    )
+    .value_parser(memory),
+    directive("audit-log-max-files", "N", "Rotated audit logs kept")
+      .value_parser(value_parser!(u64).range(0..=1000)),
+    yes_no(
+      "audit-log-redact-values",
+      "Only write the command names and keys to the audit log",
+    ),
+    directive(
+      "record-file",
+      "FILE",
+      "Record the commands of every client to this file, to replay them",
+    ),
+    directive(
+      "http-admin-port",
+      "PORT",
+      "Port of the HTTP admin API, 0 to disable",
+    )
+    .value_parser(value_parser!(u16)),
+    directive(
+      "http-admin-bind",
+      "ADDRESS",
+      "Address the HTTP admin API listens on",
+    ),
+    directive(
+      "http-admin-token",
+      "TOKEN",
+      "Bearer token required by the HTTP admin API POST endpoints",
+    ),
+    directive(
+      "hotkeys-capacity",
+      "KEYS",
+      "Keys tracked by the hot key report, 0 to disable",
+    )
+    .value_parser(value_parser!(u64).range(0..=1024)),
+    directive("maxclients", "N", "Maximum number of connected clients")
+      .value_parser(value_parser!(u64).range(1..)),
+    directive(
+      "timeout",
+      "SECONDS",
+      "Close idle clients after this long, 0 to disable",
+    )
+    .value_parser(value_parser!(u64)),
+    directive(
+      "tcp-keepalive",
+      "SECONDS",
+      "TCP keepalive period, 0 to disable",
+    )
+    .value_parser(value_parser!(u64)),
+    directive(
+      "tcp-backlog",
+      "N",
+      "Connections queued while waiting to be accepted",
+    )
+    .value_parser(value_parser!(i32).range(0..)),
+    yes_no(
+      "tcp-nodelay",
+      "Disable Nagle's algorithm on client connections",
+    ),
+    multi_value_directive(
+      "client-output-buffer-limit",
+      "LIMIT",
+      "<class> <hard> <soft> <seconds> ... for normal, replica and pubsub clients",
+    ),
+    directive("maxmemory", "BYTES", "Memory limit of the dataset").value_parser(memory),
+    directive(
+      "maxmemory-clients",
+      "BYTES|PERCENT",
+      "Memory limit of all clients",
+    )
+    .value_parser(memory_or_percentage),
+    directive("maxmemory-policy", "POLICY", "Keys evicted past maxmemory")
+      .value_parser(PossibleValuesParser::new(eviction::POLICIES)),
+    directive(
+      "maxmemory-samples",
+      "COUNT",
+      "Keys sampled to pick one to evict",
+    )
+    .value_parser(value_parser!(u64).range(1..=64)),
+    yes_no(
+      "negative-lookup-cache",
+      "Answer GET misses from a filter over the keys",
+    ),
+    directive(
+      "offload-min-keys",
+      "KEYS",
+      "Keyspace size from which KEYS and DEBUG DIGEST run on a blocking thread, 0 to disable",
+    )
+    .value_parser(value_parser!(u64).range(0..=i32::MAX as u64)),
+    directive(
+      "ratelimit",
+      "COMMANDS",
+      "Commands per second allowed, 0 to disable",
+    )
+    .value_parser(rate),
+    directive("ratelimit-burst", "COMMANDS", "Commands allowed in a burst").value_parser(rate),
+    directive(
+      "ratelimit-policy",
+      "delay|reject",
+      "What happens to commands over the limit",
+    )
+    .value_parser(PossibleValuesParser::new(["delay", "reject"])),
+    directive("ratelimit-scope", "client|user", "Who shares a rate limit")
+      .value_parser(PossibleValuesParser::new(["client", "user"])),
+    multi_value_directive(
+      "read-through-command",
+      "PROGRAM",
+      "Program printing the value of a key GET misses, given the key as last argument",
+    ),
+    directive(
+      "read-through-timeout",
+      "MILLISECONDS",
+      "Time the read-through program or handler may take",
+    )
+    .value_parser(value_parser!(u64).range(1..=i32::MAX as u64)),
+    directive(
+      "read-through-ttl",
+      "SECONDS",
+      "TTL of keys loaded on a miss, 0 to keep them",
+    )
+    .value_parser(value_parser!(u64).range(0..=i32::MAX as u64)),
+    multi_value_directive(
+      "latency-tracking-info-percentiles",
+      "PERCENTILE",
+      "Percentiles reported by INFO latencystats",
+    ),
+    yes_no(
+      "lazyfree-lazy-expire",
+      "Free expired keys in the background",
+    ),
+    yes_no(
+      "lazyfree-lazy-server-del",
+      "Free overwritten values in the background",
+    ),
+    yes_no("lazyfree-lazy-user-del", "Make DEL behave like UNLINK"),
+    yes_no(
+      "lazyfree-lazy-user-flush",
+      "Make FLUSHALL behave like FLUSHALL ASYNC",
+    ),
+    yes_no(
+      "lazyfree-lazy-eviction",
+      "Free evicted keys in the background",
+    ),
+    directive("tls-port", "PORT", "TCP port accepting TLS connections")
+      .value_parser(value_parser!(u16)),
+    directive(
+      "tls-cert-file",
+      "FILE",
+      "PEM certificate presented by the server",
+    )
+    .value_parser(existing_file),
+    directive("tls-key-file", "FILE", "PEM private key of the certificate")
+      .value_parser(existing_file),
+    directive(
+      "tls-ca-cert-file",
+      "FILE",
+      "PEM CA verifying client certificates",
+    )
+    .value_parser(existing_file),
+    directive(
+      "tls-auth-clients",
+      "yes|no|optional",
+      "Whether TLS clients must present a certificate",
+    )
+    .value_parser(PossibleValuesParser::new(["yes", "no", "optional"])),
+    directive(
+      "tls-auth-clients-user",
+      "off|CN|SAN",
+      "Certificate field naming the ACL user of TLS clients",
+    )
+    .value_parser(PossibleValuesParser::new(["off", "CN", "SAN"])),
+    yes_no(
+      "tls-proxy-protocol",
+      "Expect a PROXY protocol header on TLS connections",
+    ),
+  ]
+}
+
+/// The command line interface, with --help and --version
+pub fn cli() -> Command {
+  Command::new("redis-server")
+    .version(env!("CARGO_PKG_VERSION"))
+    .about("A Redis compatible server")
+    .arg(
+      Arg::new("config-file")
+        .value_name("CONFIG_FILE")
+        .help("Configuration file in redis.conf format, overridden by the options")
+        .value_parser(existing_file),
+    )
+    .arg(
+      Arg::new("diff-rdb")
+        .long("diff-rdb")
+        .value_names(["A", "B"])
+        .num_args(2)
+        .help("Compare two RDB files as JSON and exit: 0 when equal, 1 when they differ")
+        .value_parser(existing_file),
+    )
+    .arg(
+      Arg::new("cli")
+        .long("cli")
+        .value_names(["HOST", "PORT"])
+        .num_args(0..=2)
+        .help(
+          "Run an interactive client of the server at HOST and PORT, 127.0.0.1 6379 by default",
+        ),
+    )
+    .args(directives())
+}
+
+/// Parses the command line (binary name included) into directives and their
+/// values. Prints the help, the version or the usage error and exits when
+/// asked to or when an argument is invalid.
+pub fn parse_cli_arguments(args: Vec<String>) -> CLIArguments {
+  let matches = cli().get_matches_from(args);
+  let config_file = matches.get_raw("config-file").and_then(|mut values| {
+    let path = Path::new(values.next()?);
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    Some(("config-file".to_string(), vec![path.display().to_string()]))
+  });
+  let diff_rdb = matches.get_raw("diff-rdb").map(|files| {
+    let files = files
+      .map(|file| file.to_string_lossy().into_owned())
+      .collect();
+    ("diff-rdb".to_string(), files)
+  });
+  // Given without a value when connecting to the default address
+  let client = matches.contains_id("cli").then(|| {
+    let address = matches
+      .get_raw("cli")
+      .into_iter()
+      .flatten()
+      .map(|value| value.to_string_lossy().into_owned())
+      .collect();
+    ("cli".to_string(), address)
+  });
+  let arguments: CLIArguments = config_file
     .into_iter()
-    .filter(|s| !s.is_empty())
-    .collect::<Vec<String>>()
-    .chunks(2)
-    .filter_map(|chunk| {
-      if chunk.len() == 2 {
-        Some((chunk[0].clone().to_lowercase(), chunk[1].clone()))
-      } else {
-        None
+    .chain(diff_rdb)
+    .chain(client)
+    .chain(directives().iter().filter_map(|directive| {
+      let name = directive.get_id().as_str();
+      let values = matches
+        .get_raw(name)?
+        .map(|value| value.to_string_lossy().into_owned())
+        .collect();
+      Some((name.to_string(), values))
+    }))
+    .collect();
+
+  for (argument, values) in &arguments {
+    if argument == "replicaof" {
+      if let Err(e) = replica_of(values) {
+        cli()
+          .error(
+            ErrorKind::InvalidValue,
+            format!("invalid value for '--replicaof': {}", e),
+          )
+          .exit();
       }
-    })
-    .collect()
+    }
+  }
+  arguments
 }
 
 pub async fn process_configuration_arguments(
@@ -38,22 +498,23 @@ pub async fn process_configuration_arguments(
   config: Arc<AsyncMutex<Config>>,
 ) {
   let config = config.lock().await;
-  for (argument, argument_value) in arguments {
+  for (argument, values) in arguments {
+    let argument_value = values.join(" ");
     match argument.as_str() {
-      "--dir" => {
-        println!("Dir: {}", argument_value);
+      "dir" => {
+        info!("Dir: {}", argument_value);
         let directory = argument_value.clone();
         config.set("dir".to_string(), argument_value);
         // Create the directory if it doesn't exist
         create_dir_all(directory.clone()).unwrap();
       }
-      "--dbfilename" => {
-        println!("DBFilename: {}", argument_value);
+      "dbfilename" => {
+        info!("DBFilename: {}", argument_value);
         config.set("dbfilename".to_string(), argument_value);
 
         let file_path = format!(
           "{}/{}",
-          config.get("dir").unwrap(),
+          config.get("dir").unwrap_or_else(|| ".".to_string()),
           config.get("dbfilename").unwrap()
         );
         // Create the file if it doesn't exist
@@ -63,23 +524,21 @@ pub async fn process_configuration_arguments(
           File::create(file_path).unwrap();
         }
       }
-      "--replicaof" => {
+      "replicaof" => {
+        // Stored as "<host> <port>", without the brackets of IPv6 hosts
+        let Ok((host, port)) = parse_host_port(&argument_value) else {
+          continue;
+        };
         info!(
           "Role: Slave. This redis instance is a replica of {}",
-          argument_value
+          format_host_port(&host, port)
         );
-        let directory = argument_value.clone();
-        config.set("replicaof".to_string(), argument_value);
-        // Create the directory if it doesn't exist
-        create_dir_all(directory.clone()).unwrap();
-      }
-      _ => {
-        // If there is no replicaof argument, then this instance is a master.
-        // generate random id
-        let replication_id = nanoid!(40, &ALPHABET);
-        config.set("replication_id".to_string(), replication_id.to_string());
-        config.set("replication_offset".to_string(), "0".to_string());
+        config.set("replicaof".to_string(), format!("{} {}", host, port));
       }
+      _ => config.set_values(argument, values),
     }
   }
+
+  config.set("replication_id".to_string(), generate_replication_id());
+  config.set("replication_offset".to_string(), "0".to_string());
 }
@@ -0,0 +1,172 @@
+/**
+ * The audit log: an append-only file recording every write command that ran,
+ * for deployments that must be able to tell who changed what and when.
+ *
+ * `audit-log <file>` turns it on. Each command is one JSON line with the Unix
+ * time in milliseconds, the client address, the authenticated user and the
+ * arguments:
+ *
+ * ```json
+ * {"time":1718000000000,"addr":"127.0.0.1:50412","user":"default","argv":["SET","user:1","alice"]}
+ * ```
+ *
+ * With `audit-log-redact-values yes` only the command name and the keys are
+ * kept, every other argument is written as `(redacted)`. Once the file grows
+ * past `audit-log-max-size` it is renamed to `<file>.1`, shifting the older
+ * ones up to `<file>.<audit-log-max-files>`, and a new file is started. A max
+ * size of 0 never rotates, 0 files keeps no old ones. Commands refused before
+ * they run (ACL, cluster redirects, rate limits) are not logged.
+ */
+use crate::config::{parse_memory, Config};
+use serde_json::json;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// Written instead of the values when they are redacted
+const REDACTED: &str = "(redacted)";
+
+struct Output {
+  path: PathBuf,
+  file: File,
+  /// Bytes in the current file
+  size: u64,
+}
+
+struct Settings {
+  output: Option<Output>,
+  redact_values: bool,
+  max_size: u64,
+  max_files: usize,
+}
+
+pub struct AuditLog {
+  settings: Mutex<Settings>,
+}
+
+impl AuditLog {
+  pub fn new() -> Self {
+    Self {
+      settings: Mutex::new(Settings {
+        output: None,
+        redact_values: false,
+        max_size: 0,
+        max_files: 0,
+      }),
+    }
+  }
+
+  /// Takes the `audit-log` parameters from the configuration, opening or
+  /// closing the file as needed
+  pub fn apply_config(&self, config: &Config) -> Result<(), String> {
+    let path = config.get("audit-log").unwrap_or_default();
+    let mut settings = self.settings.lock().unwrap();
+    settings.redact_values = config.get("audit-log-redact-values").as_deref() == Some("yes");
+    settings.max_size = config
+      .get_or_default("audit-log-max-size")
+      .and_then(|value| parse_memory(&value))
+      .unwrap_or(0);
+    settings.max_files = config
+      .get_or_default("audit-log-max-files")
+      .and_then(|value| value.parse::<usize>().ok())
+      .unwrap_or(0);
+
+    if path.is_empty() {
+      settings.output = None;
+    } else if settings.output.as_ref().map(|output| &output.path) != Some(&PathBuf::from(&path)) {
+      settings.output = Some(open(PathBuf::from(&path))?);
+    }
+    Ok(())
+  }
+
+  pub fn enabled(&self) -> bool {
+    self.settings.lock().unwrap().output.is_some()
+  }
+
+  /// Logs a write command. `keys` are the arguments kept when values are
+  /// redacted.
+  pub fn record(&self, argv: &[String], keys: &[String], addr: &str, user: &str) {
+    let mut guard = self.settings.lock().unwrap();
+    let settings = &mut *guard;
+    let redact_values = settings.redact_values;
+    let Some(output) = settings.output.as_mut() else {
+      return;
+    };
+    let argv: Vec<&str> = argv
+      .iter()
+      .enumerate()
+      .map(|(index, argument)| {
+        if !redact_values || index == 0 || keys.contains(argument) {
+          argument.as_str()
+        } else {
+          REDACTED
+        }
+      })
+      .collect();
+    let time = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .unwrap_or_default()
+      .as_millis() as u64;
+    let line = json!({
+      "time": time,
+      "addr": addr,
+      "user": user,
+      "argv": argv,
+    })
+    .to_string()
+      + "\n";
+
+    if let Err(e) = output.file.write_all(line.as_bytes()) {
+      warn!(
+        "Failed to write to the audit log {}: {}",
+        output.path.display(),
+        e
+      );
+      return;
+    }
+    output.size += line.len() as u64;
+    if settings.max_size > 0 && output.size >= settings.max_size {
+      let path = output.path.clone();
+      match rotate(&path, settings.max_files).and_then(|()| open(path)) {
+        Ok(rotated) => settings.output = Some(rotated),
+        Err(e) => warn!("Failed to rotate the audit log: {}", e),
+      }
+    }
+  }
+}
+
+impl Default for AuditLog {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+fn open(path: PathBuf) -> Result<Output, String> {
+  let file = OpenOptions::new()
+    .create(true)
+    .append(true)
+    .open(&path)
+    .map_err(|e| format!("Can't open the audit log {}: {}", path.display(), e))?;
+  let size = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+  Ok(Output { path, file, size })
+}
+
+/// `<path>.<n>` becomes `<path>.<n + 1>`, the oldest past `max_files` is
+/// dropped, and `path` becomes `<path>.1`
+fn rotate(path: &Path, max_files: usize) -> Result<(), String> {
+  let numbered = |n: usize| PathBuf::from(format!("{}.{}", path.display(), n));
+  let error = |e: std::io::Error| format!("{}: {}", path.display(), e);
+  if max_files == 0 {
+    return fs::remove_file(path).map_err(error);
+  }
+  let _ = fs::remove_file(numbered(max_files));
+  for n in (1..max_files).rev() {
+    if numbered(n).exists() {
+      fs::rename(numbered(n), numbered(n + 1)).map_err(error)?;
+    }
+  }
+  fs::rename(path, numbered(1)).map_err(error)
+}
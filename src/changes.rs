@@ -0,0 +1,70 @@
+/**
+ * The change feed of the keyspace, for applications embedding the server that
+ * mirror its data into other systems (a search index, a cache tier, an event
+ * bus) without polling it.
+ *
+ * `Storage::subscribe_changes()` returns a broadcast receiver of `Change`s,
+ * one per key written, removed or given a new deadline, carrying the state of
+ * the key before and after. FLUSHALL sends a removal per key. Nothing is
+ * recorded while nobody is subscribed. Subscribers that fall more than
+ * `CHANGE_FEED_CAPACITY` changes behind get `RecvError::Lagged` with the
+ * number of changes they missed, and should resynchronize from a snapshot.
+ *
+ * ```no_run
+ * # async fn mirror(storage: &redis_starter_rust::Storage) {
+ * use redis_starter_rust::changes::ChangeKind;
+ *
+ * let mut changes = storage.subscribe_changes();
+ * while let Ok(change) = changes.recv().await {
+ *   match change.kind {
+ *     ChangeKind::Set | ChangeKind::Expire => println!("{} = {:?}", change.key, change.after),
+ *     ChangeKind::Del | ChangeKind::Expired | ChangeKind::Evicted => {
+ *       println!("{} removed", change.key)
+ *     }
+ *   }
+ * }
+ * # }
+ * ```
+ */
+use std::time::SystemTime;
+
+/// Changes buffered for the slowest subscriber
+pub const CHANGE_FEED_CAPACITY: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+  /// Written by SET, INCRBY... or loaded at startup
+  Set,
+  /// Removed by DEL, UNLINK or FLUSHALL
+  Del,
+  /// Given a new deadline by EXPIRE, PEXPIRE or PERSIST
+  Expire,
+  /// Removed because its deadline passed
+  Expired,
+  /// Removed past `maxmemory`, see `eviction`
+  Evicted,
+}
+
+/// A key's value and deadline at one point in time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyState {
+  pub value: String,
+  /// Wall clock time the key expires at
+  pub expires_at: Option<SystemTime>,
+}
+
+impl KeyState {
+  pub(crate) fn new(value: String, expires_at: Option<SystemTime>) -> Self {
+    Self { value, expires_at }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+  pub kind: ChangeKind,
+  pub key: String,
+  /// `None` when the key didn't exist
+  pub before: Option<KeyState>,
+  /// `None` when the key was removed
+  pub after: Option<KeyState>,
+}
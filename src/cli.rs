@@ -0,0 +1,355 @@
+/**
+ * `redis-server --cli [host port]` is a small interactive client, for trying
+ * the server out without installing redis-cli. It connects to 127.0.0.1:6379
+ * unless told otherwise, reads commands with line editing and history (see
+ * `lineedit`), quoted as in the configuration file, and prints the replies as
+ * redis-cli does:
+ *
+ * ```text
+ * 127.0.0.1:6379> KEYS user:*
+ * 1) "user:1"
+ * 2) "user:2"
+ * ```
+ *
+ * When the connection drops the command is sent again over a new one; while
+ * the server can't be reached the prompt says so and every command tries to
+ * connect. After MONITOR the commands it reports are printed until Ctrl-C.
+ * `quit`, `exit` and Ctrl-D leave.
+ */
+use crate::address::parse_host_port;
+use crate::configfile::split_arguments;
+use crate::lineedit::LineEditor;
+use crate::server::DEFAULT_PORT;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+
+/// Commands whose replies are printed as they are, being meant for people
+const RAW_COMMANDS: [&str; 4] = ["info", "lolwut", "client list", "cluster nodes"];
+
+/// A reply, RESP2 or RESP3
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+  Status(String),
+  Error(String),
+  Integer(i64),
+  Bulk(Vec<u8>),
+  Nil,
+  Array(Vec<Reply>),
+  Double(String),
+  Boolean(bool),
+  BigNumber(String),
+  Verbatim(String),
+  Map(Vec<(Reply, Reply)>),
+  Set(Vec<Reply>),
+  Push(Vec<Reply>),
+}
+
+/// A connection to a server, made again when it drops
+pub struct Client {
+  host: String,
+  port: u16,
+  connection: Option<(TcpStream, BufReader<TcpStream>)>,
+}
+
+impl Client {
+  pub fn new(host: &str, port: u16) -> Self {
+    Self {
+      host: host.to_string(),
+      port,
+      connection: None,
+    }
+  }
+
+  pub fn connected(&self) -> bool {
+    self.connection.is_some()
+  }
+
+  pub fn connect(&mut self) -> io::Result<()> {
+    let stream = TcpStream::connect((self.host.as_str(), self.port))?;
+    let reader = BufReader::new(stream.try_clone()?);
+    self.connection = Some((stream, reader));
+    Ok(())
+  }
+
+  /// Sends `arguments` and reads the reply, connecting again and sending
+  /// once more if the connection dropped
+  pub fn call(&mut self, arguments: &[String]) -> io::Result<Reply> {
+    if self.connected() {
+      match self.send(arguments) {
+        Ok(reply) => return Ok(reply),
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => return Err(e),
+        Err(_) => self.connection = None,
+      }
+    }
+    self.connect()?;
+    self.send(arguments).inspect_err(|_| self.connection = None)
+  }
+
+  /// Reads a reply the server sends by itself, such as a published message
+  pub fn read(&mut self) -> io::Result<Reply> {
+    let Some((_, reader)) = &mut self.connection else {
+      return Err(io::ErrorKind::NotConnected.into());
+    };
+    read_reply(reader).inspect_err(|_| self.connection = None)
+  }
+
+  fn send(&mut self, arguments: &[String]) -> io::Result<Reply> {
+    let Some((stream, reader)) = &mut self.connection else {
+      return Err(io::ErrorKind::NotConnected.into());
+    };
+    stream.write_all(&encode_command(arguments))?;
+    read_reply(reader)
+  }
+}
+
+/// `arguments` as a RESP array of bulk strings
+pub fn encode_command(arguments: &[String]) -> Vec<u8> {
+  let mut command = format!("*{}\r\n", arguments.len()).into_bytes();
+  for argument in arguments {
+    command.extend_from_slice(format!("${}\r\n", argument.len()).as_bytes());
+    command.extend_from_slice(argument.as_bytes());
+    command.extend_from_slice(b"\r\n");
+  }
+  command
+}
+
+/// Reads a reply, skipping RESP3 attributes
+pub fn read_reply(reader: &mut impl BufRead) -> io::Result<Reply> {
+  let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+  let line = read_line(reader)?;
+  let (kind, rest) = line.split_at(1);
+  let length = || -> io::Result<i64> {
+    rest
+      .parse()
+      .map_err(|_| invalid(format!("Protocol error: invalid length '{}'", rest)))
+  };
+  Ok(match kind {
+    "+" => Reply::Status(rest.to_string()),
+    "-" => Reply::Error(rest.to_string()),
+    ":" => Reply::Integer(length()?),
+    "," => Reply::Double(rest.to_string()),
+    "#" => Reply::Boolean(rest == "t"),
+    "(" => Reply::BigNumber(rest.to_string()),
+    "_" => Reply::Nil,
+    "$" | "=" | "!" => {
+      let length = length()?;
+      if length < 0 {
+        return Ok(Reply::Nil);
+      }
+      let mut bytes = vec![0; length as usize + 2];
+      reader.read_exact(&mut bytes)?;
+      bytes.truncate(length as usize);
+      match kind {
+        "$" => Reply::Bulk(bytes),
+        // The format comes first, as in "txt:"
+        "=" => Reply::Verbatim(String::from_utf8_lossy(bytes.get(4..).unwrap_or(&[])).into_owned()),
+        _ => Reply::Error(String::from_utf8_lossy(&bytes).into_owned()),
+      }
+    }
+    "*" | "~" | ">" => {
+      let length = length()?;
+      if length < 0 {
+        return Ok(Reply::Nil);
+      }
+      let elements = read_elements(reader, length)?;
+      match kind {
+        "*" => Reply::Array(elements),
+        "~" => Reply::Set(elements),
+        _ => Reply::Push(elements),
+      }
+    }
+    "%" | "|" => {
+      let mut pairs = read_elements(reader, length()? * 2)?.into_iter();
+      let mut entries = Vec::new();
+      while let (Some(key), Some(value)) = (pairs.next(), pairs.next()) {
+        entries.push((key, value));
+      }
+      if kind == "|" {
+        return read_reply(reader);
+      }
+      Reply::Map(entries)
+    }
+    _ => return Err(invalid(format!("Protocol error: unexpected '{}'", line))),
+  })
+}
+
+fn read_elements(reader: &mut impl BufRead, count: i64) -> io::Result<Vec<Reply>> {
+  (0..count).map(|_| read_reply(reader)).collect()
+}
+
+/// A line of the reply, without its line break
+fn read_line(reader: &mut impl BufRead) -> io::Result<String> {
+  let mut line = String::new();
+  if reader.read_line(&mut line)? == 0 {
+    return Err(io::ErrorKind::UnexpectedEof.into());
+  }
+  let line = line.trim_end_matches(['\r', '\n']);
+  if line.is_empty() {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidData,
+      "Protocol error: empty line",
+    ));
+  }
+  Ok(line.to_string())
+}
+
+/// `reply` as redis-cli prints it on a terminal
+pub fn format_reply(reply: &Reply) -> String {
+  format_lines(reply).join("\n")
+}
+
+fn format_lines(reply: &Reply) -> Vec<String> {
+  match reply {
+    Reply::Status(status) => vec![status.clone()],
+    Reply::Error(error) => vec![format!("(error) {}", error)],
+    Reply::Integer(integer) => vec![format!("(integer) {}", integer)],
+    Reply::Bulk(bytes) => vec![quote(bytes)],
+    Reply::Nil => vec!["(nil)".to_string()],
+    Reply::Double(double) => vec![format!("(double) {}", double)],
+    Reply::Boolean(boolean) => vec![format!("({})", boolean)],
+    Reply::BigNumber(number) => vec![format!("(big number) {}", number)],
+    Reply::Verbatim(text) => text
+      .trim_end()
+      .split('\n')
+      .map(|line| line.trim_end_matches('\r').to_string())
+      .collect(),
+    Reply::Array(elements) | Reply::Push(elements) if elements.is_empty() => {
+      vec!["(empty array)".to_string()]
+    }
+    Reply::Set(elements) if elements.is_empty() => vec!["(empty set)".to_string()],
+    Reply::Map(entries) if entries.is_empty() => vec!["(empty hash)".to_string()],
+    Reply::Array(elements) | Reply::Push(elements) | Reply::Set(elements) => {
+      numbered(elements.iter().map(format_lines), ")")
+    }
+    Reply::Map(entries) => numbered(
+      entries.iter().map(|(key, value)| {
+        let mut lines = format_lines(value);
+        lines[0] = format!("{} => {}", format_lines(key).join(" "), lines[0]);
+        lines
+      }),
+      "#",
+    ),
+  }
+}
+
+/// Elements numbered from 1, the lines after the first of an element
+/// indented under it
+fn numbered(elements: impl ExactSizeIterator<Item = Vec<String>>, marker: &str) -> Vec<String> {
+  let width = elements.len().to_string().len();
+  let mut lines = Vec::new();
+  for (index, element) in elements.enumerate() {
+    let label = format!("{:>width$}{} ", index + 1, marker, width = width);
+    for (line_index, line) in element.into_iter().enumerate() {
+      match line_index {
+        0 => lines.push(format!("{}{}", label, line)),
+        _ => lines.push(format!("{}{}", " ".repeat(label.len()), line)),
+      }
+    }
+  }
+  lines
+}
+
+/// A string between quotes, escaped like `sdscatrepr`
+fn quote(bytes: &[u8]) -> String {
+  let mut quoted = String::from("\"");
+  for &byte in bytes {
+    match byte {
+      b'\\' => quoted.push_str("\\\\"),
+      b'"' => quoted.push_str("\\\""),
+      b'\n' => quoted.push_str("\\n"),
+      b'\r' => quoted.push_str("\\r"),
+      b'\t' => quoted.push_str("\\t"),
+      0x07 => quoted.push_str("\\a"),
+      0x08 => quoted.push_str("\\b"),
+      b' '..=b'~' => quoted.push(byte as char),
+      _ => quoted.push_str(&format!("\\x{:02x}", byte)),
+    }
+  }
+  quoted.push('"');
+  quoted
+}
+
+/// The reply to print for `arguments`
+fn display(arguments: &[String], reply: &Reply) -> String {
+  let command = arguments
+    .iter()
+    .take(2)
+    .map(|argument| argument.to_lowercase())
+    .collect::<Vec<String>>();
+  let raw = RAW_COMMANDS
+    .iter()
+    .any(|raw| *raw == command[0] || *raw == command.join(" "));
+  match reply {
+    Reply::Bulk(bytes) if raw => String::from_utf8_lossy(bytes).trim_end().to_string(),
+    _ => format_reply(reply),
+  }
+}
+
+/// Runs the client against `address` (none, the host, or the host and the
+/// port), returning the exit status
+pub fn run(address: &[String]) -> i32 {
+  let (host, port) = match address {
+    [] => ("127.0.0.1".to_string(), DEFAULT_PORT),
+    [host] => parse_host_port(host).unwrap_or_else(|_| (host.clone(), DEFAULT_PORT)),
+    _ => match parse_host_port(&address.join(" ")) {
+      Ok(address) => address,
+      Err(e) => {
+        eprintln!("Invalid address: {}", e);
+        return 1;
+      }
+    },
+  };
+  let mut client = Client::new(&host, port);
+  let unreachable = |e: io::Error| {
+    println!(
+      "Could not connect to the server at {}:{}: {}",
+      host, port, e
+    );
+  };
+  if let Err(e) = client.connect() {
+    unreachable(e);
+  }
+
+  let mut editor = LineEditor::new();
+  loop {
+    let prompt = match client.connected() {
+      true => format!("{}:{}> ", host, port),
+      false => "not connected> ".to_string(),
+    };
+    let line = match editor.read_line(&prompt) {
+      Ok(Some(line)) => line,
+      Ok(None) => return 0,
+      Err(e) => {
+        eprintln!("Failed to read the input: {}", e);
+        return 1;
+      }
+    };
+    let arguments = match split_arguments(&line) {
+      Ok(arguments) if arguments.is_empty() => continue,
+      Ok(arguments) => arguments,
+      Err(_) => {
+        println!("Invalid argument(s)");
+        continue;
+      }
+    };
+    let command = arguments[0].to_lowercase();
+    if command == "quit" || command == "exit" {
+      return 0;
+    }
+    match client.call(&arguments) {
+      Ok(reply) => println!("{}", display(&arguments, &reply)),
+      Err(e) if e.kind() == io::ErrorKind::InvalidData => println!("{}", e),
+      Err(e) => {
+        unreachable(e);
+        continue;
+      }
+    }
+    // The server keeps sending after MONITOR
+    if command == "monitor" {
+      println!("Reading messages... (press Ctrl-C to quit)");
+      while let Ok(reply) = client.read() {
+        println!("{}", format_reply(&reply));
+      }
+    }
+  }
+}
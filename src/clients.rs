@@ -0,0 +1,1143 @@
+/**
+ * Registry of connected clients.
+ *
+ * Every connection registers itself on accept and unregisters when its task ends.
+ * The registry backs the CLIENT command family and is formatted the same way as
+ * Redis's CLIENT LIST so existing tooling can parse it.
+ */
+use crate::acl::DEFAULT_USER;
+use crate::config::{parse_memory, Config};
+use crate::cron::Scheduler;
+use crate::output::{OutputBuffer, OutputLimits};
+use crate::parser::RedisValue;
+use crate::stats::Stats;
+use crate::telemetry;
+use crate::tracking::{TrackingOptions, TrackingTable};
+use dashmap::DashMap;
+use socket2::{SockRef, TcpKeepalive};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::{Mutex as AsyncMutex, Notify};
+use tokio::time::Instant;
+use tracing::{debug, warn};
+
+/// Connections accepted at most at the same time, unless `maxclients` says otherwise
+pub const DEFAULT_MAX_CLIENTS: usize = 10000;
+
+/// `maxclients` from the configuration
+pub fn max_clients(config: &Config) -> usize {
+  config
+    .get("maxclients")
+    .and_then(|max| max.parse::<usize>().ok())
+    .unwrap_or(DEFAULT_MAX_CLIENTS)
+}
+
+/// Seconds between TCP keepalive probes unless `tcp-keepalive` says otherwise
+pub const DEFAULT_TCP_KEEPALIVE: u64 = 300;
+
+/// Size of the buffer each connection reads commands into
+pub const QUERY_BUFFER_SIZE: usize = 512;
+
+/// Bytes a client may buffer without completing a command before it is
+/// disconnected, Redis's default `client-query-buffer-limit`
+pub const QUERY_BUFFER_LIMIT: usize = 1024 * 1024 * 1024;
+
+/// How often idle connections and client memory are checked
+const CLIENTS_CRON_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Time the clients cron may take before it counts as an overrun
+const CLIENTS_CRON_BUDGET: Duration = Duration::from_millis(50);
+
+/// What a connection is used for, as reported by CLIENT LIST TYPE
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientKind {
+  Normal,
+  /// Our link to the master we replicate from
+  Master,
+  /// A replica streaming our replication feed
+  Replica,
+  /// A client in pub/sub mode
+  PubSub,
+}
+
+impl ClientKind {
+  /// Parses a TYPE argument of CLIENT LIST and CLIENT KILL
+  pub fn parse(name: &str) -> Option<Self> {
+    match name.to_lowercase().as_str() {
+      "normal" => Some(ClientKind::Normal),
+      "master" => Some(ClientKind::Master),
+      "replica" | "slave" => Some(ClientKind::Replica),
+      "pubsub" => Some(ClientKind::PubSub),
+      _ => None,
+    }
+  }
+
+  /// Replica links and subscribers sit idle by design, so `timeout` spares them
+  pub fn may_time_out(&self) -> bool {
+    *self == ClientKind::Normal
+  }
+}
+
+/// State tracked for a single connection
+#[derive(Debug, Clone)]
+pub struct ClientInfo {
+  pub id: u64,
+  pub addr: SocketAddr,
+  pub laddr: SocketAddr,
+  pub fd: i64,
+  pub name: String,
+  pub connected_at: Instant,
+  pub last_interaction: Instant,
+  /// Name of the last command, e.g. `client|list`
+  pub last_command: String,
+  pub db: u32,
+  pub kind: ClientKind,
+  /// Bytes of the last read still held in the query buffer
+  pub query_buffer: usize,
+  /// ACL user the connection runs commands as
+  pub user: String,
+  /// Whether the connection has authenticated as `user`
+  pub authenticated: bool,
+  /// CLIENT NO-EVICT: exempt from client eviction
+  pub no_evict: bool,
+  /// CLIENT NO-TOUCH: reads don't update the access time of keys
+  pub no_touch: bool,
+  /// In MONITOR mode: receives every command and runs none
+  pub monitor: bool,
+  /// ASKING: the next command may use a slot this node is importing
+  pub asking: bool,
+  /// CLIENT SETINFO LIB-NAME and LIB-VER: the client library
+  pub lib_name: String,
+  pub lib_ver: String,
+  /// CLIENT SETINFO TRACEPARENT: the application trace commands belong to
+  pub traceparent: Option<String>,
+  /// CLIENT TRACKING settings, `None` while tracking is off
+  pub tracking: Option<TrackingOptions>,
+  /// Protocol version picked with HELLO, 2 or 3
+  pub resp: u8,
+  /// Signalled to make the connection task drop the client
+  pub kill: Arc<Notify>,
+  /// Out-of-band messages for the connection task to write
+  pub push: UnboundedSender<Vec<u8>>,
+  pub output: Arc<OutputBuffer>,
+}
+
+impl ClientInfo {
+  /// Single CLIENT LIST line describing the client, without the trailing newline
+  pub fn describe(&self) -> String {
+    let now = Instant::now();
+    format!(
+      "id={} addr={} laddr={} fd={} name={} age={} idle={} flags={} db={} sub=0 psub=0 ssub=0 multi=-1 qbuf={} qbuf-free={} argv-mem=0 multi-mem=0 rbs={} rbp=0 obl=0 oll={} omem={} tot-mem={} events=r cmd={} user={} redir=-1 resp={} lib-name={} lib-ver={}",
+      self.id,
+      self.addr,
+      self.laddr,
+      self.fd,
+      self.name,
+      now.duration_since(self.connected_at).as_secs(),
+      now.duration_since(self.last_interaction).as_secs(),
+      self.flags(),
+      self.db,
+      self.query_buffer,
+      QUERY_BUFFER_SIZE.saturating_sub(self.query_buffer),
+      QUERY_BUFFER_SIZE,
+      self.output.messages(),
+      self.output.bytes(),
+      self.memory(),
+      if self.last_command.is_empty() {
+        "NULL"
+      } else {
+        &self.last_command
+      },
+      self.user,
+      self.resp,
+      self.lib_name,
+      self.lib_ver,
+    )
+  }
+
+  /// Memory used by the connection: its bookkeeping, query buffer and queued output
+  pub fn memory(&self) -> usize {
+    std::mem::size_of::<ClientInfo>()
+      + self.name.len()
+      + self.last_command.len()
+      + self.user.len()
+      + self.lib_name.len()
+      + self.lib_ver.len()
+      + QUERY_BUFFER_SIZE.max(self.query_buffer)
+      + self.output.bytes()
+  }
+
+  /// Whether client eviction may pick this client. Replication links are spared,
+  /// like clients that asked for it with CLIENT NO-EVICT.
+  pub fn evictable(&self) -> bool {
+    !self.no_evict && matches!(self.kind, ClientKind::Normal | ClientKind::PubSub)
+  }
+
+  /// Client flags in CLIENT LIST notation
+  pub fn flags(&self) -> String {
+    let mut flags = String::new();
+    match self.kind {
+      ClientKind::Master => flags.push('M'),
+      ClientKind::Replica => flags.push('S'),
+      ClientKind::PubSub => flags.push('P'),
+      ClientKind::Normal => {}
+    }
+    if self.monitor {
+      flags.push('O');
+    }
+    if self.no_evict {
+      flags.push('e');
+    }
+    if self.no_touch {
+      flags.push('T');
+    }
+    if let Some(tracking) = &self.tracking {
+      flags.push('t');
+      if tracking.bcast {
+        flags.push('B');
+      }
+      if tracking.broken_redirect {
+        flags.push('R');
+      }
+    }
+    if flags.is_empty() {
+      flags.push('N');
+    }
+    flags
+  }
+}
+
+/// Which commands CLIENT PAUSE holds back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PauseMode {
+  /// Only commands that may modify the dataset
+  Write,
+  /// Every command
+  All,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Pause {
+  mode: PauseMode,
+  until: Instant,
+}
+
+pub struct ClientRegistry {
+  next_id: AtomicU64,
+  clients: DashMap<u64, ClientInfo>,
+  pause: Mutex<Option<Pause>>,
+  /// Woken up when a pause is lifted before its deadline
+  unpaused: Notify,
+  output_limits: Mutex<OutputLimits>,
+  /// Clients in MONITOR mode
+  monitors: Mutex<Vec<u64>>,
+  tracking: TrackingTable,
+  /// `maxmemory-clients` in bytes, 0 when client eviction is off
+  max_memory: AtomicU64,
+  /// Counters of the server, for the evicted clients
+  stats: Arc<Stats>,
+}
+
+impl ClientRegistry {
+  pub fn new(stats: Arc<Stats>) -> Self {
+    Self {
+      next_id: AtomicU64::new(1),
+      clients: DashMap::new(),
+      pause: Mutex::new(None),
+      unpaused: Notify::new(),
+      output_limits: Mutex::new(OutputLimits::default()),
+      monitors: Mutex::new(Vec::new()),
+      tracking: TrackingTable::new(),
+      max_memory: AtomicU64::new(0),
+      stats,
+    }
+  }
+
+  /// Registers a freshly accepted connection. Returns its client id, the
+  /// notifier the connection must watch to honor CLIENT KILL and the queue of
+  /// out-of-band messages it must write out.
+  pub fn register(
+    &self,
+    addr: SocketAddr,
+    laddr: SocketAddr,
+    fd: i64,
+  ) -> (u64, Arc<Notify>, UnboundedReceiver<Vec<u8>>) {
+    let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+    let now = Instant::now();
+    let kill = Arc::new(Notify::new());
+    let (push, pushed) = unbounded_channel();
+    self.clients.insert(
+      id,
+      ClientInfo {
+        id,
+        addr,
+        laddr,
+        fd,
+        name: String::new(),
+        connected_at: now,
+        last_interaction: now,
+        last_command: String::new(),
+        db: 0,
+        kind: ClientKind::Normal,
+        query_buffer: 0,
+        user: DEFAULT_USER.to_string(),
+        authenticated: false,
+        no_evict: false,
+        no_touch: false,
+        monitor: false,
+        asking: false,
+        lib_name: String::new(),
+        lib_ver: String::new(),
+        traceparent: None,
+        tracking: None,
+        resp: 2,
+        kill: kill.clone(),
+        push,
+        output: Arc::new(OutputBuffer::default()),
+      },
+    );
+    (id, kill, pushed)
+  }
+
+  pub fn unregister(&self, id: u64) {
+    self.stop_tracking(id);
+    self.clients.remove(&id);
+    self.stop_monitor(id);
+  }
+
+  /// Records how many bytes are waiting in the client's query buffer
+  pub fn read_query(&self, id: u64, bytes: usize) {
+    if let Some(mut client) = self.clients.get_mut(&id) {
+      client.query_buffer = bytes;
+    }
+  }
+
+  /// Records that the client just issued `command`
+  pub fn touch(&self, id: u64, command: &str) {
+    if let Some(mut client) = self.clients.get_mut(&id) {
+      client.last_interaction = Instant::now();
+      client.last_command = command.to_string();
+    }
+  }
+
+  pub fn get(&self, id: u64) -> Option<ClientInfo> {
+    self.clients.get(&id).map(|client| client.clone())
+  }
+
+  /// Runs `update` on the client, if it is still connected
+  pub fn update<R>(&self, id: u64, update: impl FnOnce(&mut ClientInfo) -> R) -> Option<R> {
+    self
+      .clients
+      .get_mut(&id)
+      .map(|mut client| update(&mut client))
+  }
+
+  /// Keys and prefixes watched by CLIENT TRACKING
+  pub fn tracking_table(&self) -> &TrackingTable {
+    &self.tracking
+  }
+
+  /// Number of connected clients
+  pub fn len(&self) -> usize {
+    self.clients.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.clients.is_empty()
+  }
+
+  /// Snapshot of every connected client, ordered by id
+  pub fn all(&self) -> Vec<ClientInfo> {
+    let mut clients: Vec<ClientInfo> = self
+      .clients
+      .iter()
+      .map(|client| client.value().clone())
+      .collect();
+    clients.sort_by_key(|client| client.id);
+    clients
+  }
+
+  /// Marks the client as authenticated as `user`
+  pub fn set_user(&self, id: u64, user: &str) {
+    if let Some(mut client) = self.clients.get_mut(&id) {
+      client.user = user.to_string();
+      client.authenticated = true;
+    }
+  }
+
+  /// ACL user of the client and whether it has authenticated
+  pub fn session(&self, id: u64) -> (String, bool) {
+    self
+      .clients
+      .get(&id)
+      .map(|client| (client.user.clone(), client.authenticated))
+      .unwrap_or_else(|| (DEFAULT_USER.to_string(), false))
+  }
+
+  /// RESET: brings the connection back to the state of a fresh one. The client is
+  /// logged back in as the default user only when that needs no password.
+  pub fn reset(&self, id: u64, default_login: bool) {
+    if let Some(mut client) = self.clients.get_mut(&id) {
+      client.name = String::new();
+      client.db = 0;
+      client.no_evict = false;
+      client.no_touch = false;
+      client.user = DEFAULT_USER.to_string();
+      client.authenticated = default_login;
+      client.monitor = false;
+      client.asking = false;
+      client.traceparent = None;
+      client.resp = 2;
+    }
+    self.stop_monitor(id);
+    self.stop_tracking(id);
+  }
+
+  /// Disconnects every client authenticated as `user`
+  pub fn kill_user(&self, user: &str) {
+    for client in self.all() {
+      if client.authenticated && client.user == user {
+        self.kill_client(client.id);
+      }
+    }
+  }
+
+  /// Whether the client sent ASKING before this command, clearing the flag
+  /// since it only applies to one command
+  pub fn take_asking(&self, id: u64) -> bool {
+    self
+      .update(id, |client| std::mem::take(&mut client.asking))
+      .unwrap_or(false)
+  }
+
+  /// Whether reads issued by the client should leave key access times alone
+  pub fn no_touch(&self, id: u64) -> bool {
+    self
+      .clients
+      .get(&id)
+      .map(|client| client.no_touch)
+      .unwrap_or(false)
+  }
+
+  /// Trace context set with CLIENT SETINFO TRACEPARENT
+  pub fn traceparent(&self, id: u64) -> Option<String> {
+    self
+      .clients
+      .get(&id)
+      .and_then(|client| client.traceparent.clone())
+  }
+
+  /// CLIENT SETINFO LIB-NAME|LIB-VER|TRACEPARENT <value>. An empty
+  /// TRACEPARENT detaches the connection from the application trace.
+  fn set_info(&self, id: u64, args: &[String]) -> RedisValue {
+    let [attribute, value] = args else {
+      return wrong_arguments("client|setinfo");
+    };
+    let attribute = attribute.to_lowercase();
+    match attribute.as_str() {
+      "lib-name" | "lib-ver" => {}
+      "traceparent" if value.is_empty() || telemetry::parse_traceparent(value).is_some() => {}
+      "traceparent" => return RedisValue::Error("ERR Invalid traceparent".to_string()),
+      _ => return RedisValue::Error(format!("ERR Unrecognized option '{}'", args[0])),
+    }
+    if value.chars().any(|c| !('!'..='~').contains(&c)) {
+      return RedisValue::Error(format!(
+        "ERR {} cannot contain spaces, newlines or special characters.",
+        attribute
+      ));
+    }
+    if let Some(mut client) = self.clients.get_mut(&id) {
+      match attribute.as_str() {
+        "lib-name" => client.lib_name = value.clone(),
+        "lib-ver" => client.lib_ver = value.clone(),
+        _ => client.traceparent = (!value.is_empty()).then(|| value.clone()),
+      }
+    }
+    RedisValue::SimpleString("OK".to_string())
+  }
+
+  /// Protocol version of client `id`, 2 until it sends HELLO 3
+  pub fn resp(&self, id: u64) -> u8 {
+    self.clients.get(&id).map_or(2, |client| client.resp)
+  }
+
+  pub fn set_resp(&self, id: u64, resp: u8) {
+    if let Some(mut client) = self.clients.get_mut(&id) {
+      client.resp = resp;
+    }
+  }
+
+  /// Names are restricted to printable characters without spaces, like Redis
+  pub fn set_name(&self, id: u64, name: &str) -> Result<(), String> {
+    if name.chars().any(|c| !('!'..='~').contains(&c)) {
+      return Err(
+        "ERR Client names cannot contain spaces, newlines or special characters.".to_string(),
+      );
+    }
+    if let Some(mut client) = self.clients.get_mut(&id) {
+      client.name = name.to_string();
+    }
+    Ok(())
+  }
+
+  /// Executes a CLIENT subcommand on behalf of client `id`
+  pub fn handle_command(&self, id: u64, subcommand: &str, args: &[String]) -> RedisValue {
+    match subcommand {
+      "ID" => RedisValue::Integer(id as i64),
+      "GETNAME" => match self.get(id) {
+        Some(client) if !client.name.is_empty() => RedisValue::BulkString(Some(client.name)),
+        _ => RedisValue::BulkString(None),
+      },
+      "SETNAME" => match args {
+        [name] => match self.set_name(id, name) {
+          Ok(()) => RedisValue::SimpleString("OK".to_string()),
+          Err(e) => RedisValue::Error(e),
+        },
+        _ => wrong_arguments("client|setname"),
+      },
+      "SETINFO" => self.set_info(id, args),
+      "INFO" => match self.get(id) {
+        Some(client) => RedisValue::BulkString(Some(format!("{}\n", client.describe()))),
+        None => RedisValue::BulkString(None),
+      },
+      "LIST" => self.list(args),
+      "KILL" => self.kill(id, args),
+      "PAUSE" => self.pause_command(args),
+      "NO-EVICT" => self.toggle(id, args, "client|no-evict", |client, on| {
+        client.no_evict = on
+      }),
+      "NO-TOUCH" => self.toggle(id, args, "client|no-touch", |client, on| {
+        client.no_touch = on
+      }),
+      "UNPAUSE" => {
+        self.unpause();
+        RedisValue::SimpleString("OK".to_string())
+      }
+      "TRACKING" => self.tracking_command(id, args),
+      "CACHING" => self.caching_command(id, args),
+      "GETREDIR" => self.getredir_command(id),
+      "TRACKINGINFO" => self.trackinginfo_command(id),
+      _ => RedisValue::Error(format!(
+        "ERR unknown subcommand '{}'. Try CLIENT HELP.",
+        subcommand.to_lowercase()
+      )),
+    }
+  }
+
+  /// CLIENT NO-EVICT|NO-TOUCH ON|OFF
+  fn toggle(
+    &self,
+    id: u64,
+    args: &[String],
+    command: &str,
+    apply: impl FnOnce(&mut ClientInfo, bool),
+  ) -> RedisValue {
+    let on = match args {
+      [value] if value.eq_ignore_ascii_case("on") => true,
+      [value] if value.eq_ignore_ascii_case("off") => false,
+      [_] => return RedisValue::Error("ERR syntax error".to_string()),
+      _ => return wrong_arguments(command),
+    };
+    if let Some(mut client) = self.clients.get_mut(&id) {
+      apply(&mut client, on);
+    }
+    RedisValue::SimpleString("OK".to_string())
+  }
+
+  /// CLIENT LIST [TYPE normal|master|replica|pubsub] [ID client-id ...]
+  fn list(&self, args: &[String]) -> RedisValue {
+    let mut clients = self.all();
+
+    match args.first().map(|a| a.to_uppercase()).as_deref() {
+      None => {}
+      Some("TYPE") if args.len() == 2 => match ClientKind::parse(&args[1]) {
+        Some(kind) => clients.retain(|client| client.kind == kind),
+        None => return RedisValue::Error(format!("ERR Unknown client type '{}'", args[1])),
+      },
+      Some("ID") if args.len() > 1 => {
+        let mut ids = Vec::new();
+        for id in &args[1..] {
+          match id.parse::<u64>() {
+            Ok(id) if id > 0 => ids.push(id),
+            _ => return RedisValue::Error("ERR Invalid client ID".to_string()),
+          }
+        }
+        clients.retain(|client| ids.contains(&client.id));
+      }
+      Some(_) => return RedisValue::Error("ERR syntax error".to_string()),
+    }
+
+    let list: String = clients
+      .iter()
+      .map(|client| format!("{}\n", client.describe()))
+      .collect();
+    RedisValue::BulkString(Some(list))
+  }
+}
+
+/// Filters accepted by CLIENT KILL
+#[derive(Debug, Default)]
+struct KillFilter {
+  id: Option<u64>,
+  addr: Option<String>,
+  laddr: Option<String>,
+  kind: Option<ClientKind>,
+  user: Option<String>,
+  max_age: Option<u64>,
+  skip_me: bool,
+}
+
+impl KillFilter {
+  fn parse(args: &[String]) -> Result<Self, String> {
+    let mut filter = KillFilter {
+      skip_me: true,
+      ..Default::default()
+    };
+
+    if !args.len().is_multiple_of(2) {
+      return Err("ERR syntax error".to_string());
+    }
+
+    for pair in args.chunks(2) {
+      let value = &pair[1];
+      match pair[0].to_uppercase().as_str() {
+        "ID" => match value.parse::<u64>() {
+          Ok(id) if id > 0 => filter.id = Some(id),
+          _ => return Err("ERR client-id should be greater than 0".to_string()),
+        },
+        "ADDR" => filter.addr = Some(value.clone()),
+        "LADDR" => filter.laddr = Some(value.clone()),
+        "TYPE" => match ClientKind::parse(value) {
+          Some(kind) => filter.kind = Some(kind),
+          None => return Err(format!("ERR Unknown client type '{}'", value)),
+        },
+        "USER" => filter.user = Some(value.clone()),
+        "SKIPME" => match value.to_lowercase().as_str() {
+          "yes" => filter.skip_me = true,
+          "no" => filter.skip_me = false,
+          _ => return Err("ERR syntax error".to_string()),
+        },
+        "MAXAGE" => match value.parse::<u64>() {
+          Ok(age) => filter.max_age = Some(age),
+          Err(_) => return Err("ERR syntax error".to_string()),
+        },
+        _ => return Err("ERR syntax error".to_string()),
+      }
+    }
+
+    Ok(filter)
+  }
+
+  fn matches(&self, client: &ClientInfo, caller: u64) -> bool {
+    if self.skip_me && client.id == caller {
+      return false;
+    }
+    if self.id.is_some_and(|id| id != client.id) {
+      return false;
+    }
+    if self
+      .addr
+      .as_ref()
+      .is_some_and(|addr| *addr != client.addr.to_string())
+    {
+      return false;
+    }
+    if self
+      .laddr
+      .as_ref()
+      .is_some_and(|laddr| *laddr != client.laddr.to_string())
+    {
+      return false;
+    }
+    if self.kind.is_some_and(|kind| kind != client.kind) {
+      return false;
+    }
+    if self.user.as_ref().is_some_and(|user| *user != client.user) {
+      return false;
+    }
+    if self
+      .max_age
+      .is_some_and(|max_age| client.connected_at.elapsed().as_secs() < max_age)
+    {
+      return false;
+    }
+    true
+  }
+}
+
+impl ClientRegistry {
+  /// Disconnects a client: it is dropped from the registry right away and its
+  /// connection task is woken up to close the socket.
+  pub fn kill_client(&self, id: u64) -> bool {
+    self.stop_tracking(id);
+    match self.clients.remove(&id) {
+      Some((_, client)) => {
+        client.kill.notify_one();
+        true
+      }
+      None => false,
+    }
+  }
+
+  /// CLIENT KILL ip:port, or CLIENT KILL <filter> <value> ...
+  fn kill(&self, caller: u64, args: &[String]) -> RedisValue {
+    // Old style: a single address, replies with OK or an error
+    if let [addr] = args {
+      let target = self
+        .all()
+        .into_iter()
+        .find(|client| client.addr.to_string() == *addr);
+      return match target {
+        Some(client) => {
+          self.kill_client(client.id);
+          RedisValue::SimpleString("OK".to_string())
+        }
+        None => RedisValue::Error("ERR No such client".to_string()),
+      };
+    }
+
+    if args.is_empty() {
+      return wrong_arguments("client|kill");
+    }
+
+    let filter = match KillFilter::parse(args) {
+      Ok(filter) => filter,
+      Err(e) => return RedisValue::Error(e),
+    };
+
+    let killed = self
+      .all()
+      .into_iter()
+      .filter(|client| filter.matches(client, caller))
+      .filter(|client| self.kill_client(client.id))
+      .count();
+
+    RedisValue::Integer(killed as i64)
+  }
+}
+
+impl ClientRegistry {
+  /// Pauses clients for `duration`. An ongoing pause is only ever extended and
+  /// made stricter, never shortened or relaxed.
+  pub fn pause(&self, mode: PauseMode, duration: Duration) {
+    let mut pause = self.pause.lock().unwrap();
+    let mut next = Pause {
+      mode,
+      until: Instant::now() + duration,
+    };
+    if let Some(current) = pause.filter(|current| current.until > Instant::now()) {
+      next.until = next.until.max(current.until);
+      if current.mode == PauseMode::All {
+        next.mode = PauseMode::All;
+      }
+    }
+    *pause = Some(next);
+  }
+
+  /// Lifts the pause and releases every client waiting on it
+  pub fn unpause(&self) {
+    self.pause.lock().unwrap().take();
+    self.unpaused.notify_waiters();
+  }
+
+  /// Deadline of the pause that applies to a command, if any
+  fn paused_until(&self, is_write: bool) -> Option<Instant> {
+    let pause = (*self.pause.lock().unwrap())?;
+    if pause.until <= Instant::now() {
+      return None;
+    }
+    match pause.mode {
+      PauseMode::All => Some(pause.until),
+      PauseMode::Write if is_write => Some(pause.until),
+      PauseMode::Write => None,
+    }
+  }
+
+  /// Whether a pause applies to a command
+  pub fn is_paused(&self, is_write: bool) -> bool {
+    self.paused_until(is_write).is_some()
+  }
+
+  /// Holds the caller until no pause applies to its command. Paused commands are
+  /// queued rather than rejected, so clients just observe extra latency.
+  pub async fn wait_while_paused(&self, is_write: bool) {
+    loop {
+      // Registered before the check so an UNPAUSE in between isn't missed
+      let unpaused = self.unpaused.notified();
+      let Some(until) = self.paused_until(is_write) else {
+        return;
+      };
+      tokio::select! {
+        _ = tokio::time::sleep_until(until) => {}
+        _ = unpaused => {}
+      }
+    }
+  }
+
+  /// CLIENT PAUSE timeout [WRITE|ALL]
+  fn pause_command(&self, args: &[String]) -> RedisValue {
+    let (timeout, mode) = match args {
+      [timeout] => (timeout, PauseMode::All),
+      [timeout, mode] => match mode.to_uppercase().as_str() {
+        "ALL" => (timeout, PauseMode::All),
+        "WRITE" => (timeout, PauseMode::Write),
+        _ => return RedisValue::Error("ERR syntax error".to_string()),
+      },
+      _ => return wrong_arguments("client|pause"),
+    };
+
+    let timeout = match timeout.parse::<i64>() {
+      Ok(timeout) if timeout < 0 => {
+        return RedisValue::Error("ERR timeout is negative".to_string());
+      }
+      Ok(timeout) => timeout as u64,
+      Err(_) => {
+        return RedisValue::Error("ERR timeout is not an integer or out of range".to_string());
+      }
+    };
+
+    self.pause(mode, Duration::from_millis(timeout));
+    RedisValue::SimpleString("OK".to_string())
+  }
+}
+
+impl ClientRegistry {
+  pub fn set_output_limits(&self, limits: OutputLimits) {
+    *self.output_limits.lock().unwrap() = limits;
+  }
+
+  pub fn output_limits(&self) -> OutputLimits {
+    *self.output_limits.lock().unwrap()
+  }
+
+  /// Queues an out-of-band message for the client. A client whose queue goes
+  /// past the output buffer limits of its class is disconnected, in which case
+  /// false is returned.
+  pub fn push(&self, id: u64, message: Vec<u8>) -> bool {
+    let Some((kind, push, output)) = self
+      .clients
+      .get(&id)
+      .map(|client| (client.kind, client.push.clone(), client.output.clone()))
+    else {
+      return false;
+    };
+
+    // Accounted before sending, so the write can't be recorded first
+    let bytes = message.len();
+    output.queued(bytes);
+    if push.send(message).is_err() {
+      output.written(bytes);
+      return false;
+    }
+
+    if output.over_limit(&self.output_limits().for_kind(kind)) {
+      warn!(
+        "Client {} closed for overcoming of output buffer limits ({} bytes queued)",
+        id,
+        output.bytes()
+      );
+      self.kill_client(id);
+      return false;
+    }
+    self.evict_clients();
+    self.clients.contains_key(&id)
+  }
+
+  /// Records that a pushed message of `bytes` was written to the client's socket
+  pub fn written(&self, id: u64, bytes: usize) {
+    if let Some(client) = self.clients.get(&id) {
+      client.output.written(bytes);
+    }
+  }
+
+  pub fn set_max_memory(&self, bytes: u64) {
+    self.max_memory.store(bytes, Ordering::Relaxed);
+  }
+
+  /// Applies `maxmemory-clients`, which may be a percentage of `maxmemory`
+  pub fn apply_max_memory(&self, config: &Config) -> Result<(), String> {
+    let Some(max_memory) = config.get("maxmemory-clients") else {
+      return Ok(());
+    };
+    let maxmemory = config
+      .get("maxmemory")
+      .and_then(|maxmemory| parse_memory(&maxmemory));
+    let bytes = parse_max_memory(&max_memory, maxmemory)
+      .ok_or_else(|| format!("Invalid maxmemory-clients: {}", max_memory))?;
+    self.set_max_memory(bytes);
+    Ok(())
+  }
+
+  /// Memory used by every connection together
+  pub fn memory(&self) -> usize {
+    self
+      .clients
+      .iter()
+      .map(|client| client.value().memory())
+      .sum()
+  }
+
+  /// Lines of the `# Clients` INFO section
+  pub fn info(&self, max_clients: usize) -> Vec<String> {
+    let clients = self.all();
+    let count = |kind: ClientKind| clients.iter().filter(|client| client.kind == kind).count();
+    let max_output = clients
+      .iter()
+      .map(|client| client.output.bytes())
+      .max()
+      .unwrap_or(0);
+    let max_input = clients
+      .iter()
+      .map(|client| client.query_buffer)
+      .max()
+      .unwrap_or(0);
+    vec![
+      format!(
+        "connected_clients:{}",
+        clients.len() - count(ClientKind::Replica)
+      ),
+      "cluster_connections:0".to_string(),
+      format!("maxclients:{}", max_clients),
+      format!("client_recent_max_input_buffer:{}", max_input),
+      format!("client_recent_max_output_buffer:{}", max_output),
+      "blocked_clients:0".to_string(),
+      format!("tracking_clients:{}", self.tracking.len()),
+      format!("pubsub_clients:{}", count(ClientKind::PubSub)),
+      "watching_clients:0".to_string(),
+      "clients_in_timeout_table:0".to_string(),
+      "total_watched_keys:0".to_string(),
+      "total_blocking_keys:0".to_string(),
+    ]
+  }
+
+  /// Enforces `maxmemory-clients`: while connections use more than that in total,
+  /// the evictable client using the most memory is disconnected. Returns how many
+  /// clients were evicted.
+  pub fn evict_clients(&self) -> usize {
+    let max_memory = self.max_memory.load(Ordering::Relaxed) as usize;
+    if max_memory == 0 {
+      return 0;
+    }
+    let clients = self.all();
+    let mut used: usize = clients.iter().map(ClientInfo::memory).sum();
+    if used <= max_memory {
+      return 0;
+    }
+
+    let mut candidates: Vec<(usize, ClientInfo)> = clients
+      .into_iter()
+      .filter(ClientInfo::evictable)
+      .map(|client| (client.memory(), client))
+      .collect();
+    candidates.sort_by_key(|(memory, _)| std::cmp::Reverse(*memory));
+
+    let mut evicted = 0;
+    for (memory, client) in candidates {
+      if used <= max_memory {
+        break;
+      }
+      used -= memory;
+      if self.kill_client(client.id) {
+        warn!(
+          "Evicting client {} ({}) using {} bytes: maxmemory-clients reached",
+          client.id, client.addr, memory
+        );
+        evicted += 1;
+      }
+    }
+    Stats::add(&self.stats.evicted_clients, evicted);
+    evicted
+  }
+
+  /// Disconnects every client that has been idle for longer than `timeout`.
+  /// Returns how many were closed.
+  pub fn close_idle(&self, timeout: Duration) -> usize {
+    self
+      .all()
+      .into_iter()
+      .filter(|client| client.kind.may_time_out() && !client.monitor)
+      .filter(|client| client.last_interaction.elapsed() > timeout)
+      .filter(|client| {
+        debug!("Closing idle client {} ({})", client.id, client.addr);
+        self.kill_client(client.id)
+      })
+      .count()
+  }
+}
+
+/// Parses `maxmemory-clients`: either bytes (`64mb`) or a percentage of
+/// `maxmemory` (`5%`). 0 turns client eviction off.
+pub fn parse_max_memory(value: &str, maxmemory: Option<u64>) -> Option<u64> {
+  match value.trim().strip_suffix('%') {
+    Some(percent) => {
+      let percent = percent
+        .parse::<u64>()
+        .ok()
+        .filter(|percent| *percent <= 100)?;
+      Some(maxmemory.unwrap_or(0) * percent / 100)
+    }
+    None => parse_memory(value),
+  }
+}
+
+/// Schedules the clients cron: it enforces `timeout`, closing connections idle
+/// for that many seconds (0, the default, keeps them forever), and evicts
+/// clients that `maxmemory-clients` can't afford anymore.
+pub fn register_clients_cron(
+  scheduler: &mut Scheduler,
+  clients: Arc<ClientRegistry>,
+  config: Arc<AsyncMutex<Config>>,
+) {
+  scheduler.every(
+    "clients-cron",
+    CLIENTS_CRON_INTERVAL,
+    CLIENTS_CRON_BUDGET,
+    move || {
+      let clients = clients.clone();
+      let config = config.clone();
+      async move {
+        let timeout = config
+          .lock()
+          .await
+          .get("timeout")
+          .and_then(|timeout| timeout.parse::<u64>().ok())
+          .unwrap_or(0);
+        if timeout > 0 {
+          clients.close_idle(Duration::from_secs(timeout));
+        }
+        clients.evict_clients();
+      }
+    },
+  );
+}
+
+/// Turns on TCP keepalive for the connection, probing after `seconds` of
+/// silence like Redis's `tcp-keepalive`. 0 leaves keepalive off.
+pub fn set_tcp_keepalive(stream: &TcpStream, seconds: u64) {
+  if seconds == 0 {
+    return;
+  }
+  let keepalive = TcpKeepalive::new().with_time(Duration::from_secs(seconds));
+  #[cfg(any(target_os = "linux", target_os = "macos", target_os = "freebsd"))]
+  let keepalive = keepalive.with_interval(Duration::from_secs((seconds / 3).max(1)));
+  if let Err(e) = SockRef::from(stream).set_tcp_keepalive(&keepalive) {
+    warn!("Failed to enable TCP keepalive: {}", e);
+  }
+}
+
+/// Turns Nagle's algorithm off for the connection when `tcp-nodelay` is on,
+/// so small replies aren't held back waiting for more data
+pub fn set_tcp_nodelay(stream: &TcpStream, enabled: bool) {
+  if let Err(e) = stream.set_nodelay(enabled) {
+    warn!("Failed to set TCP_NODELAY: {}", e);
+  }
+}
+
+impl ClientRegistry {
+  /// MONITOR: from now on the client is sent every command the server processes
+  pub fn start_monitor(&self, id: u64) {
+    if let Some(mut client) = self.clients.get_mut(&id) {
+      client.monitor = true;
+    }
+    let mut monitors = self.monitors.lock().unwrap();
+    if !monitors.contains(&id) {
+      monitors.push(id);
+    }
+  }
+
+  fn stop_monitor(&self, id: u64) {
+    self
+      .monitors
+      .lock()
+      .unwrap()
+      .retain(|monitor| *monitor != id);
+  }
+
+  pub fn is_monitor(&self, id: u64) -> bool {
+    self
+      .clients
+      .get(&id)
+      .map(|client| client.monitor)
+      .unwrap_or(false)
+  }
+
+  pub fn has_monitors(&self) -> bool {
+    !self.monitors.lock().unwrap().is_empty()
+  }
+
+  /// Sends the command `argv` issued by client `id` to every monitor, in the
+  /// format redis-cli MONITOR prints:
+  /// `+1339518083.107412 [0 127.0.0.1:60866] "keys" "*"`
+  pub fn feed_monitors(&self, id: u64, argv: &[String]) {
+    let monitors = self.monitors.lock().unwrap().clone();
+    if monitors.is_empty() {
+      return;
+    }
+    let Some(client) = self.get(id) else {
+      return;
+    };
+
+    let now = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .unwrap_or_default();
+    let mut line = format!(
+      "+{}.{:06} [{} {}]",
+      now.as_secs(),
+      now.subsec_micros(),
+      client.db,
+      client.addr
+    );
+    for (i, arg) in argv.iter().enumerate() {
+      line.push(' ');
+      // Passwords never show up in the feed
+      if i > 0 && argv[0].eq_ignore_ascii_case("auth") {
+        line.push_str("\"(redacted)\"");
+      } else {
+        line.push_str(&quote(arg));
+      }
+    }
+    line.push_str("\r\n");
+
+    for monitor in monitors {
+      self.push(monitor, line.clone().into_bytes());
+    }
+  }
+}
+
+/// Quotes an argument the way Redis's sdscatrepr does
+fn quote(arg: &str) -> String {
+  let mut quoted = String::with_capacity(arg.len() + 2);
+  quoted.push('"');
+  for byte in arg.bytes() {
+    match byte {
+      b'\\' => quoted.push_str("\\\\"),
+      b'"' => quoted.push_str("\\\""),
+      b'\n' => quoted.push_str("\\n"),
+      b'\r' => quoted.push_str("\\r"),
+      b'\t' => quoted.push_str("\\t"),
+      0x07 => quoted.push_str("\\a"),
+      0x08 => quoted.push_str("\\b"),
+      b' '..=b'~' => quoted.push(byte as char),
+      _ => quoted.push_str(&format!("\\x{:02x}", byte)),
+    }
+  }
+  quoted.push('"');
+  quoted
+}
+
+impl Default for ClientRegistry {
+  fn default() -> Self {
+    Self::new(Arc::new(Stats::new()))
+  }
+}
+
+fn wrong_arguments(command: &str) -> RedisValue {
+  RedisValue::Error(format!(
+    "ERR wrong number of arguments for '{}' command",
+    command
+  ))
+}
@@ -0,0 +1,89 @@
+/**
+ * Time as the keyspace sees it. Deadlines, TTLs, idle times and active expiry
+ * all read the `Clock` of their `Storage`: the system clock in production, a
+ * `MockClock` in tests, which only moves when told to so that expiring a key
+ * doesn't take sleeping until it does.
+ *
+ * ```no_run
+ * use redis_starter_rust::clock::MockClock;
+ * use redis_starter_rust::Storage;
+ * use std::sync::Arc;
+ * use std::time::Duration;
+ *
+ * let clock = Arc::new(MockClock::new());
+ * let storage = Storage::with_clock(clock.clone());
+ * storage.set("key".to_string(), "value".to_string(), vec![("EX".to_string(), "10".to_string())]);
+ * clock.advance(Duration::from_secs(10));
+ * assert_eq!(storage.get("key"), None);
+ * ```
+ */
+use std::fmt::Debug;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use tokio::time::Instant;
+
+pub trait Clock: Debug + Send + Sync {
+  /// Monotonic time, which deadlines are compared against
+  fn now(&self) -> Instant;
+
+  /// Wall clock time at `now`, for absolute deadlines
+  fn system_time(&self) -> SystemTime;
+}
+
+/// The clock of the system
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+  fn now(&self) -> Instant {
+    Instant::now()
+  }
+
+  fn system_time(&self) -> SystemTime {
+    SystemTime::now()
+  }
+}
+
+/// A clock standing still until advanced, for tests
+#[derive(Debug)]
+pub struct MockClock {
+  started_at: Instant,
+  wall_clock: SystemTime,
+  elapsed: Mutex<Duration>,
+}
+
+impl MockClock {
+  /// A clock showing the current time
+  pub fn new() -> Self {
+    Self {
+      started_at: Instant::now(),
+      wall_clock: SystemTime::now(),
+      elapsed: Mutex::new(Duration::ZERO),
+    }
+  }
+
+  /// Moves the clock forward by `duration`
+  pub fn advance(&self, duration: Duration) {
+    *self.elapsed.lock().unwrap() += duration;
+  }
+
+  fn elapsed(&self) -> Duration {
+    *self.elapsed.lock().unwrap()
+  }
+}
+
+impl Default for MockClock {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Clock for MockClock {
+  fn now(&self) -> Instant {
+    self.started_at + self.elapsed()
+  }
+
+  fn system_time(&self) -> SystemTime {
+    self.wall_clock + self.elapsed()
+  }
+}
@@ -0,0 +1,1187 @@
+/**
+ * Redis Cluster: the keyspace is split in 16384 hash slots, each served by one
+ * node.
+ *
+ * A key belongs to the slot CRC16(key) mod 16384, computed on the key's hash
+ * tag when it has one: the part between the first `{` and the next `}`, if not
+ * empty, so that `{user1000}.following` and `{user1000}.followers` land in the
+ * same slot. The slot table maps every slot to the id of the node owning it.
+ *
+ * Cluster mode is turned on with `cluster-enabled yes`. Each node has a random
+ * 40 characters id identifying it to the others.
+ *
+ * Before a command runs, its keys are checked against the slot table: keys in
+ * different slots are refused with CROSSSLOT, keys of a slot owned by another
+ * node get a `MOVED <slot> <host:port>` redirection to it. While a slot is
+ * being migrated away, keys already moved are redirected with `ASK` for this
+ * command only.
+ *
+ * Slots are moved between nodes the way `redis-cli --cluster reshard` does it:
+ * CLUSTER SETSLOT marks the slot IMPORTING on the target and MIGRATING on the
+ * source, the keys are moved (GETKEYSINSLOT lists them), then SETSLOT NODE
+ * hands the slot over. Meanwhile the target only serves the slot to clients
+ * that sent ASKING just before, as told by an ASK redirection.
+ *
+ * The node table, slot assignments and epochs are written to
+ * `cluster-config-file` (nodes.conf in `dir`) after every change, in the
+ * CLUSTER NODES format followed by a `vars` line, and loaded back at startup so
+ * that a node keeps its id and slots across restarts.
+ *
+ * Cluster-aware clients learn the topology on connect from CLUSTER SLOTS,
+ * SHARDS or NODES, which are all generated from the same node table.
+ *
+ * A cluster is assembled the way `redis-cli --cluster create` does it: each
+ * master takes its slots with CLUSTER ADDSLOTS (or ADDSLOTSRANGE) and an epoch
+ * of its own with SET-CONFIG-EPOCH, one node MEETs the others, then replicas
+ * pick their master with CLUSTER REPLICATE. DELSLOTS gives slots up, FORGET
+ * drops a node and RESET takes a node back to a cluster of its own.
+ *
+ * There is no cluster bus. Every `GOSSIP_INTERVAL` a node reads CLUSTER NODES
+ * from the client port of every node it knows, which mustn't require a
+ * password, and takes what a node says about itself as the truth: its role,
+ * epoch and slots, unless another node claims one of them with a greater
+ * config epoch, and learns the nodes the others know. A node MEETing another
+ * introduces itself to it with CLUSTER MEET in turn, from there the two
+ * learn the rest of the cluster from each other. Nodes are never marked as
+ * failing, and replicas don't copy the data of their master, the server not
+ * replicating.
+ */
+use crate::address::{format_host_port, unbracket};
+use crate::config::Config;
+use crate::cron::Scheduler;
+use crate::parser::RedisValue;
+use crate::storage::Storage;
+use nanoid::nanoid;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tracing::{debug, error, info};
+
+/// Number of hash slots the keyspace is split in
+pub const CLUSTER_SLOTS: usize = 16384;
+
+/// The cluster bus listens on the client port plus this offset
+const BUS_PORT_OFFSET: u16 = 10000;
+
+/// How often a node reads the node tables of the others
+pub const GOSSIP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Time a gossip run may take before it counts as an overrun, the nodes are
+/// read in the background
+const GOSSIP_BUDGET: Duration = Duration::from_millis(10);
+
+/// How long a node has to answer
+const NODE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long a forgotten node isn't learned back, as in Redis
+const FORGET_TTL: Duration = Duration::from_secs(60);
+
+const DISABLED_ERROR: &str = "ERR This instance has cluster support disabled";
+
+const CROSSSLOT_ERROR: &str = "CROSSSLOT Keys in request don't hash to the same slot";
+
+const CLUSTERDOWN_UNBOUND_ERROR: &str = "CLUSTERDOWN Hash slot not served";
+
+const TRYAGAIN_ERROR: &str = "TRYAGAIN Multiple keys request during rehashing of slot";
+
+/// Characters of node ids, lowercase hex like Redis's
+const NODE_ID_ALPHABET: [char; 16] = [
+  '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', 'a', 'b', 'c', 'd', 'e', 'f',
+];
+
+/// CRC16-CCITT (XModem), the checksum Redis Cluster hashes keys with
+fn crc16(bytes: &[u8]) -> u16 {
+  bytes.iter().fold(0u16, |crc, byte| {
+    (0..8).fold(crc ^ ((*byte as u16) << 8), |crc, _| {
+      if crc & 0x8000 != 0 {
+        (crc << 1) ^ 0x1021
+      } else {
+        crc << 1
+      }
+    })
+  })
+}
+
+/// The part of the key that is hashed: its `{hash tag}` when it has a non
+/// empty one, the whole key otherwise
+pub fn hash_tag(key: &str) -> &str {
+  let Some(start) = key.find('{') else {
+    return key;
+  };
+  match key[start + 1..].find('}') {
+    Some(length) if length > 0 => &key[start + 1..start + 1 + length],
+    _ => key,
+  }
+}
+
+/// Hash slot of a key
+pub fn key_slot(key: &str) -> u16 {
+  crc16(hash_tag(key).as_bytes()) % CLUSTER_SLOTS as u16
+}
+
+pub fn generate_node_id() -> String {
+  nanoid!(40, &NODE_ID_ALPHABET)
+}
+
+/// A node of the cluster, as this node knows it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClusterNode {
+  pub id: String,
+  pub ip: String,
+  pub port: u16,
+  /// Port of the cluster bus
+  pub bus_port: u16,
+  /// Master of a replica, `None` for masters
+  pub master: Option<String>,
+  pub config_epoch: u64,
+}
+
+/// Reads the cluster config file, or the CLUSTER NODES of another node: one
+/// line per node, then the `vars` line of the file
+fn parse_nodes_file(content: &str) -> Result<ClusterState, String> {
+  let mut myself = None;
+  let mut state = ClusterState::new(String::new());
+  for (number, line) in content.lines().enumerate() {
+    let error = |message: &str| format!("line {}: {}", number + 1, message);
+    let slot = |slot: &str| parse_slot(slot).map_err(|_| error("invalid slot"));
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words[..] {
+      [] => continue,
+      ["vars", ref vars @ ..] => {
+        for pair in vars.chunks(2) {
+          if let ["currentEpoch", epoch] = pair {
+            state.current_epoch = epoch.parse().map_err(|_| error("invalid currentEpoch"))?;
+          }
+        }
+      }
+      [id, address, flags, master, _ping, _pong, epoch, _link, ref ranges @ ..] => {
+        let (host_port, bus_port) = address
+          .split_once('@')
+          .ok_or_else(|| error("invalid address"))?;
+        let (host, port) = host_port
+          .rsplit_once(':')
+          .ok_or_else(|| error("invalid address"))?;
+        let flags: Vec<&str> = flags.split(',').collect();
+        if flags.contains(&"myself") {
+          myself = Some(id.to_string());
+        }
+        let node = ClusterNode {
+          id: id.to_string(),
+          ip: unbracket(host).to_string(),
+          port: port.parse().map_err(|_| error("invalid port"))?,
+          // Redis 7 appends the hostname to the bus port
+          bus_port: bus_port
+            .split(',')
+            .next()
+            .and_then(|port| port.parse().ok())
+            .ok_or_else(|| error("invalid cluster bus port"))?,
+          master: (flags.contains(&"slave") && master != "-").then(|| master.to_string()),
+          config_epoch: epoch.parse().map_err(|_| error("invalid config epoch"))?,
+        };
+        state.nodes.insert(node.id.clone(), node);
+
+        for range in ranges {
+          if let Some(moving) = range
+            .strip_prefix('[')
+            .and_then(|range| range.strip_suffix(']'))
+          {
+            if let Some((moved, target)) = moving.split_once("->-") {
+              state.migrating.insert(slot(moved)?, target.to_string());
+            } else if let Some((moved, source)) = moving.split_once("-<-") {
+              state.importing.insert(slot(moved)?, source.to_string());
+            } else {
+              return Err(error("invalid slot migration"));
+            }
+            continue;
+          }
+          let (start, end) = range.split_once('-').unwrap_or((range, range));
+          for owned in slot(start)?..=slot(end)? {
+            state.slots[owned as usize] = Some(id.to_string());
+          }
+        }
+      }
+      _ => return Err(error("invalid node line")),
+    }
+  }
+  state.myself = myself.ok_or_else(|| "no node is flagged myself".to_string())?;
+  Ok(state)
+}
+
+/// `[start, end]` ranges of consecutive slots, in order
+fn slot_ranges(slots: &[u16]) -> Vec<(u16, u16)> {
+  let mut ranges: Vec<(u16, u16)> = Vec::new();
+  for &slot in slots {
+    match ranges.last_mut() {
+      Some((_, end)) if *end + 1 == slot => *end = slot,
+      _ => ranges.push((slot, slot)),
+    }
+  }
+  ranges
+}
+
+struct ClusterState {
+  /// Id of this node
+  myself: String,
+  nodes: BTreeMap<String, ClusterNode>,
+  current_epoch: u64,
+  /// Id of the node owning each slot
+  slots: Vec<Option<String>>,
+  /// Slots being moved to another node, with the id of the node
+  migrating: HashMap<u16, String>,
+  /// Slots being moved here, with the id of the node they come from
+  importing: HashMap<u16, String>,
+  /// Nodes FORGET dropped, not to be learned back from the others until then
+  forgotten: HashMap<String, Instant>,
+}
+
+impl ClusterState {
+  /// The state of a node knowing no other, serving no slot
+  fn new(myself: String) -> Self {
+    Self {
+      myself,
+      nodes: BTreeMap::new(),
+      current_epoch: 0,
+      slots: vec![None; CLUSTER_SLOTS],
+      migrating: HashMap::new(),
+      importing: HashMap::new(),
+      forgotten: HashMap::new(),
+    }
+  }
+
+  /// `host:port` clients reach a node on
+  fn address(&self, id: &str) -> String {
+    match self.nodes.get(id) {
+      Some(node) => format_host_port(&node.ip, node.port),
+      None => String::new(),
+    }
+  }
+
+  /// Slots served by a node, in order
+  fn slots_of(&self, id: &str) -> Vec<u16> {
+    (0..CLUSTER_SLOTS as u16)
+      .filter(|slot| self.slots[*slot as usize].as_deref() == Some(id))
+      .collect()
+  }
+
+  fn assigned_slots(&self) -> usize {
+    self.slots.iter().filter(|owner| owner.is_some()).count()
+  }
+
+  /// Replicas of a master
+  fn replicas_of(&self, id: &str) -> Vec<&ClusterNode> {
+    self
+      .nodes
+      .values()
+      .filter(|node| node.master.as_deref() == Some(id))
+      .collect()
+  }
+
+  /// The CLUSTER NODES view: `<id> <ip:port@cport> <flags> <master>
+  /// <ping-sent> <pong-recv> <config-epoch> <link-state> <slot> ...`, one node
+  /// per line
+  fn describe(&self) -> String {
+    let myself = self.myself.as_str();
+    let mut lines = String::new();
+    for node in self.nodes.values() {
+      let mut flags = Vec::new();
+      if node.id == myself {
+        flags.push("myself");
+      }
+      flags.push(if node.master.is_some() {
+        "slave"
+      } else {
+        "master"
+      });
+      let mut line = format!(
+        "{} {}@{} {} {} 0 0 {} connected",
+        node.id,
+        format_host_port(&node.ip, node.port),
+        node.bus_port,
+        flags.join(","),
+        node.master.as_deref().unwrap_or("-"),
+        node.config_epoch
+      );
+      for (start, end) in slot_ranges(&self.slots_of(&node.id)) {
+        if start == end {
+          line.push_str(&format!(" {}", start));
+        } else {
+          line.push_str(&format!(" {}-{}", start, end));
+        }
+      }
+      // Slots on the move are listed on this node's line only
+      if node.id == myself {
+        let mut migrating: Vec<_> = self.migrating.iter().collect();
+        migrating.sort();
+        for (slot, target) in migrating {
+          line.push_str(&format!(" [{}->-{}]", slot, target));
+        }
+        let mut importing: Vec<_> = self.importing.iter().collect();
+        importing.sort();
+        for (slot, source) in importing {
+          line.push_str(&format!(" [{}-<-{}]", slot, source));
+        }
+      }
+      lines.push_str(&line);
+      lines.push('\n');
+    }
+    lines
+  }
+
+  fn forgotten(&self, id: &str) -> bool {
+    self
+      .forgotten
+      .get(id)
+      .is_some_and(|until| *until > Instant::now())
+  }
+
+  /// Takes what the node reached at `ip:port` says about itself in `view`,
+  /// its CLUSTER NODES, and learns the nodes it knows. Returns whether the
+  /// state changed.
+  fn merge(&mut self, ip: &str, port: u16, view: &ClusterState) -> bool {
+    let peer = &view.myself;
+    let Some(reported) = view.nodes.get(peer) else {
+      return false;
+    };
+    if *peer == self.myself || self.forgotten(peer) {
+      return false;
+    }
+    let node = ClusterNode {
+      id: peer.clone(),
+      ip: ip.to_string(),
+      port,
+      ..reported.clone()
+    };
+    let mut changed = self.nodes.get(peer) != Some(&node);
+    self.nodes.insert(peer.clone(), node);
+
+    for slot in 0..CLUSTER_SLOTS {
+      let claimed = view.slots[slot].as_ref() == Some(peer);
+      let owner = self.slots[slot].as_ref();
+      if claimed && owner != Some(peer) {
+        // A claim with a greater epoch wins, as after a migration
+        let owner_epoch = owner
+          .and_then(|owner| self.nodes.get(owner))
+          .map(|owner| owner.config_epoch);
+        if owner_epoch.is_none_or(|epoch| epoch < reported.config_epoch) {
+          self.slots[slot] = Some(peer.clone());
+          changed = true;
+        }
+      } else if !claimed && owner == Some(peer) {
+        self.slots[slot] = None;
+        changed = true;
+      }
+    }
+
+    for node in view.nodes.values() {
+      if node.id != self.myself && !self.nodes.contains_key(&node.id) && !self.forgotten(&node.id) {
+        self.nodes.insert(node.id.clone(), node.clone());
+        changed = true;
+      }
+    }
+    if view.current_epoch > self.current_epoch {
+      self.current_epoch = view.current_epoch;
+      changed = true;
+    }
+    changed
+  }
+
+  /// Masters serving at least one slot
+  fn size(&self) -> usize {
+    self
+      .nodes
+      .values()
+      .filter(|node| node.master.is_none() && self.slots.contains(&Some(node.id.clone())))
+      .count()
+  }
+}
+
+pub struct Cluster {
+  enabled: bool,
+  state: RwLock<ClusterState>,
+  /// `cluster-config-file` in `dir`, the state is saved to
+  file: PathBuf,
+}
+
+impl Cluster {
+  pub fn new(config: &Config) -> Self {
+    let enabled = config
+      .get("cluster-enabled")
+      .is_some_and(|value| value.eq_ignore_ascii_case("yes"));
+    let port = config
+      .get("port")
+      .and_then(|port| port.parse::<u16>().ok())
+      .unwrap_or(6379);
+    let myself = ClusterNode {
+      id: generate_node_id(),
+      ip: String::new(),
+      port,
+      bus_port: port.wrapping_add(BUS_PORT_OFFSET),
+      master: None,
+      config_epoch: 0,
+    };
+    let file = Path::new(&config.get("dir").unwrap_or_else(|| ".".to_string())).join(
+      config
+        .get("cluster-config-file")
+        .unwrap_or_else(|| "nodes.conf".to_string()),
+    );
+    let mut state = ClusterState::new(myself.id.clone());
+    state.nodes.insert(myself.id.clone(), myself);
+    Self {
+      enabled,
+      state: RwLock::new(state),
+      file,
+    }
+  }
+
+  /// Takes the node id, node table and slots back from the cluster config
+  /// file, or creates the file on the first start of the node. Nothing is
+  /// changed when the file contains an error.
+  pub fn load(&mut self) -> Result<(), String> {
+    if !self.enabled {
+      return Ok(());
+    }
+    let path = self.file.display().to_string();
+    let content = match fs::read_to_string(&self.file) {
+      Ok(content) => content,
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+        info!("No cluster configuration found, I'm {}", self.myself());
+        return self.save(&self.state.read().unwrap());
+      }
+      Err(e) => return Err(format!("{}: {}", path, e)),
+    };
+
+    let mut state = parse_nodes_file(&content).map_err(|e| format!("{}: {}", path, e))?;
+    // The ports may have changed since the file was written
+    let (port, bus_port) = {
+      let current = self.state.read().unwrap();
+      let node = &current.nodes[&current.myself];
+      (node.port, node.bus_port)
+    };
+    if let Some(node) = state.nodes.get_mut(&state.myself) {
+      node.port = port;
+      node.bus_port = bus_port;
+    }
+    info!("Node configuration loaded, I'm {}", state.myself);
+    self.state = RwLock::new(state);
+    Ok(())
+  }
+
+  /// Writes the node table to the cluster config file, through a temporary
+  /// file so that a crash never leaves half of it
+  fn save(&self, state: &ClusterState) -> Result<(), String> {
+    let content = format!(
+      "{}vars currentEpoch {} lastVoteEpoch 0\n",
+      state.describe(),
+      state.current_epoch
+    );
+    let temporary = self.file.with_extension("tmp");
+    fs::write(&temporary, content)
+      .and_then(|()| fs::rename(&temporary, &self.file))
+      .map_err(|e| format!("{}: {}", self.file.display(), e))
+  }
+
+  pub fn enabled(&self) -> bool {
+    self.enabled
+  }
+
+  /// Id of this node
+  pub fn myself(&self) -> String {
+    self.state.read().unwrap().myself.clone()
+  }
+
+  /// The error redirecting a command accessing `keys` elsewhere, if it can't
+  /// run on this node. `asking` is set when the client sent ASKING before the
+  /// command, `exists` tells whether a key is stored here.
+  pub fn redirect(
+    &self,
+    keys: &[&str],
+    asking: bool,
+    exists: impl Fn(&str) -> bool,
+  ) -> Option<String> {
+    let (first, rest) = keys.split_first()?;
+    let slot = key_slot(first);
+    if rest.iter().any(|key| key_slot(key) != slot) {
+      return Some(CROSSSLOT_ERROR.to_string());
+    }
+
+    let state = self.state.read().unwrap();
+    let Some(owner) = state.slots[slot as usize].as_ref() else {
+      return Some(CLUSTERDOWN_UNBOUND_ERROR.to_string());
+    };
+    if *owner != state.myself {
+      // Clients sent here by an ASK may use the slot being imported, as long
+      // as all the keys already arrived
+      if asking && state.importing.contains_key(&slot) {
+        if keys.len() > 1 && !keys.iter().all(|key| exists(key)) {
+          return Some(TRYAGAIN_ERROR.to_string());
+        }
+        return None;
+      }
+      return Some(format!("MOVED {} {}", slot, state.address(owner)));
+    }
+    if let Some(target) = state.migrating.get(&slot) {
+      // Keys gone from here were already moved to the target
+      let missing = keys.iter().filter(|key| !exists(key)).count();
+      if missing == keys.len() {
+        return Some(format!("ASK {} {}", slot, state.address(target)));
+      }
+      if missing > 0 {
+        return Some(TRYAGAIN_ERROR.to_string());
+      }
+    }
+    None
+  }
+
+  /// Routes CLUSTER subcommands
+  pub fn handle_command(&self, subcommand: &str, args: &[String], storage: &Storage) -> RedisValue {
+    if !self.enabled {
+      return RedisValue::Error(DISABLED_ERROR.to_string());
+    }
+    match subcommand {
+      "KEYSLOT" => match args {
+        [key] => RedisValue::Integer(key_slot(key) as i64),
+        _ => wrong_arguments("cluster|keyslot"),
+      },
+      "INFO" => RedisValue::BulkString(Some(self.info())),
+      "MYID" => RedisValue::BulkString(Some(self.myself())),
+      "NODES" => RedisValue::BulkString(Some(self.nodes())),
+      "SLOTS" => RedisValue::Nested(self.slots()),
+      "SHARDS" => RedisValue::Nested(self.shards()),
+      "SETSLOT" => match args {
+        [slot, action, rest @ ..] => match parse_slot(slot) {
+          Ok(slot) => match self.set_slot(slot, &action.to_uppercase(), rest, storage) {
+            Ok(()) => RedisValue::SimpleString("OK".to_string()),
+            Err(e) => RedisValue::Error(e),
+          },
+          Err(e) => RedisValue::Error(e),
+        },
+        _ => wrong_arguments("cluster|setslot"),
+      },
+      "COUNTKEYSINSLOT" => match args {
+        [slot] => match parse_slot(slot) {
+          Ok(slot) => RedisValue::Integer(keys_in_slot(storage, slot).len() as i64),
+          Err(e) => RedisValue::Error(e),
+        },
+        _ => wrong_arguments("cluster|countkeysinslot"),
+      },
+      "GETKEYSINSLOT" => match args {
+        [slot, count] => match (parse_slot(slot), count.parse::<usize>()) {
+          (Ok(slot), Ok(count)) => {
+            let mut keys = keys_in_slot(storage, slot);
+            keys.truncate(count);
+            RedisValue::Array(keys)
+          }
+          (Err(e), _) => RedisValue::Error(e),
+          (_, Err(_)) => RedisValue::Error("ERR Invalid number of keys".to_string()),
+        },
+        _ => wrong_arguments("cluster|getkeysinslot"),
+      },
+      "ADDSLOTS" | "DELSLOTS" if !args.is_empty() => {
+        let slots: Result<Vec<u16>, String> = args.iter().map(|slot| parse_slot(slot)).collect();
+        reply(slots.and_then(|slots| self.assign_slots(slots, subcommand == "ADDSLOTS")))
+      }
+      "ADDSLOTSRANGE" | "DELSLOTSRANGE" if !args.is_empty() && args.len().is_multiple_of(2) => {
+        let slots = parse_slot_ranges(args);
+        reply(slots.and_then(|slots| self.assign_slots(slots, subcommand == "ADDSLOTSRANGE")))
+      }
+      "ADDSLOTS" | "DELSLOTS" | "ADDSLOTSRANGE" | "DELSLOTSRANGE" => {
+        wrong_arguments(&format!("cluster|{}", subcommand.to_lowercase()))
+      }
+      "SET-CONFIG-EPOCH" => match args {
+        [epoch] => reply(self.set_config_epoch(epoch)),
+        _ => wrong_arguments("cluster|set-config-epoch"),
+      },
+      "REPLICATE" => match args {
+        [node] => reply(self.replicate(node, storage)),
+        _ => wrong_arguments("cluster|replicate"),
+      },
+      "FORGET" => match args {
+        [node] => reply(self.forget(node)),
+        _ => wrong_arguments("cluster|forget"),
+      },
+      "RESET" => match args {
+        [] => reply(self.reset(false, storage)),
+        [mode] if mode.eq_ignore_ascii_case("soft") => reply(self.reset(false, storage)),
+        [mode] if mode.eq_ignore_ascii_case("hard") => reply(self.reset(true, storage)),
+        [_] => RedisValue::Error("ERR syntax error".to_string()),
+        _ => wrong_arguments("cluster|reset"),
+      },
+      _ => RedisValue::Error(format!(
+        "ERR unknown subcommand '{}'. Try CLUSTER HELP.",
+        subcommand.to_lowercase()
+      )),
+    }
+  }
+}
+
+impl Cluster {
+  /// Writes the state after a change, logging a failure: the change is made
+  /// all the same
+  fn changed(&self, state: &ClusterState) {
+    if let Err(e) = self.save(state) {
+      error!("Failed to save the cluster configuration: {}", e);
+    }
+  }
+
+  /// CLUSTER ADDSLOTS or DELSLOTS, `add` telling which, on all the slots or
+  /// none
+  fn assign_slots(&self, slots: Vec<u16>, add: bool) -> Result<(), String> {
+    let mut state = self.state.write().unwrap();
+    let mut given = vec![false; CLUSTER_SLOTS];
+    for &slot in &slots {
+      if std::mem::replace(&mut given[slot as usize], true) {
+        return Err(format!("ERR Slot {} specified multiple times", slot));
+      }
+      match (add, state.slots[slot as usize].is_some()) {
+        (true, true) => return Err(format!("ERR Slot {} is already busy", slot)),
+        (false, false) => return Err(format!("ERR Slot {} is already unassigned", slot)),
+        _ => {}
+      }
+    }
+    let owner = add.then(|| state.myself.clone());
+    for slot in slots {
+      state.slots[slot as usize] = owner.clone();
+      state.importing.remove(&slot);
+      state.migrating.remove(&slot);
+    }
+    self.changed(&state);
+    Ok(())
+  }
+
+  /// CLUSTER SET-CONFIG-EPOCH, giving a new node an epoch distinct from the
+  /// other masters' before it meets them
+  fn set_config_epoch(&self, epoch: &str) -> Result<(), String> {
+    let epoch = epoch
+      .parse::<u64>()
+      .map_err(|_| format!("ERR Invalid config epoch specified: {}", epoch))?;
+    let mut state = self.state.write().unwrap();
+    if state.nodes.len() > 1 {
+      return Err(
+        "ERR The user can assign a config epoch only when the node does not know any other node."
+          .to_string(),
+      );
+    }
+    let myself = state.myself.clone();
+    let node = state.nodes.get_mut(&myself).expect("myself is a node");
+    if node.config_epoch != 0 {
+      return Err("ERR Node config epoch is already non-zero".to_string());
+    }
+    node.config_epoch = epoch;
+    state.current_epoch = state.current_epoch.max(epoch);
+    self.changed(&state);
+    Ok(())
+  }
+
+  /// CLUSTER REPLICATE, making this node a replica of the master `id`
+  fn replicate(&self, id: &str, storage: &Storage) -> Result<(), String> {
+    let mut state = self.state.write().unwrap();
+    let myself = state.myself.clone();
+    let Some(master) = state.nodes.get(id) else {
+      return Err(format!("ERR Unknown node {}", id));
+    };
+    if id == myself {
+      return Err("ERR Can't replicate myself".to_string());
+    }
+    if master.master.is_some() {
+      return Err("ERR I can only replicate a master, not a replica.".to_string());
+    }
+    let is_master = state.nodes[&myself].master.is_none();
+    if is_master && (!state.slots_of(&myself).is_empty() || !storage.is_empty()) {
+      return Err(
+        "ERR To set a master the node must be empty and without assigned slots.".to_string(),
+      );
+    }
+    if let Some(node) = state.nodes.get_mut(&myself) {
+      node.master = Some(id.to_string());
+    }
+    state.migrating.clear();
+    state.importing.clear();
+    self.changed(&state);
+    Ok(())
+  }
+
+  /// CLUSTER FORGET, dropping node `id` and its slots. It isn't learned
+  /// back from the others for `FORGET_TTL`, time for them to forget it too.
+  fn forget(&self, id: &str) -> Result<(), String> {
+    let mut state = self.state.write().unwrap();
+    if id == state.myself {
+      return Err("ERR I tried hard but I can't forget myself...".to_string());
+    }
+    if !state.nodes.contains_key(id) {
+      return Err(format!("ERR Unknown node {}", id));
+    }
+    if state.nodes[&state.myself].master.as_deref() == Some(id) {
+      return Err("ERR Can't forget my master!".to_string());
+    }
+    state.nodes.remove(id);
+    for owner in state.slots.iter_mut() {
+      if owner.as_deref() == Some(id) {
+        *owner = None;
+      }
+    }
+    for node in state.nodes.values_mut() {
+      if node.master.as_deref() == Some(id) {
+        node.master = None;
+      }
+    }
+    state.migrating.retain(|_, node| node != id);
+    state.importing.retain(|_, node| node != id);
+    state
+      .forgotten
+      .insert(id.to_string(), Instant::now() + FORGET_TTL);
+    self.changed(&state);
+    Ok(())
+  }
+
+  /// CLUSTER RESET: the node forgets the others and its slots, and becomes a
+  /// master. A hard reset also gives it a new id and sets its epochs to 0.
+  fn reset(&self, hard: bool, storage: &Storage) -> Result<(), String> {
+    let mut state = self.state.write().unwrap();
+    let mut node = state.nodes[&state.myself].clone();
+    if node.master.is_none() && !storage.is_empty() {
+      return Err(
+        "ERR CLUSTER RESET can't be called with master nodes containing keys".to_string(),
+      );
+    }
+    node.master = None;
+    if hard {
+      node.id = generate_node_id();
+      node.config_epoch = 0;
+    }
+    let mut reset = ClusterState::new(node.id.clone());
+    if !hard {
+      reset.current_epoch = state.current_epoch;
+    }
+    reset.nodes.insert(node.id.clone(), node);
+    *state = reset;
+    info!("Cluster reset, I'm {}", state.myself);
+    self.changed(&state);
+    Ok(())
+  }
+
+  /// CLUSTER MEET <ip> <port> [<bus-port>]: reads the node table of the node
+  /// at `ip:port`, which learns this node in turn, see the module
+  /// documentation. The bus port is checked and ignored, there being no bus.
+  pub async fn meet(&self, args: &[String]) -> RedisValue {
+    if !self.enabled {
+      return RedisValue::Error(DISABLED_ERROR.to_string());
+    }
+    let (ip, port) = match args {
+      [ip, port] => (ip, port),
+      [ip, port, bus_port] => {
+        if bus_port.parse::<u16>().is_err() {
+          return RedisValue::Error(format!("ERR Invalid bus port specified: {}", bus_port));
+        }
+        (ip, port)
+      }
+      _ => return wrong_arguments("cluster|meet"),
+    };
+    let Ok(port) = port.parse::<u16>() else {
+      return RedisValue::Error(format!("ERR Invalid base port specified: {}", port));
+    };
+    let ip = unbracket(ip);
+    if ip.parse::<IpAddr>().is_err() {
+      return RedisValue::Error(format!(
+        "ERR Invalid node address specified: {}",
+        format_host_port(ip, port)
+      ));
+    }
+    match self.pull(ip, port, true).await {
+      Ok(()) => RedisValue::SimpleString("OK".to_string()),
+      Err(e) => RedisValue::Error(format!(
+        "ERR Can't meet {}: {}",
+        format_host_port(ip, port),
+        e
+      )),
+    }
+  }
+
+  /// Reads the node table of the node at `ip:port` and merges it, then
+  /// `introduce`s this node to it if it doesn't know it
+  async fn pull(&self, ip: &str, port: u16, introduce: bool) -> Result<(), String> {
+    let exchange = async {
+      let stream = TcpStream::connect((ip, port))
+        .await
+        .map_err(|e| e.to_string())?;
+      // The address the node reaches this one at, unless it was known
+      let local_ip = stream.local_addr().map_err(|e| e.to_string())?.ip();
+      let mut stream = BufReader::new(stream);
+      let view = parse_nodes_file(&call(&mut stream, &["CLUSTER", "NODES"]).await?)?;
+
+      let (known, my_port) = {
+        let mut state = self.state.write().unwrap();
+        let myself = state.myself.clone();
+        let mut changed = state.merge(ip, port, &view);
+        let node = state.nodes.get_mut(&myself).expect("myself is a node");
+        if node.ip.is_empty() {
+          node.ip = local_ip.to_string();
+          changed = true;
+        }
+        let my_port = node.port;
+        if changed {
+          self.changed(&state);
+        }
+        (view.nodes.contains_key(&myself), my_port)
+      };
+      if introduce && !known && view.myself != self.myself() {
+        let (ip, port) = (local_ip.to_string(), my_port.to_string());
+        call(&mut stream, &["CLUSTER", "MEET", &ip, &port]).await?;
+      }
+      Ok(())
+    };
+    tokio::time::timeout(NODE_TIMEOUT, exchange)
+      .await
+      .map_err(|_| "timed out".to_string())?
+  }
+
+  /// Reads the node tables of the other nodes, one at a time
+  pub async fn gossip(&self) {
+    let nodes: Vec<(String, String, u16)> = {
+      let state = self.state.read().unwrap();
+      state
+        .nodes
+        .values()
+        .filter(|node| node.id != state.myself)
+        .map(|node| (node.id.clone(), node.ip.clone(), node.port))
+        .collect()
+    };
+    for (id, ip, port) in nodes {
+      if let Err(e) = self.pull(&ip, port, false).await {
+        debug!(
+          "Failed to reach node {} at {}: {}",
+          id,
+          format_host_port(&ip, port),
+          e
+        );
+      }
+    }
+  }
+
+  /// CLUSTER SETSLOT <slot> IMPORTING|MIGRATING|NODE <node-id> or STABLE
+  fn set_slot(
+    &self,
+    slot: u16,
+    action: &str,
+    args: &[String],
+    storage: &Storage,
+  ) -> Result<(), String> {
+    let mut state = self.state.write().unwrap();
+    let myself = state.myself.clone();
+    let owned = state.slots[slot as usize].as_deref() == Some(myself.as_str());
+    let node = match (action, args) {
+      ("STABLE", []) => None,
+      ("IMPORTING" | "MIGRATING" | "NODE", [node]) => {
+        if !state.nodes.contains_key(node) {
+          return Err(format!("ERR I don't know about node {}", node));
+        }
+        Some(node.clone())
+      }
+      _ => {
+        return Err(
+          "ERR Invalid CLUSTER SETSLOT action or number of arguments. Try CLUSTER HELP".to_string(),
+        )
+      }
+    };
+
+    match (action, node) {
+      ("IMPORTING", Some(node)) => {
+        if owned || node == myself {
+          return Err(format!("ERR I'm already the owner of hash slot {}", slot));
+        }
+        state.importing.insert(slot, node);
+      }
+      ("MIGRATING", Some(node)) => {
+        if !owned {
+          return Err(format!("ERR I'm not the owner of hash slot {}", slot));
+        }
+        if node == myself {
+          return Err("ERR Can't MIGRATE to myself".to_string());
+        }
+        state.migrating.insert(slot, node);
+      }
+      ("NODE", Some(node)) => {
+        if owned && node != myself && !keys_in_slot(storage, slot).is_empty() {
+          return Err(format!("ERR Can't assign hashslot {} to a different node while I still hold keys for this hash slot.", slot));
+        }
+        if node != myself {
+          state.migrating.remove(&slot);
+        }
+        // Taking over an imported slot closes the migration, with a new epoch
+        // so that the other nodes accept the change
+        if node == myself && state.importing.remove(&slot).is_some() {
+          state.current_epoch += 1;
+          let epoch = state.current_epoch;
+          if let Some(myself) = state.nodes.get_mut(&myself) {
+            myself.config_epoch = epoch;
+          }
+        }
+        state.slots[slot as usize] = Some(node);
+      }
+      _ => {
+        state.migrating.remove(&slot);
+        state.importing.remove(&slot);
+      }
+    }
+    if let Err(e) = self.save(&state) {
+      error!("Failed to save the cluster configuration: {}", e);
+    }
+    Ok(())
+  }
+
+  /// CLUSTER INFO
+  fn info(&self) -> String {
+    let state = self.state.read().unwrap();
+    let assigned = state.assigned_slots();
+    let my_epoch = state
+      .nodes
+      .get(&state.myself)
+      .map(|node| node.config_epoch)
+      .unwrap_or_default();
+    let fields = [
+      format!(
+        "cluster_state:{}",
+        if assigned == CLUSTER_SLOTS {
+          "ok"
+        } else {
+          "fail"
+        }
+      ),
+      format!("cluster_slots_assigned:{}", assigned),
+      format!("cluster_slots_ok:{}", assigned),
+      "cluster_slots_pfail:0".to_string(),
+      "cluster_slots_fail:0".to_string(),
+      format!("cluster_known_nodes:{}", state.nodes.len()),
+      format!("cluster_size:{}", state.size()),
+      format!("cluster_current_epoch:{}", state.current_epoch),
+      format!("cluster_my_epoch:{}", my_epoch),
+      "cluster_stats_messages_sent:0".to_string(),
+      "cluster_stats_messages_received:0".to_string(),
+      "total_cluster_links_buffer_limit_exceeded:0".to_string(),
+    ];
+    fields.map(|field| format!("{}\r\n", field)).concat()
+  }
+
+  /// CLUSTER NODES: `<id> <ip:port@cport> <flags> <master> <ping-sent>
+  /// <pong-recv> <config-epoch> <link-state> <slot> ...`, one node per line
+  fn nodes(&self) -> String {
+    self.state.read().unwrap().describe()
+  }
+
+  /// A node as CLUSTER SLOTS lists it: ip, port, id and extra metadata
+  fn slots_node(node: &ClusterNode) -> RedisValue {
+    RedisValue::Nested(vec![
+      RedisValue::BulkString(Some(node.ip.clone())),
+      RedisValue::Integer(node.port as i64),
+      RedisValue::BulkString(Some(node.id.clone())),
+      RedisValue::Nested(vec![]),
+    ])
+  }
+
+  /// CLUSTER SLOTS: every slot range with its master, then its replicas
+  fn slots(&self) -> Vec<RedisValue> {
+    let state = self.state.read().unwrap();
+    let mut ranges: Vec<(u16, u16, &ClusterNode)> = state
+      .nodes
+      .values()
+      .flat_map(|node| {
+        slot_ranges(&state.slots_of(&node.id))
+          .into_iter()
+          .map(move |(start, end)| (start, end, node))
+      })
+      .collect();
+    ranges.sort_by_key(|(start, _, _)| *start);
+    ranges
+      .into_iter()
+      .map(|(start, end, master)| {
+        let mut range = vec![
+          RedisValue::Integer(start as i64),
+          RedisValue::Integer(end as i64),
+          Self::slots_node(master),
+        ];
+        range.extend(
+          state
+            .replicas_of(&master.id)
+            .into_iter()
+            .map(Self::slots_node),
+        );
+        RedisValue::Nested(range)
+      })
+      .collect()
+  }
+
+  /// A node as CLUSTER SHARDS lists it, a flat map of its attributes
+  fn shard_node(node: &ClusterNode) -> RedisValue {
+    let bulk = |value: &str| RedisValue::BulkString(Some(value.to_string()));
+    RedisValue::Nested(vec![
+      bulk("id"),
+      bulk(&node.id),
+      bulk("port"),
+      RedisValue::Integer(node.port as i64),
+      bulk("ip"),
+      bulk(&node.ip),
+      bulk("endpoint"),
+      bulk(&node.ip),
+      bulk("role"),
+      bulk(if node.master.is_some() {
+        "replica"
+      } else {
+        "master"
+      }),
+      bulk("replication-offset"),
+      RedisValue::Integer(0),
+      bulk("health"),
+      bulk("online"),
+    ])
+  }
+
+  /// CLUSTER SHARDS: each master with its slot ranges and its replicas
+  fn shards(&self) -> Vec<RedisValue> {
+    let state = self.state.read().unwrap();
+    state
+      .nodes
+      .values()
+      .filter(|node| node.master.is_none())
+      .map(|master| {
+        let slots = slot_ranges(&state.slots_of(&master.id))
+          .into_iter()
+          .flat_map(|(start, end)| {
+            [
+              RedisValue::Integer(start as i64),
+              RedisValue::Integer(end as i64),
+            ]
+          })
+          .collect();
+        let mut nodes = vec![Self::shard_node(master)];
+        nodes.extend(
+          state
+            .replicas_of(&master.id)
+            .into_iter()
+            .map(Self::shard_node),
+        );
+        RedisValue::Nested(vec![
+          RedisValue::BulkString(Some("slots".to_string())),
+          RedisValue::Nested(slots),
+          RedisValue::BulkString(Some("nodes".to_string())),
+          RedisValue::Nested(nodes),
+        ])
+      })
+      .collect()
+  }
+}
+
+/// A slot number given as argument
+fn parse_slot(slot: &str) -> Result<u16, String> {
+  slot
+    .parse::<u16>()
+    .ok()
+    .filter(|slot| (*slot as usize) < CLUSTER_SLOTS)
+    .ok_or_else(|| "ERR Invalid or out of range slot".to_string())
+}
+
+/// Slots of `start end` pairs, as ADDSLOTSRANGE takes them
+fn parse_slot_ranges(args: &[String]) -> Result<Vec<u16>, String> {
+  let mut slots = Vec::new();
+  for range in args.chunks(2) {
+    let (start, end) = (parse_slot(&range[0])?, parse_slot(&range[1])?);
+    if start > end {
+      return Err(format!(
+        "ERR start slot number {} is greater than end slot number {}",
+        start, end
+      ));
+    }
+    slots.extend(start..=end);
+  }
+  Ok(slots)
+}
+
+/// Keys stored here that hash to `slot`, in order
+fn keys_in_slot(storage: &Storage, slot: u16) -> Vec<String> {
+  let mut keys: Vec<String> = storage
+    .keys("*")
+    .into_iter()
+    .filter(|key| key_slot(key) == slot)
+    .collect();
+  keys.sort();
+  keys
+}
+
+fn reply(result: Result<(), String>) -> RedisValue {
+  match result {
+    Ok(()) => RedisValue::SimpleString("OK".to_string()),
+    Err(e) => RedisValue::Error(e),
+  }
+}
+
+/// Sends a command to another node, returning its simple or bulk string reply
+async fn call(stream: &mut BufReader<TcpStream>, args: &[&str]) -> Result<String, String> {
+  let mut command = format!("*{}\r\n", args.len());
+  for arg in args {
+    command.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+  }
+  stream
+    .get_mut()
+    .write_all(command.as_bytes())
+    .await
+    .map_err(|e| e.to_string())?;
+  let mut line = String::new();
+  stream
+    .read_line(&mut line)
+    .await
+    .map_err(|e| e.to_string())?;
+  let line = line.trim_end();
+  if let Some(status) = line.strip_prefix('+') {
+    return Ok(status.to_string());
+  }
+  if let Some(error) = line.strip_prefix('-') {
+    return Err(error.to_string());
+  }
+  let length = line
+    .strip_prefix('$')
+    .and_then(|length| length.parse::<usize>().ok())
+    .ok_or_else(|| format!("unexpected reply '{}'", line))?;
+  let mut bulk = vec![0; length + 2];
+  stream
+    .read_exact(&mut bulk)
+    .await
+    .map_err(|e| e.to_string())?;
+  bulk.truncate(length);
+  String::from_utf8(bulk).map_err(|_| "invalid UTF-8 reply".to_string())
+}
+
+/// Schedules the job reading the node tables of the other nodes
+pub fn register_cluster_gossip(scheduler: &mut Scheduler, cluster: Arc<Cluster>) {
+  if !cluster.enabled() {
+    return;
+  }
+  let running = Arc::new(AtomicBool::new(false));
+  scheduler.every(
+    "cluster-gossip",
+    GOSSIP_INTERVAL,
+    GOSSIP_BUDGET,
+    move || {
+      let (cluster, running) = (cluster.clone(), running.clone());
+      async move {
+        // In the background, a node slow to answer mustn't hold the other jobs
+        if !running.swap(true, Ordering::Relaxed) {
+          tokio::spawn(async move {
+            cluster.gossip().await;
+            running.store(false, Ordering::Relaxed);
+          });
+        }
+      }
+    },
+  );
+}
+
+fn wrong_arguments(command: &str) -> RedisValue {
+  RedisValue::Error(format!(
+    "ERR wrong number of arguments for '{}' command",
+    command
+  ))
+}
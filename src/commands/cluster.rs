@@ -0,0 +1,35 @@
+/**
+ * Cluster commands: CLUSTER and ASKING
+ */
+use super::{Context, HandlerFuture};
+use crate::parser::{Command, RedisValue};
+
+pub fn cluster<'a>(context: &'a Context<'a>, command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let Command::CLUSTER(subcommand, args) = command else {
+      unreachable!()
+    };
+    // MEET talks to the other node, without holding the keyspace
+    if subcommand == "MEET" {
+      return context.cluster.meet(&args).await.into();
+    }
+    let storage = context.storage().await;
+    context
+      .cluster
+      .handle_command(&subcommand, &args, &storage)
+      .into()
+  })
+}
+
+pub fn asking<'a>(context: &'a Context<'a>, _command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    if !context.cluster.enabled() {
+      return RedisValue::Error("ERR This instance has cluster support disabled".to_string())
+        .into();
+    }
+    context
+      .clients
+      .update(context.client_id, |client| client.asking = true);
+    RedisValue::SimpleString("OK".to_string()).into()
+  })
+}
@@ -0,0 +1,138 @@
+/**
+ * Connection commands: PING, ECHO, AUTH, HELLO, CLIENT and RESET
+ */
+use super::{Context, HandlerFuture};
+use crate::acl::DEFAULT_USER;
+use crate::parser::{Command, RedisValue};
+
+pub fn ping<'a>(_context: &'a Context<'a>, command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let Command::PING(message) = command else {
+      unreachable!()
+    };
+    RedisValue::SimpleString(message.unwrap_or_else(|| "PONG".to_string())).into()
+  })
+}
+
+pub fn echo<'a>(_context: &'a Context<'a>, command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let Command::ECHO(message) = command else {
+      unreachable!()
+    };
+    RedisValue::SimpleString(message).into()
+  })
+}
+
+pub fn auth<'a>(context: &'a Context<'a>, command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let Command::AUTH(args) = command else {
+      unreachable!()
+    };
+    context
+      .acl
+      .auth(context.clients, context.client_id, &args)
+      .into()
+  })
+}
+
+/// HELLO [protover [AUTH username password] [SETNAME clientname]]: switches
+/// the connection to RESP2 or RESP3 and describes the server
+pub fn hello<'a>(context: &'a Context<'a>, command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let Command::HELLO(args) = command else {
+      unreachable!()
+    };
+    let (version, mut options) = match args.split_first() {
+      Some((version, options)) => (Some(version), options),
+      None => (None, &args[..]),
+    };
+    let resp = match version.map(|version| version.parse::<u8>()) {
+      None => context.clients.resp(context.client_id),
+      Some(Ok(resp @ 2..=3)) => resp,
+      Some(_) => {
+        return RedisValue::Error("NOPROTO unsupported protocol version".to_string()).into()
+      }
+    };
+    let mut auth = None;
+    let mut name = None;
+    loop {
+      match options {
+        [] => break,
+        [option, user, password, rest @ ..] if option.eq_ignore_ascii_case("AUTH") => {
+          auth = Some([user.clone(), password.clone()]);
+          options = rest;
+        }
+        [option, client_name, rest @ ..] if option.eq_ignore_ascii_case("SETNAME") => {
+          name = Some(client_name);
+          options = rest;
+        }
+        [option, ..] => {
+          return RedisValue::Error(format!("ERR Syntax error in HELLO option '{}'", option)).into()
+        }
+      }
+    }
+
+    if let Some(credentials) = auth {
+      let reply = context
+        .acl
+        .auth(context.clients, context.client_id, &credentials);
+      if matches!(reply, RedisValue::Error(_)) {
+        return reply.into();
+      }
+    }
+    if !context.clients.session(context.client_id).1 {
+      return RedisValue::Error(
+        "NOAUTH HELLO must be called with the client already authenticated, otherwise the \
+         HELLO <proto> AUTH <user> <pass> option can be used to authenticate the client and \
+         select the RESP protocol version at the same time"
+          .to_string(),
+      )
+      .into();
+    }
+    if let Some(name) = name {
+      if let Err(e) = context.clients.set_name(context.client_id, name) {
+        return RedisValue::Error(e).into();
+      }
+    }
+    context.clients.set_resp(context.client_id, resp);
+
+    let field =
+      |name: &str, value: RedisValue| (RedisValue::BulkString(Some(name.to_string())), value);
+    let text = |value: &str| RedisValue::BulkString(Some(value.to_string()));
+    let mode = if context.cluster.enabled() {
+      "cluster"
+    } else {
+      "standalone"
+    };
+    RedisValue::Map(vec![
+      field("server", text("redis")),
+      field("version", text(env!("CARGO_PKG_VERSION"))),
+      field("proto", RedisValue::Integer(resp as i64)),
+      field("id", RedisValue::Integer(context.client_id as i64)),
+      field("mode", text(mode)),
+      field("role", text("master")),
+      field("modules", RedisValue::Nested(vec![])),
+    ])
+    .into()
+  })
+}
+
+pub fn client<'a>(context: &'a Context<'a>, command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let Command::CLIENT(subcommand, args) = command else {
+      unreachable!()
+    };
+    context
+      .clients
+      .handle_command(context.client_id, &subcommand, &args)
+      .into()
+  })
+}
+
+pub fn reset<'a>(context: &'a Context<'a>, _command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let default_user = context.acl.authenticate(DEFAULT_USER, None);
+    context.clients.reset(context.client_id, default_user);
+    RedisValue::SimpleString("RESET".to_string()).into()
+  })
+}
@@ -0,0 +1,230 @@
+/**
+ * Keyspace commands: DEL, UNLINK, KEYS, SCAN, DUMP, RESTORE, the TTL commands
+ * and OBJECT
+ */
+use super::{Context, HandlerFuture};
+use crate::dump;
+use crate::glob::glob_match;
+use crate::offload;
+use crate::parser::{Command, RedisValue};
+use crate::storage::Storage;
+use bytes::Bytes;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub fn del<'a>(context: &'a Context<'a>, command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let Command::DEL(keys) = command else {
+      unreachable!()
+    };
+    let removed = context.storage().await.del(&keys);
+    RedisValue::Integer(removed as i64).into()
+  })
+}
+
+pub fn unlink<'a>(context: &'a Context<'a>, command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let Command::UNLINK(keys) = command else {
+      unreachable!()
+    };
+    let removed = context.storage().await.unlink(&keys);
+    RedisValue::Integer(removed as i64).into()
+  })
+}
+
+pub fn keys<'a>(context: &'a Context<'a>, command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let Command::KEYS(pattern) = command else {
+      unreachable!()
+    };
+    let min_keys = offload::min_keys(&*context.config.lock().await);
+    let storage = context.storage().await;
+    if !offload::should_offload(min_keys, storage.len()) {
+      return RedisValue::Array(storage.keys(&pattern)).into();
+    }
+    let snapshot = storage.begin_snapshot();
+    drop(storage);
+    let keys = offload::run(move || {
+      snapshot
+        .keys()
+        .into_iter()
+        .filter(|key| glob_match(&pattern, key))
+        .collect()
+    })
+    .await;
+    RedisValue::Array(keys).into()
+  })
+}
+
+pub fn scan<'a>(context: &'a Context<'a>, command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let Command::SCAN(cursor, pattern, count) = command else {
+      unreachable!()
+    };
+    let (cursor, keys) = context
+      .storage()
+      .await
+      .scan(cursor, pattern.as_deref(), count);
+    RedisValue::Nested(vec![
+      RedisValue::BulkString(Some(cursor.to_string())),
+      RedisValue::Array(keys),
+    ])
+    .into()
+  })
+}
+
+pub fn dump<'a>(context: &'a Context<'a>, command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let Command::DUMP(key) = command else {
+      unreachable!()
+    };
+    match context.storage().await.get(&key) {
+      Some(value) => RedisValue::BulkBytes(Bytes::from(dump::serialize(&value))),
+      None => RedisValue::BulkString(None),
+    }
+    .into()
+  })
+}
+
+pub fn restore<'a>(context: &'a Context<'a>, command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let Command::RESTORE(key, ttl, payload, replace, absttl) = command else {
+      unreachable!()
+    };
+    if ttl < 0 {
+      return RedisValue::Error("ERR Invalid TTL value, must be >= 0".to_string()).into();
+    }
+    let sanitize = dump::sanitize(&*context.config.lock().await);
+    let value = match dump::deserialize(&payload, sanitize)
+      .and_then(|value| String::from_utf8(value).map_err(|_| dump::BAD_FORMAT.to_string()))
+    {
+      Ok(value) => value,
+      Err(e) => return RedisValue::Error(e).into(),
+    };
+    // A TTL of 0 restores a persistent key
+    let ttl = match (ttl, absttl) {
+      (0, _) => None,
+      (deadline, true) => {
+        let deadline = UNIX_EPOCH + Duration::from_millis(deadline as u64);
+        Some(
+          deadline
+            .duration_since(SystemTime::now())
+            .unwrap_or_default(),
+        )
+      }
+      (millis, false) => Some(Duration::from_millis(millis as u64)),
+    };
+
+    let storage = context.storage().await;
+    if !replace && storage.exists(&key) {
+      return RedisValue::Error("BUSYKEY Target key name already exists.".to_string()).into();
+    }
+    match ttl {
+      // Already expired, like Redis the key is deleted rather than restored
+      Some(ttl) if ttl.is_zero() => {
+        storage.del(&[key]);
+      }
+      Some(ttl) => storage.set(
+        key,
+        value,
+        vec![("PX".to_string(), ttl.as_millis().to_string())],
+      ),
+      None => storage.set(key, value, vec![]),
+    }
+    RedisValue::SimpleString("OK".to_string()).into()
+  })
+}
+
+/** Applies EXPIRE/PEXPIRE: a non-positive timeout deletes the key right away */
+fn expire_key(storage: &Storage, key: &str, timeout: Duration, requested: i64) -> bool {
+  if requested <= 0 {
+    return storage.del(&[key.to_string()]) > 0;
+  }
+  storage.set_expiry(key, Some(storage.now() + timeout))
+}
+
+pub fn expire<'a>(context: &'a Context<'a>, command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let Command::EXPIRE(key, seconds) = command else {
+      unreachable!()
+    };
+    let timeout = Duration::from_secs(seconds.max(0) as u64);
+    let updated = expire_key(&*context.storage().await, &key, timeout, seconds);
+    RedisValue::Integer(updated as i64).into()
+  })
+}
+
+pub fn pexpire<'a>(context: &'a Context<'a>, command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let Command::PEXPIRE(key, millis) = command else {
+      unreachable!()
+    };
+    let timeout = Duration::from_millis(millis.max(0) as u64);
+    let updated = expire_key(&*context.storage().await, &key, timeout, millis);
+    RedisValue::Integer(updated as i64).into()
+  })
+}
+
+pub fn ttl<'a>(context: &'a Context<'a>, command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let Command::TTL(key) = command else {
+      unreachable!()
+    };
+    let ttl = match context.storage().await.ttl(&key) {
+      None => -2,
+      Some(None) => -1,
+      Some(Some(remaining)) => remaining.as_millis().div_ceil(1000) as i64,
+    };
+    RedisValue::Integer(ttl).into()
+  })
+}
+
+pub fn pttl<'a>(context: &'a Context<'a>, command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let Command::PTTL(key) = command else {
+      unreachable!()
+    };
+    let ttl = match context.storage().await.ttl(&key) {
+      None => -2,
+      Some(None) => -1,
+      Some(Some(remaining)) => remaining.as_millis() as i64,
+    };
+    RedisValue::Integer(ttl).into()
+  })
+}
+
+pub fn persist<'a>(context: &'a Context<'a>, command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let Command::PERSIST(key) = command else {
+      unreachable!()
+    };
+    let storage = context.storage().await;
+    let had_ttl = matches!(storage.ttl(&key), Some(Some(_)));
+    if had_ttl {
+      storage.set_expiry(&key, None);
+    }
+    RedisValue::Integer(had_ttl as i64).into()
+  })
+}
+
+pub fn object_encoding<'a>(context: &'a Context<'a>, command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let Command::OBJECTENCODING(key) = command else {
+      unreachable!()
+    };
+    let encoding = context.storage().await.encoding(&key);
+    RedisValue::BulkString(encoding.map(|encoding| encoding.to_string())).into()
+  })
+}
+
+pub fn object_idletime<'a>(context: &'a Context<'a>, command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let Command::OBJECTIDLETIME(key) = command else {
+      unreachable!()
+    };
+    match context.storage().await.idle_time(&key) {
+      Some(idle) => RedisValue::Integer(idle.as_secs() as i64),
+      None => RedisValue::BulkString(None),
+    }
+    .into()
+  })
+}
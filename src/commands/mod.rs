@@ -0,0 +1,248 @@
+/**
+ * The handlers of the built-in commands, one function per command, looked up
+ * by upper case name in `HANDLERS`. Commands with subcommands are registered
+ * either per subcommand (`CONFIG|GET`) or once for all of them (`CLIENT`),
+ * depending on whether the parser tells the subcommands apart.
+ *
+ * `serve_client` does what applies to every command before dispatching it:
+ * AUTH, MONITOR mode, ACL, cluster redirects, rate limits, CLIENT PAUSE; and
+ * after: commandstats, the slow log, the audit log and CLIENT TRACKING. A
+ * handler only runs its command and returns the reply.
+ *
+ * Handlers receive the parsed command and a `Context` with the client's
+ * session and the server's state. Adding a command means a `Command` variant
+ * and its parsing in `parser.rs`, its ACL categories in `acl.rs`, and a
+ * handler registered here.
+ */
+mod cluster;
+mod connection;
+mod keyspace;
+mod server;
+mod strings;
+
+use crate::acl::Acl;
+use crate::clients::ClientRegistry;
+use crate::cluster::Cluster;
+use crate::config::Config;
+use crate::module::{check_arity, ModuleCommands, Session};
+use crate::parser::{command_argv, Command, RedisValue};
+use crate::ratelimit::RateLimiter;
+use crate::readthrough::ReadThrough;
+use crate::state::ServerState;
+use crate::storage::Storage;
+use crate::storagelock::{KeyLocks, StorageLock};
+use crate::telemetry::{self, Traced};
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::ops::Deref;
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+use tokio::sync::{Mutex as AsyncMutex, RwLockReadGuard, RwLockWriteGuard};
+use tracing::{debug, Span};
+
+/// What a command left to do once it ran
+pub enum Outcome {
+  Reply(RedisValue),
+  /// SHUTDOWN succeeded: the server stops once the replies are written
+  Shutdown,
+}
+
+impl From<RedisValue> for Outcome {
+  fn from(value: RedisValue) -> Self {
+    Outcome::Reply(value)
+  }
+}
+
+pub type HandlerFuture<'a> = Pin<Box<dyn Future<Output = Outcome> + Send + 'a>>;
+
+/// Runs a parsed command, which is always the variant it is registered for
+pub type Handler = for<'a> fn(&'a Context<'a>, Command) -> HandlerFuture<'a>;
+
+/// The client a command runs for and the state of the server
+pub struct Context<'a> {
+  pub client_id: u64,
+  pub addr: SocketAddr,
+  /// The command as the client sent it
+  pub frame: &'a [u8],
+  pub storage: &'a Arc<StorageLock>,
+  pub config: &'a Arc<AsyncMutex<Config>>,
+  pub clients: &'a ClientRegistry,
+  pub acl: &'a Acl,
+  pub rate_limiter: &'a RateLimiter,
+  pub cluster: &'a Cluster,
+  pub modules: &'a ModuleCommands,
+  pub read_through: &'a ReadThrough,
+  /// Counters and logs of the server
+  pub state: &'a Arc<ServerState>,
+  /// Span of the dispatch, waiting for the keyspace lock is traced under it
+  pub span: &'a Span,
+  /// Whether `dispatch` holds the keys of the command, see `storage`
+  pub holds_keys: bool,
+}
+
+/// The keyspace as a command holds it, see `Context::storage`
+pub enum StorageGuard<'a> {
+  Shared(Traced<RwLockReadGuard<'a, Storage>>),
+  Whole(Traced<RwLockWriteGuard<'a, Storage>>),
+}
+
+impl Deref for StorageGuard<'_> {
+  type Target = Storage;
+
+  fn deref(&self) -> &Storage {
+    match self {
+      StorageGuard::Shared(guard) => guard,
+      StorageGuard::Whole(guard) => guard,
+    }
+  }
+}
+
+impl Context<'_> {
+  /// Locks the keyspace: a command whose keys are held shares it with the
+  /// commands on other keys, any other locks all of it
+  pub async fn storage(&self) -> StorageGuard<'_> {
+    match self.holds_keys {
+      true => StorageGuard::Shared(telemetry::share_storage(self.storage, self.span).await),
+      false => StorageGuard::Whole(telemetry::lock_storage(self.storage, self.span).await),
+    }
+  }
+
+  /// Locks `key` and shares the keyspace, as `dispatch` does for the commands
+  /// on keys, for a command that lets go of its key while it waits
+  pub async fn lock_key(&self, key: &str) -> (KeyLocks<'_>, StorageGuard<'_>) {
+    let stripes = self.storage.lock_keys(&[key]).await;
+    let storage = telemetry::share_storage(self.storage, self.span).await;
+    (stripes, StorageGuard::Shared(storage))
+  }
+
+  /// Name of the authenticated user
+  pub fn user(&self) -> String {
+    self.clients.session(self.client_id).0
+  }
+}
+
+/// Every built-in command, by upper case name
+const HANDLERS: &[(&str, Handler)] = &[
+  ("ACL", server::acl),
+  ("ASKING", cluster::asking),
+  ("AUTH", connection::auth),
+  ("BGSAVE", server::bgsave),
+  ("CLIENT", connection::client),
+  ("CLUSTER", cluster::cluster),
+  ("CONFIG|GET", server::config_get),
+  ("CONFIG|RESETSTAT", server::config_resetstat),
+  ("CONFIG|SET", server::config_set),
+  ("DEBUG", server::debug),
+  ("DECR", strings::decr),
+  ("DECRBY", strings::decrby),
+  ("DEL", keyspace::del),
+  ("DUMP", keyspace::dump),
+  ("ECHO", connection::echo),
+  ("EXPIRE", keyspace::expire),
+  ("FLUSHALL", server::flushall),
+  ("GET", strings::get),
+  ("HELLO", connection::hello),
+  ("INCR", strings::incr),
+  ("INCRBY", strings::incrby),
+  ("INFO", server::info),
+  ("KEYS", keyspace::keys),
+  ("LOLWUT", server::lolwut),
+  ("MEMORY|PURGE", server::memory_purge),
+  ("MEMORY|STATS", server::memory_stats),
+  ("MEMORY|USAGE", server::memory_usage),
+  ("MONITOR", server::monitor),
+  ("OBJECT|ENCODING", keyspace::object_encoding),
+  ("OBJECT|IDLETIME", keyspace::object_idletime),
+  ("PERSIST", keyspace::persist),
+  ("PEXPIRE", keyspace::pexpire),
+  ("PING", connection::ping),
+  ("PTTL", keyspace::pttl),
+  ("RESET", connection::reset),
+  ("RESTORE", keyspace::restore),
+  ("SCAN", keyspace::scan),
+  ("SET", strings::set),
+  ("SHUTDOWN", server::shutdown),
+  ("SLOWLOG", server::slowlog),
+  ("TIME", server::time),
+  ("TTL", keyspace::ttl),
+  ("UNLINK", keyspace::unlink),
+];
+
+fn registry() -> &'static HashMap<&'static str, Handler> {
+  static REGISTRY: OnceLock<HashMap<&'static str, Handler>> = OnceLock::new();
+  REGISTRY.get_or_init(|| HANDLERS.iter().copied().collect())
+}
+
+/// The handler of a command by name, `config|get` or `CONFIG GET` for
+/// instance. Commands registered once for all their subcommands are found
+/// by either name.
+pub fn handler(name: &str) -> Option<Handler> {
+  let name = name.to_uppercase().replace(' ', "|");
+  let registry = registry();
+  registry.get(name.as_str()).copied().or_else(|| {
+    let (container, _) = name.split_once('|')?;
+    registry.get(container).copied()
+  })
+}
+
+/// Runs a command, built-in or custom
+pub async fn dispatch(context: &Context<'_>, command: Command) -> Outcome {
+  let handler = match &command {
+    Command::UNKNOWN(_) => None,
+    command => handler(&command.name()),
+  };
+  let Some(handler) = handler else {
+    return custom(context, command).await;
+  };
+  // A built-in command on keys runs next to the commands on other keys. GET
+  // locks its key itself, see `strings::get`.
+  let keys = command.keys();
+  if keys.is_empty() || matches!(command, Command::GET(_)) {
+    return handler(context, command).await;
+  }
+  let _keys = context.storage.lock_keys(&keys).await;
+  let context = Context {
+    holds_keys: true,
+    ..*context
+  };
+  handler(&context, command).await
+}
+
+/// A command registered with `RedisServerBuilder::command`, or an unknown one
+async fn custom(context: &Context<'_>, command: Command) -> Outcome {
+  let name = match command {
+    Command::UNKNOWN(name) => name,
+    command => command.name(),
+  };
+  let argv = command_argv(context.frame);
+  let Some(handler) = context.modules.get(&name).cloned() else {
+    debug!("Unknown command: {}", name);
+    let arguments: Vec<String> = argv
+      .iter()
+      .skip(1)
+      .map(|argument| format!("'{}' ", argument))
+      .collect();
+    return RedisValue::Error(format!(
+      "ERR unknown command '{}', with args beginning with: {}",
+      argv.first().unwrap_or(&name),
+      arguments.concat()
+    ))
+    .into();
+  };
+  if let Err(e) = check_arity(handler.as_ref(), argv.len()) {
+    return RedisValue::Error(e).into();
+  }
+  let client = context.clients.get(context.client_id);
+  let session = Session {
+    client_id: context.client_id,
+    addr: client
+      .as_ref()
+      .map(|client| client.addr)
+      .unwrap_or(context.addr),
+    user: context.user(),
+    name: client.map(|client| client.name).unwrap_or_default(),
+  };
+  let storage = context.storage().await;
+  handler.execute(&session, &storage, &argv[1..]).await.into()
+}
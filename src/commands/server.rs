@@ -0,0 +1,221 @@
+/**
+ * Server commands: INFO, CONFIG, ACL, DEBUG, MEMORY, SLOWLOG, MONITOR,
+ * persistence and SHUTDOWN
+ */
+use super::{Context, HandlerFuture, Outcome};
+use crate::parser::{Command, RedisValue};
+use crate::rdb::RdbOptions;
+use crate::shutdown::prepare_shutdown;
+use crate::{allocator, configset, cron, encryption, lolwut, rdb, telemetry};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub fn info<'a>(context: &'a Context<'a>, command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let Command::INFO(sections) = command else {
+      unreachable!()
+    };
+    let info = crate::info::info(
+      &sections,
+      context.storage,
+      context.config,
+      context.clients,
+      context.state,
+    )
+    .await;
+    RedisValue::BulkString(Some(info)).into()
+  })
+}
+
+pub fn config_get<'a>(context: &'a Context<'a>, command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let Command::CONFIGGET(patterns) = command else {
+      unreachable!()
+    };
+    RedisValue::Array(context.config.lock().await.parameters(&patterns)).into()
+  })
+}
+
+pub fn config_set<'a>(context: &'a Context<'a>, command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let Command::CONFIGSET(arguments) = command else {
+      unreachable!()
+    };
+    configset::config_set(
+      &arguments,
+      context.storage,
+      context.config,
+      context.clients,
+      context.acl,
+      context.rate_limiter,
+      context.state,
+    )
+    .await
+    .into()
+  })
+}
+
+pub fn config_resetstat<'a>(context: &'a Context<'a>, _command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    context.state.stats.reset();
+    cron::reset_stats();
+    context.storage.lock().await.negative_cache().reset_stats();
+    RedisValue::SimpleString("OK".to_string()).into()
+  })
+}
+
+pub fn acl<'a>(context: &'a Context<'a>, command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let Command::ACL(subcommand, args) = command else {
+      unreachable!()
+    };
+    let user = context.user();
+    context
+      .acl
+      .handle_command(context.clients, &user, &subcommand, &args)
+      .into()
+  })
+}
+
+pub fn flushall<'a>(context: &'a Context<'a>, command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let Command::FLUSHALL(lazy) = command else {
+      unreachable!()
+    };
+    telemetry::lock_storage(context.storage, context.span)
+      .await
+      .flushall(lazy);
+    RedisValue::SimpleString("OK".to_string()).into()
+  })
+}
+
+pub fn debug<'a>(context: &'a Context<'a>, command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let Command::DEBUG(subcommand, args) = command else {
+      unreachable!()
+    };
+    crate::debug::handle_command(
+      &subcommand,
+      &args,
+      context.storage,
+      context.config,
+      &context.state.faults,
+    )
+    .await
+    .into()
+  })
+}
+
+pub fn time<'a>(_context: &'a Context<'a>, _command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let now = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .unwrap_or_default();
+    RedisValue::Array(vec![
+      now.as_secs().to_string(),
+      now.subsec_micros().to_string(),
+    ])
+    .into()
+  })
+}
+
+pub fn lolwut<'a>(_context: &'a Context<'a>, command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let Command::LOLWUT(args) = command else {
+      unreachable!()
+    };
+    match lolwut::lolwut(&args) {
+      Ok(art) => RedisValue::BulkString(Some(art)),
+      Err(e) => RedisValue::Error(e),
+    }
+    .into()
+  })
+}
+
+pub fn memory_stats<'a>(context: &'a Context<'a>, _command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let storage = context.storage().await;
+    RedisValue::Array(allocator::memory_stats(&storage, storage.len())).into()
+  })
+}
+
+pub fn memory_purge<'a>(_context: &'a Context<'a>, _command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    match allocator::purge() {
+      Ok(()) => RedisValue::SimpleString("OK".to_string()),
+      Err(e) => RedisValue::Error(e),
+    }
+    .into()
+  })
+}
+
+pub fn memory_usage<'a>(context: &'a Context<'a>, command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let Command::MEMORYUSAGE(key) = command else {
+      unreachable!()
+    };
+    match context.storage().await.memory_usage(&key) {
+      Some(bytes) => RedisValue::Integer(bytes as i64),
+      None => RedisValue::BulkString(None),
+    }
+    .into()
+  })
+}
+
+pub fn slowlog<'a>(context: &'a Context<'a>, command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let Command::SLOWLOG(subcommand, args) = command else {
+      unreachable!()
+    };
+    context
+      .state
+      .slowlog
+      .handle_command(&subcommand, &args)
+      .into()
+  })
+}
+
+pub fn monitor<'a>(context: &'a Context<'a>, _command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    context.clients.start_monitor(context.client_id);
+    RedisValue::SimpleString("OK".to_string()).into()
+  })
+}
+
+pub fn bgsave<'a>(context: &'a Context<'a>, _command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let (path, key, options) = {
+      let config = context.config.lock().await;
+      (
+        rdb::snapshot_path(&config),
+        encryption::key(&config),
+        RdbOptions::from_config(&config),
+      )
+    };
+    match key.and_then(|key| {
+      rdb::bgsave(
+        context.state.clone(),
+        context.storage.clone(),
+        path,
+        key,
+        options,
+      )
+    }) {
+      Ok(()) => RedisValue::SimpleString("Background saving started".to_string()),
+      Err(e) => RedisValue::Error(e),
+    }
+    .into()
+  })
+}
+
+pub fn shutdown<'a>(context: &'a Context<'a>, command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let Command::SHUTDOWN(save) = command else {
+      unreachable!()
+    };
+    match prepare_shutdown(context.storage, context.config, context.state, save).await {
+      // Like Redis, a successful SHUTDOWN gets no reply: the connection just closes
+      Ok(()) => Outcome::Shutdown,
+      Err(e) => RedisValue::Error(e).into(),
+    }
+  })
+}
@@ -0,0 +1,99 @@
+/**
+ * String commands: GET, SET and the counters
+ */
+use super::{Context, HandlerFuture};
+use crate::parser::{Command, RedisValue};
+use crate::storage::Storage;
+
+pub fn get<'a>(context: &'a Context<'a>, command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let Command::GET(key) = command else {
+      unreachable!()
+    };
+    let touch = !context.clients.no_touch(context.client_id);
+    // The key is let go while the miss handler runs, so that a slow system of
+    // record doesn't hold up the writers to it or to the keys of its stripe
+    let value = {
+      let (_key, storage) = context.lock_key(&key).await;
+      storage.lookup(&key, touch)
+    };
+    if let Some(value) = value {
+      return RedisValue::BulkBytes(value).into();
+    }
+    match context.read_through.load(&key, context.config).await {
+      Ok(Some(loaded)) => {
+        let (_key, storage) = context.lock_key(&key).await;
+        // A key written while loading wins over the loaded value
+        match storage.peek(&key) {
+          Some(value) => RedisValue::BulkString(Some(value)),
+          None => {
+            let options = match loaded.ttl {
+              Some(ttl) => vec![("PX".to_string(), ttl.as_millis().to_string())],
+              None => Vec::new(),
+            };
+            storage.set(key, loaded.value.clone(), options);
+            RedisValue::BulkString(Some(loaded.value))
+          }
+        }
+      }
+      Ok(None) => RedisValue::BulkString(None),
+      Err(e) => RedisValue::Error(e),
+    }
+    .into()
+  })
+}
+
+pub fn set<'a>(context: &'a Context<'a>, command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let Command::SET(key, value, options) = command else {
+      unreachable!()
+    };
+    let storage = context.storage().await;
+    storage.set(key, value, options.unwrap_or_default());
+    RedisValue::SimpleString("OK".to_string()).into()
+  })
+}
+
+/** Applies INCR/DECR/INCRBY/DECRBY */
+fn counter(storage: &Storage, key: &str, delta: i64) -> RedisValue {
+  match storage.incr_by(key, delta) {
+    Ok(value) => RedisValue::Integer(value),
+    Err(e) => RedisValue::Error(format!("ERR {}", e)),
+  }
+}
+
+pub fn incr<'a>(context: &'a Context<'a>, command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let Command::INCR(key) = command else {
+      unreachable!()
+    };
+    counter(&*context.storage().await, &key, 1).into()
+  })
+}
+
+pub fn decr<'a>(context: &'a Context<'a>, command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let Command::DECR(key) = command else {
+      unreachable!()
+    };
+    counter(&*context.storage().await, &key, -1).into()
+  })
+}
+
+pub fn incrby<'a>(context: &'a Context<'a>, command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let Command::INCRBY(key, amount) = command else {
+      unreachable!()
+    };
+    counter(&*context.storage().await, &key, amount).into()
+  })
+}
+
+pub fn decrby<'a>(context: &'a Context<'a>, command: Command) -> HandlerFuture<'a> {
+  Box::pin(async move {
+    let Command::DECRBY(key, amount) = command else {
+      unreachable!()
+    };
+    counter(&*context.storage().await, &key, -amount).into()
+  })
+}
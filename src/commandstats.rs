@@ -0,0 +1,190 @@
+/**
+ * Per-command statistics behind INFO commandstats and INFO latencystats.
+ *
+ * Every executed command is timed and accounted under its name (`get`,
+ * `client|list`...): calls, total microseconds, calls rejected before running
+ * (ACL, rate limiting...) and calls that ran but replied with an error.
+ *
+ * Latencies also go into a log-linear histogram, eight buckets per power of two,
+ * so percentiles are reported within about 9% of the real value while each
+ * command only keeps a fixed number of counters.
+ */
+use crate::config::Config;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+const BUCKETS_PER_POWER_OF_TWO: f64 = 8.0;
+/// Enough buckets to cover latencies up to 2^40 microseconds
+const BUCKETS: usize = 8 * 40 + 1;
+
+/// Percentiles reported when `latency-tracking-info-percentiles` isn't set
+const DEFAULT_PERCENTILES: [f64; 3] = [50.0, 99.0, 99.9];
+
+struct LatencyHistogram {
+  /// Bucket 0 holds latencies under 1us, bucket `i` latencies under 2^(i/8)us
+  buckets: Vec<u64>,
+  count: u64,
+}
+
+impl LatencyHistogram {
+  fn new() -> Self {
+    Self {
+      buckets: vec![0; BUCKETS],
+      count: 0,
+    }
+  }
+
+  fn record(&mut self, usec: f64) {
+    let bucket = if usec < 1.0 {
+      0
+    } else {
+      (usec.log2() * BUCKETS_PER_POWER_OF_TWO) as usize + 1
+    };
+    self.buckets[bucket.min(BUCKETS - 1)] += 1;
+    self.count += 1;
+  }
+
+  /// Upper bound, in microseconds, of the bucket holding the percentile
+  fn percentile(&self, percentile: f64) -> f64 {
+    let rank = ((percentile / 100.0 * self.count as f64).ceil() as u64).max(1);
+    let mut seen = 0;
+    for (bucket, count) in self.buckets.iter().enumerate() {
+      seen += count;
+      if seen >= rank {
+        return 2f64.powf(bucket as f64 / BUCKETS_PER_POWER_OF_TWO);
+      }
+    }
+    0.0
+  }
+}
+
+struct CommandStat {
+  calls: u64,
+  usec: u64,
+  rejected_calls: u64,
+  failed_calls: u64,
+  latency: LatencyHistogram,
+}
+
+impl CommandStat {
+  fn new() -> Self {
+    Self {
+      calls: 0,
+      usec: 0,
+      rejected_calls: 0,
+      failed_calls: 0,
+      latency: LatencyHistogram::new(),
+    }
+  }
+}
+
+pub struct CommandStats {
+  commands: Mutex<BTreeMap<String, CommandStat>>,
+}
+
+impl CommandStats {
+  pub const fn new() -> Self {
+    Self {
+      commands: Mutex::new(BTreeMap::new()),
+    }
+  }
+
+  /// Accounts a command that ran for `elapsed`
+  pub fn record(&self, name: &str, elapsed: Duration, failed: bool) {
+    let usec = elapsed.as_secs_f64() * 1_000_000.0;
+    let mut commands = self.commands.lock().unwrap();
+    let stat = commands
+      .entry(name.to_string())
+      .or_insert_with(CommandStat::new);
+    stat.calls += 1;
+    stat.usec += usec as u64;
+    if failed {
+      stat.failed_calls += 1;
+    }
+    stat.latency.record(usec);
+  }
+
+  /// Accounts a command that was refused before it could run
+  pub fn rejected(&self, name: &str) {
+    let mut commands = self.commands.lock().unwrap();
+    commands
+      .entry(name.to_string())
+      .or_insert_with(CommandStat::new)
+      .rejected_calls += 1;
+  }
+
+  pub fn reset(&self) {
+    self.commands.lock().unwrap().clear();
+  }
+
+  /// Calls and total microseconds of every command that ran
+  pub fn totals(&self) -> Vec<(String, u64, u64)> {
+    let commands = self.commands.lock().unwrap();
+    commands
+      .iter()
+      .map(|(name, stat)| (name.clone(), stat.calls, stat.usec))
+      .collect()
+  }
+
+  /// Lines of the `# Commandstats` INFO section
+  pub fn info(&self) -> Vec<String> {
+    let commands = self.commands.lock().unwrap();
+    commands
+      .iter()
+      .map(|(name, stat)| {
+        let per_call = if stat.calls == 0 {
+          0.0
+        } else {
+          stat.usec as f64 / stat.calls as f64
+        };
+        format!(
+          "cmdstat_{}:calls={},usec={},usec_per_call={:.2},rejected_calls={},failed_calls={}",
+          name, stat.calls, stat.usec, per_call, stat.rejected_calls, stat.failed_calls
+        )
+      })
+      .collect()
+  }
+
+  /// Lines of the `# Latencystats` INFO section
+  pub fn latency_info(&self, percentiles: &[f64]) -> Vec<String> {
+    let commands = self.commands.lock().unwrap();
+    commands
+      .iter()
+      .filter(|(_, stat)| stat.latency.count > 0)
+      .map(|(name, stat)| {
+        let summary: Vec<String> = percentiles
+          .iter()
+          .map(|percentile| {
+            format!(
+              "p{}={:.3}",
+              percentile,
+              stat.latency.percentile(*percentile)
+            )
+          })
+          .collect();
+        format!("latency_percentiles_usec_{}:{}", name, summary.join(","))
+      })
+      .collect()
+  }
+}
+
+impl Default for CommandStats {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+/// Percentiles listed by `latency-tracking-info-percentiles`, e.g. "50 99 99.9"
+pub fn info_percentiles(config: &Config) -> Vec<f64> {
+  config
+    .get("latency-tracking-info-percentiles")
+    .map(|percentiles| {
+      percentiles
+        .split_whitespace()
+        .filter_map(|percentile| percentile.parse::<f64>().ok())
+        .filter(|percentile| (0.0..=100.0).contains(percentile))
+        .collect()
+    })
+    .unwrap_or_else(|| DEFAULT_PERCENTILES.to_vec())
+}
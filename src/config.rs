@@ -1,7 +1,18 @@
+use crate::address;
+use crate::csv;
+use crate::encryption;
+use crate::eviction;
+use crate::glob::glob_match_nocase;
+use crate::output::{OutputLimits, DEFAULT_OUTPUT_BUFFER_LIMITS};
+use crate::syslog;
 use dashmap::DashMap;
+use tracing::level_filters::LevelFilter;
 
+/// Configuration directives. A directive holds one or more values, like
+/// `save 900 1 300 10`; `get` sees them joined by spaces.
+#[derive(Clone)]
 pub struct Config {
-  config: DashMap<String, String>,
+  config: DashMap<String, Vec<String>>,
 }
 
 impl Config {
@@ -12,10 +23,27 @@ impl Config {
   }
 
   pub fn set(&self, key: String, value: String) {
-    self.config.insert(key, value);
+    self.config.insert(key, vec![value]);
+  }
+
+  /// Sets a directive taking several values
+  pub fn set_values(&self, key: String, values: Vec<String>) {
+    self.config.insert(key, values);
   }
 
   pub fn get(&self, key: &str) -> Option<String> {
+    self.config.get(key).map(|v| v.value().join(" "))
+  }
+
+  /// Value of a parameter, its default when it isn't set
+  pub fn get_or_default(&self, key: &str) -> Option<String> {
+    self
+      .get(key)
+      .or_else(|| find_parameter(key).map(|parameter| parameter.default.to_string()))
+  }
+
+  /// Values of a directive, one per argument it was given
+  pub fn get_values(&self, key: &str) -> Option<Vec<String>> {
     self.config.get(key).map(|v| v.value().clone())
   }
 
@@ -23,11 +51,479 @@ impl Config {
     self
       .config
       .iter()
-      .map(|v| (v.key().clone(), v.value().clone()))
+      .map(|v| (v.key().clone(), v.value().join(" ")))
       .collect()
   }
 
+  /// CONFIG GET: every known parameter matching one of the glob `patterns`,
+  /// as name and value pairs. Unset parameters report their default, secrets
+  /// that are set report `REDACTED`.
+  pub fn parameters(&self, patterns: &[String]) -> Vec<String> {
+    let mut reply = Vec::new();
+    for parameter in PARAMETERS {
+      if patterns
+        .iter()
+        .any(|pattern| glob_match_nocase(pattern, parameter.name))
+      {
+        let value = self
+          .get(parameter.name)
+          .unwrap_or_else(|| parameter.default.to_string());
+        reply.push(parameter.name.to_string());
+        reply.push(match is_secret(parameter.name) && !value.is_empty() {
+          true => REDACTED.to_string(),
+          false => value,
+        });
+      }
+    }
+    reply
+  }
+
+  /// Unsets a directive
+  pub fn remove(&self, key: &str) {
+    self.config.remove(key);
+  }
+
   pub fn has(&self, key: &str) -> bool {
     self.config.contains_key(key)
   }
 }
+
+/// Shown by CONFIG GET instead of the value of a secret
+const REDACTED: &str = "(redacted)";
+
+/// Parameters CONFIG GET doesn't reveal: the encryption key, the program
+/// printing it, and the token of the HTTP admin API
+const SECRET_PARAMETERS: &[&str] = &[
+  "encryption-key",
+  "encryption-key-command",
+  "http-admin-token",
+];
+
+/// Whether the value of a parameter is kept from CONFIG GET
+pub fn is_secret(name: &str) -> bool {
+  SECRET_PARAMETERS
+    .iter()
+    .any(|secret| secret.eq_ignore_ascii_case(name))
+}
+
+/// Parses a memory amount the way Redis config does: `100`, `1k` (1000),
+/// `1kb` (1024), `5mb`, `2gb`...
+pub fn parse_memory(value: &str) -> Option<u64> {
+  let value = value.trim().to_lowercase();
+  let units: [(&str, u64); 6] = [
+    ("kb", 1024),
+    ("mb", 1024 * 1024),
+    ("gb", 1024 * 1024 * 1024),
+    ("k", 1000),
+    ("m", 1000 * 1000),
+    ("g", 1000 * 1000 * 1000),
+  ];
+  let (number, multiplier) = units
+    .iter()
+    .find_map(|(suffix, multiplier)| {
+      value
+        .strip_suffix(suffix)
+        .map(|number| (number, *multiplier))
+    })
+    .unwrap_or((value.as_str(), 1));
+  number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+/// Parses `loglevel` into the matching `tracing` filter: `debug`, `verbose`,
+/// `notice`, `warning` or `nothing`
+pub fn parse_log_level(value: &str) -> Option<LevelFilter> {
+  match value.to_lowercase().as_str() {
+    "debug" => Some(LevelFilter::TRACE),
+    "verbose" => Some(LevelFilter::DEBUG),
+    "notice" => Some(LevelFilter::INFO),
+    "warning" => Some(LevelFilter::WARN),
+    "nothing" => Some(LevelFilter::OFF),
+    _ => None,
+  }
+}
+
+/// Values a configuration parameter accepts
+pub enum ParameterType {
+  /// `yes` or `no`
+  Bool,
+  Integer {
+    min: i64,
+    max: i64,
+  },
+  /// A byte amount like `100mb`
+  Memory,
+  /// One of a fixed set of words
+  Enum(&'static [&'static str]),
+  /// Checked by a parameter specific function
+  Custom(fn(&str) -> Result<(), String>),
+  String,
+}
+
+impl ParameterType {
+  /// Checks a value, returning the reason it's refused
+  pub fn validate(&self, value: &str) -> Result<(), String> {
+    match self {
+      ParameterType::Bool => match value.to_lowercase().as_str() {
+        "yes" | "no" => Ok(()),
+        _ => Err("argument must be 'yes' or 'no'".to_string()),
+      },
+      ParameterType::Integer { min, max } => match value.parse::<i64>() {
+        Ok(number) if (*min..=*max).contains(&number) => Ok(()),
+        Ok(_) => Err(format!(
+          "argument must be between {} and {} inclusive",
+          min, max
+        )),
+        Err(_) => Err("argument couldn't be parsed into an integer".to_string()),
+      },
+      ParameterType::Memory => match parse_memory(value) {
+        Some(_) => Ok(()),
+        None => Err("argument must be a memory value".to_string()),
+      },
+      ParameterType::Enum(values) => {
+        if values.iter().any(|v| v.eq_ignore_ascii_case(value)) {
+          Ok(())
+        } else {
+          Err(format!(
+            "argument(s) must be one of the following: {}",
+            values.join(", ")
+          ))
+        }
+      }
+      ParameterType::Custom(validate) => validate(value),
+      ParameterType::String => Ok(()),
+    }
+  }
+}
+
+/// A configuration parameter known to CONFIG GET and CONFIG SET
+pub struct Parameter {
+  pub name: &'static str,
+  pub kind: ParameterType,
+  /// Value in effect when the parameter isn't set
+  pub default: &'static str,
+  /// Whether CONFIG SET may change it while the server runs
+  pub mutable: bool,
+}
+
+const fn parameter(name: &'static str, kind: ParameterType, default: &'static str) -> Parameter {
+  Parameter {
+    name,
+    kind,
+    default,
+    mutable: true,
+  }
+}
+
+const fn immutable(name: &'static str, kind: ParameterType, default: &'static str) -> Parameter {
+  Parameter {
+    name,
+    kind,
+    default,
+    mutable: false,
+  }
+}
+
+const YES_NO: ParameterType = ParameterType::Bool;
+const SECONDS: ParameterType = ParameterType::Integer {
+  min: 0,
+  max: i32::MAX as i64,
+};
+
+/// `save`: pairs of `<seconds> <changes>`, or nothing to disable snapshots
+fn validate_save(value: &str) -> Result<(), String> {
+  let numbers: Vec<&str> = value.split_whitespace().collect();
+  if !numbers.len().is_multiple_of(2) || numbers.iter().any(|n| n.parse::<u64>().is_err()) {
+    return Err("Invalid save parameters".to_string());
+  }
+  Ok(())
+}
+
+fn validate_percentiles(value: &str) -> Result<(), String> {
+  let valid = value
+    .split_whitespace()
+    .all(|p| p.parse::<f64>().is_ok_and(|p| (0.0..=100.0).contains(&p)));
+  if valid {
+    Ok(())
+  } else {
+    Err("latency-tracking-info-percentiles should be between 0 and 100".to_string())
+  }
+}
+
+fn validate_client_memory(value: &str) -> Result<(), String> {
+  let valid = match value.strip_suffix('%') {
+    Some(percentage) => percentage.parse::<u64>().is_ok(),
+    None => parse_memory(value).is_some(),
+  };
+  if valid {
+    Ok(())
+  } else {
+    Err("argument must be a memory or percent value".to_string())
+  }
+}
+
+fn validate_rate(value: &str) -> Result<(), String> {
+  match value.parse::<f64>() {
+    Ok(rate) if rate.is_finite() && rate >= 0.0 => Ok(()),
+    _ => Err("argument must be a positive number".to_string()),
+  }
+}
+
+fn validate_dir(value: &str) -> Result<(), String> {
+  if std::path::Path::new(value).is_dir() {
+    Ok(())
+  } else {
+    Err(format!("No such directory: {}", value))
+  }
+}
+
+fn validate_dbfilename(value: &str) -> Result<(), String> {
+  if value.is_empty() || value.contains('/') {
+    Err("dbfilename can't be a path, just a filename".to_string())
+  } else {
+    Ok(())
+  }
+}
+
+/// Every parameter CONFIG GET and CONFIG SET know, sorted by name
+pub const PARAMETERS: &[Parameter] = &[
+  parameter("acllog-max-len", SECONDS, "128"),
+  immutable("aclfile", ParameterType::String, ""),
+  // There is no append only file, only RDB snapshots
+  immutable("appendonly", YES_NO, "no"),
+  parameter("audit-log", ParameterType::String, ""),
+  parameter(
+    "audit-log-max-files",
+    ParameterType::Integer { min: 0, max: 1000 },
+    "10",
+  ),
+  parameter("audit-log-max-size", ParameterType::Memory, "100mb"),
+  parameter("audit-log-redact-values", YES_NO, "no"),
+  immutable("bind", ParameterType::String, "127.0.0.1"),
+  parameter(
+    "client-output-buffer-limit",
+    ParameterType::Custom(|value| OutputLimits::default().parse(value).map(|_| ())),
+    DEFAULT_OUTPUT_BUFFER_LIMITS,
+  ),
+  immutable("cluster-config-file", ParameterType::String, "nodes.conf"),
+  immutable("cluster-enabled", YES_NO, "no"),
+  immutable("daemonize", YES_NO, "no"),
+  parameter(
+    "dbfilename",
+    ParameterType::Custom(validate_dbfilename),
+    "dump.rdb",
+  ),
+  parameter("dir", ParameterType::Custom(validate_dir), "."),
+  parameter(
+    "encryption-key",
+    ParameterType::Custom(encryption::validate_key),
+    "",
+  ),
+  // Runs a program, so it can't be changed by clients
+  immutable("encryption-key-command", ParameterType::String, ""),
+  parameter(
+    "hotkeys-capacity",
+    ParameterType::Integer { min: 0, max: 1024 },
+    "64",
+  ),
+  immutable("http-admin-bind", ParameterType::String, "127.0.0.1"),
+  immutable(
+    "http-admin-port",
+    ParameterType::Integer {
+      min: 0,
+      max: u16::MAX as i64,
+    },
+    "0",
+  ),
+  parameter("http-admin-token", ParameterType::String, ""),
+  immutable("import-csv", ParameterType::String, ""),
+  immutable(
+    "import-csv-columns",
+    ParameterType::Custom(csv::validate_columns),
+    "key value",
+  ),
+  immutable("import-json", ParameterType::String, ""),
+  immutable("import-protocol", ParameterType::String, ""),
+  immutable(
+    "io-threads",
+    ParameterType::Integer { min: 1, max: 128 },
+    "1",
+  ),
+  parameter(
+    "latency-tracking-info-percentiles",
+    ParameterType::Custom(validate_percentiles),
+    "50 99 99.9",
+  ),
+  parameter("lazyfree-lazy-eviction", YES_NO, "no"),
+  parameter("lazyfree-lazy-expire", YES_NO, "no"),
+  parameter("lazyfree-lazy-server-del", YES_NO, "no"),
+  parameter("lazyfree-lazy-user-del", YES_NO, "no"),
+  parameter("lazyfree-lazy-user-flush", YES_NO, "no"),
+  immutable("logfile", ParameterType::String, ""),
+  parameter(
+    "loglevel",
+    ParameterType::Enum(&["debug", "verbose", "notice", "warning", "nothing"]),
+    "notice",
+  ),
+  parameter(
+    "maxclients",
+    ParameterType::Integer {
+      min: 1,
+      max: u32::MAX as i64,
+    },
+    "10000",
+  ),
+  parameter("maxmemory", ParameterType::Memory, "0"),
+  parameter(
+    "maxmemory-clients",
+    ParameterType::Custom(validate_client_memory),
+    "0",
+  ),
+  parameter(
+    "maxmemory-policy",
+    ParameterType::Enum(&eviction::POLICIES),
+    "noeviction",
+  ),
+  parameter(
+    "maxmemory-samples",
+    ParameterType::Integer { min: 1, max: 64 },
+    "5",
+  ),
+  parameter("negative-lookup-cache", YES_NO, "no"),
+  parameter(
+    "offload-min-keys",
+    ParameterType::Integer {
+      min: 0,
+      max: i32::MAX as i64,
+    },
+    "10000",
+  ),
+  immutable("otlp-endpoint", ParameterType::String, ""),
+  immutable(
+    "port",
+    ParameterType::Integer {
+      min: 0,
+      max: u16::MAX as i64,
+    },
+    "6379",
+  ),
+  immutable("pidfile", ParameterType::String, ""),
+  parameter("protected-mode", YES_NO, "yes"),
+  immutable("proxy-protocol", YES_NO, "no"),
+  parameter("ratelimit", ParameterType::Custom(validate_rate), "0"),
+  parameter("ratelimit-burst", ParameterType::Custom(validate_rate), "0"),
+  parameter(
+    "ratelimit-policy",
+    ParameterType::Enum(&["delay", "reject"]),
+    "delay",
+  ),
+  parameter(
+    "ratelimit-scope",
+    ParameterType::Enum(&["client", "user"]),
+    "client",
+  ),
+  parameter("rdbchecksum", YES_NO, "yes"),
+  parameter("rdbcompression", YES_NO, "yes"),
+  // Runs a program, so it can't be changed by clients
+  immutable("read-through-command", ParameterType::String, ""),
+  parameter(
+    "read-through-timeout",
+    ParameterType::Integer {
+      min: 1,
+      max: i32::MAX as i64,
+    },
+    "5000",
+  ),
+  parameter("read-through-ttl", SECONDS, "300"),
+  parameter("record-file", ParameterType::String, ""),
+  immutable("replay-file", ParameterType::String, ""),
+  immutable("replay-timing", YES_NO, "no"),
+  immutable(
+    "replicaof",
+    ParameterType::Custom(|value| address::parse_host_port(value).map(|_| ())),
+    "",
+  ),
+  parameter(
+    "sanitize-dump-payload",
+    ParameterType::Enum(&["no", "yes", "clients"]),
+    "no",
+  ),
+  parameter("save", ParameterType::Custom(validate_save), ""),
+  parameter(
+    "slowlog-log-slower-than",
+    ParameterType::Integer {
+      min: -1,
+      max: i64::MAX,
+    },
+    "10000",
+  ),
+  parameter(
+    "slowlog-max-len",
+    ParameterType::Integer {
+      min: 0,
+      max: i64::MAX,
+    },
+    "128",
+  ),
+  parameter(
+    "statsd-address",
+    ParameterType::Custom(|value| match value.is_empty() {
+      true => Ok(()),
+      false => address::parse_host_port(value).map(|_| ()),
+    }),
+    "",
+  ),
+  parameter("statsd-datadog-tags", YES_NO, "no"),
+  parameter(
+    "statsd-flush-interval",
+    ParameterType::Integer { min: 1, max: 3600 },
+    "10",
+  ),
+  parameter("statsd-prefix", ParameterType::String, "redis"),
+  immutable("syslog-enabled", YES_NO, "no"),
+  immutable(
+    "syslog-facility",
+    ParameterType::Enum(&syslog::FACILITIES),
+    "local0",
+  ),
+  immutable("syslog-ident", ParameterType::String, syslog::DEFAULT_IDENT),
+  immutable(
+    "tcp-backlog",
+    ParameterType::Integer {
+      min: 0,
+      max: i32::MAX as i64,
+    },
+    "511",
+  ),
+  parameter("tcp-keepalive", SECONDS, "300"),
+  parameter("tcp-nodelay", YES_NO, "yes"),
+  parameter("timeout", SECONDS, "0"),
+  immutable(
+    "tls-auth-clients",
+    ParameterType::Enum(&["yes", "no", "optional"]),
+    "yes",
+  ),
+  parameter(
+    "tls-auth-clients-user",
+    ParameterType::Enum(&["off", "CN", "SAN"]),
+    "off",
+  ),
+  immutable("tls-ca-cert-file", ParameterType::String, ""),
+  immutable("tls-cert-file", ParameterType::String, ""),
+  immutable("tls-key-file", ParameterType::String, ""),
+  immutable(
+    "tls-port",
+    ParameterType::Integer {
+      min: 0,
+      max: u16::MAX as i64,
+    },
+    "0",
+  ),
+  immutable("tls-proxy-protocol", YES_NO, "no"),
+];
+
+/// Looks a parameter up by name, case insensitively
+pub fn find_parameter(name: &str) -> Option<&'static Parameter> {
+  PARAMETERS
+    .iter()
+    .find(|parameter| parameter.name.eq_ignore_ascii_case(name))
+}
@@ -0,0 +1,289 @@
+/**
+ * The configuration file, in redis.conf format, named by the first command
+ * line argument: `redis-server /etc/redis/redis.conf --port 7000`.
+ *
+ * Each line holds a directive and its arguments, split like Redis does: on
+ * spaces, with "double quoted" (escapes allowed) and 'single quoted' strings.
+ * `save` and `client-output-buffer-limit` lines add up, for any other directive
+ * the last line wins.
+ *
+ * `REDIS_<PARAMETER>` environment variables (REDIS_MAXMEMORY,
+ * REDIS_MAXMEMORY_CLIENTS for maxmemory-clients...) override the file, and
+ * directives given on the command line override both, so containers can be
+ * configured without templating a file. Their values are split like file lines.
+ *
+ * On SIGHUP the file is read again and every mutable parameter whose value
+ * changed goes through CONFIG SET, so it is validated and applied the same way.
+ * Parameters gone from the file return to their default, changes to immutable
+ * ones are only reported since they need a restart.
+ */
+use crate::acl::Acl;
+use crate::arguments::CLIArguments;
+use crate::clients::ClientRegistry;
+use crate::config::{find_parameter, Config, PARAMETERS};
+use crate::configset::config_set;
+use crate::parser::RedisValue;
+use crate::ratelimit::RateLimiter;
+use crate::state::ServerState;
+use crate::storagelock::StorageLock;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex as AsyncMutex;
+use tracing::{error, info, warn};
+
+/// Prefix of the environment variables overriding parameters
+const ENV_PREFIX: &str = "REDIS_";
+
+/// Directives whose lines accumulate instead of replacing each other
+const ACCUMULATING_DIRECTIVES: [&str; 2] = ["save", "client-output-buffer-limit"];
+
+/// Splits a line into arguments like Redis's `sdssplitargs`
+pub fn split_arguments(line: &str) -> Result<Vec<String>, String> {
+  let mut arguments = Vec::new();
+  let mut chars = line.chars().peekable();
+  loop {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    let Some(first) = chars.next() else {
+      return Ok(arguments);
+    };
+
+    let mut argument = String::new();
+    match first {
+      '"' => loop {
+        match chars.next() {
+          Some('"') => break,
+          Some('\\') => match chars.next() {
+            Some('n') => argument.push('\n'),
+            Some('r') => argument.push('\r'),
+            Some('t') => argument.push('\t'),
+            Some('x') => {
+              let hex: String = chars.by_ref().take(2).collect();
+              let byte = u8::from_str_radix(&hex, 16).map_err(|_| "Invalid escape sequence")?;
+              argument.push(byte as char);
+            }
+            Some(c) => argument.push(c),
+            None => return Err("Unbalanced quotes in configuration line".to_string()),
+          },
+          Some(c) => argument.push(c),
+          None => return Err("Unbalanced quotes in configuration line".to_string()),
+        }
+      },
+      '\'' => loop {
+        match chars.next() {
+          Some('\'') => break,
+          Some('\\') if chars.peek() == Some(&'\'') => argument.push(chars.next().unwrap()),
+          Some(c) => argument.push(c),
+          None => return Err("Unbalanced quotes in configuration line".to_string()),
+        }
+      },
+      c => {
+        argument.push(c);
+        while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+          argument.push(c);
+        }
+      }
+    }
+    // A closing quote must be followed by a space or the end of the line
+    if matches!(first, '"' | '\'') && chars.peek().is_some_and(|c| !c.is_whitespace()) {
+      return Err("Closing quote must be followed by a space".to_string());
+    }
+    arguments.push(argument);
+  }
+}
+
+/// Reads and validates the directives of a configuration file
+pub fn read_config_file(path: &str) -> Result<CLIArguments, String> {
+  let content = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+  let mut directives: CLIArguments = Vec::new();
+
+  for (number, line) in content.lines().enumerate() {
+    let error = |message: &str| format!("{}:{}: {}", path, number + 1, message);
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+      continue;
+    }
+
+    let arguments = split_arguments(line).map_err(|e| error(&e))?;
+    let Some((name, values)) = arguments.split_first() else {
+      continue;
+    };
+    let name = name.to_lowercase();
+    let parameter = find_parameter(&name)
+      .filter(|_| !values.is_empty())
+      .ok_or_else(|| error("Bad directive or wrong number of arguments"))?;
+    parameter
+      .kind
+      .validate(&values.join(" "))
+      .map_err(|e| error(&e))?;
+
+    match directives
+      .iter_mut()
+      .find(|(directive, _)| *directive == name)
+    {
+      Some((_, existing)) if ACCUMULATING_DIRECTIVES.contains(&name.as_str()) => {
+        existing.extend(values.iter().cloned())
+      }
+      Some((_, existing)) => *existing = values.to_vec(),
+      None => directives.push((name, values.to_vec())),
+    }
+  }
+  Ok(directives)
+}
+
+/// Name of the environment variable overriding `parameter`
+fn env_variable(parameter: &str) -> String {
+  format!(
+    "{}{}",
+    ENV_PREFIX,
+    parameter.to_uppercase().replace('-', "_")
+  )
+}
+
+/// Reads and validates the directives set through environment variables
+pub fn read_environment() -> Result<CLIArguments, String> {
+  let mut directives: CLIArguments = Vec::new();
+  for parameter in PARAMETERS {
+    let variable = env_variable(parameter.name);
+    let value = match std::env::var(&variable) {
+      Ok(value) => value,
+      Err(std::env::VarError::NotPresent) => continue,
+      Err(e) => return Err(format!("{}: {}", variable, e)),
+    };
+    let error = |message: &str| format!("{}: {}", variable, message);
+    let mut values = split_arguments(&value).map_err(|e| error(&e))?;
+    // An empty value is meaningful, e.g. REDIS_SAVE="" disables snapshots
+    if values.is_empty() {
+      values.push(String::new());
+    }
+    parameter
+      .kind
+      .validate(&values.join(" "))
+      .map_err(|e| error(&e))?;
+    directives.push((parameter.name.to_string(), values));
+  }
+  Ok(directives)
+}
+
+/// The directives of the configuration file named on the command line, if
+/// any, then the environment ones and the command line ones so that they take
+/// precedence in that order
+pub fn with_config_file(cli_arguments: &CLIArguments) -> Result<CLIArguments, String> {
+  let config_file = cli_arguments
+    .iter()
+    .find(|(argument, _)| argument == "config-file")
+    .map(|(_, values)| values.join(" "));
+  let mut arguments = match config_file {
+    Some(path) => read_config_file(&path)?,
+    None => Vec::new(),
+  };
+  arguments.extend(read_environment()?);
+  arguments.extend(cli_arguments.iter().cloned());
+  Ok(arguments)
+}
+
+/// Re-reads the configuration file and applies what changed
+pub async fn reload(
+  cli_arguments: &CLIArguments,
+  storage: &Arc<StorageLock>,
+  config: &Arc<AsyncMutex<Config>>,
+  clients: &ClientRegistry,
+  acl: &Acl,
+  rate_limiter: &RateLimiter,
+  state: &ServerState,
+) {
+  let arguments = match with_config_file(cli_arguments) {
+    Ok(arguments) => arguments,
+    Err(e) => {
+      error!(
+        "Config reload failed, keeping the current configuration: {}",
+        e
+      );
+      return;
+    }
+  };
+  // Later directives win, like at startup
+  let configured: HashMap<String, String> = arguments
+    .into_iter()
+    .map(|(name, values)| (name, values.join(" ")))
+    .collect();
+
+  let mut changes = Vec::new();
+  {
+    let config = config.lock().await;
+    for parameter in PARAMETERS {
+      let current = config
+        .get(parameter.name)
+        .unwrap_or_else(|| parameter.default.to_string());
+      let wanted = match configured.get(parameter.name) {
+        Some(value) => value.clone(),
+        None if parameter.mutable => parameter.default.to_string(),
+        None => continue,
+      };
+      if wanted == current {
+        continue;
+      }
+      if !parameter.mutable {
+        warn!(
+          "Config reload: {} changed from '{}' to '{}', restart to apply it",
+          parameter.name, current, wanted
+        );
+        continue;
+      }
+      info!(
+        "Config reload: {} changed from '{}' to '{}'",
+        parameter.name, current, wanted
+      );
+      changes.push(parameter.name.to_string());
+      changes.push(wanted);
+    }
+  }
+
+  if changes.is_empty() {
+    info!("Config reload: nothing changed");
+    return;
+  }
+  match config_set(&changes, storage, config, clients, acl, rate_limiter, state).await {
+    RedisValue::Error(e) => error!("Config reload failed, nothing was changed: {}", e),
+    _ => info!("Config reload: {} parameter(s) applied", changes.len() / 2),
+  }
+}
+
+/// Reloads the configuration file whenever SIGHUP is received
+pub fn spawn_reload_on_sighup(
+  cli_arguments: CLIArguments,
+  storage: Arc<StorageLock>,
+  config: Arc<AsyncMutex<Config>>,
+  clients: Arc<ClientRegistry>,
+  acl: Arc<Acl>,
+  rate_limiter: Arc<RateLimiter>,
+  state: Arc<ServerState>,
+) {
+  #[cfg(unix)]
+  tokio::spawn(async move {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut hangup = match signal(SignalKind::hangup()) {
+      Ok(hangup) => hangup,
+      Err(e) => {
+        error!("Failed to install the SIGHUP handler: {}", e);
+        return;
+      }
+    };
+    while hangup.recv().await.is_some() {
+      if !config.lock().await.has("config-file") {
+        warn!("Received SIGHUP but no configuration file was given, nothing to reload");
+        continue;
+      }
+      info!("Received SIGHUP, reloading the configuration file");
+      reload(
+        &cli_arguments,
+        &storage,
+        &config,
+        &clients,
+        &acl,
+        &rate_limiter,
+        &state,
+      )
+      .await;
+    }
+  });
+}
@@ -0,0 +1,175 @@
+/**
+ * CONFIG SET parameter value [parameter value ...]
+ *
+ * Every pair is validated against the parameter's type before anything
+ * changes. Then the values are stored and applied: the subsystem owning the
+ * parameter is reconfigured right away (client eviction re-runs for a lower
+ * `maxmemory-clients`, keys are evicted for a lower `maxmemory`, the log level
+ * changes, the rate limiter starts over...).
+ * Parameters read whenever they're needed (`timeout`, `maxclients`...) only
+ * have to be stored. When applying fails the previous values are restored, so
+ * the command changes everything or nothing, like in Redis.
+ *
+ * All that happens under the configuration lock alone. The parameters of the
+ * keyspace (`hotkeys-capacity`, `lazyfree-*`...) can't fail to apply, they
+ * are applied afterwards from a copy of the configuration, so that the
+ * keyspace is never locked while the configuration is: SHUTDOWN locks them
+ * the other way around.
+ */
+use crate::acl::Acl;
+use crate::clients::ClientRegistry;
+use crate::config::{find_parameter, parse_log_level, Config};
+use crate::logging;
+use crate::parser::RedisValue;
+use crate::ratelimit::RateLimiter;
+use crate::state::ServerState;
+use crate::storage::Storage;
+use crate::storagelock::StorageLock;
+use std::sync::Arc;
+use tokio::sync::Mutex as AsyncMutex;
+
+fn failed(parameter: &str, reason: &str) -> RedisValue {
+  RedisValue::Error(format!(
+    "ERR CONFIG SET failed (possibly related to argument '{}') - {}",
+    parameter, reason
+  ))
+}
+
+/// Makes a stored parameter take effect, unless it belongs to the keyspace
+fn apply(
+  parameter: &str,
+  config: &Config,
+  clients: &ClientRegistry,
+  acl: &Acl,
+  rate_limiter: &RateLimiter,
+  state: &ServerState,
+) -> Result<(), String> {
+  let value = config.get(parameter).unwrap_or_default();
+  match parameter {
+    "acllog-max-len" => {
+      let max_len = value
+        .parse::<usize>()
+        .map_err(|_| "argument couldn't be parsed into an integer".to_string())?;
+      acl.log.set_max_len(max_len);
+    }
+    parameter if parameter.starts_with("audit-log") => state.audit_log.apply_config(config)?,
+    "record-file" => state.recorder.apply_config(config)?,
+    "client-output-buffer-limit" => {
+      clients.set_output_limits(clients.output_limits().parse(&value)?)
+    }
+    "maxmemory" | "maxmemory-clients" => clients.apply_max_memory(config)?,
+    "loglevel" => logging::set_level(parse_log_level(&value).ok_or("Invalid log level")?),
+    "ratelimit" | "ratelimit-burst" | "ratelimit-policy" | "ratelimit-scope" => {
+      rate_limiter.reconfigure(config)?
+    }
+    "slowlog-log-slower-than" | "slowlog-max-len" => state.slowlog.apply_config(config),
+    _ => {}
+  }
+  Ok(())
+}
+
+/// Whether `parameter` belongs to the keyspace, see `apply_to_storage`
+fn storage_parameter(parameter: &str) -> bool {
+  matches!(
+    parameter,
+    "hotkeys-capacity"
+      | "negative-lookup-cache"
+      | "maxmemory"
+      | "maxmemory-policy"
+      | "maxmemory-samples"
+  ) || parameter.starts_with("lazyfree-")
+}
+
+/// Makes a stored parameter of the keyspace take effect
+fn apply_to_storage(parameter: &str, storage: &Storage, config: &Config) {
+  match parameter {
+    "hotkeys-capacity" => storage.hotkeys().apply_config(config),
+    "negative-lookup-cache" => storage.apply_negative_cache_config(config),
+    parameter if parameter.starts_with("lazyfree-") => {
+      storage.lazyfree().options.apply_config(config)
+    }
+    // A lower limit evicts right away rather than at the next write
+    "maxmemory" | "maxmemory-policy" | "maxmemory-samples" => {
+      storage.eviction().apply_config(config);
+      let _ = storage.perform_evictions();
+    }
+    _ => {}
+  }
+}
+
+pub async fn config_set(
+  arguments: &[String],
+  storage: &Arc<StorageLock>,
+  config: &Arc<AsyncMutex<Config>>,
+  clients: &ClientRegistry,
+  acl: &Acl,
+  rate_limiter: &RateLimiter,
+  state: &ServerState,
+) -> RedisValue {
+  if arguments.is_empty() || !arguments.len().is_multiple_of(2) {
+    return RedisValue::Error("ERR wrong number of arguments for 'config|set' command".to_string());
+  }
+
+  let mut changes: Vec<(&'static str, &str)> = Vec::new();
+  for pair in arguments.chunks(2) {
+    let (name, value) = (&pair[0], pair[1].as_str());
+    let Some(parameter) = find_parameter(name) else {
+      return RedisValue::Error(format!(
+        "ERR Unknown option or number of arguments for CONFIG SET - '{}'",
+        name
+      ));
+    };
+    if !parameter.mutable {
+      return failed(name, "can't set immutable config");
+    }
+    if changes
+      .iter()
+      .any(|(changed, _)| *changed == parameter.name)
+    {
+      return failed(name, "duplicate parameter");
+    }
+    if let Err(reason) = parameter.kind.validate(value) {
+      return failed(name, &reason);
+    }
+    changes.push((parameter.name, value));
+  }
+
+  let config = config.lock().await;
+  let previous: Vec<(&str, Option<String>)> = changes
+    .iter()
+    .map(|(name, _)| (*name, config.get(name)))
+    .collect();
+  for (name, value) in &changes {
+    config.set(name.to_string(), value.to_string());
+  }
+
+  for (name, _) in &changes {
+    if let Err(reason) = apply(name, &config, clients, acl, rate_limiter, state) {
+      for (name, value) in &previous {
+        match value {
+          Some(value) => config.set(name.to_string(), value.clone()),
+          None => config.remove(name),
+        }
+      }
+      for (name, _) in &previous {
+        let _ = apply(name, &config, clients, acl, rate_limiter, state);
+      }
+      return failed(name, &reason);
+    }
+  }
+  let storage_changes: Vec<&str> = changes
+    .iter()
+    .map(|(name, _)| *name)
+    .filter(|name| storage_parameter(name))
+    .collect();
+  if !storage_changes.is_empty() {
+    let snapshot = config.clone();
+    drop(config);
+    let storage = storage.lock().await;
+    for name in storage_changes {
+      apply_to_storage(name, &storage, &snapshot);
+    }
+  }
+  clients.evict_clients();
+  RedisValue::SimpleString("OK".to_string())
+}
@@ -0,0 +1,215 @@
+/**
+ * The socket of a client connection. Every read and reply goes through it, so
+ * that's where network traffic is accounted and where error replies are noticed
+ * for the failed_calls of INFO commandstats. Replies to a command are written
+ * in `reply` spans under the command's span.
+ *
+ * Replies are buffered rather than written one `write` per command: the
+ * server flushes them once it has run every complete command it read, before
+ * waiting for more, so a pipeline costs one write instead of one per command.
+ * A buffer growing past `FLUSH_THRESHOLD` is written out right away, and
+ * replies that large skip it, as do large values of the keyspace: GET writes
+ * them from the stored buffer without copying them first. Reply buffers
+ * outlive their connection in a small pool, so that clients connecting and
+ * leaving don't each grow a new one.
+ *
+ * The socket is either a plain TCP stream or a TLS session over one, the rest
+ * of the server only sees the AsyncRead/AsyncWrite of the `Stream` trait and,
+ * for TLS, the certificate the client was verified with.
+ */
+use crate::clients::QUERY_BUFFER_SIZE;
+use crate::parser::{serialize_resp3, serialize_response, RedisValue};
+use crate::stats::Stats;
+use bytes::BytesMut;
+use std::io;
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::pki_types::CertificateDer;
+use tokio_rustls::TlsAcceptor;
+use tracing::{Instrument, Span};
+
+/// Buffered reply bytes that get written without waiting for the flush
+pub const FLUSH_THRESHOLD: usize = 64 * 1024;
+
+/// Reply buffers kept for the next connections
+const POOLED_BUFFERS: usize = 64;
+
+static REPLY_BUFFERS: Mutex<Vec<Vec<u8>>> = Mutex::new(Vec::new());
+
+fn reply_buffer() -> Vec<u8> {
+  REPLY_BUFFERS.lock().unwrap().pop().unwrap_or_default()
+}
+
+fn recycle_reply_buffer(mut buffer: Vec<u8>) {
+  buffer.clear();
+  let mut pool = REPLY_BUFFERS.lock().unwrap();
+  if pool.len() < POOLED_BUFFERS && buffer.capacity() > 0 {
+    pool.push(buffer);
+  }
+}
+
+/// A byte stream a client talks over
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+pub struct Connection {
+  stream: Box<dyn Stream>,
+  /// Verified certificate of a TLS client
+  peer_certificate: Option<CertificateDer<'static>>,
+  /// Error replies sent since the last `take_error_replies`
+  error_replies: u64,
+  /// Span of the command being replied to
+  command_span: Option<Span>,
+  /// Replies not written yet
+  output: Vec<u8>,
+  /// Counters of the server the traffic is accounted to
+  stats: Option<Arc<Stats>>,
+}
+
+impl Connection {
+  pub fn new(stream: impl Stream + 'static) -> Self {
+    Self {
+      stream: Box::new(stream),
+      peer_certificate: None,
+      error_replies: 0,
+      command_span: None,
+      output: reply_buffer(),
+      stats: None,
+    }
+  }
+
+  /// Wraps an accepted socket, completing the TLS handshake first for TLS
+  /// listeners
+  pub async fn accept(stream: TcpStream, tls: Option<TlsAcceptor>) -> io::Result<Self> {
+    match tls {
+      Some(acceptor) => {
+        let stream = acceptor.accept(stream).await?;
+        let peer_certificate = stream
+          .get_ref()
+          .1
+          .peer_certificates()
+          .and_then(|certificates| certificates.first().cloned());
+        let mut connection = Self::new(stream);
+        connection.peer_certificate = peer_certificate;
+        Ok(connection)
+      }
+      None => Ok(Self::new(stream)),
+    }
+  }
+
+  /// Accounts the traffic to `stats` from now on
+  pub fn set_stats(&mut self, stats: Arc<Stats>) {
+    self.stats = Some(stats);
+  }
+
+  fn count(&self, counter: fn(&Stats) -> &AtomicU64, amount: usize) {
+    if let Some(stats) = &self.stats {
+      Stats::add(counter(stats), amount);
+    }
+  }
+
+  /// Certificate the client presented during the TLS handshake
+  pub fn peer_certificate(&self) -> Option<&CertificateDer<'static>> {
+    self.peer_certificate.as_ref()
+  }
+
+  pub async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let read = self.stream.read(buf).await?;
+    self.count(|stats| &stats.total_net_input_bytes, read);
+    Ok(read)
+  }
+
+  /// Reads what the client sent onto the end of `buf`, which keeps its
+  /// allocation from one read to the next
+  pub async fn read_buf(&mut self, buf: &mut BytesMut) -> io::Result<usize> {
+    buf.reserve(QUERY_BUFFER_SIZE);
+    let read = self.stream.read_buf(buf).await?;
+    self.count(|stats| &stats.total_net_input_bytes, read);
+    Ok(read)
+  }
+
+  /// Queues a serialized reply for the client, writing the queue once it grows
+  /// past `FLUSH_THRESHOLD`
+  pub async fn write_response(&mut self, response: &[u8]) -> io::Result<()> {
+    if response.starts_with(b"-") {
+      self.error_replies += 1;
+      self.count(|stats| &stats.total_error_replies, 1);
+    }
+    let span = self.reply_span(response.len());
+    self.queue(response, &span).await
+  }
+
+  /// Queues a reply in protocol version `resp`, writing a stored value
+  /// straight from the keyspace's buffer rather than serializing a copy of it
+  pub async fn write_value(&mut self, value: RedisValue, resp: u8) -> io::Result<()> {
+    let RedisValue::BulkBytes(bytes) = value else {
+      let response = match resp {
+        3 => serialize_resp3(value),
+        _ => serialize_response(value),
+      };
+      return self.write_response(&response).await;
+    };
+    let header = format!("${}\r\n", bytes.len());
+    let span = self.reply_span(header.len() + bytes.len() + 2);
+    self.queue(header.as_bytes(), &span).await?;
+    self.queue(&bytes, &span).await?;
+    self.queue(b"\r\n", &span).await
+  }
+
+  fn reply_span(&self, bytes: usize) -> Span {
+    match &self.command_span {
+      Some(command) => tracing::info_span!(parent: command, "reply", bytes),
+      None => Span::none(),
+    }
+  }
+
+  /// Appends to the queued replies; a chunk too large to be worth copying is
+  /// written directly after what is queued
+  async fn queue(&mut self, bytes: &[u8], span: &Span) -> io::Result<()> {
+    self.count(|stats| &stats.total_net_output_bytes, bytes.len());
+    if bytes.len() >= FLUSH_THRESHOLD {
+      self.flush().await?;
+      return self.stream.write_all(bytes).instrument(span.clone()).await;
+    }
+    span.in_scope(|| self.output.extend_from_slice(bytes));
+    if self.output.len() >= FLUSH_THRESHOLD {
+      self.flush().await?;
+    }
+    Ok(())
+  }
+
+  /// Writes the queued replies
+  pub async fn flush(&mut self) -> io::Result<()> {
+    if self.output.is_empty() {
+      return Ok(());
+    }
+    let span = tracing::debug_span!("flush", bytes = self.output.len());
+    let result = async {
+      self.stream.write_all(&self.output).await?;
+      self.stream.flush().await
+    }
+    .instrument(span)
+    .await;
+    self.output.clear();
+    result
+  }
+
+  /// Sets the command the next replies answer, `None` between commands
+  pub fn set_command_span(&mut self, span: Option<Span>) {
+    self.command_span = span;
+  }
+
+  /// Number of error replies sent since the last call
+  pub fn take_error_replies(&mut self) -> u64 {
+    std::mem::take(&mut self.error_replies)
+  }
+}
+
+impl Drop for Connection {
+  fn drop(&mut self) {
+    recycle_reply_buffer(std::mem::take(&mut self.output));
+  }
+}
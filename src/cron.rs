@@ -0,0 +1,229 @@
+/**
+ * The periodic jobs of the server, Redis's serverCron: active expiry, the
+ * clients cron, the memory and stats samplers. Subsystems register their jobs
+ * on the server's `Scheduler` instead of spawning their own interval, and a
+ * single task runs them one at a time as they fall due.
+ *
+ * A job starts at a random offset within its interval, so that jobs of the
+ * same period don't all land on the same tick, then runs at a fixed rate.
+ * Ticks missed while other jobs ran are skipped rather than run in a burst. A
+ * run taking longer than the job's budget counts as an overrun and is logged.
+ * `INFO cronstats` reports the runs, time and overruns of every job, which
+ * CONFIG RESETSTAT resets:
+ *
+ * ```text
+ * cronstat_active-expire:runs=1200,usec=3514,usec_per_run=2.93,max_usec=210,overruns=0
+ * ```
+ *
+ * Jobs feeding process wide figures, such as the memory sampler, are
+ * registered with `every_in_process`: each server of the process schedules
+ * them and the first one due runs them, so they run once per interval for as
+ * long as any server does.
+ */
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tracing::warn;
+
+/// Time between two warnings about the overruns of a job
+const OVERRUN_WARNING_INTERVAL: Duration = Duration::from_secs(60);
+
+type JobFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+struct Job {
+  name: &'static str,
+  interval: Duration,
+  budget: Duration,
+  process_wide: bool,
+  run: Box<dyn FnMut() -> JobFuture + Send>,
+  /// When the job runs next
+  next: Instant,
+}
+
+#[derive(Default)]
+pub struct Scheduler {
+  jobs: Vec<Job>,
+}
+
+impl Scheduler {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Runs `job` every `interval`, counting runs longer than `budget` as
+  /// overruns
+  pub fn every<F, Fut>(
+    &mut self,
+    name: &'static str,
+    interval: Duration,
+    budget: Duration,
+    job: F,
+  ) -> &mut Self
+  where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+  {
+    self.register(name, interval, budget, false, job)
+  }
+
+  /// Like `every`, for a job that runs once per interval in the whole process
+  /// however many servers schedule it
+  pub fn every_in_process<F, Fut>(
+    &mut self,
+    name: &'static str,
+    interval: Duration,
+    budget: Duration,
+    job: F,
+  ) -> &mut Self
+  where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+  {
+    self.register(name, interval, budget, true, job)
+  }
+
+  fn register<F, Fut>(
+    &mut self,
+    name: &'static str,
+    interval: Duration,
+    budget: Duration,
+    process_wide: bool,
+    mut job: F,
+  ) -> &mut Self
+  where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+  {
+    assert!(!interval.is_zero(), "job {} has no interval", name);
+    self.jobs.push(Job {
+      name,
+      interval,
+      budget,
+      process_wide,
+      run: Box::new(move || Box::pin(job())),
+      next: Instant::now() + jitter(interval),
+    });
+    self
+  }
+
+  /// Spawns the task running the jobs
+  pub fn spawn(mut self) -> JoinHandle<()> {
+    tokio::spawn(async move {
+      let Some(first) = self.jobs.iter().map(|job| job.next).min() else {
+        return;
+      };
+      let mut next = first;
+      loop {
+        tokio::time::sleep_until(next).await;
+        for job in &mut self.jobs {
+          if job.next <= Instant::now() {
+            job.run().await;
+          }
+        }
+        next = self.jobs.iter().map(|job| job.next).min().unwrap_or(next);
+      }
+    })
+  }
+}
+
+impl Job {
+  async fn run(&mut self) {
+    let now = Instant::now();
+    while self.next <= now {
+      self.next += self.interval;
+    }
+    if self.process_wide && !claim(self.name, self.interval, now) {
+      return;
+    }
+    let started = Instant::now();
+    (self.run)().await;
+    record(self.name, self.budget, started.elapsed());
+  }
+}
+
+/// A random part of `interval`
+fn jitter(interval: Duration) -> Duration {
+  let random = RandomState::new().build_hasher().finish();
+  interval.mul_f64((random % 1000) as f64 / 1000.0)
+}
+
+/// Last run of the process wide jobs
+static PROCESS_WIDE: Mutex<BTreeMap<&'static str, Instant>> = Mutex::new(BTreeMap::new());
+
+/// Whether a process wide job is due, marking it run if so. Another server
+/// running it a bit earlier in the same interval counts.
+fn claim(name: &'static str, interval: Duration, now: Instant) -> bool {
+  let mut last_runs = PROCESS_WIDE.lock().unwrap();
+  let due = last_runs
+    .get(name)
+    .is_none_or(|last| now.saturating_duration_since(*last) >= interval.mul_f64(0.9));
+  if due {
+    last_runs.insert(name, now);
+  }
+  due
+}
+
+#[derive(Default)]
+struct JobStats {
+  runs: u64,
+  usec: u64,
+  max_usec: u64,
+  overruns: u64,
+  warned_at: Option<Instant>,
+}
+
+static JOB_STATS: Mutex<BTreeMap<&'static str, JobStats>> = Mutex::new(BTreeMap::new());
+
+fn record(name: &'static str, budget: Duration, elapsed: Duration) {
+  let mut jobs = JOB_STATS.lock().unwrap();
+  let stats = jobs.entry(name).or_default();
+  let usec = elapsed.as_micros() as u64;
+  stats.runs += 1;
+  stats.usec += usec;
+  stats.max_usec = stats.max_usec.max(usec);
+  if elapsed <= budget {
+    return;
+  }
+  stats.overruns += 1;
+  let now = Instant::now();
+  if stats
+    .warned_at
+    .is_none_or(|warned_at| now.duration_since(warned_at) >= OVERRUN_WARNING_INTERVAL)
+  {
+    stats.warned_at = Some(now);
+    warn!(
+      "Background job {} took {:?}, over its {:?} budget ({} overruns so far)",
+      name, elapsed, budget, stats.overruns
+    );
+  }
+}
+
+/// Lines of the `# Cronstats` INFO section
+pub fn info() -> Vec<String> {
+  let jobs = JOB_STATS.lock().unwrap();
+  jobs
+    .iter()
+    .map(|(name, stats)| {
+      let per_run = if stats.runs == 0 {
+        0.0
+      } else {
+        stats.usec as f64 / stats.runs as f64
+      };
+      format!(
+        "cronstat_{}:runs={},usec={},usec_per_run={:.2},max_usec={},overruns={}",
+        name, stats.runs, stats.usec, per_run, stats.max_usec, stats.overruns
+      )
+    })
+    .collect()
+}
+
+/// CONFIG RESETSTAT
+pub fn reset_stats() {
+  JOB_STATS.lock().unwrap().clear();
+}
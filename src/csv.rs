@@ -0,0 +1,225 @@
+/**
+ * Seeding string keys from a CSV file at startup, as exported by most
+ * databases: `--import-csv <file>` loads one key per row, after the RDB file.
+ *
+ * The first row is the header. `import-csv-columns` names the header columns
+ * holding the key, the value and, optionally, the TTL in seconds, by default
+ * `key value`. Fields follow RFC 4180: separated by commas, quoted with double
+ * quotes when they hold commas, quotes or line breaks, quotes inside quoted
+ * fields doubled. An empty TTL means the key doesn't expire.
+ *
+ * Rows are stored in batches, so the active expire task and the samplers get
+ * the storage lock in between, and progress is logged every few seconds.
+ * Rows that can't be loaded are counted and skipped.
+ */
+use crate::storagelock::StorageLock;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Rows stored per storage lock
+const BATCH_SIZE: usize = 10_000;
+
+/// Time between progress lines
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// What an import loaded
+#[derive(Debug, Default, PartialEq)]
+pub struct CsvSummary {
+  pub keys: u64,
+  pub errors: u64,
+}
+
+/// `import-csv-columns`: the header names of the key, value and TTL columns
+pub fn validate_columns(value: &str) -> Result<(), String> {
+  match value.split_whitespace().count() {
+    2 | 3 => Ok(()),
+    _ => Err("expected <key column> <value column> [<ttl column>]".to_string()),
+  }
+}
+
+/// A row as the arguments of a SET
+struct Entry {
+  key: String,
+  value: String,
+  options: Vec<(String, String)>,
+}
+
+/// Positions of the configured columns in a row
+struct Columns {
+  key: usize,
+  value: usize,
+  ttl: Option<usize>,
+}
+
+impl Columns {
+  fn find(names: &str, header: &[String]) -> Result<Self, String> {
+    let position = |name: &str| {
+      header
+        .iter()
+        .position(|column| column == name)
+        .ok_or_else(|| format!("no column named '{}' in the header", name))
+    };
+    let names: Vec<&str> = names.split_whitespace().collect();
+    validate_columns(&names.join(" "))?;
+    Ok(Self {
+      key: position(names[0])?,
+      value: position(names[1])?,
+      ttl: names.get(2).map(|name| position(name)).transpose()?,
+    })
+  }
+
+  /// The SET arguments of a row
+  fn entry(&self, row: &[String]) -> Result<Entry, String> {
+    let field = |index: usize| {
+      row
+        .get(index)
+        .ok_or_else(|| format!("expected at least {} fields, got {}", index + 1, row.len()))
+    };
+    let key = field(self.key)?;
+    if key.is_empty() {
+      return Err("empty key".to_string());
+    }
+    let value = field(self.value)?;
+    let mut options = Vec::new();
+    if let Some(ttl) = self.ttl {
+      let ttl = field(ttl)?;
+      if !ttl.is_empty() {
+        match ttl.parse::<u64>() {
+          Ok(seconds) if seconds > 0 => options.push(("EX".to_string(), seconds.to_string())),
+          _ => return Err(format!("invalid TTL '{}'", ttl)),
+        }
+      }
+    }
+    Ok(Entry {
+      key: key.clone(),
+      value: value.clone(),
+      options,
+    })
+  }
+}
+
+/// Loads the rows of the CSV file at `path`
+pub async fn import_csv(
+  path: &str,
+  columns: &str,
+  storage: &Arc<StorageLock>,
+) -> Result<CsvSummary, String> {
+  let file = File::open(path).map_err(|e| format!("Can't open the CSV file {}: {}", path, e))?;
+  let mut records = Records::new(BufReader::new(file));
+  let read_error = |e: String| format!("Failed to read {}: {}", path, e);
+  let header = records
+    .next_record()
+    .map_err(read_error)?
+    .ok_or_else(|| format!("{} is empty", path))?;
+  let columns = Columns::find(columns, &header).map_err(|e| format!("{}: {}", path, e))?;
+  info!("Importing {}", path);
+
+  let started = Instant::now();
+  let mut last_progress = started;
+  let mut summary = CsvSummary::default();
+  let mut batch = Vec::with_capacity(BATCH_SIZE);
+  loop {
+    let record = records.next_record().map_err(read_error)?;
+    let done = record.is_none();
+    if let Some(row) = record {
+      match columns.entry(&row) {
+        Ok(entry) => batch.push(entry),
+        Err(e) => {
+          if summary.errors == 0 {
+            warn!(
+              "First error while importing {}: line {}: {}",
+              path, records.line, e
+            );
+          }
+          summary.errors += 1;
+        }
+      }
+    }
+
+    if batch.len() == BATCH_SIZE || (done && !batch.is_empty()) {
+      summary.keys += batch.len() as u64;
+      let storage = storage.lock().await;
+      for entry in batch.drain(..) {
+        storage.set(entry.key, entry.value, entry.options);
+      }
+    }
+    if done {
+      break;
+    }
+    if last_progress.elapsed() >= PROGRESS_INTERVAL {
+      last_progress = Instant::now();
+      info!("Imported {} keys from {} so far", summary.keys, path);
+    }
+  }
+
+  info!(
+    "Imported {} keys from {} in {:.3} seconds, {} rows skipped",
+    summary.keys,
+    path,
+    started.elapsed().as_secs_f64(),
+    summary.errors
+  );
+  Ok(summary)
+}
+
+/// RFC 4180 records, which may span lines when quoted fields hold line breaks
+struct Records<R> {
+  reader: R,
+  /// Line the last record ended on
+  line: u64,
+}
+
+impl<R: BufRead> Records<R> {
+  fn new(reader: R) -> Self {
+    Self { reader, line: 0 }
+  }
+
+  fn next_record(&mut self) -> Result<Option<Vec<String>>, String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut line = String::new();
+    loop {
+      line.clear();
+      let read = self
+        .reader
+        .read_line(&mut line)
+        .map_err(|e| e.to_string())?;
+      if read == 0 {
+        if quoted {
+          return Err(format!("line {}: unterminated quoted field", self.line));
+        }
+        return Ok(None);
+      }
+      self.line += 1;
+      if !quoted && fields.is_empty() && field.is_empty() && line.trim_end().is_empty() {
+        // Blank lines between records
+        continue;
+      }
+
+      let mut chars = line.chars().peekable();
+      while let Some(c) = chars.next() {
+        match (quoted, c) {
+          (true, '"') if chars.peek() == Some(&'"') => {
+            chars.next();
+            field.push('"');
+          }
+          (true, '"') => quoted = false,
+          (true, c) => field.push(c),
+          (false, '"') if field.is_empty() => quoted = true,
+          (false, ',') => fields.push(std::mem::take(&mut field)),
+          (false, '\r') if chars.peek() == Some(&'\n') => {}
+          (false, '\n') => {}
+          (false, c) => field.push(c),
+        }
+      }
+      if !quoted {
+        fields.push(field);
+        return Ok(Some(fields));
+      }
+    }
+  }
+}
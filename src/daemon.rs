@@ -0,0 +1,103 @@
+/**
+ * Running as a daemon: `daemonize`, `pidfile` and `logfile`, for init scripts
+ * managing the server like they manage redis-server.
+ *
+ * With `daemonize yes` the process forks before the runtime starts, the parent
+ * exits and the child leaves the terminal's session with its standard streams
+ * on /dev/null. The PID goes to `pidfile` (/var/run/redis.pid by default when
+ * daemonized), which is removed on shutdown. Logs are written to `logfile`
+ * rather than stderr when it is set.
+ */
+use crate::arguments::CLIArguments;
+use crate::syslog::SyslogOptions;
+use std::fs::File;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+/// Where the PID is written when daemonized without a `pidfile`
+const DEFAULT_PIDFILE: &str = "/var/run/redis.pid";
+
+/// The pidfile written at startup, to remove on shutdown
+static PIDFILE: OnceLock<PathBuf> = OnceLock::new();
+
+/// How the process runs, settled before anything else starts
+pub struct ProcessOptions {
+  pub daemonize: bool,
+  pub pidfile: Option<String>,
+  pub logfile: Option<String>,
+  pub syslog: Option<SyslogOptions>,
+  /// OpenTelemetry collector the spans are exported to
+  pub otlp_endpoint: Option<String>,
+}
+
+impl ProcessOptions {
+  pub fn from_arguments(arguments: &CLIArguments) -> Self {
+    // Later directives win, like in the configuration
+    let directive = |name: &str| {
+      arguments
+        .iter()
+        .rev()
+        .find(|(argument, _)| argument == name)
+        .map(|(_, values)| values.join(" "))
+        .filter(|value| !value.is_empty())
+    };
+    let daemonize = directive("daemonize").is_some_and(|value| value.eq_ignore_ascii_case("yes"));
+    let pidfile = directive("pidfile").or_else(|| daemonize.then(|| DEFAULT_PIDFILE.to_string()));
+    Self {
+      daemonize,
+      pidfile,
+      logfile: directive("logfile"),
+      syslog: SyslogOptions::from_arguments(arguments),
+      otlp_endpoint: directive("otlp-endpoint"),
+    }
+  }
+}
+
+/// Detaches from the terminal. Must run before any thread is started.
+#[cfg(unix)]
+pub fn daemonize() -> Result<(), String> {
+  use std::os::unix::io::AsRawFd;
+
+  // SAFETY: the process is still single threaded, nothing else can observe
+  // the fork
+  match unsafe { libc::fork() } {
+    -1 => return Err(format!("fork: {}", std::io::Error::last_os_error())),
+    0 => {}
+    // SAFETY: the parent leaves without running destructors or atexit
+    // handlers, which belong to the child now
+    _ => unsafe { libc::_exit(0) },
+  }
+  // SAFETY: plain syscalls on the child's own process and descriptors
+  unsafe {
+    libc::setsid();
+    let null = File::options()
+      .read(true)
+      .write(true)
+      .open("/dev/null")
+      .map_err(|e| format!("/dev/null: {}", e))?;
+    for fd in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+      libc::dup2(null.as_raw_fd(), fd);
+    }
+  }
+  Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn daemonize() -> Result<(), String> {
+  Err("daemonize is only supported on Unix".to_string())
+}
+
+/// Writes the PID of the server to `path`
+pub fn write_pidfile(path: &str) -> Result<(), String> {
+  std::fs::write(path, format!("{}\n", std::process::id()))
+    .map_err(|e| format!("Failed to write PID file {}: {}", path, e))?;
+  let _ = PIDFILE.set(PathBuf::from(path));
+  Ok(())
+}
+
+/// Removes the pidfile written at startup, if any
+pub fn remove_pidfile() {
+  if let Some(path) = PIDFILE.get() {
+    let _ = std::fs::remove_file(path);
+  }
+}
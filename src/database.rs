@@ -8,15 +8,51 @@
  * 524544495330303130fa0972656469732d76657206372e302e3130fa0a72656469732d62697473c040fa056374696d65c2d5bbcc66fa08757365642d6d656dc2d0171100fa08616f662d62617365c000fe00fb0201fc86de7dad91010000000362617a037a61670003666f6f03626172ff20b3abf967cff893
  * ```
  *
+ * At startup the file is loaded in parallel: one thread walks it to find where
+ * each record starts, which only takes reading lengths, and hands the records
+ * in batches to loading threads that decode them and insert them in the
+ * keyspace. Progress is logged every `LOAD_PROGRESS_INTERVAL`.
+ *
+ * Strings may be stored as integers or LZF compressed, as Redis writes them
+ * (and `rdb.rs` with `rdbcompression yes`).
  */
+use crate::encryption::{self, EncryptionKey};
+use crate::lzf;
+use crate::rdb;
+use crate::storagelock::StorageLock;
 use crate::{config::Config, storage::Storage};
 use dashmap::DashMap;
-use log::{debug, error, info, warn};
 use std::io::{Error, ErrorKind};
-use std::time::{Duration, SystemTime};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::time::{Duration, Instant, SystemTime};
 use std::vec;
 use std::{str, sync::Arc};
 use tokio::sync::Mutex;
+use tracing::{debug, error, info, warn};
+
+/// Records handed to a loading thread at once
+const LOAD_BATCH: usize = 1024;
+
+/// Batches waiting for each loading thread, so that walking the file stays
+/// a little ahead of loading it
+const LOAD_QUEUE: usize = 4;
+
+/// Most threads loading an RDB file
+const MAX_LOAD_THREADS: usize = 8;
+
+/// Time between two progress reports while loading
+const LOAD_PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A record of the file: where its key-value pair starts and its deadline
+type Record = (usize, Option<SystemTime>);
+
+/// Threads loading an RDB file: one per core, up to `MAX_LOAD_THREADS`
+pub fn load_threads() -> usize {
+  std::thread::available_parallelism()
+    .map_or(1, |cores| cores.get())
+    .min(MAX_LOAD_THREADS)
+}
 
 /// Auxiliary value type
 #[derive(Debug, Clone)]
@@ -25,74 +61,77 @@ pub enum AuxValue {
   Integer(i64),
 }
 
-pub async fn populate_hot_storage(storage: &Arc<Mutex<Storage>>, config: &Arc<Mutex<Config>>) {
+/// Loads the RDB file, decrypting it with `key`. Fails only when the file is
+/// encrypted and can't be decrypted, other errors are logged and leave the keyspace empty.
+pub async fn populate_hot_storage(
+  storage: &Arc<StorageLock>,
+  config: &Arc<Mutex<Config>>,
+  key: Option<&EncryptionKey>,
+) -> Result<(), String> {
   // Extract the directory and dbfilename from the configuration
   // and populate the storage with the data
 
-  let storage = storage.lock().await;
   let config = config.lock().await;
+  let mut storage = storage.lock().await;
 
-  // Extract the directory and dbfilename from the configuration
-  // and populate the storage with the data
-  // If the dir or dbfilename is not present, return early
-  if !config.has("dir") || !config.has("dbfilename") {
-    info!("Configuration does not contain dir or dbfilename. Skipping read.");
-    return;
-  }
-
-  let directory = config.get("dir").unwrap();
-  let dbfilename = config.get("dbfilename").unwrap();
-  let rdb_file_path = format!("{}/{}", directory, dbfilename);
+  // The same path saves write to, dir and dbfilename having defaults
+  let rdb_file_path = rdb::snapshot_path(&config).display().to_string();
 
-  println!("Reading RDB file: {}", rdb_file_path);
+  info!("Reading RDB file: {}", rdb_file_path);
 
   let rdb_data = match std::fs::read(&rdb_file_path) {
     Ok(data) => data,
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+      info!("No RDB file at {}, starting empty", rdb_file_path);
+      return Ok(());
+    }
     Err(e) => {
       error!("Failed to read RDB file: {}", e);
-      return;
+      return Ok(());
     }
   };
+  let encrypted = encryption::is_encrypted(&rdb_data);
+  let rdb_data = encryption::decrypt(rdb_data, key)
+    .map_err(|e| format!("Can't load {}: {}", rdb_file_path, e))?;
+  if encrypted {
+    info!("Decrypted RDB file: {}", rdb_file_path);
+  }
 
+  let started = Instant::now();
+  let threads = load_threads();
   let mut parser = RDBParser::new(rdb_data);
-
-  if let Err(e) = parser.parse() {
-    eprintln!("Error parsing RDB file: {}", e);
-    dbg!(e);
-    // Handle the error appropriately
-  } else {
-    // Use the parsed data as needed
-    println!(
-      "Parsed {} non-expiring entries and {} expiring entries",
-      parser.entries.len(),
-      parser.expiry_entries.len()
-    );
+  match parser.load(&storage, threads) {
+    Ok(keys) => info!(
+      "Loaded {} keys in {:?} with {} threads",
+      keys,
+      started.elapsed(),
+      threads
+    ),
+    Err(e) => {
+      error!("Error parsing RDB file: {}", e);
+      // What was loaded before the error is dropped, not served half loaded
+      storage.flushall(Some(false));
+    }
   }
+  Ok(())
+}
 
-  parser.entries.iter().for_each(|(key, value)| {
-    let key = RDBParser::stringify(key);
-    let value = RDBParser::stringify(value);
-    storage.set(key, value, vec![]);
-  });
-
-  parser
-    .expiry_entries
-    .iter()
-    .for_each(|(key, value, expiry_time)| {
-      let key = RDBParser::stringify(key);
-      let value = RDBParser::stringify(value);
-
-      let now = SystemTime::now();
-      let time_since_expiry = expiry_time.duration_since(now).unwrap_or_default();
-
-      storage.set(
-        key,
-        value,
-        vec![("EX".to_string(), time_since_expiry.as_secs().to_string())],
-      );
-    });
-
-  drop(parser)
+/// Inserts a loaded key, with the time left until its deadline
+fn insert(storage: &Storage, key: &[u8], value: &[u8], expiry_time: Option<SystemTime>) {
+  let options = match expiry_time {
+    Some(expiry_time) => {
+      let time_until_expiry = expiry_time
+        .duration_since(SystemTime::now())
+        .unwrap_or_default();
+      vec![("PX".to_string(), time_until_expiry.as_millis().to_string())]
+    }
+    None => vec![],
+  };
+  storage.set(
+    RDBParser::stringify(key),
+    RDBParser::stringify(value),
+    options,
+  );
 }
 
 /// Parser struct for the RDBParser
@@ -154,28 +193,141 @@ impl RDBParser {
     Ok(())
   }
 
+  /// Loads the file into `storage` with `threads` loading threads, while this
+  /// one walks the file. Returns the number of keys loaded.
+  pub fn load(&mut self, storage: &Storage, threads: usize) -> Result<usize, Error> {
+    self.rdb_version = self
+      .parse_rdb_version(&self.data)
+      .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    let (aux_fields, index) = self.parse_auxiliary_fields(&self.data)?;
+    self.print_rdb_info(self.rdb_version, aux_fields.clone());
+    self.aux_fields = aux_fields;
+
+    let parser = &*self;
+    let loaded = AtomicUsize::new(0);
+    std::thread::scope(|scope| {
+      let mut queues = Vec::with_capacity(threads);
+      let mut loaders = Vec::with_capacity(threads);
+      for _ in 0..threads.max(1) {
+        let (queue, batches) = sync_channel(LOAD_QUEUE);
+        queues.push(queue);
+        let loaded = &loaded;
+        loaders.push(scope.spawn(move || parser.load_batches(storage, batches, loaded)));
+      }
+      let walked = parser.walk(index, &queues, &loaded);
+      // Loading threads stop once their queue is drained
+      drop(queues);
+      let mut result = walked;
+      for loader in loaders {
+        let loaded = loader.join().expect("RDB loading thread panicked");
+        result = result.and(loaded);
+      }
+      result
+    })?;
+    Ok(loaded.into_inner())
+  }
+
+  /// Walks the records from `index`, handing them to the loading threads
+  fn walk(
+    &self,
+    mut index: usize,
+    queues: &[SyncSender<Vec<Record>>],
+    loaded: &AtomicUsize,
+  ) -> Result<(), Error> {
+    let data = &self.data;
+    let mut batch = Vec::with_capacity(LOAD_BATCH);
+    let mut batches = 0;
+    let mut reported = Instant::now();
+
+    while index < data.len() {
+      let expiry_time = match data[index] {
+        0xFE => {
+          index = self.skip_database_selector(data, index)?;
+          continue;
+        }
+        0xFD | 0xFC => {
+          let (expiry_bytes, expiry_time) = self.decode_expiry_time(&data[index..])?;
+          index += expiry_bytes;
+          Some(expiry_time)
+        }
+        0xFF => break,
+        _ => None,
+      };
+      batch.push((index, expiry_time));
+      index = self.skip_key_value_pair(data, index)?;
+
+      if batch.len() == LOAD_BATCH {
+        let full = std::mem::replace(&mut batch, Vec::with_capacity(LOAD_BATCH));
+        // A loading thread only hangs up on an error, which it reports
+        if queues[batches % queues.len()].send(full).is_err() {
+          return Ok(());
+        }
+        batches += 1;
+        if reported.elapsed() >= LOAD_PROGRESS_INTERVAL {
+          info!(
+            "Loading RDB: {}% read, {} keys loaded",
+            index * 100 / data.len(),
+            loaded.load(Ordering::Relaxed)
+          );
+          reported = Instant::now();
+        }
+      }
+    }
+    if !batch.is_empty() {
+      let _ = queues[batches % queues.len()].send(batch);
+    }
+    Ok(())
+  }
+
+  /// Decodes the records of the batches and inserts them in `storage`
+  fn load_batches(
+    &self,
+    storage: &Storage,
+    batches: Receiver<Vec<Record>>,
+    loaded: &AtomicUsize,
+  ) -> Result<(), Error> {
+    for batch in batches {
+      for &(start, expiry_time) in &batch {
+        let mut index = start;
+        let (key, value) = self.process_key_value_pair(&self.data, &mut index)?;
+        insert(storage, &key, &value, expiry_time);
+      }
+      loaded.fetch_add(batch.len(), Ordering::Relaxed);
+    }
+    Ok(())
+  }
+
+  /// Keys without a deadline and their values, once parsed
+  pub fn entries(&self) -> &[(Vec<u8>, Vec<u8>)] {
+    &self.entries
+  }
+
+  /// Keys with a deadline, their values and deadlines, once parsed
+  pub fn expiry_entries(&self) -> &[(Vec<u8>, Vec<u8>, SystemTime)] {
+    &self.expiry_entries
+  }
+
   pub fn stringify(value: &[u8]) -> String {
     String::from_utf8_lossy(value).into_owned()
   }
 
   /// Print the RDB file information
   fn print_rdb_info(&self, version: u32, aux_fields: DashMap<String, AuxValue>) {
-    println!("RDB file version: {}", version);
-    println!("Auxiliary Fields:");
+    debug!("RDB file version: {}", version);
     for entry in aux_fields.iter() {
       match entry.value() {
-        AuxValue::String(s) => println!("  {}: {}", entry.key(), s),
-        AuxValue::Integer(i) => println!("  {}: {}", entry.key(), i),
+        AuxValue::String(s) => debug!("RDB auxiliary field {}: {}", entry.key(), s),
+        AuxValue::Integer(i) => debug!("RDB auxiliary field {}: {}", entry.key(), i),
       }
     }
 
     // Explicitly print redis-bits if it exists
     if let Some(entry) = aux_fields.get("redis-bits") {
       if let AuxValue::Integer(redis_bits) = entry.value() {
-        println!("\nRedis Bits: {}", redis_bits);
+        debug!("Redis Bits: {}", redis_bits);
       }
     } else {
-      println!("\nRedis Bits: Not found in auxiliary fields");
+      debug!("Redis Bits: Not found in auxiliary fields");
     }
   }
 
@@ -219,19 +371,31 @@ impl RDBParser {
         let length = ((first_byte as usize & 0x3f) << 8) | data[1] as usize;
         Ok((2, length))
       }
-      128..=191 => {
-        if data.len() < 4 {
+      0x80 => {
+        if data.len() < 5 {
           return Err(Error::new(
             ErrorKind::UnexpectedEof,
-            "Insufficient data for long length",
+            "Insufficient data for 32-bit length",
           ));
         }
-        let length = ((first_byte as usize & 0x3f) << 24)
-          | ((data[1] as usize) << 16)
-          | ((data[2] as usize) << 8)
-          | data[3] as usize;
-        Ok((4, length))
+        let length = u32::from_be_bytes([data[1], data[2], data[3], data[4]]) as usize;
+        Ok((5, length))
       }
+      0x81 => {
+        if data.len() < 9 {
+          return Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            "Insufficient data for 64-bit length",
+          ));
+        }
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&data[1..9]);
+        Ok((9, u64::from_be_bytes(bytes) as usize))
+      }
+      130..=191 => Err(Error::new(
+        ErrorKind::InvalidData,
+        format!("Invalid length encoding ({})", first_byte),
+      )),
       192..=253 => Ok((1, (first_byte as usize - 192))),
       254 => {
         if data.len() < 5 {
@@ -258,7 +422,15 @@ impl RDBParser {
 
     let first_byte = data[0];
     match first_byte {
-      0xC0 => Ok((2, data[1] as i64)),
+      0xC0 => {
+        if data.len() < 2 {
+          return Err(Error::new(
+            ErrorKind::InvalidData,
+            "Insufficient data for 8-bit integer",
+          ));
+        }
+        Ok((2, data[1] as i8 as i64))
+      }
       0xC1 => {
         if data.len() < 3 {
           return Err(Error::new(
@@ -311,6 +483,9 @@ impl RDBParser {
       ));
     }
 
+    if data[0] >> 6 == 3 {
+      return self.decode_special_string(data);
+    }
     let (length_bytes, length) = self.decode_length(data)?;
     debug!(
       "Decoded length: {} bytes, length encoding used {} bytes",
@@ -339,6 +514,55 @@ impl RDBParser {
     Ok((total_bytes, result))
   }
 
+  /// Bytes taken by a string stored as an integer or LZF compressed, and
+  /// where its compressed data starts
+  fn special_string_size(&self, data: &[u8]) -> Result<(usize, usize), Error> {
+    match data[0] & 0x3f {
+      0 => Ok((2, 1)),
+      1 => Ok((3, 1)),
+      2 => Ok((5, 1)),
+      3 => {
+        let (compressed_bytes, compressed_length) = self.decode_length(&data[1..])?;
+        let (length_bytes, _) = self.decode_length(&data[1 + compressed_bytes..])?;
+        let start = 1 + compressed_bytes + length_bytes;
+        let size = start
+          .checked_add(compressed_length)
+          .filter(|size| *size <= data.len())
+          .ok_or_else(|| {
+            Error::new(
+              ErrorKind::UnexpectedEof,
+              "Insufficient data for compressed string",
+            )
+          })?;
+        Ok((size, start))
+      }
+      encoding => Err(Error::new(
+        ErrorKind::InvalidData,
+        format!("Unknown string encoding ({})", encoding),
+      )),
+    }
+  }
+
+  /// Decodes a string stored as an integer or LZF compressed
+  fn decode_special_string(&self, data: &[u8]) -> Result<(usize, Vec<u8>), Error> {
+    let (size, start) = self.special_string_size(data)?;
+    if data[0] & 0x3f != 3 {
+      let (_, value) = self.decode_integer(data)?;
+      return Ok((size, value.to_string().into_bytes()));
+    }
+    let (compressed_bytes, _) = self.decode_length(&data[1..])?;
+    let (_, length) = self.decode_length(&data[1 + compressed_bytes..])?;
+    let value =
+      lzf::decompress(&data[start..size]).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    if value.len() != length {
+      return Err(Error::new(
+        ErrorKind::InvalidData,
+        "Compressed string of the wrong length",
+      ));
+    }
+    Ok((size, value))
+  }
+
   /// Parse the auxiliary fields from the RDB file
   pub fn parse_auxiliary_fields(
     &self,
@@ -522,46 +746,11 @@ impl RDBParser {
     while index < data.len() {
       match data[index] {
         0xFE => {
-          // Database selector
-          index += 2; // Skip selector and DB number
-          if index < data.len() && data[index] == 0xFB {
-            // Resizedb field
-            index += 1;
-            let (size_bytes, _) = self.decode_length(&data[index..])?;
-            index += size_bytes;
-            let (expire_size_bytes, _) = self.decode_length(&data[index..])?;
-            index += expire_size_bytes;
-          }
+          index = self.skip_database_selector(data, index)?;
         }
         0xFD | 0xFC => {
           // Expiry time
-          let (expiry_bytes, expiry_time) = if data[index] == 0xFD {
-            (
-              5,
-              SystemTime::UNIX_EPOCH
-                + Duration::from_secs(u32::from_le_bytes([
-                  data[index + 1],
-                  data[index + 2],
-                  data[index + 3],
-                  data[index + 4],
-                ]) as u64),
-            )
-          } else {
-            (
-              9,
-              SystemTime::UNIX_EPOCH
-                + Duration::from_millis(u64::from_le_bytes([
-                  data[index + 1],
-                  data[index + 2],
-                  data[index + 3],
-                  data[index + 4],
-                  data[index + 5],
-                  data[index + 6],
-                  data[index + 7],
-                  data[index + 8],
-                ])),
-            )
-          };
+          let (expiry_bytes, expiry_time) = self.decode_expiry_time(&data[index..])?;
           index += expiry_bytes;
           let (key, value) = self.process_key_value_pair(data, &mut index)?;
           expiry_entries.push((key, value, expiry_time));
@@ -581,6 +770,70 @@ impl RDBParser {
     Ok((entries, expiry_entries))
   }
 
+  /// Index following the database selector at `index`, and its resizedb
+  /// field if any
+  fn skip_database_selector(&self, data: &[u8], mut index: usize) -> Result<usize, Error> {
+    index += 2; // Skip selector and DB number
+    if index < data.len() && data[index] == 0xFB {
+      // Resizedb field
+      index += 1;
+      let (size_bytes, _) = self.decode_length(&data[index..])?;
+      index += size_bytes;
+      let (expire_size_bytes, _) = self.decode_length(&data[index..])?;
+      index += expire_size_bytes;
+    }
+    Ok(index)
+  }
+
+  /// Decodes an expiry time, in seconds (0xFD) or milliseconds (0xFC)
+  fn decode_expiry_time(&self, data: &[u8]) -> Result<(usize, SystemTime), Error> {
+    let length = if data[0] == 0xFD { 5 } else { 9 };
+    if data.len() < length {
+      return Err(Error::new(
+        ErrorKind::UnexpectedEof,
+        "Insufficient data for expiry time",
+      ));
+    }
+    let expiry_time = if length == 5 {
+      let seconds = u32::from_le_bytes([data[1], data[2], data[3], data[4]]);
+      SystemTime::UNIX_EPOCH + Duration::from_secs(seconds as u64)
+    } else {
+      let mut millis = [0u8; 8];
+      millis.copy_from_slice(&data[1..9]);
+      SystemTime::UNIX_EPOCH + Duration::from_millis(u64::from_le_bytes(millis))
+    };
+    Ok((length, expiry_time))
+  }
+
+  /// Index following the key-value pair at `index`. String values are skipped
+  /// without being decoded.
+  fn skip_key_value_pair(&self, data: &[u8], mut index: usize) -> Result<usize, Error> {
+    let value_type = data[index];
+    index += 1;
+    index += self.skip_string(&data[index..])?;
+    if value_type == 0 {
+      index += self.skip_string(&data[index..])?;
+    } else {
+      self.decode_value(data, value_type, &mut index)?;
+    }
+    Ok(index)
+  }
+
+  /// Bytes taken by the length encoded string at the start of `data`
+  fn skip_string(&self, data: &[u8]) -> Result<usize, Error> {
+    if data.first().is_some_and(|byte| byte >> 6 == 3) {
+      return Ok(self.special_string_size(data)?.0);
+    }
+    let (length_bytes, length) = self.decode_length(data)?;
+    if data.len() < length_bytes + length {
+      return Err(Error::new(
+        ErrorKind::UnexpectedEof,
+        "Insufficient data for encoded string",
+      ));
+    }
+    Ok(length_bytes + length)
+  }
+
   fn process_key_value_pair(
     &self,
     data: &[u8],
@@ -0,0 +1,348 @@
+/**
+ * DEBUG: hooks into internal subsystems for integration tests, modeled on the
+ * subcommands Redis's own test suite relies on.
+ */
+use crate::arguments::generate_replication_id;
+use crate::config::{parse_memory, Config};
+use crate::fault::Faults;
+use crate::glob::glob_match;
+use crate::json;
+use crate::offload;
+use crate::parser::RedisValue;
+use crate::storage::Storage;
+use crate::storagelock::StorageLock;
+use bytes::Bytes;
+use sha1::{Digest, Sha1};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex as AsyncMutex;
+
+/// Keys a DEBUG BIGKEYS step inspects before letting other clients in
+const BIGKEYS_BATCH: usize = 1000;
+
+const HELP: [&str; 24] = [
+  "DEBUG <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+  "BIGKEYS [<count>]",
+  "    Scan the keyspace for the <count> (default 10) largest keys of every type.",
+  "CHANGE-REPL-ID",
+  "    Change the replication IDs of the instance.",
+  "DIGEST",
+  "    Output a hex signature representing the current DB content.",
+  "DIGEST-VALUE <key> [<key> ...]",
+  "    Output a hex signature of the values of all the specified keys.",
+  "FAULT <LATENCY <ms> [<percent>]|CONNECTION-RESET <percent>|RDB-WRITE-ERROR <0|1>|OFF|LIST>",
+  "    Inject faults into command handling and saves, see fault.rs.",
+  "EXPORT-JSON <path>",
+  "    Write the dataset to <path> as JSON.",
+  "HOTKEYS [<count>|RESET]",
+  "    Show the <count> (default 10) most accessed keys, or forget them.",
+  "OBJECT <key>",
+  "    Show low level info about the `key` and associated value.",
+  "QUICKLIST-PACKED-THRESHOLD <size>",
+  "    Sets the threshold for elements to be inserted as plain vs packed nodes.",
+  "SET-ACTIVE-EXPIRE <0|1>",
+  "    Setting it to 0 disables expiring keys in background when they are not accessed.",
+  "SLEEP <seconds>",
+  "    Stop the server for <seconds>. Decimals allowed.",
+  "STRINGMATCH-LEN: run a fuzz tester against the glob matcher.",
+];
+
+/// Executes a DEBUG subcommand
+pub async fn handle_command(
+  subcommand: &str,
+  args: &[String],
+  storage: &Arc<StorageLock>,
+  config: &Arc<AsyncMutex<Config>>,
+  faults: &Faults,
+) -> RedisValue {
+  let ok = || RedisValue::SimpleString("OK".to_string());
+
+  match (subcommand, args) {
+    ("HELP", []) => RedisValue::Array(HELP.iter().map(|line| line.to_string()).collect()),
+    ("SLEEP", [seconds]) => match seconds.parse::<f64>() {
+      Ok(seconds) if seconds.is_finite() && seconds >= 0.0 => {
+        // Holding the keyspace makes every other client wait, like a blocked Redis
+        let _storage = storage.lock().await;
+        tokio::time::sleep(Duration::from_secs_f64(seconds)).await;
+        ok()
+      }
+      _ => RedisValue::Error("ERR value is not a valid float".to_string()),
+    },
+    ("SET-ACTIVE-EXPIRE", [enabled]) => match enabled.as_str() {
+      "0" | "1" => {
+        storage
+          .lock()
+          .await
+          .expiry_index()
+          .set_active_expire(enabled == "1");
+        ok()
+      }
+      _ => RedisValue::Error("ERR value is not an integer or out of range".to_string()),
+    },
+    ("BIGKEYS", []) => bigkeys(storage, 10).await,
+    ("BIGKEYS", [count]) => match count.parse::<usize>() {
+      Ok(count) if count > 0 => bigkeys(storage, count).await,
+      _ => RedisValue::Error("ERR value is out of range, must be positive".to_string()),
+    },
+    ("FAULT", args) => faults.handle_command(args),
+    ("HOTKEYS", []) => hotkeys(storage, 10).await,
+    ("HOTKEYS", [count]) if count.eq_ignore_ascii_case("RESET") => {
+      storage.lock().await.hotkeys().reset();
+      ok()
+    }
+    ("HOTKEYS", [count]) => match count.parse::<usize>() {
+      Ok(count) if count > 0 => hotkeys(storage, count).await,
+      _ => RedisValue::Error("ERR value is out of range, must be positive".to_string()),
+    },
+    ("OBJECT", [key]) => match storage.lock().await.debug_object(key) {
+      Some(description) => RedisValue::SimpleString(description),
+      None => RedisValue::Error("ERR no such key".to_string()),
+    },
+    ("DIGEST", []) => {
+      let min_keys = offload::min_keys(&*config.lock().await);
+      let storage = storage.lock().await;
+      let digest = if offload::should_offload(min_keys, storage.len()) {
+        let snapshot = storage.begin_snapshot();
+        drop(storage);
+        offload::run(move || digest_entries(snapshot.entries())).await
+      } else {
+        dataset_digest(&storage)
+      };
+      RedisValue::SimpleString(hex::encode(digest))
+    }
+    ("DIGEST-VALUE", keys) => {
+      let storage = storage.lock().await;
+      RedisValue::Array(
+        keys
+          .iter()
+          .map(|key| {
+            let mut digest = [0u8; 20];
+            if let Some(value) = storage.lookup(key, false) {
+              mix_digest(&mut digest, &value);
+            }
+            hex::encode(digest)
+          })
+          .collect(),
+      )
+    }
+    ("EXPORT-JSON", [path]) => {
+      let storage = storage.lock().await;
+      match json::export_file(&storage, Path::new(path)) {
+        Ok(()) => ok(),
+        Err(e) => RedisValue::Error(format!("ERR {}", e)),
+      }
+    }
+    ("STRINGMATCH-LEN", []) => {
+      stringmatch_fuzz();
+      RedisValue::SimpleString("Apparently Redis did not crash: test passed".to_string())
+    }
+    ("CHANGE-REPL-ID", []) => {
+      let config = config.lock().await;
+      config.set("replication_id".to_string(), generate_replication_id());
+      config.set("replication_offset".to_string(), "0".to_string());
+      ok()
+    }
+    ("QUICKLIST-PACKED-THRESHOLD", [size]) => match parse_memory(size) {
+      Some(size) if size > 1 && size < 4 * 1024 * 1024 * 1024 => {
+        config
+          .lock()
+          .await
+          .set("quicklist-packed-threshold".to_string(), size.to_string());
+        ok()
+      }
+      _ => RedisValue::Error(
+        "ERR argument must be a memory value bigger than 1 and smaller than 4gb".to_string(),
+      ),
+    },
+    _ => RedisValue::Error(format!(
+      "ERR unknown subcommand or wrong number of arguments for '{}'. Try DEBUG HELP.",
+      subcommand.to_lowercase()
+    )),
+  }
+}
+
+/// The largest keys of a type, by length and by memory
+struct Largest {
+  keys: usize,
+  by_length: BinaryHeap<Reverse<(usize, String)>>,
+  by_memory: BinaryHeap<Reverse<(usize, String)>>,
+}
+
+/// Keeps the `count` largest of `top`
+fn push_largest(
+  top: &mut BinaryHeap<Reverse<(usize, String)>>,
+  count: usize,
+  size: usize,
+  key: &str,
+) {
+  if top.len() < count {
+    top.push(Reverse((size, key.to_string())));
+  } else if top
+    .peek()
+    .is_some_and(|Reverse((smallest, _))| size > *smallest)
+  {
+    top.pop();
+    top.push(Reverse((size, key.to_string())));
+  }
+}
+
+/// DEBUG HOTKEYS: the `count` most accessed keys with their access counts,
+/// see `hotkeys`
+async fn hotkeys(storage: &Arc<StorageLock>, count: usize) -> RedisValue {
+  let top = storage.lock().await.hotkeys().top(count);
+  RedisValue::Nested(
+    top
+      .into_iter()
+      .map(|(key, counter)| {
+        RedisValue::Nested(vec![
+          RedisValue::BulkString(Some(key)),
+          RedisValue::Integer(counter.count as i64),
+        ])
+      })
+      .collect(),
+  )
+}
+
+/// DEBUG BIGKEYS: the `count` largest keys of every type by length and by
+/// memory. The keyspace is scanned a batch at a time, other clients run in
+/// between, so keys written meanwhile may or may not be counted.
+async fn bigkeys(storage: &Arc<StorageLock>, count: usize) -> RedisValue {
+  let mut types: BTreeMap<&str, Largest> = BTreeMap::new();
+  let mut cursor = 0;
+  loop {
+    let keyspace = storage.lock().await;
+    let (next, keys) = keyspace.scan(cursor, None, BIGKEYS_BATCH);
+    for key in keys {
+      let (Some(value), Some(memory)) = (keyspace.lookup(&key, false), keyspace.memory_usage(&key))
+      else {
+        continue;
+      };
+      // Strings are the only type, their length is their size in bytes
+      let largest = types.entry("string").or_insert_with(|| Largest {
+        keys: 0,
+        by_length: BinaryHeap::new(),
+        by_memory: BinaryHeap::new(),
+      });
+      largest.keys += 1;
+      push_largest(&mut largest.by_length, count, value.len(), &key);
+      push_largest(&mut largest.by_memory, count, memory, &key);
+    }
+    drop(keyspace);
+    if next == 0 {
+      break;
+    }
+    cursor = next;
+    tokio::task::yield_now().await;
+  }
+
+  let ranked = |top: BinaryHeap<Reverse<(usize, String)>>| {
+    RedisValue::Nested(
+      top
+        .into_sorted_vec()
+        .into_iter()
+        .map(|Reverse((size, key))| {
+          RedisValue::Nested(vec![
+            RedisValue::BulkString(Some(key)),
+            RedisValue::Integer(size as i64),
+          ])
+        })
+        .collect(),
+    )
+  };
+  let bulk = |s: &str| RedisValue::BulkString(Some(s.to_string()));
+  RedisValue::Nested(
+    types
+      .into_iter()
+      .map(|(name, largest)| {
+        RedisValue::Nested(vec![
+          bulk("type"),
+          bulk(name),
+          bulk("keys"),
+          RedisValue::Integer(largest.keys as i64),
+          bulk("largest-by-length"),
+          ranked(largest.by_length),
+          bulk("largest-by-memory"),
+          ranked(largest.by_memory),
+        ])
+      })
+      .collect(),
+  )
+}
+
+/// Throws random patterns made of glob metacharacters at the matcher, which
+/// must never panic or hang on them
+fn stringmatch_fuzz() {
+  const ALPHABET: &[u8] = b"*?[]^-\\ab";
+  let mut state = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|elapsed| elapsed.as_nanos() as u64)
+    .unwrap_or(0)
+    | 1;
+  let mut random = move |bound: usize| {
+    state ^= state << 13;
+    state ^= state >> 7;
+    state ^= state << 17;
+    (state % bound as u64) as usize
+  };
+
+  for _ in 0..100_000 {
+    let mut random_string = |max_len: usize| -> String {
+      let len = random(max_len);
+      (0..len)
+        .map(|_| ALPHABET[random(ALPHABET.len())] as char)
+        .collect()
+    };
+    let pattern = random_string(16);
+    let string = random_string(16);
+    glob_match(&pattern, &string);
+  }
+}
+
+/// XORs the SHA1 of `data` into `digest`
+fn xor_digest(digest: &mut [u8; 20], data: &[u8]) {
+  let hash = Sha1::digest(data);
+  for (byte, hash) in digest.iter_mut().zip(hash.iter()) {
+    *byte ^= hash;
+  }
+}
+
+/// XORs the SHA1 of `data` into `digest`, then replaces `digest` with its own
+/// SHA1. Unlike a plain XOR this is order dependent, so "ab" and "ba" differ.
+fn mix_digest(digest: &mut [u8; 20], data: &[u8]) {
+  xor_digest(digest, data);
+  let hash = Sha1::digest(&digest[..]);
+  digest.copy_from_slice(&hash[..20]);
+}
+
+/// Digest of the whole dataset, computed like Redis's DEBUG DIGEST so that two
+/// servers holding the same data agree. Every key gets its own digest (mixing
+/// key, value and whether it is volatile) and those are XORed together, which
+/// makes the result independent of iteration order. An empty dataset digests to
+/// all zeros.
+pub fn dataset_digest(storage: &Storage) -> [u8; 20] {
+  digest_entries(storage.entries())
+}
+
+/// `dataset_digest` of entries taken out of the keyspace
+pub fn digest_entries(entries: Vec<(String, Bytes, Option<SystemTime>)>) -> [u8; 20] {
+  let mut digest = [0u8; 20];
+  if entries.is_empty() {
+    return digest;
+  }
+
+  // Database number, only db 0 exists
+  mix_digest(&mut digest, &0u32.to_be_bytes());
+  for (key, value, expires_at) in entries {
+    let mut key_digest = [0u8; 20];
+    mix_digest(&mut key_digest, key.as_bytes());
+    mix_digest(&mut key_digest, &value);
+    if expires_at.is_some() {
+      xor_digest(&mut key_digest, b"!!expire!!");
+    }
+    xor_digest(&mut digest, &key_digest);
+  }
+  digest
+}
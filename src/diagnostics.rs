@@ -0,0 +1,182 @@
+/**
+ * Runtime diagnostics: on SIGUSR1 the server logs a report of its state, for
+ * looking into a server that no client can get an answer from, or without
+ * disturbing the ones that can:
+ *
+ * ```text
+ * Diagnostics report
+ * tasks: workers=8 server_tasks=6 connections=12
+ * keyspace: keys=10000 expires=120 used_memory=1.20M
+ * keyspace shard 0: keys=624 used_memory=78.12K
+ * ...
+ * memory: allocated=3.10M peak=3.52M resident=8.44M clients=240.00K
+ * clients: connected=12 pubsub=1 monitors=0 tracking=2 replicas=0 max_idle=310s
+ * replication: role:master connected_slaves:0 master_replid:... master_repl_offset:0
+ * slowlog: 3 of 3 entries
+ * slowlog #2: 15230us at 1760000000 by 127.0.0.1:51000 'KEYS' '*'
+ * ```
+ *
+ * The keyspace shards are the stripes of its memory counter. The keyspace and
+ * the configuration are waited for up to `LOCK_TIMEOUT`: a command holding
+ * them longer is the likely culprit, the report says so in their place
+ * rather than hang. Only the last `SLOWLOG_ENTRIES` slow commands are listed.
+ */
+use crate::allocator::{self, human_bytes};
+use crate::clients::{ClientInfo, ClientKind, ClientRegistry};
+use crate::config::Config;
+use crate::info::replication_info;
+use crate::slowlog::SlowLog;
+use crate::state::ServerState;
+use crate::storagelock::StorageLock;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex as AsyncMutex;
+use tokio::time::Instant;
+use tracing::{error, info};
+
+/// Slow commands listed in a report
+pub const SLOWLOG_ENTRIES: usize = 10;
+
+/// Time the report waits for the keyspace or the configuration
+const LOCK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The lines of a report. `server_tasks` are the tasks the server spawned at
+/// start, besides one per connection.
+pub async fn report(
+  storage: &StorageLock,
+  config: &AsyncMutex<Config>,
+  clients: &ClientRegistry,
+  slowlog: &SlowLog,
+  server_tasks: usize,
+) -> Vec<String> {
+  let mut lines = vec!["Diagnostics report".to_string()];
+  lines.push(format!(
+    "tasks: workers={} server_tasks={} connections={}",
+    tokio::runtime::Handle::current().metrics().num_workers(),
+    server_tasks,
+    clients.len()
+  ));
+
+  let mut dataset = None;
+  match tokio::time::timeout(LOCK_TIMEOUT, storage.lock()).await {
+    Ok(storage) => {
+      dataset = Some(storage.used_memory());
+      lines.push(format!(
+        "keyspace: keys={} expires={} used_memory={}",
+        storage.len(),
+        storage.expiry_index().len(),
+        human_bytes(storage.used_memory())
+      ));
+      let shards = storage
+        .keys_per_shard()
+        .into_iter()
+        .zip(storage.used_memory_per_shard());
+      for (index, (keys, memory)) in shards.enumerate() {
+        lines.push(format!(
+          "keyspace shard {}: keys={} used_memory={}",
+          index,
+          keys,
+          human_bytes(memory)
+        ));
+      }
+    }
+    Err(_) => lines.push(format!(
+      "keyspace: locked for over {}s by a running command",
+      LOCK_TIMEOUT.as_secs()
+    )),
+  }
+
+  let memory = allocator::stats();
+  lines.push(format!(
+    "memory: allocated={} peak={} resident={} clients={}{}",
+    human_bytes(memory.allocated),
+    human_bytes(memory.peak_allocated),
+    human_bytes(memory.resident),
+    human_bytes(clients.memory()),
+    dataset.map_or(String::new(), |dataset| format!(
+      " dataset={}",
+      human_bytes(dataset)
+    ))
+  ));
+
+  let all = clients.all();
+  let count = |filter: fn(&&ClientInfo) -> bool| all.iter().filter(filter).count();
+  let now = Instant::now();
+  lines.push(format!(
+    "clients: connected={} pubsub={} monitors={} tracking={} replicas={} max_idle={}s",
+    all.len(),
+    count(|client| client.kind == ClientKind::PubSub),
+    count(|client| client.monitor),
+    count(|client| client.tracking.is_some()),
+    count(|client| client.kind == ClientKind::Replica),
+    all
+      .iter()
+      .map(|client| now.duration_since(client.last_interaction).as_secs())
+      .max()
+      .unwrap_or(0)
+  ));
+
+  match tokio::time::timeout(LOCK_TIMEOUT, config.lock()).await {
+    Ok(config) => lines.push(format!(
+      "replication: {}",
+      replication_info(&config).join(" ")
+    )),
+    Err(_) => lines.push(format!(
+      "replication: configuration locked for over {}s",
+      LOCK_TIMEOUT.as_secs()
+    )),
+  }
+
+  let entries = slowlog.entries(SLOWLOG_ENTRIES);
+  lines.push(format!(
+    "slowlog: {} of {} entries",
+    entries.len(),
+    slowlog.len()
+  ));
+  for entry in entries {
+    let argv: Vec<String> = entry
+      .argv
+      .iter()
+      .map(|argument| format!("'{}'", argument))
+      .collect();
+    lines.push(format!(
+      "slowlog #{}: {}us at {} by {}{} {}",
+      entry.id,
+      entry.duration,
+      entry.timestamp,
+      entry.addr,
+      match entry.name.is_empty() {
+        true => String::new(),
+        false => format!(" ({})", entry.name),
+      },
+      argv.join(" ")
+    ));
+  }
+  lines
+}
+
+/// Logs a report whenever SIGUSR1 is received
+pub fn spawn_report_on_sigusr1(
+  storage: Arc<StorageLock>,
+  config: Arc<AsyncMutex<Config>>,
+  clients: Arc<ClientRegistry>,
+  state: Arc<ServerState>,
+  server_tasks: usize,
+) {
+  #[cfg(unix)]
+  tokio::spawn(async move {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut user_defined = match signal(SignalKind::user_defined1()) {
+      Ok(user_defined) => user_defined,
+      Err(e) => {
+        error!("Failed to install the SIGUSR1 handler: {}", e);
+        return;
+      }
+    };
+    while user_defined.recv().await.is_some() {
+      for line in report(&storage, &config, &clients, &state.slowlog, server_tasks).await {
+        info!("{}", line);
+      }
+    }
+  });
+}
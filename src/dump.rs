@@ -0,0 +1,230 @@
+/**
+ * DUMP and RESTORE payloads, in Redis's format so that values move between
+ * this server and Redis: the value as RDB encodes it, the RDB version it was
+ * written with on two bytes and a CRC64 of both on eight, little endian.
+ *
+ * ```text
+ * 00 05 "hello" 0b 00 <crc64>
+ * ```
+ *
+ * RESTORE takes payloads from clients, which must not be trusted. Decoding
+ * is bounds checked whatever the settings: a payload can't make the server
+ * read past it, nor allocate more than the value it decodes to, and anything
+ * malformed is refused with `Bad data format`. With `sanitize-dump-payload
+ * yes` payloads are also checked deeply before they are restored, refusing
+ * those Redis would never write: bytes left after the value, lengths in a
+ * longer encoding than needed and compressed strings declaring another length
+ * than they decompress to. `clients` sanitizes the payloads of client
+ * connections, which are all of them since this server has no master link.
+ */
+use crate::config::Config;
+use crate::lzf;
+
+/// RDB version of the payloads written, the highest one read
+const RDB_VERSION: u16 = 11;
+
+const TYPE_STRING: u8 = 0;
+
+const ENCODING_INT8: u8 = 0;
+const ENCODING_INT16: u8 = 1;
+const ENCODING_INT32: u8 = 2;
+const ENCODING_LZF: u8 = 3;
+
+/// Reflected polynomial of the CRC64 variant Redis uses (Jones)
+const CRC64_POLYNOMIAL: u64 = 0x95ac_9329_ac4b_c9b5;
+
+pub const BAD_FORMAT: &str = "ERR Bad data format";
+pub const BAD_FOOTER: &str = "ERR DUMP payload version or checksum are wrong";
+
+/// Whether RESTORE payloads are sanitized, after `sanitize-dump-payload`
+pub fn sanitize(config: &Config) -> bool {
+  matches!(
+    config.get("sanitize-dump-payload").as_deref(),
+    Some("yes") | Some("clients")
+  )
+}
+
+/// CRC64 of every byte value, for `crc64` to go a byte at a time, RDB files
+/// being large
+const CRC64_TABLE: [u64; 256] = crc64_table();
+
+const fn crc64_table() -> [u64; 256] {
+  let mut table = [0u64; 256];
+  let mut byte = 0;
+  while byte < 256 {
+    let mut crc = byte as u64;
+    let mut bit = 0;
+    while bit < 8 {
+      crc = if crc & 1 == 1 {
+        (crc >> 1) ^ CRC64_POLYNOMIAL
+      } else {
+        crc >> 1
+      };
+      bit += 1;
+    }
+    table[byte] = crc;
+    byte += 1;
+  }
+  table
+}
+
+/// CRC64 of `data`, as Redis computes it for RDB files and DUMP payloads
+pub fn crc64(data: &[u8]) -> u64 {
+  data.iter().fold(0u64, |crc, byte| {
+    CRC64_TABLE[((crc ^ *byte as u64) & 0xff) as usize] ^ (crc >> 8)
+  })
+}
+
+/// The DUMP payload of a string value
+pub fn serialize(value: &[u8]) -> Vec<u8> {
+  let mut payload = Vec::with_capacity(value.len() + 20);
+  payload.push(TYPE_STRING);
+  write_length(&mut payload, value.len());
+  payload.extend_from_slice(value);
+  payload.extend_from_slice(&RDB_VERSION.to_le_bytes());
+  let crc = crc64(&payload);
+  payload.extend_from_slice(&crc.to_le_bytes());
+  payload
+}
+
+fn write_length(out: &mut Vec<u8>, length: usize) {
+  if length < 1 << 6 {
+    out.push(length as u8);
+  } else if length < 1 << 14 {
+    out.push(0x40 | (length >> 8) as u8);
+    out.push(length as u8);
+  } else if length <= u32::MAX as usize {
+    out.push(0x80);
+    out.extend_from_slice(&(length as u32).to_be_bytes());
+  } else {
+    out.push(0x81);
+    out.extend_from_slice(&(length as u64).to_be_bytes());
+  }
+}
+
+/// The string value of a RESTORE payload, checked deeply with `sanitize`
+pub fn deserialize(payload: &[u8], sanitize: bool) -> Result<Vec<u8>, String> {
+  if payload.len() < 10 {
+    return Err(BAD_FOOTER.to_string());
+  }
+  let (body, footer) = payload.split_at(payload.len() - 10);
+  let version = u16::from_le_bytes([footer[0], footer[1]]);
+  let crc = u64::from_le_bytes(footer[2..].try_into().unwrap());
+  if version > RDB_VERSION || crc != crc64(&payload[..payload.len() - 8]) {
+    return Err(BAD_FOOTER.to_string());
+  }
+
+  let mut reader = Reader {
+    data: body,
+    position: 0,
+    sanitize,
+  };
+  // Strings are the only type stored, other ones can't be restored
+  if reader.byte()? != TYPE_STRING {
+    return Err(BAD_FORMAT.to_string());
+  }
+  let value = reader.string()?;
+  if sanitize && reader.position != body.len() {
+    return Err(BAD_FORMAT.to_string());
+  }
+  Ok(value)
+}
+
+/// A length, or a string stored in a special encoding
+enum Length {
+  Plain(usize),
+  Encoded(u8),
+}
+
+struct Reader<'a> {
+  data: &'a [u8],
+  position: usize,
+  sanitize: bool,
+}
+
+impl Reader<'_> {
+  fn take(&mut self, count: usize) -> Result<&[u8], String> {
+    let end = self
+      .position
+      .checked_add(count)
+      .filter(|end| *end <= self.data.len())
+      .ok_or(BAD_FORMAT)?;
+    let bytes = &self.data[self.position..end];
+    self.position = end;
+    Ok(bytes)
+  }
+
+  fn byte(&mut self) -> Result<u8, String> {
+    Ok(self.take(1)?[0])
+  }
+
+  fn length(&mut self) -> Result<Length, String> {
+    let first = self.byte()?;
+    let (length, shortest) = match first >> 6 {
+      0 => return Ok(Length::Plain((first & 0x3f) as usize)),
+      1 => {
+        let length = ((first as usize & 0x3f) << 8) | self.byte()? as usize;
+        (length, 1 << 6)
+      }
+      2 if first == 0x80 => {
+        let bytes = self.take(4)?;
+        let length = u32::from_be_bytes(bytes.try_into().unwrap()) as usize;
+        (length, 1 << 14)
+      }
+      2 if first == 0x81 => {
+        let bytes = self.take(8)?;
+        let length = u64::from_be_bytes(bytes.try_into().unwrap());
+        let length = usize::try_from(length).map_err(|_| BAD_FORMAT)?;
+        (length, u32::MAX as usize + 1)
+      }
+      2 => return Err(BAD_FORMAT.to_string()),
+      _ => return Ok(Length::Encoded(first & 0x3f)),
+    };
+    // Redis writes every length in the shortest encoding fitting it
+    if self.sanitize && length < shortest {
+      return Err(BAD_FORMAT.to_string());
+    }
+    Ok(Length::Plain(length))
+  }
+
+  fn plain_length(&mut self) -> Result<usize, String> {
+    match self.length()? {
+      Length::Plain(length) => Ok(length),
+      Length::Encoded(_) => Err(BAD_FORMAT.to_string()),
+    }
+  }
+
+  fn string(&mut self) -> Result<Vec<u8>, String> {
+    match self.length()? {
+      Length::Plain(length) => Ok(self.take(length)?.to_vec()),
+      Length::Encoded(ENCODING_INT8) => Ok((self.byte()? as i8).to_string().into_bytes()),
+      Length::Encoded(ENCODING_INT16) => {
+        let bytes = self.take(2)?;
+        Ok(
+          i16::from_le_bytes(bytes.try_into().unwrap())
+            .to_string()
+            .into_bytes(),
+        )
+      }
+      Length::Encoded(ENCODING_INT32) => {
+        let bytes = self.take(4)?;
+        Ok(
+          i32::from_le_bytes(bytes.try_into().unwrap())
+            .to_string()
+            .into_bytes(),
+        )
+      }
+      Length::Encoded(ENCODING_LZF) => {
+        let compressed_length = self.plain_length()?;
+        let length = self.plain_length()?;
+        let compressed = self.take(compressed_length)?;
+        let value = lzf::decompress(compressed).map_err(|_| BAD_FORMAT)?;
+        if self.sanitize && value.len() != length {
+          return Err(BAD_FORMAT.to_string());
+        }
+        Ok(value)
+      }
+      Length::Encoded(_) => Err(BAD_FORMAT.to_string()),
+    }
+  }
+}
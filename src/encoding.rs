@@ -0,0 +1,142 @@
+/**
+ * Compact in-memory representations for string values.
+ *
+ * Mirrors Redis's object encodings:
+ *  - `int`: the value is the canonical decimal form of an i64 and is stored as one
+ *  - `embstr`: short strings; up to `INLINE_CAPACITY` bytes live inline with no heap
+ *    allocation at all, longer ones up to `EMBSTR_SIZE_LIMIT` use a single exactly
+ *    sized allocation
+ *  - `raw`: everything else
+ *
+ * Longer strings are kept in reference-counted `Bytes`, so reading one hands
+ * out a handle to the stored buffer instead of a copy of it.
+ */
+use crate::memory::MemoryUsage;
+use bytes::Bytes;
+use std::fmt;
+use std::mem::size_of;
+
+/// Longest string stored inline, chosen so that `Value` stays as small as a
+/// `Bytes` handle
+pub const INLINE_CAPACITY: usize = 30;
+
+/// Longest string reported with the `embstr` encoding, same as Redis
+pub const EMBSTR_SIZE_LIMIT: usize = 44;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+  Int(i64),
+  Inline {
+    len: u8,
+    bytes: [u8; INLINE_CAPACITY],
+  },
+  /// Always valid UTF-8, like the strings it is built from
+  Raw(Bytes),
+}
+
+impl Value {
+  /// Picks the most compact representation for `value`
+  pub fn new(value: String) -> Self {
+    if let Some(integer) = Self::canonical_integer(&value) {
+      return Value::Int(integer);
+    }
+
+    if value.len() <= INLINE_CAPACITY {
+      let mut bytes = [0u8; INLINE_CAPACITY];
+      bytes[..value.len()].copy_from_slice(value.as_bytes());
+      return Value::Inline {
+        len: value.len() as u8,
+        bytes,
+      };
+    }
+
+    Value::Raw(Bytes::from(value.into_bytes()))
+  }
+
+  /// Only strings that round-trip exactly (no sign, padding or leading zeros)
+  /// can be stored as integers without changing what GET returns.
+  fn canonical_integer(value: &str) -> Option<i64> {
+    if value.is_empty() || value.len() > 20 {
+      return None;
+    }
+    let integer = value.parse::<i64>().ok()?;
+    if integer.to_string() == value {
+      Some(integer)
+    } else {
+      None
+    }
+  }
+
+  /// Name of the encoding, as reported by OBJECT ENCODING
+  pub fn encoding(&self) -> &'static str {
+    match self {
+      Value::Int(_) => "int",
+      Value::Inline { .. } => "embstr",
+      Value::Raw(bytes) if bytes.len() <= EMBSTR_SIZE_LIMIT => "embstr",
+      Value::Raw(_) => "raw",
+    }
+  }
+
+  /// The value as an integer, if it holds one
+  pub fn as_integer(&self) -> Option<i64> {
+    match self {
+      Value::Int(integer) => Some(*integer),
+      _ => None,
+    }
+  }
+
+  /// The value as bytes: a handle to the stored buffer for `raw` strings, a
+  /// small copy for the others
+  pub fn to_bytes(&self) -> Bytes {
+    match self {
+      Value::Int(integer) => Bytes::from(integer.to_string()),
+      Value::Inline { len, bytes } => Bytes::copy_from_slice(&bytes[..*len as usize]),
+      Value::Raw(bytes) => bytes.clone(),
+    }
+  }
+
+  /// Length of the string representation in bytes
+  pub fn len(&self) -> usize {
+    match self {
+      Value::Int(integer) => integer.to_string().len(),
+      Value::Inline { len, .. } => *len as usize,
+      Value::Raw(bytes) => bytes.len(),
+    }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+}
+
+impl From<String> for Value {
+  fn from(value: String) -> Self {
+    Value::new(value)
+  }
+}
+
+impl fmt::Display for Value {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Value::Int(integer) => write!(f, "{}", integer),
+      Value::Inline { len, bytes } => {
+        let s = std::str::from_utf8(&bytes[..*len as usize])
+          .expect("inline values are always built from valid UTF-8");
+        f.write_str(s)
+      }
+      Value::Raw(bytes) => {
+        let s = std::str::from_utf8(bytes).expect("raw values are always built from valid UTF-8");
+        f.write_str(s)
+      }
+    }
+  }
+}
+
+impl MemoryUsage for Value {
+  fn memory_usage(&self) -> usize {
+    match self {
+      Value::Int(_) | Value::Inline { .. } => size_of::<Value>(),
+      Value::Raw(bytes) => size_of::<Value>() + bytes.len(),
+    }
+  }
+}
@@ -0,0 +1,154 @@
+/**
+ * Encryption at rest of RDB snapshots, for deployments that can't keep data
+ * on disk in the clear.
+ *
+ * Snapshots are sealed with AES-256-GCM once a key is configured, which can
+ * come from, in order of precedence:
+ *
+ * - `encryption-key-command`: a program run with its arguments, printing the
+ *   key on stdout, such as a KMS or secrets manager client. It can only be
+ *   set at startup, CONFIG SET would let clients run any program.
+ * - `encryption-key`: the key itself, which CONFIG GET redacts
+ * - the `REDIS_ENCRYPTION_KEY` environment variable
+ *
+ * Keys are 32 bytes written as 64 hex digits. An encrypted file starts with
+ * `MAGIC`, then the 12 bytes nonce, then the ciphertext and its tag; the
+ * header is authenticated along with the snapshot. Loading tells encrypted
+ * files apart from plain ones, so turning encryption on keeps the existing
+ * dump readable and the next save encrypts it. An encrypted dump without the
+ * key, or with another one, stops the server from starting rather than
+ * letting it overwrite the dump with an empty keyspace.
+ */
+use crate::config::Config;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use std::process::{Command, Stdio};
+
+/// Starts every encrypted file, the format version included
+pub const MAGIC: &[u8] = b"REDISRS-ENC1";
+
+/// Environment variable read when neither parameter is set
+pub const KEY_VARIABLE: &str = "REDIS_ENCRYPTION_KEY";
+
+const NONCE_LENGTH: usize = 12;
+
+#[derive(Clone)]
+pub struct EncryptionKey([u8; 32]);
+
+impl EncryptionKey {
+  /// Parses 64 hex digits
+  pub fn parse(hex: &str) -> Result<Self, String> {
+    let bytes = hex::decode(hex.trim()).map_err(|_| "the key must be hex digits".to_string())?;
+    let key: [u8; 32] = bytes
+      .try_into()
+      .map_err(|_| "the key must be 32 bytes, 64 hex digits".to_string())?;
+    Ok(Self(key))
+  }
+
+  fn cipher(&self) -> Aes256Gcm {
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&self.0))
+  }
+}
+
+/// Validates `encryption-key`, empty meaning none
+pub fn validate_key(value: &str) -> Result<(), String> {
+  if value.is_empty() {
+    return Ok(());
+  }
+  EncryptionKey::parse(value).map(|_| ())
+}
+
+/// The key snapshots are encrypted with, `None` when encryption is off
+pub fn key(config: &Config) -> Result<Option<EncryptionKey>, String> {
+  let configured = |name: &str| config.get(name).filter(|value| !value.is_empty());
+  if let Some(command) = configured("encryption-key-command") {
+    let key = run_command(&command)?;
+    return EncryptionKey::parse(&key)
+      .map(Some)
+      .map_err(|e| format!("encryption-key-command printed an invalid key: {}", e));
+  }
+  if let Some(key) = configured("encryption-key") {
+    return EncryptionKey::parse(&key)
+      .map(Some)
+      .map_err(|e| format!("Invalid encryption-key: {}", e));
+  }
+  env_key()
+}
+
+/// The key of `REDIS_ENCRYPTION_KEY`, for tools reading dumps without a
+/// configuration
+pub fn env_key() -> Result<Option<EncryptionKey>, String> {
+  match std::env::var(KEY_VARIABLE) {
+    Ok(key) if !key.is_empty() => EncryptionKey::parse(&key)
+      .map(Some)
+      .map_err(|e| format!("Invalid {}: {}", KEY_VARIABLE, e)),
+    _ => Ok(None),
+  }
+}
+
+fn run_command(command: &str) -> Result<String, String> {
+  let mut words = command.split_whitespace();
+  let program = words.next().unwrap_or_default();
+  let output = Command::new(program)
+    .args(words)
+    .stdin(Stdio::null())
+    .stderr(Stdio::inherit())
+    .output()
+    .map_err(|e| format!("{}: {}", program, e))?;
+  if !output.status.success() {
+    return Err(format!("{} exited with {}", program, output.status));
+  }
+  String::from_utf8(output.stdout).map_err(|_| format!("{} printed invalid UTF-8", program))
+}
+
+pub fn is_encrypted(data: &[u8]) -> bool {
+  data.starts_with(MAGIC)
+}
+
+/// Encrypts a snapshot
+pub fn seal(key: &EncryptionKey, data: &[u8]) -> Result<Vec<u8>, String> {
+  let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+  let mut header = MAGIC.to_vec();
+  header.extend_from_slice(&nonce);
+  let payload = Payload {
+    msg: data,
+    aad: &header,
+  };
+  let ciphertext = key
+    .cipher()
+    .encrypt(&nonce, payload)
+    .map_err(|_| "encryption failed".to_string())?;
+  header.extend_from_slice(&ciphertext);
+  Ok(header)
+}
+
+/// Decrypts a snapshot sealed by `seal`
+pub fn open(key: &EncryptionKey, data: &[u8]) -> Result<Vec<u8>, String> {
+  let header_length = MAGIC.len() + NONCE_LENGTH;
+  if !is_encrypted(data) || data.len() < header_length {
+    return Err("not an encrypted file".to_string());
+  }
+  let (header, ciphertext) = data.split_at(header_length);
+  let payload = Payload {
+    msg: ciphertext,
+    aad: header,
+  };
+  key
+    .cipher()
+    .decrypt(Nonce::from_slice(&header[MAGIC.len()..]), payload)
+    .map_err(|_| "decryption failed, wrong key or corrupted file".to_string())
+}
+
+/// The plain contents of a file that may be encrypted
+pub fn decrypt(data: Vec<u8>, key: Option<&EncryptionKey>) -> Result<Vec<u8>, String> {
+  if !is_encrypted(&data) {
+    return Ok(data);
+  }
+  match key {
+    Some(key) => open(key, &data),
+    None => Err(format!(
+      "the file is encrypted, set encryption-key, encryption-key-command or {}",
+      KEY_VARIABLE
+    )),
+  }
+}
@@ -0,0 +1,157 @@
+/**
+ * Eviction of keys past `maxmemory`, as in Redis.
+ *
+ * Before a write command runs, keys are removed while the dataset
+ * (`used_memory_dataset`) is over `maxmemory`, picked by `maxmemory-policy`:
+ *
+ * - `noeviction`: none
+ * - `allkeys-lru`, `volatile-lru`: the least recently accessed of
+ *   `maxmemory-samples` keys taken at random, among all keys or the ones with
+ *   a deadline
+ * - `allkeys-random`, `volatile-random`: a key taken at random
+ * - `volatile-ttl`: the key closest to expiring
+ *
+ * A volatile policy falls back to the keys closest to expiring when none of
+ * the sampled keys has a deadline. When nothing can be evicted, the writes
+ * that may grow the dataset are refused with an OOM error, while deletions
+ * still run. Lowering `maxmemory` with CONFIG SET evicts right away.
+ *
+ * Evicted keys are counted in `evicted_keys`, appear in the change feed and
+ * are key events; `lazyfree-lazy-eviction` frees their large values in the
+ * background.
+ */
+use crate::allocator::human_bytes;
+use crate::config::{parse_memory, Config};
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+/// The values of `maxmemory-policy`
+pub const POLICIES: [&str; 6] = [
+  "noeviction",
+  "allkeys-lru",
+  "allkeys-random",
+  "volatile-lru",
+  "volatile-random",
+  "volatile-ttl",
+];
+
+/// Default `maxmemory-samples`
+pub const DEFAULT_SAMPLES: usize = 5;
+
+/// Reply to the writes refused past `maxmemory`
+pub const OOM_ERROR: &str = "OOM command not allowed when used memory > 'maxmemory'.";
+
+/// Which keys go first past `maxmemory`, in the order of `POLICIES`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionPolicy {
+  NoEviction,
+  AllKeysLru,
+  AllKeysRandom,
+  VolatileLru,
+  VolatileRandom,
+  VolatileTtl,
+}
+
+impl EvictionPolicy {
+  const ALL: [EvictionPolicy; 6] = [
+    EvictionPolicy::NoEviction,
+    EvictionPolicy::AllKeysLru,
+    EvictionPolicy::AllKeysRandom,
+    EvictionPolicy::VolatileLru,
+    EvictionPolicy::VolatileRandom,
+    EvictionPolicy::VolatileTtl,
+  ];
+
+  pub fn parse(name: &str) -> Option<Self> {
+    POLICIES
+      .iter()
+      .position(|policy| policy.eq_ignore_ascii_case(name))
+      .map(|index| Self::ALL[index])
+  }
+
+  pub fn name(&self) -> &'static str {
+    POLICIES[*self as usize]
+  }
+
+  /// Whether only the keys with a deadline may be evicted
+  pub fn volatile(&self) -> bool {
+    matches!(
+      self,
+      EvictionPolicy::VolatileLru | EvictionPolicy::VolatileRandom | EvictionPolicy::VolatileTtl
+    )
+  }
+}
+
+/// The `maxmemory` settings of a keyspace
+pub struct Eviction {
+  max_memory: AtomicUsize,
+  policy: AtomicU8,
+  samples: AtomicUsize,
+  /// Held while evicting, see `begin`
+  running: Mutex<()>,
+}
+
+impl Eviction {
+  pub fn new() -> Self {
+    Self {
+      max_memory: AtomicUsize::new(0),
+      policy: AtomicU8::new(EvictionPolicy::NoEviction as u8),
+      samples: AtomicUsize::new(DEFAULT_SAMPLES),
+      running: Mutex::new(()),
+    }
+  }
+
+  /// Evicts one writer at a time: writers on different keys share the
+  /// keyspace (see `storagelock`) and would pick the same keys
+  pub fn begin(&self) -> MutexGuard<'_, ()> {
+    self.running.lock().unwrap()
+  }
+
+  /// Reads `maxmemory`, `maxmemory-policy` and `maxmemory-samples`
+  pub fn apply_config(&self, config: &Config) {
+    let max_memory = config
+      .get("maxmemory")
+      .and_then(|value| parse_memory(&value))
+      .unwrap_or(0) as usize;
+    self.max_memory.store(max_memory, Ordering::Relaxed);
+    let policy = config
+      .get("maxmemory-policy")
+      .and_then(|value| EvictionPolicy::parse(&value))
+      .unwrap_or(EvictionPolicy::NoEviction);
+    self.policy.store(policy as u8, Ordering::Relaxed);
+    let samples = config
+      .get("maxmemory-samples")
+      .and_then(|value| value.parse().ok())
+      .unwrap_or(DEFAULT_SAMPLES);
+    self.samples.store(samples, Ordering::Relaxed);
+  }
+
+  /// `maxmemory` in bytes, 0 when eviction is off
+  pub fn max_memory(&self) -> usize {
+    self.max_memory.load(Ordering::Relaxed)
+  }
+
+  pub fn policy(&self) -> EvictionPolicy {
+    EvictionPolicy::ALL[self.policy.load(Ordering::Relaxed) as usize]
+  }
+
+  /// Keys sampled to pick one to evict
+  pub fn samples(&self) -> usize {
+    self.samples.load(Ordering::Relaxed)
+  }
+
+  /// Lines of the `# Memory` INFO section
+  pub fn info(&self) -> Vec<String> {
+    vec![
+      format!("maxmemory:{}", self.max_memory()),
+      format!("maxmemory_human:{}", human_bytes(self.max_memory())),
+      format!("maxmemory_policy:{}", self.policy().name()),
+    ]
+  }
+}
+
+impl Default for Eviction {
+  fn default() -> Self {
+    Self::new()
+  }
+}
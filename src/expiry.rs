@@ -0,0 +1,154 @@
+/**
+ * Secondary index over keys that carry a TTL.
+ *
+ * The keyspace itself is a hash map, so finding the keys that are about to expire
+ * would otherwise mean scanning everything. The index keeps the deadlines ordered
+ * so active expiration (and TTL-based eviction) only touch the keys they need.
+ */
+use crate::cron::Scheduler;
+use crate::storagelock::StorageLock;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// How often the active expiration cycle runs (Redis's default `hz 10`)
+pub const ACTIVE_EXPIRE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Upper bound of keys reclaimed per cycle so a mass expiry can't starve clients
+pub const ACTIVE_EXPIRE_KEYS_PER_CYCLE: usize = 200;
+
+/// Time a cycle may take before it counts as an overrun, a quarter of the
+/// interval like Redis's `ACTIVE_EXPIRE_CYCLE_SLOW_TIME_PERC`
+const ACTIVE_EXPIRE_BUDGET: Duration = Duration::from_millis(25);
+
+#[derive(Default)]
+struct IndexInner {
+  deadlines: BTreeSet<(Instant, String)>,
+  keys: HashMap<String, Instant>,
+}
+
+/// Deadline-ordered index of volatile keys
+#[derive(Default)]
+pub struct ExpiryIndex {
+  inner: Mutex<IndexInner>,
+  /// Set by DEBUG SET-ACTIVE-EXPIRE 0, keys then only expire when accessed
+  active_expire_disabled: AtomicBool,
+}
+
+impl ExpiryIndex {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Turns the active expiration cycle on or off
+  pub fn set_active_expire(&self, enabled: bool) {
+    self
+      .active_expire_disabled
+      .store(!enabled, Ordering::Relaxed);
+  }
+
+  pub fn active_expire_enabled(&self) -> bool {
+    !self.active_expire_disabled.load(Ordering::Relaxed)
+  }
+
+  /// Records (or moves) the deadline of `key`
+  pub fn insert(&self, key: &str, deadline: Instant) {
+    let mut inner = self.inner.lock().unwrap();
+    if let Some(previous) = inner.keys.insert(key.to_string(), deadline) {
+      inner.deadlines.remove(&(previous, key.to_string()));
+    }
+    inner.deadlines.insert((deadline, key.to_string()));
+  }
+
+  /// Forgets the deadline of `key`, if any
+  pub fn remove(&self, key: &str) {
+    let mut inner = self.inner.lock().unwrap();
+    if let Some(previous) = inner.keys.remove(key) {
+      inner.deadlines.remove(&(previous, key.to_string()));
+    }
+  }
+
+  /// Removes and returns up to `limit` keys whose deadline is before `now`
+  pub fn pop_expired(&self, now: Instant, limit: usize) -> Vec<String> {
+    let mut inner = self.inner.lock().unwrap();
+    let mut expired = Vec::new();
+
+    while expired.len() < limit {
+      let due = match inner.deadlines.first() {
+        Some((deadline, _)) => *deadline <= now,
+        None => false,
+      };
+      if !due {
+        break;
+      }
+
+      if let Some((_, key)) = inner.deadlines.pop_first() {
+        inner.keys.remove(&key);
+        expired.push(key);
+      }
+    }
+
+    expired
+  }
+
+  /// The `count` keys closest to expiring, soonest first
+  pub fn soonest(&self, count: usize) -> Vec<(String, Instant)> {
+    let inner = self.inner.lock().unwrap();
+    inner
+      .deadlines
+      .iter()
+      .take(count)
+      .map(|(deadline, key)| (key.clone(), *deadline))
+      .collect()
+  }
+
+  /// Average time left at `now` before the indexed keys expire
+  pub fn average_ttl(&self, now: Instant) -> Duration {
+    let inner = self.inner.lock().unwrap();
+    if inner.deadlines.is_empty() {
+      return Duration::ZERO;
+    }
+    let total: Duration = inner
+      .deadlines
+      .iter()
+      .map(|(deadline, _)| deadline.saturating_duration_since(now))
+      .sum();
+    total / inner.deadlines.len() as u32
+  }
+
+  /// Number of keys with a deadline
+  pub fn len(&self) -> usize {
+    self.inner.lock().unwrap().keys.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  pub fn clear(&self) {
+    let mut inner = self.inner.lock().unwrap();
+    inner.deadlines.clear();
+    inner.keys.clear();
+  }
+}
+
+/// Schedules the job that periodically reclaims expired keys without waiting
+/// for them to be read
+pub fn register_active_expire(scheduler: &mut Scheduler, storage: Arc<StorageLock>) {
+  scheduler.every(
+    "active-expire",
+    ACTIVE_EXPIRE_INTERVAL,
+    ACTIVE_EXPIRE_BUDGET,
+    move || {
+      let storage = storage.clone();
+      async move {
+        let storage = storage.lock().await;
+        if storage.expiry_index().active_expire_enabled() {
+          storage.active_expire_cycle(ACTIVE_EXPIRE_KEYS_PER_CYCLE);
+        }
+      }
+    },
+  );
+}
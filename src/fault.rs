@@ -0,0 +1,152 @@
+/**
+ * Fault injection, for applications to check how they cope with a misbehaving
+ * server. DEBUG FAULT turns faults on and off at runtime, none are active
+ * until then:
+ *
+ * - `DEBUG FAULT LATENCY <milliseconds> [<percent>]` delays commands, all of
+ *   them or the given share
+ * - `DEBUG FAULT CONNECTION-RESET <percent>` hangs up on clients at that share
+ *   of their commands, before running them and without replying
+ * - `DEBUG FAULT RDB-WRITE-ERROR <0|1>` fails every RDB save, BGSAVE and the
+ *   save at shutdown alike, as a full disk would
+ * - `DEBUG FAULT OFF` clears them all, `DEBUG FAULT LIST` shows them
+ *
+ * DEBUG commands are spared, so that faults can always be turned off again.
+ * The server has no append only file nor replication link, so there are no
+ * faults for them. Each server has faults of its own, like its slow log.
+ */
+use crate::parser::RedisValue;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+use tracing::warn;
+
+pub struct Faults {
+  latency_ms: AtomicU64,
+  latency_percent: AtomicU64,
+  reset_percent: AtomicU64,
+  rdb_write_error: AtomicBool,
+}
+
+impl Faults {
+  pub fn new() -> Self {
+    Self {
+      latency_ms: AtomicU64::new(0),
+      latency_percent: AtomicU64::new(0),
+      reset_percent: AtomicU64::new(0),
+      rdb_write_error: AtomicBool::new(false),
+    }
+  }
+
+  /// How long to hold the next command back, if at all
+  pub fn latency(&self) -> Option<Duration> {
+    let latency_ms = self.latency_ms.load(Ordering::Relaxed);
+    (latency_ms > 0 && chance(self.latency_percent.load(Ordering::Relaxed)))
+      .then(|| Duration::from_millis(latency_ms))
+  }
+
+  /// Whether to hang up on the client instead of running its next command
+  pub fn reset_connection(&self) -> bool {
+    chance(self.reset_percent.load(Ordering::Relaxed))
+  }
+
+  /// Whether RDB saves fail
+  pub fn rdb_write_error(&self) -> bool {
+    self.rdb_write_error.load(Ordering::Relaxed)
+  }
+
+  fn clear(&self) {
+    self.latency_ms.store(0, Ordering::Relaxed);
+    self.latency_percent.store(0, Ordering::Relaxed);
+    self.reset_percent.store(0, Ordering::Relaxed);
+    self.rdb_write_error.store(false, Ordering::Relaxed);
+  }
+
+  /// DEBUG FAULT
+  pub fn handle_command(&self, args: &[String]) -> RedisValue {
+    let ok = || RedisValue::SimpleString("OK".to_string());
+    let percent = |value: &str| value.parse::<u64>().ok().filter(|percent| *percent <= 100);
+    let invalid_percent = || RedisValue::Error("ERR percent must be between 0 and 100".to_string());
+
+    let unknown = || {
+      RedisValue::Error(
+        "ERR unknown fault or wrong number of arguments. Try DEBUG HELP.".to_string(),
+      )
+    };
+    let Some((fault, args)) = args.split_first() else {
+      return unknown();
+    };
+    match (fault.to_uppercase().as_str(), args) {
+      ("LATENCY", [latency_ms, rest @ ..]) if rest.len() <= 1 => {
+        let Ok(latency_ms) = latency_ms.parse::<u64>() else {
+          return RedisValue::Error("ERR value is not an integer or out of range".to_string());
+        };
+        let Some(latency_percent) = rest.first().map_or(Some(100), |value| percent(value)) else {
+          return invalid_percent();
+        };
+        self.latency_ms.store(latency_ms, Ordering::Relaxed);
+        self
+          .latency_percent
+          .store(latency_percent, Ordering::Relaxed);
+        warn!(
+          "Injecting {}ms of latency into {}% of commands",
+          latency_ms, latency_percent
+        );
+        ok()
+      }
+      ("CONNECTION-RESET", [reset_percent]) => {
+        let Some(reset_percent) = percent(reset_percent) else {
+          return invalid_percent();
+        };
+        self.reset_percent.store(reset_percent, Ordering::Relaxed);
+        warn!("Resetting connections at {}% of commands", reset_percent);
+        ok()
+      }
+      ("RDB-WRITE-ERROR", [enabled]) => match enabled.as_str() {
+        "0" | "1" => {
+          self
+            .rdb_write_error
+            .store(enabled == "1", Ordering::Relaxed);
+          if enabled == "1" {
+            warn!("Failing RDB saves");
+          }
+          ok()
+        }
+        _ => RedisValue::Error("ERR value is not an integer or out of range".to_string()),
+      },
+      ("OFF", []) => {
+        self.clear();
+        ok()
+      }
+      ("LIST", []) => RedisValue::Array(vec![
+        format!(
+          "latency:{}ms,{}%",
+          self.latency_ms.load(Ordering::Relaxed),
+          self.latency_percent.load(Ordering::Relaxed)
+        ),
+        format!(
+          "connection-reset:{}%",
+          self.reset_percent.load(Ordering::Relaxed)
+        ),
+        format!("rdb-write-error:{}", self.rdb_write_error() as u8),
+      ]),
+      _ => unknown(),
+    }
+  }
+}
+
+impl Default for Faults {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+/// True `percent` times out of a hundred
+fn chance(percent: u64) -> bool {
+  match percent {
+    0 => false,
+    100.. => true,
+    _ => RandomState::new().build_hasher().finish() % 100 < percent,
+  }
+}
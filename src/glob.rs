@@ -0,0 +1,100 @@
+//! Glob-style pattern matching, with the same rules as Redis's `stringmatchlen`:
+//!  - `*` matches any sequence of characters, `?` any single character
+//!  - `[abc]`, `[a-z]` and `[^a-z]` match character classes
+//!  - `\` escapes the next character
+
+/// Whether `string` matches the glob `pattern`
+pub fn glob_match(pattern: &str, string: &str) -> bool {
+  matches(pattern.as_bytes(), string.as_bytes(), false)
+}
+
+/// Case insensitive variant of `glob_match`
+pub fn glob_match_nocase(pattern: &str, string: &str) -> bool {
+  matches(pattern.as_bytes(), string.as_bytes(), true)
+}
+
+fn matches(pattern: &[u8], string: &[u8], nocase: bool) -> bool {
+  let eq = |a: u8, b: u8| {
+    if nocase {
+      a.eq_ignore_ascii_case(&b)
+    } else {
+      a == b
+    }
+  };
+
+  let (mut p, mut s) = (0, 0);
+  while p < pattern.len() {
+    match pattern[p] {
+      b'*' => {
+        // Collapse consecutive stars, then try every possible split
+        while p + 1 < pattern.len() && pattern[p + 1] == b'*' {
+          p += 1;
+        }
+        if p + 1 == pattern.len() {
+          return true;
+        }
+        return (s..=string.len())
+          .any(|start| matches(&pattern[p + 1..], &string[start..], nocase));
+      }
+      b'?' => {
+        if s >= string.len() {
+          return false;
+        }
+        s += 1;
+      }
+      b'[' => {
+        if s >= string.len() {
+          return false;
+        }
+        p += 1;
+        let negate = p < pattern.len() && pattern[p] == b'^';
+        if negate {
+          p += 1;
+        }
+
+        let mut matched = false;
+        while p < pattern.len() && pattern[p] != b']' {
+          if pattern[p] == b'\\' && p + 1 < pattern.len() {
+            p += 1;
+            matched |= eq(pattern[p], string[s]);
+          } else if p + 2 < pattern.len() && pattern[p + 1] == b'-' && pattern[p + 2] != b']' {
+            let (mut start, mut end) = (pattern[p], pattern[p + 2]);
+            if start > end {
+              std::mem::swap(&mut start, &mut end);
+            }
+            let c = string[s];
+            matched |= (start..=end).contains(&c)
+              || (nocase
+                && ((start..=end).contains(&c.to_ascii_lowercase())
+                  || (start..=end).contains(&c.to_ascii_uppercase())));
+            p += 2;
+          } else {
+            matched |= eq(pattern[p], string[s]);
+          }
+          p += 1;
+        }
+
+        if matched == negate {
+          return false;
+        }
+        s += 1;
+      }
+      b'\\' if p + 1 < pattern.len() => {
+        p += 1;
+        if s >= string.len() || !eq(pattern[p], string[s]) {
+          return false;
+        }
+        s += 1;
+      }
+      c => {
+        if s >= string.len() || !eq(c, string[s]) {
+          return false;
+        }
+        s += 1;
+      }
+    }
+    p += 1;
+  }
+
+  s == string.len()
+}
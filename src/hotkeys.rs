@@ -0,0 +1,150 @@
+/**
+ * Hot keys: the most accessed keys of the keyspace, for operators chasing a
+ * skewed workload, reported by DEBUG HOTKEYS and the `hotkeys` INFO section.
+ *
+ * Every read and write of a key is counted by a Space-Saving sketch of
+ * `hotkeys-capacity` counters: a key holding a counter gets it incremented,
+ * a new one takes over the counter of the least accessed key and starts from
+ * its count. The counts of the keys accessed more than a capacity-th of the
+ * time are always there, and never below the truth; a key's `error` bounds
+ * how much it may be above. 0 turns the tracking off.
+ *
+ * Counts are since startup, or the last DEBUG HOTKEYS RESET.
+ */
+use crate::config::Config;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Counters kept when `hotkeys-capacity` isn't set
+pub const DEFAULT_CAPACITY: usize = 64;
+
+/// Keys the INFO section lists
+const INFO_KEYS: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Counter {
+  /// Accesses counted, at most `error` above the truth
+  pub count: u64,
+  pub error: u64,
+}
+
+pub struct HotKeys {
+  capacity: AtomicUsize,
+  counters: Mutex<HashMap<String, Counter>>,
+}
+
+impl Default for HotKeys {
+  fn default() -> Self {
+    Self {
+      capacity: AtomicUsize::new(DEFAULT_CAPACITY),
+      counters: Mutex::new(HashMap::new()),
+    }
+  }
+}
+
+impl HotKeys {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Reads `hotkeys-capacity`, forgetting the least accessed keys when it
+  /// shrank
+  pub fn apply_config(&self, config: &Config) {
+    let capacity = config
+      .get("hotkeys-capacity")
+      .and_then(|value| value.parse().ok())
+      .unwrap_or(DEFAULT_CAPACITY);
+    self.capacity.store(capacity, Ordering::Relaxed);
+    let mut counters = self.counters.lock().unwrap();
+    if counters.len() > capacity {
+      let mut keys: Vec<(String, u64)> = counters
+        .iter()
+        .map(|(key, counter)| (key.clone(), counter.count))
+        .collect();
+      keys.sort_by_key(|(_, count)| *count);
+      for (key, _) in keys.into_iter().take(counters.len() - capacity) {
+        counters.remove(&key);
+      }
+    }
+  }
+
+  /// Counts an access to `key`
+  pub fn record(&self, key: &str) {
+    let capacity = self.capacity.load(Ordering::Relaxed);
+    if capacity == 0 {
+      return;
+    }
+    let mut counters = self.counters.lock().unwrap();
+    if let Some(counter) = counters.get_mut(key) {
+      counter.count += 1;
+      return;
+    }
+    if counters.len() < capacity {
+      counters.insert(key.to_string(), Counter { count: 1, error: 0 });
+      return;
+    }
+    // The capacity is small, a walk is cheaper than keeping the counters
+    // ordered on every access
+    let Some((least, count)) = counters
+      .iter()
+      .min_by_key(|(_, counter)| counter.count)
+      .map(|(key, counter)| (key.clone(), counter.count))
+    else {
+      return;
+    };
+    counters.remove(&least);
+    counters.insert(
+      key.to_string(),
+      Counter {
+        count: count + 1,
+        error: count,
+      },
+    );
+  }
+
+  /// The `count` most accessed keys, most accessed first
+  pub fn top(&self, count: usize) -> Vec<(String, Counter)> {
+    let mut keys: Vec<(String, Counter)> = self
+      .counters
+      .lock()
+      .unwrap()
+      .iter()
+      .map(|(key, counter)| (key.clone(), *counter))
+      .collect();
+    keys.sort_by(|(a, a_counter), (b, b_counter)| {
+      b_counter.count.cmp(&a_counter.count).then_with(|| a.cmp(b))
+    });
+    keys.truncate(count);
+    keys
+  }
+
+  pub fn reset(&self) {
+    self.counters.lock().unwrap().clear();
+  }
+
+  /// Lines of the `# Hotkeys` INFO section
+  pub fn info(&self) -> Vec<String> {
+    let mut lines = vec![format!(
+      "hotkeys_capacity:{}",
+      self.capacity.load(Ordering::Relaxed)
+    )];
+    lines.extend(
+      self
+        .top(INFO_KEYS)
+        .into_iter()
+        .enumerate()
+        .map(|(rank, (key, counter))| {
+          // Escaped, a key could hold line breaks
+          format!(
+            "hotkey{}:key={},accesses={},error={}",
+            rank,
+            key.escape_debug(),
+            counter.count,
+            counter.error
+          )
+        }),
+    );
+    lines
+  }
+}
@@ -0,0 +1,158 @@
+/**
+ * Mass insertion at startup: `--import-protocol <file>` runs the commands of a
+ * file in the Redis protocol, as generated for `redis-cli --pipe`, before any
+ * client is accepted.
+ *
+ * The file is streamed to an in-process client, so the commands take the same
+ * path as over the network (ACL, cluster slots, stats) and millions of them
+ * never need to fit in memory. Like any other client it is logged in as the
+ * default user when that one has no password; otherwise the file can start
+ * with AUTH. Errors don't stop the import, they are counted and reported at
+ * the end like `redis-cli --pipe` does.
+ */
+use crate::acl::Acl;
+use crate::clients::ClientRegistry;
+use crate::cluster::Cluster;
+use crate::config::Config;
+use crate::connection::Connection;
+use crate::module::ModuleCommands;
+use crate::ratelimit::RateLimiter;
+use crate::readthrough::ReadThrough;
+use crate::server::serve_client;
+use crate::state::ServerState;
+use crate::storagelock::StorageLock;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::Mutex as AsyncMutex;
+use tracing::{info, warn, Instrument};
+
+/// Bytes of the file in flight between the reader and the client
+const PIPE_SIZE: usize = 64 * 1024;
+
+/// What an import ran
+#[derive(Debug, Default, PartialEq)]
+pub struct ImportSummary {
+  pub replies: u64,
+  pub errors: u64,
+}
+
+/// Runs the commands of the protocol file at `path`
+#[allow(clippy::too_many_arguments)]
+pub async fn import_protocol(
+  path: &str,
+  storage: Arc<StorageLock>,
+  config: Arc<AsyncMutex<Config>>,
+  clients: Arc<ClientRegistry>,
+  acl: Arc<Acl>,
+  rate_limiter: Arc<RateLimiter>,
+  cluster: Arc<Cluster>,
+  modules: Arc<ModuleCommands>,
+  read_through: Arc<ReadThrough>,
+  state: Arc<ServerState>,
+) -> Result<ImportSummary, String> {
+  let mut file = tokio::fs::File::open(path)
+    .await
+    .map_err(|e| format!("Can't open the protocol file {}: {}", path, e))?;
+  info!("Importing {}", path);
+  let started = Instant::now();
+
+  let (client, server) = tokio::io::duplex(PIPE_SIZE);
+  let (mut replies, mut commands) = tokio::io::split(client);
+  let addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0));
+  let session = tokio::spawn(
+    serve_client(
+      Connection::new(server),
+      addr,
+      addr,
+      -1,
+      storage,
+      config,
+      clients,
+      acl,
+      rate_limiter,
+      cluster,
+      modules,
+      read_through,
+      state,
+    )
+    .instrument(tracing::info_span!("import")),
+  );
+
+  // Feeding the commands while the replies are read, so neither side stalls
+  let feed = async move {
+    let copied = tokio::io::copy(&mut file, &mut commands).await;
+    // Hanging up ends the session once the last command ran
+    let _ = commands.shutdown().await;
+    copied
+  };
+  let count = async move {
+    let mut summary = ImportSummary::default();
+    let mut pending: Vec<u8> = Vec::new();
+    let mut chunk = [0; PIPE_SIZE];
+    loop {
+      match replies.read(&mut chunk).await {
+        Ok(0) | Err(_) => break,
+        Ok(n) => pending.extend_from_slice(&chunk[..n]),
+      }
+      let mut position = 0;
+      while let Some(length) = reply_length(&pending[position..]) {
+        if pending[position] == b'-' {
+          if summary.errors == 0 {
+            let line = String::from_utf8_lossy(&pending[position + 1..position + length - 2]);
+            warn!("First error while importing: {}", line);
+          }
+          summary.errors += 1;
+        }
+        summary.replies += 1;
+        position += length;
+      }
+      pending.drain(..position);
+    }
+    summary
+  };
+  let (copied, summary) = tokio::join!(feed, count);
+  let _ = session.await;
+  let bytes = copied.map_err(|e| format!("Failed to read {}: {}", path, e))?;
+
+  info!(
+    "Imported {} bytes from {} in {:.3} seconds: {} replies, {} errors",
+    bytes,
+    path,
+    started.elapsed().as_secs_f64(),
+    summary.replies,
+    summary.errors
+  );
+  Ok(summary)
+}
+
+/// Length of the first complete reply in `buffer`, arrays included, or None
+/// until more bytes arrive
+pub fn reply_length(buffer: &[u8]) -> Option<usize> {
+  let end = buffer.windows(2).position(|window| window == b"\r\n")?;
+  let number = || {
+    std::str::from_utf8(&buffer[1..end])
+      .ok()?
+      .parse::<i64>()
+      .ok()
+  };
+  match buffer[0] {
+    b'+' | b'-' | b':' => Some(end + 2),
+    b'$' => match number()? {
+      length if length < 0 => Some(end + 2),
+      length => {
+        let total = end + 2 + length as usize + 2;
+        (total <= buffer.len()).then_some(total)
+      }
+    },
+    b'*' => {
+      let mut position = end + 2;
+      for _ in 0..number()?.max(0) {
+        position += reply_length(&buffer[position..])?;
+      }
+      Some(position)
+    }
+    _ => None,
+  }
+}
@@ -0,0 +1,253 @@
+/**
+ * INFO: the server report monitoring agents scrape.
+ *
+ * The report is split in sections, each one rendered by the subsystem that owns
+ * the data (the client registry, the allocator, the RDB writer...). Sections can
+ * be picked by name, `default` (or no argument) gives the usual ones, `all` adds
+ * the per-command statistics and `everything` is `all` plus module data.
+ */
+use crate::address;
+use crate::allocator;
+use crate::arguments::generate_replication_id;
+use crate::clients::{max_clients, ClientRegistry};
+use crate::commandstats::info_percentiles;
+use crate::config::Config;
+use crate::cron;
+use crate::iothreads;
+use crate::rdb;
+use crate::state::ServerState;
+use crate::storagelock::StorageLock;
+use std::sync::{Arc, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex as AsyncMutex;
+use tokio::time::Instant;
+
+const DEFAULT_SECTIONS: [&str; 9] = [
+  "server",
+  "clients",
+  "memory",
+  "persistence",
+  "stats",
+  "replication",
+  "cpu",
+  "cluster",
+  "keyspace",
+];
+
+/// Sections `all` adds to the default ones
+const EXTRA_SECTIONS: [&str; 5] = [
+  "commandstats",
+  "latencystats",
+  "cronstats",
+  "hotkeys",
+  "ttl",
+];
+
+/// Clock ticks per second of the CPU times in procfs (USER_HZ)
+const CLOCK_TICKS_PER_SECOND: f64 = 100.0;
+
+struct Startup {
+  at: Instant,
+  unix_time: u64,
+  run_id: String,
+}
+
+static STARTUP: OnceLock<Startup> = OnceLock::new();
+
+fn startup() -> &'static Startup {
+  STARTUP.get_or_init(|| Startup {
+    at: Instant::now(),
+    unix_time: SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .unwrap_or_default()
+      .as_secs(),
+    run_id: generate_replication_id(),
+  })
+}
+
+/// Records the server start, uptime is counted from here
+pub fn record_startup() {
+  startup();
+}
+
+/// Unix time the server started at
+pub fn started_at() -> u64 {
+  startup().unix_time
+}
+
+fn server_info(config: &Config) -> Vec<String> {
+  let uptime = startup().at.elapsed().as_secs();
+  let now = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap_or_default();
+  let executable = std::env::current_exe()
+    .map(|path| path.display().to_string())
+    .unwrap_or_default();
+  vec![
+    format!("redis_version:{}", env!("CARGO_PKG_VERSION")),
+    "redis_git_sha1:00000000".to_string(),
+    "redis_git_dirty:0".to_string(),
+    "redis_mode:standalone".to_string(),
+    format!("os:{} {}", std::env::consts::OS, std::env::consts::ARCH),
+    format!("arch_bits:{}", usize::BITS),
+    "multiplexing_api:tokio".to_string(),
+    format!("process_id:{}", std::process::id()),
+    format!("run_id:{}", startup().run_id),
+    format!(
+      "tcp_port:{}",
+      config.get("port").unwrap_or_else(|| "6379".to_string())
+    ),
+    format!("server_time_usec:{}", now.as_micros()),
+    format!("uptime_in_seconds:{}", uptime),
+    format!("uptime_in_days:{}", uptime / 86400),
+    "hz:10".to_string(),
+    format!("executable:{}", executable),
+    format!(
+      "config_file:{}",
+      config.get("config-file").unwrap_or_default()
+    ),
+  ]
+}
+
+/// Lines of the `# Replication` INFO section
+pub fn replication_info(config: &Config) -> Vec<String> {
+  let role = if config.has("replicaof") {
+    "slave"
+  } else {
+    "master"
+  };
+  let mut info = vec![format!("role:{}", role)];
+  if let Some((host, port)) = config
+    .get("replicaof")
+    .and_then(|master| address::parse_host_port(&master).ok())
+  {
+    info.push(format!("master_host:{}", host));
+    info.push(format!("master_port:{}", port));
+  }
+  info.extend([
+    "connected_slaves:0".to_string(),
+    format!(
+      "master_replid:{}",
+      config.get("replication_id").unwrap_or_default()
+    ),
+    format!(
+      "master_repl_offset:{}",
+      config
+        .get("replication_offset")
+        .unwrap_or_else(|| "0".to_string())
+    ),
+  ]);
+  info
+}
+
+/// User and system CPU seconds used by the process, from procfs
+fn cpu_info() -> Vec<String> {
+  let (user, system) = std::fs::read_to_string("/proc/self/stat")
+    .ok()
+    .and_then(|stat| {
+      // Fields after the command name, which is in parentheses and may hold spaces
+      let fields: Vec<String> = stat
+        .rsplit_once(')')?
+        .1
+        .split_whitespace()
+        .map(str::to_string)
+        .collect();
+      let user = fields.get(11)?.parse::<f64>().ok()?;
+      let system = fields.get(12)?.parse::<f64>().ok()?;
+      Some((
+        user / CLOCK_TICKS_PER_SECOND,
+        system / CLOCK_TICKS_PER_SECOND,
+      ))
+    })
+    .unwrap_or((0.0, 0.0));
+  vec![
+    format!("used_cpu_sys:{:.6}", system),
+    format!("used_cpu_user:{:.6}", user),
+    "used_cpu_sys_children:0.000000".to_string(),
+    "used_cpu_user_children:0.000000".to_string(),
+  ]
+}
+
+/// Expands INFO arguments into the list of sections to render
+fn requested_sections(arguments: &[String]) -> Vec<String> {
+  if arguments.is_empty() {
+    return DEFAULT_SECTIONS.iter().map(|s| s.to_string()).collect();
+  }
+
+  let mut sections: Vec<String> = Vec::new();
+  for argument in arguments {
+    let expanded: Vec<&str> = match argument.to_lowercase().as_str() {
+      "default" => DEFAULT_SECTIONS.to_vec(),
+      "all" | "everything" => DEFAULT_SECTIONS
+        .iter()
+        .chain(EXTRA_SECTIONS.iter())
+        .copied()
+        .collect(),
+      _ => vec![argument.as_str()],
+    };
+    for section in expanded {
+      let section = section.to_lowercase();
+      if !sections.contains(&section) {
+        sections.push(section);
+      }
+    }
+  }
+  sections
+}
+
+/// Renders the INFO report for the requested sections
+pub async fn info(
+  arguments: &[String],
+  storage: &Arc<StorageLock>,
+  config: &Arc<AsyncMutex<Config>>,
+  clients: &ClientRegistry,
+  state: &ServerState,
+) -> String {
+  let mut report: Vec<String> = Vec::new();
+
+  for section in requested_sections(arguments) {
+    let (title, lines) = match section.as_str() {
+      "server" => ("Server", server_info(&*config.lock().await)),
+      "clients" => ("Clients", clients.info(max_clients(&*config.lock().await))),
+      "memory" => {
+        let storage = storage.lock().await;
+        let mut lines = allocator::memory_info(&storage);
+        lines.push(format!("mem_clients_normal:{}", clients.memory()));
+        ("Memory", lines)
+      }
+      "persistence" => ("Persistence", rdb::persistence_info(state)),
+      "stats" => {
+        let mut lines = state.stats.info();
+        let io_threads = iothreads::io_threads(&*config.lock().await);
+        lines.push(format!("io_threads_active:{}", (io_threads > 1) as u8));
+        lines.extend(storage.lock().await.negative_cache().info());
+        ("Stats", lines)
+      }
+      "replication" => ("Replication", replication_info(&*config.lock().await)),
+      "cpu" => ("CPU", cpu_info()),
+      "cluster" => ("Cluster", vec!["cluster_enabled:0".to_string()]),
+      "keyspace" => ("Keyspace", storage.lock().await.keyspace_info()),
+      "commandstats" => ("Commandstats", state.stats.commands.info()),
+      "latencystats" => {
+        let percentiles = info_percentiles(&*config.lock().await);
+        (
+          "Latencystats",
+          state.stats.commands.latency_info(&percentiles),
+        )
+      }
+      "cronstats" => ("Cronstats", cron::info()),
+      "hotkeys" => ("Hotkeys", storage.lock().await.hotkeys().info()),
+      "ttl" => ("Ttl", storage.lock().await.ttl_histogram().latest().info()),
+      _ => continue,
+    };
+
+    let mut section = format!("# {}\r\n", title);
+    for line in lines {
+      section.push_str(&line);
+      section.push_str("\r\n");
+    }
+    report.push(section);
+  }
+
+  report.join("\r\n")
+}
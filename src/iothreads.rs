@@ -0,0 +1,102 @@
+/**
+ * `io-threads N` serves connections on N threads of their own, each running a
+ * single threaded runtime, rather than on the server's work-stealing runtime.
+ * Connections are handed to the threads round robin and stay on theirs for
+ * their whole life, so the reads, parsing and replies of a client never move
+ * between cores and the threads share nothing but the server's state.
+ *
+ * Unlike Redis's I/O threads these run the commands too, not just networking
+ * and the protocol: a command on keys only locks its keys (see
+ * `storagelock`), so the threads serve commands on different keys at the same
+ * time. Commands without keys, such as KEYS or FLUSHALL, still take the whole
+ * keyspace. The default, 1, keeps connections on the server's runtime.
+ */
+use crate::config::Config;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::net::TcpStream;
+use tokio::runtime::Handle;
+use tokio::sync::oneshot;
+use tracing::warn;
+
+/// `io-threads` from the configuration
+pub fn io_threads(config: &Config) -> usize {
+  config
+    .get("io-threads")
+    .and_then(|value| value.parse::<usize>().ok())
+    .unwrap_or(1)
+    .max(1)
+}
+
+struct IoThread {
+  handle: Handle,
+  /// Dropping it stops the thread, with the connections it serves
+  _stop: oneshot::Sender<()>,
+}
+
+pub struct IoThreads {
+  threads: Vec<IoThread>,
+  next: AtomicUsize,
+}
+
+impl IoThreads {
+  /// Starts `count` threads
+  pub fn start(count: usize) -> Result<Self, String> {
+    let mut threads = Vec::with_capacity(count);
+    for index in 0..count {
+      let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| format!("Failed to start I/O thread {}: {}", index, e))?;
+      let handle = runtime.handle().clone();
+      let (stop, stopped) = oneshot::channel::<()>();
+      std::thread::Builder::new()
+        .name(format!("io-thread-{}", index))
+        // The runtime runs the spawned connections until told to stop
+        .spawn(move || {
+          let _ = runtime.block_on(stopped);
+        })
+        .map_err(|e| format!("Failed to start I/O thread {}: {}", index, e))?;
+      threads.push(IoThread {
+        handle,
+        _stop: stop,
+      });
+    }
+    Ok(Self {
+      threads,
+      next: AtomicUsize::new(0),
+    })
+  }
+
+  pub fn len(&self) -> usize {
+    self.threads.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.threads.is_empty()
+  }
+
+  fn spawn(&self, future: impl Future<Output = ()> + Send + 'static) {
+    let index = self.next.fetch_add(1, Ordering::Relaxed) % self.threads.len();
+    self.threads[index].handle.spawn(future);
+  }
+
+  /// Moves an accepted socket to the next thread, where `serve` is called
+  /// with it
+  pub fn serve(&self, stream: TcpStream, serve: impl FnOnce(TcpStream) + Send + 'static) {
+    // The socket leaves the reactor it was accepted on for the thread's
+    let stream = match stream.into_std() {
+      Ok(stream) => stream,
+      Err(e) => {
+        warn!("Failed to hand a connection to an I/O thread: {}", e);
+        return;
+      }
+    };
+    self.spawn(async move {
+      match TcpStream::from_std(stream) {
+        Ok(stream) => serve(stream),
+        Err(e) => warn!("Failed to hand a connection to an I/O thread: {}", e),
+      }
+    });
+  }
+}
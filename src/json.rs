@@ -0,0 +1,140 @@
+/**
+ * The dataset as JSON, for diffing, version control, or moving data into
+ * systems that don't read RDB. `DEBUG EXPORT-JSON <path>` writes it,
+ * `--import-json <path>` loads it at startup, after the RDB file.
+ *
+ * The document is an object with the format name, its version and the keys,
+ * sorted by name so that two exports of the same data are identical:
+ *
+ * ```json
+ * {
+ *   "format": "redis-rs-dataset",
+ *   "version": 1,
+ *   "keys": [
+ *     { "key": "session:42", "type": "string", "value": "token", "expires_at": 1718000000000 },
+ *     { "key": "user:1", "type": "string", "value": "alice" }
+ *   ]
+ * }
+ * ```
+ *
+ * `expires_at` is the Unix time in milliseconds the key expires at, as with
+ * PEXPIREAT, and is left out for keys without a TTL. Keys are strings, the one
+ * type the server stores. Keys whose deadline has passed are skipped on import.
+ */
+use crate::storage::Storage;
+use serde_json::{json, Map, Value};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const FORMAT: &str = "redis-rs-dataset";
+
+pub const VERSION: u64 = 1;
+
+/// The dataset as a JSON document
+pub fn export(storage: &Storage) -> String {
+  let mut entries = storage.string_entries();
+  entries.sort_by(|(a, _, _), (b, _, _)| a.cmp(b));
+  let keys: Vec<Value> = entries
+    .into_iter()
+    .map(|(key, value, expires_at)| {
+      let mut entry = Map::new();
+      entry.insert("key".to_string(), Value::String(key));
+      entry.insert("type".to_string(), Value::String("string".to_string()));
+      entry.insert("value".to_string(), Value::String(value));
+      if let Some(expires_at) = expires_at {
+        let millis = expires_at
+          .duration_since(UNIX_EPOCH)
+          .unwrap_or_default()
+          .as_millis() as u64;
+        entry.insert("expires_at".to_string(), json!(millis));
+      }
+      Value::Object(entry)
+    })
+    .collect();
+  let document = json!({
+    "format": FORMAT,
+    "version": VERSION,
+    "keys": keys,
+  });
+  serde_json::to_string_pretty(&document).unwrap_or_default() + "\n"
+}
+
+/// Writes the dataset to `path`, through a temporary file so that a crash
+/// never leaves half of it
+pub fn export_file(storage: &Storage, path: &Path) -> Result<(), String> {
+  let document = export(storage);
+  let temporary = path.with_file_name(format!("temp-{}.json", std::process::id()));
+  fs::File::create(&temporary)
+    .and_then(|mut file| {
+      file.write_all(document.as_bytes())?;
+      file.sync_all()
+    })
+    .and_then(|()| fs::rename(&temporary, path))
+    .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Adds the keys of a JSON document to the dataset, replacing keys of the same
+/// name. Nothing is loaded when the document has an error. Returns the number
+/// of keys loaded.
+pub fn import(storage: &Storage, document: &str) -> Result<usize, String> {
+  let document: Value = serde_json::from_str(document).map_err(|e| e.to_string())?;
+  if document.get("format").and_then(Value::as_str) != Some(FORMAT) {
+    return Err(format!("not a {} document", FORMAT));
+  }
+  match document.get("version").and_then(Value::as_u64) {
+    Some(VERSION) => {}
+    Some(version) => return Err(format!("unsupported version {}", version)),
+    None => return Err("missing version".to_string()),
+  }
+  let entries = document
+    .get("keys")
+    .and_then(Value::as_array)
+    .ok_or_else(|| "missing keys".to_string())?;
+
+  let now = SystemTime::now();
+  let mut keys = Vec::with_capacity(entries.len());
+  for (index, entry) in entries.iter().enumerate() {
+    let error = |message: &str| format!("keys[{}]: {}", index, message);
+    let field = |name: &str| entry.get(name).and_then(Value::as_str);
+    let key = field("key").ok_or_else(|| error("missing key"))?;
+    match field("type") {
+      Some("string") => {}
+      Some(kind) => return Err(error(&format!("unsupported type {}", kind))),
+      None => return Err(error("missing type")),
+    }
+    let value = field("value").ok_or_else(|| error("missing value"))?;
+    let ttl = match entry.get("expires_at") {
+      None | Some(Value::Null) => None,
+      Some(expires_at) => {
+        let millis = expires_at
+          .as_u64()
+          .ok_or_else(|| error("expires_at must be a Unix time in milliseconds"))?;
+        let deadline = UNIX_EPOCH + Duration::from_millis(millis);
+        match deadline.duration_since(now) {
+          Ok(ttl) => Some(ttl),
+          // Already expired
+          Err(_) => continue,
+        }
+      }
+    };
+    keys.push((key.to_string(), value.to_string(), ttl));
+  }
+
+  let loaded = keys.len();
+  for (key, value, ttl) in keys {
+    let options = match ttl {
+      Some(ttl) => vec![("PX".to_string(), ttl.as_millis().to_string())],
+      None => Vec::new(),
+    };
+    storage.set(key, value, options);
+  }
+  Ok(loaded)
+}
+
+/// Loads the JSON document at `path`
+pub fn import_file(storage: &Storage, path: &Path) -> Result<usize, String> {
+  let document = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+  import(storage, &document).map_err(|e| format!("{}: {}", path.display(), e))
+}
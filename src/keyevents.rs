@@ -0,0 +1,96 @@
+/**
+ * Key event handlers, for applications embedding the server that act when
+ * keys go away on their own: writing expired sessions behind to a database,
+ * warming a cache back up, without a second connection subscribed to
+ * keyspace notifications.
+ *
+ * A `KeyEventHandler` given to the builder is called with every key the
+ * server removes by itself, its value at that time and the reason. Each
+ * handler runs on a task of its own, one event at a time and in the order
+ * they happened; events wait in an unbounded queue while it is busy, so a
+ * handler must keep up with the expirations, and none are lost. Handlers run
+ * outside the keyspace lock and may use the storage.
+ *
+ * Keys removed by clients, with DEL or FLUSHALL, aren't events: the change
+ * feed (see `changes`) has them. Keys go away when they expire, or when they
+ * are evicted past `maxmemory` (see `eviction`).
+ *
+ * ```no_run
+ * use redis_starter_rust::keyevents::{KeyEvent, KeyEventFuture, KeyEventHandler};
+ *
+ * struct WriteBehind;
+ *
+ * impl KeyEventHandler for WriteBehind {
+ *   fn on_event<'a>(&'a self, event: &'a KeyEvent) -> KeyEventFuture<'a> {
+ *     Box::pin(async move { println!("{} {:?}: {}", event.key, event.reason, event.value.value) })
+ *   }
+ * }
+ *
+ * let server = redis_starter_rust::RedisServer::builder()
+ *   .key_event_handler(WriteBehind)
+ *   .build();
+ * ```
+ */
+use crate::changes::KeyState;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use tokio::sync::mpsc;
+
+/// Why a key was removed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum KeyEventReason {
+  /// Its deadline passed
+  Expired,
+  /// It was evicted past `maxmemory`
+  Evicted,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyEvent {
+  pub key: String,
+  /// The value and deadline of the key when it was removed
+  pub value: KeyState,
+  pub reason: KeyEventReason,
+}
+
+pub type KeyEventFuture<'a> = Pin<Box<dyn Future<Output = ()> + Send + 'a>>;
+
+pub trait KeyEventHandler: Send + Sync {
+  fn on_event<'a>(&'a self, event: &'a KeyEvent) -> KeyEventFuture<'a>;
+}
+
+/// Queues of the handlers of a keyspace
+#[derive(Default)]
+pub struct KeyEvents {
+  queues: Mutex<Vec<mpsc::UnboundedSender<KeyEvent>>>,
+}
+
+impl KeyEvents {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// A queue receiving every event from now on
+  pub fn subscribe(&self) -> mpsc::UnboundedReceiver<KeyEvent> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    self.queues.lock().unwrap().push(sender);
+    receiver
+  }
+
+  /// Whether anyone listens, so that events are only built when they'll be
+  /// read
+  pub fn watched(&self) -> bool {
+    !self.queues.lock().unwrap().is_empty()
+  }
+
+  /// Queues `event` for every listener, forgetting the ones gone
+  pub fn send(&self, event: KeyEvent) {
+    self
+      .queues
+      .lock()
+      .unwrap()
+      .retain(|queue| queue.send(event.clone()).is_ok());
+  }
+}
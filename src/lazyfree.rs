@@ -0,0 +1,137 @@
+/**
+ * Lazy freeing of large objects.
+ *
+ * Dropping a big value (or a whole keyspace on FLUSHALL ASYNC) can take long
+ * enough to stall the connection task that triggered it. Instead, such values are
+ * detached from the keyspace and handed over to a dedicated background thread
+ * which drops them, mirroring Redis's `lazyfree` bio thread.
+ */
+use crate::config::Config;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Arc;
+use std::thread;
+use tracing::error;
+
+/// Values smaller than this are cheaper to free inline than to hand off.
+pub const LAZYFREE_THRESHOLD_BYTES: usize = 64 * 1024;
+
+/// Which deletion paths are allowed to free values in the background.
+#[derive(Debug, Default)]
+pub struct LazyFreeOptions {
+  /// `lazyfree-lazy-expire`: expired keys
+  pub lazy_expire: AtomicBool,
+  /// `lazyfree-lazy-server-del`: values replaced by an overwrite
+  pub lazy_server_del: AtomicBool,
+  /// `lazyfree-lazy-user-del`: DEL behaves like UNLINK
+  pub lazy_user_del: AtomicBool,
+  /// `lazyfree-lazy-user-flush`: FLUSHALL without modifier behaves like ASYNC
+  pub lazy_user_flush: AtomicBool,
+  /// `lazyfree-lazy-eviction`: keys removed by the eviction policy
+  pub lazy_eviction: AtomicBool,
+}
+
+impl LazyFreeOptions {
+  /// Reads the `lazyfree-*` directives out of the configuration
+  pub fn apply_config(&self, config: &Config) {
+    let enabled = |name: &str| {
+      config
+        .get(name)
+        .map(|value| value.eq_ignore_ascii_case("yes"))
+        .unwrap_or(false)
+    };
+
+    self
+      .lazy_expire
+      .store(enabled("lazyfree-lazy-expire"), Ordering::Relaxed);
+    self
+      .lazy_server_del
+      .store(enabled("lazyfree-lazy-server-del"), Ordering::Relaxed);
+    self
+      .lazy_user_del
+      .store(enabled("lazyfree-lazy-user-del"), Ordering::Relaxed);
+    self
+      .lazy_user_flush
+      .store(enabled("lazyfree-lazy-user-flush"), Ordering::Relaxed);
+    self
+      .lazy_eviction
+      .store(enabled("lazyfree-lazy-eviction"), Ordering::Relaxed);
+  }
+}
+
+type Garbage = Box<dyn Send>;
+
+/// Handle to the background thread that drops detached values.
+pub struct LazyFree {
+  sender: Sender<Garbage>,
+  pending: Arc<AtomicUsize>,
+  freed: Arc<AtomicUsize>,
+  pub options: LazyFreeOptions,
+}
+
+impl LazyFree {
+  pub fn new() -> Self {
+    let (sender, receiver) = channel::<Garbage>();
+    let pending = Arc::new(AtomicUsize::new(0));
+    let freed = Arc::new(AtomicUsize::new(0));
+
+    let thread_pending = pending.clone();
+    let thread_freed = freed.clone();
+    let spawned = thread::Builder::new()
+      .name("lazyfree".to_string())
+      .spawn(move || {
+        for garbage in receiver {
+          drop(garbage);
+          thread_pending.fetch_sub(1, Ordering::Relaxed);
+          thread_freed.fetch_add(1, Ordering::Relaxed);
+        }
+      });
+
+    if let Err(e) = spawned {
+      error!("Failed to spawn lazyfree thread: {}", e);
+    }
+
+    Self {
+      sender,
+      pending,
+      freed,
+      options: LazyFreeOptions::default(),
+    }
+  }
+
+  /// Hands `object` over to the background thread. Falls back to dropping it
+  /// inline if the thread is gone.
+  pub fn free<T: Send + 'static>(&self, object: T) {
+    self.pending.fetch_add(1, Ordering::Relaxed);
+    if let Err(e) = self.sender.send(Box::new(object)) {
+      self.pending.fetch_sub(1, Ordering::Relaxed);
+      drop(e.0);
+    }
+  }
+
+  /// Frees `object` in the background only when `lazy` is requested and the
+  /// object is big enough to be worth the hand-off.
+  pub fn free_sized<T: Send + 'static>(&self, object: T, size: usize, lazy: bool) {
+    if lazy && size >= LAZYFREE_THRESHOLD_BYTES {
+      self.free(object);
+    } else {
+      drop(object);
+    }
+  }
+
+  /// Objects queued for the background thread that have not been dropped yet
+  pub fn pending_objects(&self) -> usize {
+    self.pending.load(Ordering::Relaxed)
+  }
+
+  /// Objects dropped by the background thread since startup
+  pub fn freed_objects(&self) -> usize {
+    self.freed.load(Ordering::Relaxed)
+  }
+}
+
+impl Default for LazyFree {
+  fn default() -> Self {
+    Self::new()
+  }
+}
@@ -0,0 +1,80 @@
+/**
+ * A Redis-compatible server, as a library: `RedisServer` runs one in process
+ * and gives direct access to its keyspace. The `redis-starter-rust` binary is
+ * a thin wrapper around it.
+ */
+pub mod acl;
+pub mod acllog;
+pub mod address;
+pub mod admin;
+pub mod allocator;
+pub mod arguments;
+pub mod audit;
+pub mod changes;
+pub mod cli;
+pub mod clients;
+pub mod clock;
+pub mod cluster;
+pub mod commands;
+pub mod commandstats;
+pub mod config;
+pub mod configfile;
+pub mod configset;
+pub mod connection;
+pub mod cron;
+pub mod csv;
+pub mod daemon;
+pub mod database;
+pub mod debug;
+pub mod diagnostics;
+pub mod dump;
+pub mod encoding;
+pub mod encryption;
+pub mod eviction;
+pub mod expiry;
+pub mod fault;
+pub mod glob;
+pub mod hotkeys;
+pub mod import;
+pub mod info;
+pub mod iothreads;
+pub mod json;
+pub mod keyevents;
+pub mod lazyfree;
+pub mod lineedit;
+pub mod listener;
+pub mod logging;
+pub mod lolwut;
+pub mod lzf;
+pub mod memory;
+pub mod module;
+pub mod negativecache;
+pub mod offload;
+pub mod output;
+pub mod parser;
+pub mod proxy;
+pub mod ratelimit;
+pub mod rdb;
+pub mod rdbdiff;
+pub mod readthrough;
+pub mod record;
+pub mod scan;
+pub mod server;
+pub mod shutdown;
+pub mod slowlog;
+pub mod snapshot;
+pub mod state;
+pub mod stats;
+pub mod statsd;
+// import the storage module
+pub mod storage;
+pub mod storagelock;
+pub mod syslog;
+pub mod telemetry;
+pub mod tls;
+pub mod tracking;
+pub mod ttlhistogram;
+
+pub use server::{RedisServer, RedisServerBuilder};
+pub use storage::Storage;
+pub use storagelock::StorageLock;
@@ -0,0 +1,283 @@
+/**
+ * Line editing for `--cli`, in the spirit of linenoise: the terminal is put in
+ * raw mode while a line is read, and the usual keys work on it.
+ *
+ * - left/right, Ctrl-B/Ctrl-F, Home/End, Ctrl-A/Ctrl-E move the cursor
+ * - up/down, Ctrl-P/Ctrl-N walk the history of the session
+ * - Backspace, Delete, Ctrl-K, Ctrl-U and Ctrl-W delete
+ * - Ctrl-L clears the screen, Ctrl-C drops the line and Ctrl-D on an empty
+ *   line ends the input
+ *
+ * When stdin isn't a terminal lines are read as they come, so that commands
+ * can be piped in.
+ */
+use std::io::{self, BufRead, Read, Write};
+
+/// Lines kept in the history
+const HISTORY_LEN: usize = 100;
+
+const CTRL_A: u8 = 1;
+const CTRL_B: u8 = 2;
+const CTRL_C: u8 = 3;
+const CTRL_D: u8 = 4;
+const CTRL_E: u8 = 5;
+const CTRL_F: u8 = 6;
+const CTRL_H: u8 = 8;
+const CTRL_K: u8 = 11;
+const CTRL_L: u8 = 12;
+const CTRL_N: u8 = 14;
+const CTRL_P: u8 = 16;
+const CTRL_U: u8 = 21;
+const CTRL_W: u8 = 23;
+const ESCAPE: u8 = 27;
+const BACKSPACE: u8 = 127;
+
+#[derive(Default)]
+pub struct LineEditor {
+  history: Vec<String>,
+}
+
+/// Raw mode, restoring the terminal as it was when dropped
+struct RawMode {
+  original: libc::termios,
+}
+
+impl RawMode {
+  fn enable() -> io::Result<Self> {
+    let mut original = unsafe { std::mem::zeroed::<libc::termios>() };
+    if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+      return Err(io::Error::last_os_error());
+    }
+    let mut raw = original;
+    raw.c_iflag &= !(libc::ICRNL | libc::IXON);
+    raw.c_lflag &= !(libc::ECHO | libc::ICANON | libc::IEXTEN | libc::ISIG);
+    raw.c_cc[libc::VMIN] = 1;
+    raw.c_cc[libc::VTIME] = 0;
+    if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &raw) } != 0 {
+      return Err(io::Error::last_os_error());
+    }
+    Ok(Self { original })
+  }
+}
+
+impl Drop for RawMode {
+  fn drop(&mut self) {
+    unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &self.original) };
+  }
+}
+
+/// The line being edited
+struct Line {
+  chars: Vec<char>,
+  cursor: usize,
+}
+
+impl Line {
+  fn set(&mut self, text: &str) {
+    self.chars = text.chars().collect();
+    self.cursor = self.chars.len();
+  }
+
+  fn insert(&mut self, c: char) {
+    self.chars.insert(self.cursor, c);
+    self.cursor += 1;
+  }
+
+  fn delete_back(&mut self) {
+    if self.cursor > 0 {
+      self.cursor -= 1;
+      self.chars.remove(self.cursor);
+    }
+  }
+
+  fn delete_word(&mut self) {
+    let mut start = self.cursor;
+    while start > 0 && self.chars[start - 1] == ' ' {
+      start -= 1;
+    }
+    while start > 0 && self.chars[start - 1] != ' ' {
+      start -= 1;
+    }
+    self.chars.drain(start..self.cursor);
+    self.cursor = start;
+  }
+
+  fn text(&self) -> String {
+    self.chars.iter().collect()
+  }
+}
+
+impl LineEditor {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Reads a line after showing `prompt`, `None` once the input ended
+  pub fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+    if unsafe { libc::isatty(libc::STDIN_FILENO) } == 0 {
+      return read_plain_line();
+    }
+    let line = {
+      let _raw = RawMode::enable()?;
+      self.edit(prompt)?
+    };
+    println!();
+    if let Some(line) = &line {
+      if !line.trim().is_empty() && self.history.last() != Some(line) {
+        self.history.push(line.clone());
+        if self.history.len() > HISTORY_LEN {
+          self.history.remove(0);
+        }
+      }
+    }
+    Ok(line)
+  }
+
+  fn edit(&self, prompt: &str) -> io::Result<Option<String>> {
+    let mut stdin = io::stdin().lock();
+    let mut stdout = io::stdout().lock();
+    let mut line = Line {
+      chars: Vec::new(),
+      cursor: 0,
+    };
+    // Index in the history of the line shown, its length for the new one
+    let mut shown = self.history.len();
+    let mut draft = String::new();
+    refresh(&mut stdout, prompt, &line)?;
+
+    loop {
+      let byte = read_byte(&mut stdin)?;
+      match byte {
+        None => return Ok(None),
+        Some(b'\r') | Some(b'\n') => return Ok(Some(line.text())),
+        Some(CTRL_C) => {
+          write!(stdout, "^C\r\n")?;
+          line.set("");
+        }
+        Some(CTRL_D) if line.chars.is_empty() => return Ok(None),
+        Some(CTRL_D) if line.cursor < line.chars.len() => {
+          line.chars.remove(line.cursor);
+        }
+        Some(BACKSPACE) | Some(CTRL_H) => line.delete_back(),
+        Some(CTRL_A) => line.cursor = 0,
+        Some(CTRL_E) => line.cursor = line.chars.len(),
+        Some(CTRL_B) => line.cursor = line.cursor.saturating_sub(1),
+        Some(CTRL_F) => line.cursor = (line.cursor + 1).min(line.chars.len()),
+        Some(CTRL_K) => line.chars.truncate(line.cursor),
+        Some(CTRL_U) => {
+          line.chars.drain(..line.cursor);
+          line.cursor = 0;
+        }
+        Some(CTRL_W) => line.delete_word(),
+        Some(CTRL_L) => write!(stdout, "\x1b[H\x1b[2J")?,
+        Some(CTRL_P) => self.walk_history(&mut line, &mut shown, &mut draft, -1),
+        Some(CTRL_N) => self.walk_history(&mut line, &mut shown, &mut draft, 1),
+        Some(ESCAPE) => match escape_sequence(&mut stdin)?.as_slice() {
+          b"[A" => self.walk_history(&mut line, &mut shown, &mut draft, -1),
+          b"[B" => self.walk_history(&mut line, &mut shown, &mut draft, 1),
+          b"[C" => line.cursor = (line.cursor + 1).min(line.chars.len()),
+          b"[D" => line.cursor = line.cursor.saturating_sub(1),
+          b"[H" | b"OH" | b"[1~" => line.cursor = 0,
+          b"[F" | b"OF" | b"[4~" => line.cursor = line.chars.len(),
+          b"[3~" if line.cursor < line.chars.len() => {
+            line.chars.remove(line.cursor);
+          }
+          _ => {}
+        },
+        Some(byte) if byte >= b' ' => {
+          if let Some(c) = read_char(&mut stdin, byte)? {
+            line.insert(c);
+          }
+        }
+        Some(_) => {}
+      }
+      refresh(&mut stdout, prompt, &line)?;
+    }
+  }
+
+  /// Shows the previous (`step` -1) or next (1) line of the history, the
+  /// line being typed past the last one
+  fn walk_history(&self, line: &mut Line, shown: &mut usize, draft: &mut String, step: isize) {
+    let Some(next) = shown
+      .checked_add_signed(step)
+      .filter(|next| *next <= self.history.len())
+    else {
+      return;
+    };
+    if *shown == self.history.len() {
+      *draft = line.text();
+    }
+    *shown = next;
+    match self.history.get(next) {
+      Some(entry) => line.set(entry),
+      None => line.set(draft),
+    }
+  }
+}
+
+fn read_plain_line() -> io::Result<Option<String>> {
+  let mut line = String::new();
+  if io::stdin().lock().read_line(&mut line)? == 0 {
+    return Ok(None);
+  }
+  Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+fn read_byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+  let mut byte = [0u8];
+  match input.read(&mut byte)? {
+    0 => Ok(None),
+    _ => Ok(Some(byte[0])),
+  }
+}
+
+/// The character starting with `first`, reading the rest of its UTF-8 bytes
+fn read_char(input: &mut impl Read, first: u8) -> io::Result<Option<char>> {
+  let length = match first {
+    0xf0.. => 4,
+    0xe0.. => 3,
+    0xc0.. => 2,
+    _ => 1,
+  };
+  let mut bytes = vec![first];
+  for _ in 1..length {
+    match read_byte(input)? {
+      Some(byte) => bytes.push(byte),
+      None => return Ok(None),
+    }
+  }
+  Ok(
+    std::str::from_utf8(&bytes)
+      .ok()
+      .and_then(|s| s.chars().next()),
+  )
+}
+
+/// The bytes following an escape, up to the end of the sequence
+fn escape_sequence(input: &mut impl Read) -> io::Result<Vec<u8>> {
+  let mut sequence = Vec::new();
+  while let Some(byte) = read_byte(input)? {
+    sequence.push(byte);
+    // `[` and `O` start the sequence, which ends with a letter or `~`
+    if sequence.len() > 1 && (byte.is_ascii_alphabetic() || byte == b'~') {
+      break;
+    }
+    if sequence.len() == 1 && byte != b'[' && byte != b'O' {
+      break;
+    }
+    if sequence.len() > 4 {
+      break;
+    }
+  }
+  Ok(sequence)
+}
+
+/// Redraws the prompt and the line, with the cursor where it is in the line
+fn refresh(out: &mut impl Write, prompt: &str, line: &Line) -> io::Result<()> {
+  let columns = prompt.chars().count() + line.cursor;
+  write!(out, "\r{}{}\x1b[0K\r", prompt, line.text())?;
+  if columns > 0 {
+    write!(out, "\x1b[{}C", columns)?;
+  }
+  out.flush()
+}
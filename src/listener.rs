@@ -0,0 +1,189 @@
+/**
+ * Listening sockets: the `bind` addresses, the TLS port and protected-mode.
+ *
+ * `bind` takes a space separated list of IPv4 and IPv6 addresses like Redis's,
+ * `*` and `::*` standing for every IPv4 and IPv6 interface. IPv6 sockets only
+ * accept IPv6, so `* ::*` binds both families on the same port. An address prefixed with `-` is
+ * optional: the server starts even when it can't be bound. The addresses are
+ * bound on `port` and, when TLS is configured, on `tls-port`. Each listener gets
+ * its own accept task feeding a single channel, so the main loop doesn't care
+ * how many there are or which ones speak TLS. `tcp-backlog` sizes the queue
+ * of connections waiting to be accepted.
+ *
+ * With protected-mode on (the default) and no password set for the default
+ * user, only loopback connections are served, everyone else gets the error
+ * Redis sends and is disconnected.
+ */
+use crate::address::unbracket;
+use crate::config::Config;
+use socket2::{Domain, Socket, Type};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use tokio_rustls::TlsAcceptor;
+use tracing::{info, warn};
+
+/// An accepted socket, with the acceptor to run the handshake for TLS listeners
+pub type Accepted = io::Result<(TcpStream, SocketAddr, Option<TlsAcceptor>)>;
+
+pub const DEFAULT_BIND: &str = "127.0.0.1";
+
+/// Pending connections the kernel queues for each listener, like Redis
+pub const DEFAULT_TCP_BACKLOG: i32 = 511;
+
+pub const PROTECTED_MODE_ERROR: &str = "DENIED Redis is running in protected mode because protected mode is enabled and no password is set for the default user. In this mode connections are only accepted from the loopback interface. If you want to connect from external computers to Redis you may adopt one of the following solutions: 1) Just disable protected mode sending the command 'CONFIG SET protected-mode no' from the loopback interface by connecting to Redis from the same host the server is running, however MAKE SURE Redis is not publicly accessible from internet if you do so. Use CONFIG REWRITE to make this change permanent. 2) Alternatively you can just disable the protected mode by editing the Redis configuration file, and setting the protected mode option to 'no', and then restarting the server. 3) If you started the server manually just for testing, restart it with the '--protected-mode no' option. 4) Set up an authentication password for the default user. NOTE: You only need to do one of the above things in order for the server to start accepting connections from the outside.";
+
+/// `bind` from the configuration, loopback only when unset. Addresses may be
+/// given as separate values or space separated in one.
+pub fn bind_addresses(config: &Config) -> Vec<String> {
+  config
+    .get_values("bind")
+    .unwrap_or_else(|| vec![DEFAULT_BIND.to_string()])
+    .iter()
+    .flat_map(|addresses| addresses.split_whitespace())
+    .map(str::to_string)
+    .collect()
+}
+
+/// `tcp-backlog` from the configuration
+pub fn tcp_backlog(config: &Config) -> i32 {
+  config
+    .get("tcp-backlog")
+    .and_then(|backlog| backlog.parse::<i32>().ok())
+    .unwrap_or(DEFAULT_TCP_BACKLOG)
+}
+
+/// Warns when the kernel silently caps the backlog, like Redis does
+fn check_backlog(backlog: i32) {
+  #[cfg(target_os = "linux")]
+  if let Some(somaxconn) = std::fs::read_to_string("/proc/sys/net/core/somaxconn")
+    .ok()
+    .and_then(|value| value.trim().parse::<i32>().ok())
+    .filter(|somaxconn| *somaxconn < backlog)
+  {
+    warn!(
+      "The TCP backlog setting of {} cannot be enforced because /proc/sys/net/core/somaxconn is set to the lower value of {}.",
+      backlog, somaxconn
+    );
+  }
+  #[cfg(not(target_os = "linux"))]
+  let _ = backlog;
+}
+
+/// `protected-mode` from the configuration, on unless set to `no`
+pub fn protected_mode(config: &Config) -> bool {
+  !config
+    .get("protected-mode")
+    .is_some_and(|value| value.eq_ignore_ascii_case("no"))
+}
+
+/// Whether a connection comes from the loopback interface
+pub fn is_loopback(addr: &SocketAddr) -> bool {
+  match addr.ip() {
+    IpAddr::V4(ip) => ip.is_loopback(),
+    IpAddr::V6(ip) => ip.is_loopback() || ip.to_ipv4_mapped().is_some_and(|ip| ip.is_loopback()),
+  }
+}
+
+/// Parses one `bind` entry into an IP address and whether it is optional
+fn parse_address(address: &str) -> Result<(IpAddr, bool), String> {
+  let (address, optional) = match address.strip_prefix('-') {
+    Some(address) => (address, true),
+    None => (address, false),
+  };
+  let ip = match address {
+    "*" => "0.0.0.0",
+    "::*" => "::",
+    "localhost" => "127.0.0.1",
+    address => unbracket(address),
+  };
+  ip.parse::<IpAddr>()
+    .map(|ip| (ip, optional))
+    .map_err(|_| format!("Invalid bind address '{}'", address))
+}
+
+/// Listens on `addr`, IPv6 sockets leaving IPv4 to their own listeners
+fn bind_socket(addr: SocketAddr, backlog: i32) -> io::Result<TcpListener> {
+  let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+  if addr.is_ipv6() {
+    socket.set_only_v6(true)?;
+  }
+  socket.set_reuse_address(true)?;
+  socket.set_nonblocking(true)?;
+  socket.bind(&addr.into())?;
+  socket.listen(backlog)?;
+  TcpListener::from_std(socket.into())
+}
+
+/// Binds every address on `port`. Fails when a mandatory address can't be
+/// bound or when nothing could be bound at all.
+fn bind(addresses: &[String], port: u16, backlog: i32) -> Result<Vec<TcpListener>, String> {
+  let mut listeners = Vec::new();
+  for address in addresses {
+    let (ip, optional) = parse_address(address)?;
+    let addr = SocketAddr::new(ip, port);
+    match bind_socket(addr, backlog) {
+      Ok(listener) => {
+        info!("Listening on {}", addr);
+        listeners.push(listener);
+      }
+      Err(e) if optional => warn!("Skipping optional bind address {}: {}", addr, e),
+      Err(e) => return Err(format!("Could not bind {}: {}", addr, e)),
+    }
+  }
+  if listeners.is_empty() {
+    return Err("Failed listening on all the bind addresses".to_string());
+  }
+  Ok(listeners)
+}
+
+/// Opens the plain listeners on `port`, unless it is 0, and the TLS ones on
+/// `tls`'s port
+pub async fn listen(
+  addresses: &[String],
+  port: u16,
+  tls: Option<(u16, TlsAcceptor)>,
+  backlog: i32,
+) -> Result<Vec<(TcpListener, Option<TlsAcceptor>)>, String> {
+  check_backlog(backlog);
+  let mut listeners = Vec::new();
+  if port != 0 {
+    for listener in bind(addresses, port, backlog)? {
+      listeners.push((listener, None));
+    }
+  }
+  if let Some((tls_port, acceptor)) = tls {
+    for listener in bind(addresses, tls_port, backlog)? {
+      listeners.push((listener, Some(acceptor.clone())));
+    }
+  }
+  if listeners.is_empty() {
+    return Err("Configured to not listen anywhere, exiting.".to_string());
+  }
+  Ok(listeners)
+}
+
+/// Accepts connections on every listener, handing them over through one channel.
+/// The listeners are closed once the receiver is dropped.
+pub fn spawn_acceptors(
+  listeners: Vec<(TcpListener, Option<TlsAcceptor>)>,
+) -> UnboundedReceiver<Accepted> {
+  let (sender, receiver) = unbounded_channel();
+  for (listener, tls) in listeners {
+    let sender = sender.clone();
+    tokio::spawn(async move {
+      loop {
+        let accepted = tokio::select! {
+          accepted = listener.accept() => accepted,
+          _ = sender.closed() => break,
+        };
+        let accepted = accepted.map(|(stream, addr)| (stream, addr, tls.clone()));
+        if sender.send(accepted).is_err() {
+          break;
+        }
+      }
+    });
+  }
+  receiver
+}
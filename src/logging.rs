@@ -0,0 +1,91 @@
+/**
+ * Logging, with `tracing`.
+ *
+ * `loglevel` picks the verbosity with Redis's names (debug, verbose, notice,
+ * warning, nothing) and can be changed at runtime with CONFIG SET. RUST_LOG
+ * adds per-module directives on top of it, e.g.
+ * `RUST_LOG=redis_starter_rust::clients=debug,redis_starter_rust::rdb=trace`.
+ *
+ * Events of a client connection are recorded in a `connection` span carrying
+ * its id and address. Per-command events are logged at debug level or below,
+ * so the default `notice` level stays quiet under load.
+ *
+ * With `syslog-enabled` the same lines also go to the syslog daemon, and with
+ * `otlp-endpoint` the spans are exported to an OpenTelemetry collector (see
+ * telemetry).
+ */
+use crate::syslog::{Syslog, SyslogOptions};
+use crate::telemetry;
+use std::fs::OpenOptions;
+use std::sync::{Mutex, OnceLock};
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+/// Level used until the configuration is read, `notice` in Redis terms
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::INFO;
+
+/// Swaps the filter when `loglevel` changes
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// `level` as the default, with the RUST_LOG directives on top
+fn filter(level: LevelFilter) -> EnvFilter {
+  EnvFilter::builder()
+    .with_default_directive(level.into())
+    .parse_lossy(std::env::var("RUST_LOG").unwrap_or_default())
+}
+
+/// Installs the subscriber, writing to `logfile` when there is one and to
+/// stderr otherwise, to syslog when enabled, and exporting spans to
+/// `otlp_endpoint` when set
+pub fn init(
+  logfile: Option<&str>,
+  syslog: Option<&SyslogOptions>,
+  otlp_endpoint: Option<&str>,
+) -> Result<(), String> {
+  let writer = match logfile {
+    Some(path) => {
+      let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("Can't open the log file {}: {}", path, e))?;
+      BoxMakeWriter::new(Mutex::new(file))
+    }
+    None => BoxMakeWriter::new(std::io::stderr),
+  };
+  let syslog = match syslog {
+    Some(options) => {
+      Some(Syslog::connect(options).map_err(|e| format!("Can't connect to syslog: {}", e))?)
+    }
+    None => None,
+  };
+  let (filter, handle) = reload::Layer::new(filter(DEFAULT_LEVEL));
+  tracing_subscriber::registry()
+    .with(filter)
+    .with(
+      tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_ansi(logfile.is_none()),
+    )
+    // syslog stamps the messages itself
+    .with(syslog.map(|syslog| {
+      tracing_subscriber::fmt::layer()
+        .with_writer(syslog)
+        .with_ansi(false)
+        .without_time()
+    }))
+    .with(telemetry::layer(otlp_endpoint)?)
+    .try_init()
+    .map_err(|e| format!("Failed to set up logging: {}", e))?;
+  let _ = FILTER.set(handle);
+  Ok(())
+}
+
+/// Applies a new `loglevel`, keeping the RUST_LOG directives
+pub fn set_level(level: LevelFilter) {
+  if let Some(handle) = FILTER.get() {
+    let _ = handle.reload(filter(level));
+  }
+}
@@ -0,0 +1,197 @@
+/**
+ * LOLWUT: renders Georg Nees' "Schotter" the way Redis 5 does, with squares that
+ * get more and more disordered towards the bottom of the canvas. Pixels are
+ * packed into Unicode braille characters, each covering 2x4 pixels.
+ */
+use std::f64::consts::PI;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DEFAULT_COLUMNS: usize = 66;
+const DEFAULT_SQUARES_PER_ROW: usize = 8;
+const DEFAULT_SQUARES_PER_COLUMN: usize = 12;
+
+struct Canvas {
+  width: usize,
+  height: usize,
+  pixels: Vec<bool>,
+}
+
+impl Canvas {
+  fn new(width: usize, height: usize) -> Self {
+    Self {
+      width,
+      height,
+      pixels: vec![false; width * height],
+    }
+  }
+
+  fn set(&mut self, x: i64, y: i64) {
+    if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+      return;
+    }
+    self.pixels[y as usize * self.width + x as usize] = true;
+  }
+
+  fn get(&self, x: usize, y: usize) -> bool {
+    x < self.width && y < self.height && self.pixels[y * self.width + x]
+  }
+
+  /// Bresenham's line algorithm
+  fn draw_line(&mut self, (mut x1, mut y1): (i64, i64), (x2, y2): (i64, i64)) {
+    let dx = (x2 - x1).abs();
+    let dy = (y2 - y1).abs();
+    let sx = if x1 < x2 { 1 } else { -1 };
+    let sy = if y1 < y2 { 1 } else { -1 };
+    let mut error = dx - dy;
+
+    loop {
+      self.set(x1, y1);
+      if x1 == x2 && y1 == y2 {
+        break;
+      }
+      let e2 = error * 2;
+      if e2 > -dy {
+        error -= dy;
+        x1 += sx;
+      }
+      if e2 < dx {
+        error += dx;
+        y1 += sy;
+      }
+    }
+  }
+
+  /// Square centered at (x, y) and rotated by `angle`
+  fn draw_square(&mut self, x: i64, y: i64, size: f64, angle: f64) {
+    let size = (size / std::f64::consts::SQRT_2).round();
+    let corners: Vec<(i64, i64)> = (0..4)
+      .map(|k| {
+        let angle = angle + PI / 4.0 + k as f64 * PI / 2.0;
+        (
+          (angle.sin() * size).round() as i64 + x,
+          (angle.cos() * size).round() as i64 + y,
+        )
+      })
+      .collect();
+    for k in 0..4 {
+      self.draw_line(corners[k], corners[(k + 1) % 4]);
+    }
+  }
+
+  /// Packs the pixels into braille characters
+  fn render(&self) -> String {
+    let mut output = String::new();
+    for y in (0..self.height).step_by(4) {
+      for x in (0..self.width).step_by(2) {
+        let dots = [
+          (0, 0, 0x01),
+          (0, 1, 0x02),
+          (0, 2, 0x04),
+          (1, 0, 0x08),
+          (1, 1, 0x10),
+          (1, 2, 0x20),
+          (0, 3, 0x40),
+          (1, 3, 0x80),
+        ];
+        let byte = dots
+          .iter()
+          .filter(|(dx, dy, _)| self.get(x + dx, y + dy))
+          .fold(0u32, |byte, (_, _, bit)| byte | bit);
+        output.push(char::from_u32(0x2800 + byte).unwrap_or(' '));
+      }
+      output.push('\n');
+    }
+    output
+  }
+}
+
+/// Small xorshift generator, the drawing only needs to look random
+struct Random(u64);
+
+impl Random {
+  fn new() -> Self {
+    let seed = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map(|elapsed| elapsed.as_nanos() as u64)
+      .unwrap_or(0);
+    Self(seed | 1)
+  }
+
+  fn next(&mut self) -> u64 {
+    self.0 ^= self.0 << 13;
+    self.0 ^= self.0 >> 7;
+    self.0 ^= self.0 << 17;
+    self.0
+  }
+
+  /// Uniform value in [0, 1)
+  fn unit(&mut self) -> f64 {
+    (self.next() >> 11) as f64 / (1u64 << 53) as f64
+  }
+
+  /// Uniform value in (-max, max)
+  fn signed(&mut self, max: f64) -> f64 {
+    let value = self.unit() * max;
+    if self.next().is_multiple_of(2) {
+      value
+    } else {
+      -value
+    }
+  }
+}
+
+fn schotter(columns: usize, squares_per_row: usize, squares_per_column: usize) -> Canvas {
+  let width = columns * 2;
+  let padding = if width > 4 { 2 } else { 0 };
+  let side = (width - padding * 2) / squares_per_row;
+  let height = side * squares_per_column + padding * 2;
+  let mut canvas = Canvas::new(width, height);
+  let mut random = Random::new();
+
+  for y in 0..squares_per_column {
+    for x in 0..squares_per_row {
+      let mut sx = (x * side + side / 2 + padding) as i64;
+      let mut sy = (y * side + side / 2 + padding) as i64;
+      let mut angle = 0.0;
+      // The first two rows stay tidy, disorder grows with every row after that
+      if y > 1 {
+        let disorder = y as f64 / squares_per_column as f64;
+        angle = random.signed(PI / 2.0 * disorder);
+        sx += (random.signed(2.0 * disorder) * side as f64 / 3.0) as i64;
+        sy += (random.signed(2.0 * disorder) * side as f64 / 3.0) as i64;
+      }
+      canvas.draw_square(sx, sy, side as f64, angle);
+    }
+  }
+
+  canvas
+}
+
+/// LOLWUT [VERSION version] [columns [squares-per-row [squares-per-column]]]
+pub fn lolwut(args: &[String]) -> Result<String, String> {
+  let args = match args {
+    [version, _, rest @ ..] if version.eq_ignore_ascii_case("version") => rest,
+    _ => args,
+  };
+
+  let mut parameters = [
+    DEFAULT_COLUMNS,
+    DEFAULT_SQUARES_PER_ROW,
+    DEFAULT_SQUARES_PER_COLUMN,
+  ];
+  let limits = [(1, 1000), (1, 200), (1, 200)];
+  for (i, arg) in args.iter().take(3).enumerate() {
+    let value = arg
+      .parse::<usize>()
+      .map_err(|_| "ERR value is not an integer or out of range".to_string())?;
+    parameters[i] = value.clamp(limits[i].0, limits[i].1);
+  }
+
+  let [columns, squares_per_row, squares_per_column] = parameters;
+  let mut output = schotter(columns, squares_per_row, squares_per_column).render();
+  output.push_str(&format!(
+    "\nGeorg Nees - schotter, plotter on paper, 1968. Redis ver. {}\n",
+    env!("CARGO_PKG_VERSION")
+  ));
+  Ok(output)
+}
@@ -0,0 +1,146 @@
+/*!
+ * LZF, the compression of the strings in RDB files and DUMP payloads.
+ *
+ * Compressed data is a sequence of runs, told apart by their first byte:
+ * below 32, the byte plus one literal bytes follow; otherwise its top three
+ * bits are a length (7 meaning the next byte adds to it) and a back reference
+ * copies length + 2 bytes from the output, the low five bits and the next
+ * byte giving their distance. The compressor finds references through a
+ * table of where each three byte sequence was last seen, as liblzf does.
+ */
+
+/// Bits of the back reference table
+const TABLE_BITS: u32 = 14;
+
+/// Longest literal run
+const MAX_LITERALS: usize = 1 << 5;
+
+/// Farthest back reference
+const MAX_OFFSET: usize = 1 << 13;
+
+/// Longest back reference
+const MAX_MATCH: usize = (1 << 8) + (1 << 3);
+
+/// Compresses strings one after the other, reusing its table
+pub struct Compressor {
+  table: Vec<usize>,
+}
+
+impl Default for Compressor {
+  fn default() -> Self {
+    Self {
+      table: vec![0; 1 << TABLE_BITS],
+    }
+  }
+}
+
+impl Compressor {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// `input` compressed, `None` unless that takes fewer than `limit` bytes
+  pub fn compress(&mut self, input: &[u8], limit: usize) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(limit);
+    // The run being written starts with its length, patched when it ends
+    out.push(0);
+    let mut literals = 0;
+    let mut position = 0;
+    while position < input.len() {
+      if out.len() >= limit {
+        return None;
+      }
+      if position + 2 < input.len() {
+        let slot = hash(&input[position..position + 3]);
+        // Entries left by earlier strings are checked like the others
+        let reference = self.table[slot];
+        self.table[slot] = position;
+        if reference < position
+          && position - reference - 1 < MAX_OFFSET
+          && input[reference..reference + 3] == input[position..position + 3]
+        {
+          let longest = (input.len() - position).min(MAX_MATCH);
+          let mut matched = 3;
+          while matched < longest && input[reference + matched] == input[position + matched] {
+            matched += 1;
+          }
+          end_run(&mut out, literals);
+          let offset = position - reference - 1;
+          let length = matched - 2;
+          if length < 7 {
+            out.push((length << 5) as u8 | (offset >> 8) as u8);
+          } else {
+            out.push(7 << 5 | (offset >> 8) as u8);
+            out.push((length - 7) as u8);
+          }
+          out.push(offset as u8);
+          out.push(0);
+          literals = 0;
+          position += matched;
+          continue;
+        }
+      }
+      out.push(input[position]);
+      literals += 1;
+      position += 1;
+      if literals == MAX_LITERALS {
+        end_run(&mut out, literals);
+        out.push(0);
+        literals = 0;
+      }
+    }
+    end_run(&mut out, literals);
+    (out.len() < limit).then_some(out)
+  }
+}
+
+/// Writes the length of the literal run ending, or drops its length byte
+/// when it is empty
+fn end_run(out: &mut Vec<u8>, literals: usize) {
+  if literals == 0 {
+    out.pop();
+  } else {
+    let start = out.len() - literals - 1;
+    out[start] = (literals - 1) as u8;
+  }
+}
+
+fn hash(bytes: &[u8]) -> usize {
+  let value = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+  (value.wrapping_mul(2_654_435_761) >> (u32::BITS - TABLE_BITS)) as usize
+}
+
+/// Decompresses LZF data, refusing back references before the start of the
+/// output and runs past the end of the input
+pub fn decompress(input: &[u8]) -> Result<Vec<u8>, String> {
+  let invalid = || "invalid LZF data".to_string();
+  let mut output = Vec::with_capacity(input.len() * 2);
+  let mut position = 0;
+  while position < input.len() {
+    let control = input[position] as usize;
+    position += 1;
+    if control < 1 << 5 {
+      // A run of control + 1 literal bytes
+      let end = position + control + 1;
+      let literal = input.get(position..end).ok_or_else(invalid)?;
+      output.extend_from_slice(literal);
+      position = end;
+    } else {
+      // A back reference of length + 2 bytes
+      let mut length = control >> 5;
+      if length == 7 {
+        length += *input.get(position).ok_or_else(invalid)? as usize;
+        position += 1;
+      }
+      let low = *input.get(position).ok_or_else(invalid)? as usize;
+      position += 1;
+      let distance = ((control & 0x1f) << 8) + low + 1;
+      let start = output.len().checked_sub(distance).ok_or_else(invalid)?;
+      // Byte by byte, the reference may overlap what it produces
+      for index in start..start + length + 2 {
+        output.push(output[index]);
+      }
+    }
+  }
+  Ok(output)
+}
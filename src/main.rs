@@ -1,200 +1,85 @@
-use env_logger::Env;
-use parser::{parse_command, serialize_response, Command, RedisValue};
+use redis_starter_rust::allocator::CountingAllocator;
+use redis_starter_rust::arguments::parse_cli_arguments;
+use redis_starter_rust::daemon::{self, ProcessOptions};
+use redis_starter_rust::shutdown::{prepare_shutdown, wait_for_signal};
+use redis_starter_rust::{cli, configfile, logging, rdbdiff, RedisServer};
 use std::env;
-use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::Mutex as AsyncMutex;
-
-pub mod parser;
-// import the storage module
-pub mod storage;
-use storage::Storage;
-
-pub mod config;
-use config::Config;
-
-pub mod arguments;
-use arguments::{parse_cli_arguments, process_configuration_arguments};
-
-pub mod database;
-use database::populate_hot_storage;
-
-#[tokio::main]
-async fn main() {
-  env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
-  println!("Starting Redis Server!");
-
-  let mut args: Vec<String> = env::args().collect();
-  // Remove the first argument which is the binary name
-  args.remove(0);
-
-  let mut port = env::var("PORT").unwrap_or_else(|_| "6379".to_string());
-
-  let arguments = parse_cli_arguments(args);
-
-  let _config = Arc::new(AsyncMutex::new(Config::new()));
+use tracing::error;
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/** Settles how the process runs (daemonized, logging...) before starting the
+ * runtime: forking is only safe while the process is single threaded */
+fn main() {
+  let args: Vec<String> = env::args().collect();
+  let cli_arguments = parse_cli_arguments(args);
+  if let Some((_, files)) = cli_arguments.iter().find(|(name, _)| name == "diff-rdb") {
+    std::process::exit(rdbdiff::run(&files[0], &files[1]));
+  }
+  if let Some((_, address)) = cli_arguments.iter().find(|(name, _)| name == "cli") {
+    std::process::exit(cli::run(address));
+  }
+  let arguments = match configfile::with_config_file(&cli_arguments) {
+    Ok(arguments) => arguments,
+    Err(e) => {
+      eprintln!("Failed to read the configuration: {}", e);
+      std::process::exit(1);
+    }
+  };
 
-  for (argument, argument_value) in arguments.clone() {
-    match argument.as_str() {
-      "--port" => {
-        println!("Port: {}", argument_value);
-        port = argument_value.clone();
-      }
-      _ => {}
+  let options = ProcessOptions::from_arguments(&arguments);
+  if options.daemonize {
+    if let Err(e) = daemon::daemonize() {
+      eprintln!("Failed to daemonize: {}", e);
+      std::process::exit(1);
     }
   }
-
-  let url = format!("127.0.0.1:{}", port);
-  let listener = TcpListener::bind(url).await.unwrap();
-
-  let _storage = Arc::new(AsyncMutex::new(Storage::new()));
-  process_configuration_arguments(arguments, _config.clone()).await;
-
-  // Only populate hot storage if the configuration is set
-  populate_hot_storage(&_storage, &_config).await;
-
-  loop {
-    let stream = listener.accept().await;
-    let storage = _storage.clone();
-    let config = _config.clone();
-
-    match stream {
-      Ok((stream, _)) => handle_connection(stream, storage, config),
-      Err(e) => {
-        println!("error: {}", e);
-      }
-    };
+  let runtime = tokio::runtime::Builder::new_multi_thread()
+    .enable_all()
+    .build()
+    .expect("Failed to start the Tokio runtime");
+  // The OTLP exporter runs on the runtime
+  let _runtime = runtime.enter();
+  if let Err(e) = logging::init(
+    options.logfile.as_deref(),
+    options.syslog.as_ref(),
+    options.otlp_endpoint.as_deref(),
+  ) {
+    eprintln!("{}", e);
+    std::process::exit(1);
+  }
+  if let Some(pidfile) = &options.pidfile {
+    if let Err(e) = daemon::write_pidfile(pidfile) {
+      error!("{}", e);
+    }
   }
-}
-
-/** Handles TCP connections to Redis Server */
-fn handle_connection(
-  mut stream: TcpStream,
-  storage: Arc<AsyncMutex<Storage>>,
-  config: Arc<AsyncMutex<Config>>,
-) {
-  println!("Accepted new connection");
-  tokio::spawn(async move {
-    loop {
-      let mut buf = [0; 512];
-      match stream.read(&mut buf).await {
-        Ok(0) => break,
-        Ok(n) => {
-          println!("Received {} bytes", n);
-          match parse_command(&buf[..n]) {
-            Ok(Command::PING(message)) => {
-              let response = match message {
-                Some(msg) => serialize_response(RedisValue::SimpleString(msg.to_string())),
-                None => serialize_response(RedisValue::SimpleString("PONG".to_string())),
-              };
-              if let Err(e) = stream.write_all(response.as_bytes()).await {
-                println!("Failed to write to stream; err = {:?}", e);
-                break;
-              }
-            }
-            Ok(Command::ECHO(message)) => {
-              let response = serialize_response(RedisValue::SimpleString(message.to_string()));
-              if let Err(e) = stream.write_all(response.as_bytes()).await {
-                println!("Failed to write to stream; err = {:?}", e);
-                break;
-              }
-            }
-            Ok(Command::UNKNOWN(cmd)) => {
-              eprintln!("Unknown command: {}", cmd);
-              let response = serialize_response(RedisValue::BulkString(Some(format!(
-                "ERR Unknown command: {}",
-                cmd
-              ))));
-              if let Err(e) = stream.write_all(response.as_bytes()).await {
-                println!("Failed to write to stream; err = {:?}", e);
-                break;
-              }
-            }
-            Ok(Command::SET(key, value, optional_ags)) => {
-              // Handle all optional parameters
-              let storage = storage.lock().await;
-              storage.set(key, value, optional_ags.unwrap_or_default());
-
-              let response = serialize_response(RedisValue::SimpleString("OK".to_string()));
-              if let Err(e) = stream.write_all(response.as_bytes()).await {
-                println!("Failed to write to stream; err = {:?}", e);
-                break;
-              }
-            }
-            Ok(Command::GET(key)) => {
-              eprintln!("GET command: key = {}", key);
-              let storage = storage.lock().await;
-              let response = match storage.get(&key) {
-                Some(value) => serialize_response(RedisValue::BulkString(Some(value))),
-                None => serialize_response(RedisValue::BulkString(None)),
-              };
-              println!("Response: {:?}", response);
-              if let Err(e) = stream.write_all(response.as_bytes()).await {
-                println!("Failed to write to stream; err = {:?}", e);
-                break;
-              }
-            }
-            Ok(Command::CONFIGGET(entry)) => {
-              let config = config.lock().await;
-              let value = config.get(&entry);
-              let mut result = Vec::new();
-              result.push(entry);
-              result.push(value.unwrap_or_default());
-              let response = serialize_response(RedisValue::Array(result));
-              if let Err(e) = stream.write_all(response.as_bytes()).await {
-                println!("Failed to write to stream; err = {:?}", e);
-                break;
-              }
-            }
-            Ok(Command::KEYS(pattern)) => {
-              let storage = storage.lock().await;
-              let keys = storage.keys(&pattern);
-              let response = serialize_response(RedisValue::Array(keys));
-              if let Err(e) = stream.write_all(response.as_bytes()).await {
-                println!("Failed to write to stream; err = {:?}", e);
-                break;
-              }
-            }
-            Ok(Command::INFO(_section)) => {
-              let is_replica = config.lock().await.has("replicaof");
-              let mut replication_info: Vec<String> = Vec::new();
-              if is_replica {
-                replication_info.push("role:slave".to_string());
-                let replication_id = config.lock().await.get("replication_id").unwrap();
-                let replication_offset = config.lock().await.get("replication_offset").unwrap();
 
-                replication_info.push(format!("master_replid:{}", replication_id));
-                replication_info.push(format!("master_repl_offset:{}", replication_offset));
-              } else {
-                replication_info.push("role:master".to_string())
-              };
+  // PORT from the environment, overridden by --port or the configuration file
+  let mut builder = RedisServer::builder();
+  if let Ok(port) = env::var("PORT") {
+    builder = builder.arguments(vec![("port".to_string(), vec![port])]);
+  }
+  let mut server = builder.arguments(arguments).build();
 
-              let info = replication_info.join("\r\n");
+  runtime.block_on(async {
+    if let Err(e) = server.start().await {
+      error!("{}", e);
+      std::process::exit(1);
+    }
+    server.reload_on_sighup(cli_arguments);
+    server.report_on_sigusr1();
 
-              let response = serialize_response(RedisValue::BulkString(Some(info)));
-              if let Err(e) = stream.write_all(response.as_bytes()).await {
-                println!("Failed to write to stream; err = {:?}", e);
-                break;
-              }
-            }
-            Err(e) => {
-              eprintln!("Failed to parse command: {}", e);
-              let response = serialize_response(RedisValue::BulkString(Some(format!(
-                "ERR Failed to parse command: {}",
-                e
-              ))));
-              if let Err(e) = stream.write_all(response.as_bytes()).await {
-                println!("Failed to write to stream; err = {:?}", e);
-                break;
-              }
-            }
-          }
-        }
-        Err(e) => {
-          println!("Failed to read from stream; err = {:?}", e);
-          break;
-        }
+    // Save and exit on SIGTERM/SIGINT, carrying on if the save fails. SHUTDOWN
+    // has saved already by the time the server stops.
+    loop {
+      tokio::select! {
+        _ = server.stopped() => std::process::exit(0),
+        _ = wait_for_signal() => {}
+      }
+      match prepare_shutdown(&server.storage(), &server.config(), &server.state(), None).await {
+        Ok(()) => std::process::exit(0),
+        Err(e) => error!("{}", e),
       }
     }
   });
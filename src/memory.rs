@@ -0,0 +1,214 @@
+/**
+ * Approximate memory accounting for the keyspace.
+ *
+ * Every `StorageValue` carries an estimate of its heap footprint (key, value and
+ * bookkeeping overhead). `Storage` keeps the running totals in a `MemoryCounter`,
+ * which stripes the counters by key hash so that concurrent writers touching
+ * different keys don't contend on a single atomic.
+ */
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::mem::size_of;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Number of counter stripes. Must be a power of two.
+const COUNTER_SHARDS: usize = 16;
+
+/// Fixed cost of a single hash table slot on top of the key and value structs
+/// (hash, control bytes and shard lock amortisation).
+pub const TABLE_SLOT_OVERHEAD: usize = 16;
+
+/// Anything that can report an approximation of the heap memory it owns.
+pub trait MemoryUsage {
+  /// Bytes owned by the value, including its own inline size.
+  fn memory_usage(&self) -> usize;
+}
+
+impl MemoryUsage for String {
+  fn memory_usage(&self) -> usize {
+    size_of::<String>() + self.capacity()
+  }
+}
+
+impl MemoryUsage for Vec<u8> {
+  fn memory_usage(&self) -> usize {
+    size_of::<Vec<u8>>() + self.capacity()
+  }
+}
+
+/// Estimated footprint of a key/value pair stored in the keyspace.
+pub fn entry_size<V: MemoryUsage>(key: &str, value: &V) -> usize {
+  key.len() + size_of::<String>() + value.memory_usage() + TABLE_SLOT_OVERHEAD
+}
+
+/// Striped byte counter used to track the memory used by the keyspace.
+#[derive(Debug)]
+pub struct MemoryCounter {
+  shards: Vec<AtomicUsize>,
+}
+
+impl MemoryCounter {
+  pub fn new() -> Self {
+    Self {
+      shards: (0..COUNTER_SHARDS).map(|_| AtomicUsize::new(0)).collect(),
+    }
+  }
+
+  /// Index of the stripe responsible for a given key.
+  pub fn shard_index(&self, key: &str) -> usize {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish() as usize & (COUNTER_SHARDS - 1)
+  }
+
+  /// Resolves the stripe responsible for a given key.
+  fn shard(&self, key: &str) -> &AtomicUsize {
+    &self.shards[self.shard_index(key)]
+  }
+
+  /// Accounts `bytes` of newly allocated memory for `key`.
+  pub fn add(&self, key: &str, bytes: usize) {
+    self.shard(key).fetch_add(bytes, Ordering::Relaxed);
+  }
+
+  /// Releases `bytes` previously accounted for `key`.
+  pub fn sub(&self, key: &str, bytes: usize) {
+    // Saturate instead of wrapping so that a bookkeeping bug can never make
+    // the server believe it is using 16 EiB of memory.
+    let _ = self
+      .shard(key)
+      .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |current| {
+        Some(current.saturating_sub(bytes))
+      });
+  }
+
+  /// Replaces the accounted size of `key` from `old` to `new` bytes.
+  pub fn resize(&self, key: &str, old: usize, new: usize) {
+    if new > old {
+      self.add(key, new - old);
+    } else if old > new {
+      self.sub(key, old - new);
+    }
+  }
+
+  /// Total bytes accounted across all stripes.
+  pub fn used(&self) -> usize {
+    self
+      .shards
+      .iter()
+      .map(|shard| shard.load(Ordering::Relaxed))
+      .sum()
+  }
+
+  /// Per-stripe totals, mostly useful for diagnostics.
+  pub fn shard_totals(&self) -> Vec<usize> {
+    self
+      .shards
+      .iter()
+      .map(|shard| shard.load(Ordering::Relaxed))
+      .collect()
+  }
+
+  /// Number of stripes, the length of `shard_totals`.
+  pub fn shard_count(&self) -> usize {
+    self.shards.len()
+  }
+
+  /// Resets every stripe to zero, used when the whole keyspace is dropped.
+  pub fn reset(&self) {
+    for shard in &self.shards {
+      shard.store(0, Ordering::Relaxed);
+    }
+  }
+}
+
+impl Default for MemoryCounter {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::encoding::{Value, INLINE_CAPACITY};
+
+  #[test]
+  fn add_and_sub() {
+    let counter = MemoryCounter::new();
+    counter.add("a", 100);
+    counter.add("b", 50);
+    assert_eq!(counter.used(), 150);
+    counter.sub("a", 30);
+    assert_eq!(counter.used(), 120);
+  }
+
+  #[test]
+  fn sub_saturates_at_zero() {
+    let counter = MemoryCounter::new();
+    counter.add("key", 10);
+    counter.sub("key", 25);
+    assert_eq!(counter.used(), 0);
+    counter.add("key", 5);
+    assert_eq!(counter.used(), 5);
+  }
+
+  #[test]
+  fn resize_stays_on_the_key_stripe() {
+    let counter = MemoryCounter::new();
+    let index = counter.shard_index("key");
+    assert!(index < counter.shard_count());
+    assert_eq!(counter.shard_index("key"), index);
+
+    counter.add("key", 100);
+    counter.resize("key", 100, 160);
+    assert_eq!(counter.shard_totals()[index], 160);
+    counter.resize("key", 160, 40);
+    assert_eq!(counter.shard_totals()[index], 40);
+    counter.resize("key", 40, 40);
+    assert_eq!(counter.used(), 40);
+    assert_eq!(counter.shard_totals().iter().sum::<usize>(), 40);
+  }
+
+  #[test]
+  fn reset() {
+    let counter = MemoryCounter::new();
+    for i in 0..100 {
+      counter.add(&format!("key:{}", i), 10);
+    }
+    assert_eq!(counter.used(), 1000);
+    counter.reset();
+    assert_eq!(counter.used(), 0);
+    assert!(counter.shard_totals().iter().all(|&total| total == 0));
+  }
+
+  #[test]
+  fn entry_size_per_encoding() {
+    let overhead = |key: &str| key.len() + size_of::<String>() + TABLE_SLOT_OVERHEAD;
+
+    let int = Value::new("12345".to_string());
+    assert_eq!(int.encoding(), "int");
+    assert_eq!(
+      entry_size("key", &int),
+      overhead("key") + size_of::<Value>()
+    );
+
+    let inline = Value::new("x".repeat(INLINE_CAPACITY));
+    assert!(matches!(inline, Value::Inline { .. }));
+    assert_eq!(
+      entry_size("key", &inline),
+      overhead("key") + size_of::<Value>()
+    );
+
+    let raw = Value::new("x".repeat(1000));
+    assert!(matches!(raw, Value::Raw(_)));
+    assert_eq!(
+      entry_size("key", &raw),
+      overhead("key") + size_of::<Value>() + 1000
+    );
+    assert_eq!(
+      entry_size("longer key", &raw),
+      entry_size("key", &raw) + "longer key".len() - "key".len()
+    );
+  }
+}
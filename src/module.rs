@@ -0,0 +1,170 @@
+/**
+ * Custom commands for applications embedding the server, so that domain
+ * specific commands don't need a fork of the dispatch.
+ *
+ * A `CommandHandler` gives the command's name, arity and flags, and executes
+ * it against the keyspace. Handlers are registered on the builder with
+ * `RedisServerBuilder::command` and run like the built-in commands: after
+ * AUTH, MONITOR, ACL and rate limit checks, timed in commandstats and the slow
+ * log. The `write` flag makes a command count as a write, so it waits out
+ * `CLIENT PAUSE WRITE`, counts as a change for `save` and goes to the audit
+ * log. ACL rules name custom commands like any other (`+json.get`), but
+ * categories other than `@all` don't cover them. Names of built-in commands
+ * can't be taken.
+ *
+ * ```no_run
+ * use redis_starter_rust::module::{CommandFuture, CommandHandler, Session};
+ * use redis_starter_rust::parser::RedisValue;
+ * use redis_starter_rust::{RedisServer, Storage};
+ *
+ * /// UPPER key: the value of a key in upper case
+ * struct Upper;
+ *
+ * impl CommandHandler for Upper {
+ *   fn name(&self) -> &str {
+ *     "upper"
+ *   }
+ *
+ *   fn arity(&self) -> i64 {
+ *     2
+ *   }
+ *
+ *   fn execute<'a>(
+ *     &'a self,
+ *     _session: &'a Session,
+ *     storage: &'a Storage,
+ *     args: &'a [String],
+ *   ) -> CommandFuture<'a> {
+ *     Box::pin(async move {
+ *       RedisValue::BulkString(
+ *         storage
+ *           .get(&args[0])
+ *           .map(|value| String::from_utf8_lossy(&value).to_uppercase()),
+ *       )
+ *     })
+ *   }
+ * }
+ *
+ * let server = RedisServer::builder().command(Upper).build();
+ * ```
+ */
+use crate::acl;
+use crate::parser::{Command, RedisValue};
+use crate::storage::Storage;
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// The reply of a custom command, computed asynchronously
+pub type CommandFuture<'a> = Pin<Box<dyn Future<Output = RedisValue> + Send + 'a>>;
+
+/// The client a custom command runs for
+#[derive(Debug, Clone)]
+pub struct Session {
+  pub client_id: u64,
+  pub addr: SocketAddr,
+  /// Authenticated user
+  pub user: String,
+  /// Name set with CLIENT SETNAME
+  pub name: String,
+}
+
+pub trait CommandHandler: Send + Sync {
+  /// Name the command is called by, case insensitive
+  fn name(&self) -> &str;
+
+  /// Number of arguments, the command name included, or `-n` for at least `n`,
+  /// as in COMMAND INFO
+  fn arity(&self) -> i64;
+
+  /// COMMAND INFO flags such as `write`, `readonly`, `denyoom` or `fast`
+  fn flags(&self) -> &[&str] {
+    &[]
+  }
+
+  /// Runs the command with its arguments, the name excluded. The keyspace is
+  /// locked meanwhile.
+  fn execute<'a>(
+    &'a self,
+    session: &'a Session,
+    storage: &'a Storage,
+    args: &'a [String],
+  ) -> CommandFuture<'a>;
+}
+
+/// The custom commands of a server, by lower case name
+#[derive(Default, Clone)]
+pub struct ModuleCommands {
+  commands: HashMap<String, Arc<dyn CommandHandler>>,
+}
+
+impl ModuleCommands {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn register(&mut self, handler: Arc<dyn CommandHandler>) -> Result<(), String> {
+    let name = handler.name().to_lowercase();
+    if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == '|') {
+      return Err(format!("Invalid command name '{}'", handler.name()));
+    }
+    if handler.arity() == 0 {
+      return Err(format!("Invalid arity 0 for command '{}'", name));
+    }
+    if acl::is_builtin_command(&name) || self.commands.contains_key(&name) {
+      return Err(format!("Command '{}' already exists", name));
+    }
+    self.commands.insert(name, handler);
+    Ok(())
+  }
+
+  pub fn get(&self, name: &str) -> Option<&Arc<dyn CommandHandler>> {
+    self.commands.get(&name.to_lowercase())
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.commands.is_empty()
+  }
+
+  /// Whether a parsed command writes, custom commands flagged `write` included
+  pub fn is_write(&self, command: &Command) -> bool {
+    match command {
+      Command::UNKNOWN(name) => self
+        .get(name)
+        .is_some_and(|handler| handler.flags().contains(&"write")),
+      command => command.is_write(),
+    }
+  }
+
+  /// Whether a parsed command may grow the dataset, custom commands flagged
+  /// `denyoom` included
+  pub fn is_denyoom(&self, command: &Command) -> bool {
+    match command {
+      Command::UNKNOWN(name) => self
+        .get(name)
+        .is_some_and(|handler| handler.flags().contains(&"denyoom")),
+      command => command.is_denyoom(),
+    }
+  }
+}
+
+/// Checks the number of arguments, the command name included, against the
+/// arity of a handler
+pub fn check_arity(handler: &dyn CommandHandler, argc: usize) -> Result<(), String> {
+  let arity = handler.arity();
+  let valid = if arity > 0 {
+    argc as i64 == arity
+  } else {
+    argc as i64 >= -arity
+  };
+  if valid {
+    Ok(())
+  } else {
+    Err(format!(
+      "ERR wrong number of arguments for '{}' command",
+      handler.name().to_lowercase()
+    ))
+  }
+}
@@ -0,0 +1,219 @@
+/**
+ * The negative lookup cache: a filter over the keys of the keyspace that GET
+ * asks before the keyspace, so that a miss is answered without touching the
+ * shards, for cache workloads where most lookups miss.
+ *
+ * The filter is a counting Bloom filter: every key bumps `HASHES` counters
+ * chosen by its hash, and a key whose counters aren't all set was never
+ * written, or removed since. Counters are decremented when keys are removed,
+ * except the saturated ones which stay set. A key can be reported present
+ * when it isn't, a false positive then looked up in the keyspace, never the
+ * reverse. With `COUNTERS_PER_KEY` counters per key about one lookup of a
+ * missing key in a hundred is a false positive; the filter is rebuilt twice
+ * as large when the keyspace outgrows it.
+ *
+ * Keys are added to the filter once in the keyspace, so that a rebuild
+ * meanwhile can't miss them. A removal skips the filter when it was rebuilt
+ * since the key was taken out of the keyspace: the rebuild may or may not have
+ * seen the key, a stale count only costs false positives.
+ *
+ * `negative-lookup-cache yes` turns it on, building it from the keys there
+ * are. INFO stats reports the misses it answered and its false positives.
+ */
+use crate::config::Config;
+use crate::snapshot::Keyspace;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::RwLock;
+
+/// Counters each key is spread over
+const HASHES: u64 = 7;
+
+/// Counters per key the filter is sized for
+const COUNTERS_PER_KEY: usize = 10;
+
+/// Keys the smallest filter is sized for
+const MIN_CAPACITY: usize = 1024;
+
+struct Filter {
+  hasher: RandomState,
+  counters: Vec<AtomicU8>,
+  /// Keys the filter is sized for, it is rebuilt when there are more
+  capacity: usize,
+  keys: AtomicUsize,
+}
+
+impl Filter {
+  fn new(capacity: usize) -> Self {
+    let capacity = capacity.max(MIN_CAPACITY);
+    Self {
+      hasher: RandomState::new(),
+      counters: (0..capacity * COUNTERS_PER_KEY)
+        .map(|_| AtomicU8::new(0))
+        .collect(),
+      capacity,
+      keys: AtomicUsize::new(0),
+    }
+  }
+
+  /// The counters of `key`, by double hashing
+  fn slots(&self, key: &str) -> impl Iterator<Item = &AtomicU8> {
+    let hash = self.hasher.hash_one(key);
+    let (first, step) = (hash & 0xffff_ffff, (hash >> 32) | 1);
+    let len = self.counters.len() as u64;
+    (0..HASHES).map(move |i| &self.counters[(first.wrapping_add(i * step) % len) as usize])
+  }
+
+  fn insert(&self, key: &str) {
+    for counter in self.slots(key) {
+      let _ = counter.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+        count.checked_add(1)
+      });
+    }
+    self.keys.fetch_add(1, Ordering::Relaxed);
+  }
+
+  fn remove(&self, key: &str) {
+    for counter in self.slots(key) {
+      // A saturated counter may count more keys than it says, it stays set
+      let _ = counter.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+        (count > 0 && count < u8::MAX).then(|| count - 1)
+      });
+    }
+    let _ = self
+      .keys
+      .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |keys| {
+        keys.checked_sub(1)
+      });
+  }
+
+  fn contains(&self, key: &str) -> bool {
+    self
+      .slots(key)
+      .all(|counter| counter.load(Ordering::Relaxed) > 0)
+  }
+}
+
+#[derive(Default)]
+pub struct NegativeCache {
+  filter: RwLock<Option<Filter>>,
+  /// Bumped when a rebuild starts and again when it ends
+  generation: AtomicU64,
+  hits: AtomicU64,
+  false_positives: AtomicU64,
+}
+
+impl NegativeCache {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn enabled(&self) -> bool {
+    self.filter.read().unwrap().is_some()
+  }
+
+  /// Reads `negative-lookup-cache`, building the filter from the keys of
+  /// `keyspace` when it is turned on
+  pub fn apply_config(&self, config: &Config, keyspace: &Keyspace) {
+    let enabled = config.get("negative-lookup-cache").as_deref() == Some("yes");
+    if enabled == self.enabled() {
+      return;
+    }
+    if enabled {
+      self.rebuild(keyspace);
+    } else {
+      *self.filter.write().unwrap() = None;
+    }
+  }
+
+  /// Replaces the filter with one sized for twice the keys of `keyspace`,
+  /// holding them. Mustn't be called with a shard of the keyspace locked.
+  pub fn rebuild(&self, keyspace: &Keyspace) {
+    let mut current = self.filter.write().unwrap();
+    self.generation.fetch_add(1, Ordering::Relaxed);
+    let filter = Filter::new(keyspace.len() * 2);
+    for entry in keyspace.iter() {
+      filter.insert(entry.key());
+    }
+    *current = Some(filter);
+    self.generation.fetch_add(1, Ordering::Relaxed);
+  }
+
+  /// To take before removing a key from the keyspace, see `remove`
+  pub fn generation(&self) -> u64 {
+    self.generation.load(Ordering::Relaxed)
+  }
+
+  /// Adds a key written, returns whether the keyspace outgrew the filter and
+  /// it should be rebuilt
+  pub fn insert(&self, key: &str) -> bool {
+    match &*self.filter.read().unwrap() {
+      Some(filter) => {
+        filter.insert(key);
+        filter.keys.load(Ordering::Relaxed) > filter.capacity
+      }
+      None => false,
+    }
+  }
+
+  /// Removes a key taken out of the keyspace at `generation`
+  pub fn remove(&self, key: &str, generation: u64) {
+    let filter = self.filter.read().unwrap();
+    if let Some(filter) = &*filter {
+      if self.generation() == generation {
+        filter.remove(key);
+      }
+    }
+  }
+
+  /// Forgets every key, on FLUSHALL
+  pub fn clear(&self) {
+    let mut filter = self.filter.write().unwrap();
+    if filter.is_some() {
+      *filter = Some(Filter::new(MIN_CAPACITY));
+    }
+  }
+
+  /// Whether `key` is surely not in the keyspace, counting the misses
+  /// answered
+  pub fn absent(&self, key: &str) -> bool {
+    let absent = self
+      .filter
+      .read()
+      .unwrap()
+      .as_ref()
+      .is_some_and(|filter| !filter.contains(key));
+    if absent {
+      self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+    absent
+  }
+
+  /// Counts a key the filter reported present that the keyspace didn't have
+  pub fn false_positive(&self) {
+    if self.enabled() {
+      self.false_positives.fetch_add(1, Ordering::Relaxed);
+    }
+  }
+
+  /// CONFIG RESETSTAT
+  pub fn reset_stats(&self) {
+    self.hits.store(0, Ordering::Relaxed);
+    self.false_positives.store(0, Ordering::Relaxed);
+  }
+
+  /// Lines of the INFO stats section
+  pub fn info(&self) -> Vec<String> {
+    vec![
+      format!(
+        "negative_lookup_cache_hits:{}",
+        self.hits.load(Ordering::Relaxed)
+      ),
+      format!(
+        "negative_lookup_cache_false_positives:{}",
+        self.false_positives.load(Ordering::Relaxed)
+      ),
+    ]
+  }
+}
@@ -0,0 +1,42 @@
+/**
+ * Offloading of the commands that walk the whole keyspace, KEYS and DEBUG
+ * DIGEST, so that a keyspace of millions of keys doesn't stall the reactor
+ * thread serving other clients.
+ *
+ * Once the keyspace holds `offload-min-keys` keys (0 turns offloading off),
+ * such a command only holds the keyspace lock to open a copy-on-write
+ * snapshot of it (see `snapshot`), which copies nothing. The lock is released
+ * right after, and the walk over the snapshot, the matching or hashing run on
+ * a blocking thread, while other clients' GETs and SETs go on. Smaller
+ * keyspaces are processed in place, that's cheaper than a thread hop.
+ */
+use crate::config::Config;
+
+/// Keyspace size from which commands are offloaded
+pub const DEFAULT_MIN_KEYS: usize = 10_000;
+
+/// `offload-min-keys` from the configuration, `None` when offloading is off
+pub fn min_keys(config: &Config) -> Option<usize> {
+  let min_keys = config
+    .get("offload-min-keys")
+    .and_then(|value| value.parse::<usize>().ok())
+    .unwrap_or(DEFAULT_MIN_KEYS);
+  (min_keys > 0).then_some(min_keys)
+}
+
+/// Whether a keyspace of `keys` keys is large enough to offload
+pub fn should_offload(min_keys: Option<usize>, keys: usize) -> bool {
+  min_keys.is_some_and(|min_keys| keys >= min_keys)
+}
+
+/// Runs `job` on a blocking thread
+pub async fn run<T, F>(job: F) -> T
+where
+  T: Send + 'static,
+  F: FnOnce() -> T + Send + 'static,
+{
+  match tokio::task::spawn_blocking(job).await {
+    Ok(result) => result,
+    Err(e) => std::panic::resume_unwind(e.into_panic()),
+  }
+}
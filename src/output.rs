@@ -0,0 +1,174 @@
+/**
+ * Client output buffer limits.
+ *
+ * Replies are written straight to the socket by the connection that asked for
+ * them, but out-of-band messages (MONITOR lines, pub/sub messages, the replication
+ * feed...) are queued per client and written out by its connection task. A
+ * consumer that reads slower than it is fed would let that queue grow without
+ * bound, so like Redis every class of client gets a hard limit, past which it is
+ * disconnected right away, and a soft limit it may only stay above for
+ * `soft_seconds`.
+ *
+ * The limits use Redis's `client-output-buffer-limit` format:
+ * `<class> <hard> <soft> <soft seconds>`, repeated, with 0 meaning no limit.
+ */
+use crate::clients::ClientKind;
+use crate::config::parse_memory;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Same defaults as Redis: normal clients are unlimited
+pub const DEFAULT_OUTPUT_BUFFER_LIMITS: &str =
+  "normal 0 0 0 replica 256mb 64mb 60 pubsub 32mb 8mb 60";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OutputLimit {
+  pub hard: u64,
+  pub soft: u64,
+  pub soft_seconds: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputLimits {
+  pub normal: OutputLimit,
+  pub replica: OutputLimit,
+  pub pubsub: OutputLimit,
+}
+
+impl OutputLimits {
+  /// Parses `client-output-buffer-limit`. Classes that aren't mentioned keep
+  /// their current limits.
+  pub fn parse(&self, value: &str) -> Result<Self, String> {
+    let words: Vec<&str> = value.split_whitespace().collect();
+    if words.is_empty() || !words.len().is_multiple_of(4) {
+      return Err("ERR Wrong number of arguments in buffer limit configuration.".to_string());
+    }
+
+    let mut limits = *self;
+    for class in words.chunks(4) {
+      let limit = match (
+        parse_memory(class[1]),
+        parse_memory(class[2]),
+        class[3].parse::<u64>(),
+      ) {
+        (Some(hard), Some(soft), Ok(soft_seconds)) => OutputLimit {
+          hard,
+          soft,
+          soft_seconds,
+        },
+        _ => {
+          return Err(
+            "ERR Error in hard, soft or soft_seconds setting in buffer limit configuration."
+              .to_string(),
+          )
+        }
+      };
+      match class[0].to_lowercase().as_str() {
+        "normal" => limits.normal = limit,
+        "replica" | "slave" => limits.replica = limit,
+        "pubsub" => limits.pubsub = limit,
+        _ => {
+          return Err(
+            "ERR Invalid client class specified in buffer limit configuration.".to_string(),
+          )
+        }
+      }
+    }
+    Ok(limits)
+  }
+
+  /// Limits that apply to a client. The link to our master is never limited.
+  pub fn for_kind(&self, kind: ClientKind) -> OutputLimit {
+    match kind {
+      ClientKind::Normal => self.normal,
+      ClientKind::Master => OutputLimit::default(),
+      ClientKind::Replica => self.replica,
+      ClientKind::PubSub => self.pubsub,
+    }
+  }
+
+  /// The limits in CONFIG GET format, sizes in bytes
+  pub fn describe(&self) -> String {
+    [
+      ("normal", self.normal),
+      ("slave", self.replica),
+      ("pubsub", self.pubsub),
+    ]
+    .iter()
+    .map(|(class, limit)| {
+      format!(
+        "{} {} {} {}",
+        class, limit.hard, limit.soft, limit.soft_seconds
+      )
+    })
+    .collect::<Vec<String>>()
+    .join(" ")
+  }
+}
+
+impl Default for OutputLimits {
+  fn default() -> Self {
+    let unlimited = OutputLimits {
+      normal: OutputLimit::default(),
+      replica: OutputLimit::default(),
+      pubsub: OutputLimit::default(),
+    };
+    unlimited.parse(DEFAULT_OUTPUT_BUFFER_LIMITS).unwrap()
+  }
+}
+
+/// Accounting of what is queued for a client but not written to its socket yet
+#[derive(Debug, Default)]
+pub struct OutputBuffer {
+  bytes: AtomicUsize,
+  messages: AtomicUsize,
+  /// Since when the buffer has been over the soft limit
+  soft_limit_reached: Mutex<Option<Instant>>,
+}
+
+impl OutputBuffer {
+  /// Bytes waiting to be written
+  pub fn bytes(&self) -> usize {
+    self.bytes.load(Ordering::Relaxed)
+  }
+
+  /// Messages waiting to be written
+  pub fn messages(&self) -> usize {
+    self.messages.load(Ordering::Relaxed)
+  }
+
+  pub fn queued(&self, bytes: usize) {
+    self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    self.messages.fetch_add(1, Ordering::Relaxed);
+  }
+
+  pub fn written(&self, bytes: usize) {
+    self.bytes.fetch_sub(bytes, Ordering::Relaxed);
+    self.messages.fetch_sub(1, Ordering::Relaxed);
+  }
+
+  /// Whether the client went past `limit` and must be disconnected. Being over
+  /// the soft limit starts a timer, the client is only dropped if it is still
+  /// over the limit once `soft_seconds` have passed.
+  pub fn over_limit(&self, limit: &OutputLimit) -> bool {
+    let bytes = self.bytes() as u64;
+    if limit.hard > 0 && bytes >= limit.hard {
+      return true;
+    }
+
+    let mut reached = self.soft_limit_reached.lock().unwrap();
+    if limit.soft == 0 || bytes < limit.soft {
+      *reached = None;
+      return false;
+    }
+    match *reached {
+      Some(since) => since.elapsed() > Duration::from_secs(limit.soft_seconds),
+      None => {
+        *reached = Some(Instant::now());
+        false
+      }
+    }
+  }
+}
@@ -1,156 +1,608 @@
+use bytes::Bytes;
 use std::str;
 
-use log::info;
-
 #[derive(Debug)]
 pub enum Command {
   PING(Option<String>),
   ECHO(String),
   SET(String, String, Option<Vec<(String, String)>>),
   GET(String),
-  CONFIGGET(String),
+  CONFIGGET(Vec<String>),
   UNKNOWN(String),
   KEYS(String),
-  INFO(String),
+  /// Cursor, MATCH pattern and COUNT
+  SCAN(u64, Option<String>, usize),
+  INFO(Vec<String>),
+  MEMORYUSAGE(String),
+  DEL(Vec<String>),
+  UNLINK(Vec<String>),
+  FLUSHALL(Option<bool>),
+  EXPIRE(String, i64),
+  PEXPIRE(String, i64),
+  TTL(String),
+  PTTL(String),
+  PERSIST(String),
+  INCR(String),
+  DECR(String),
+  INCRBY(String, i64),
+  DECRBY(String, i64),
+  OBJECTENCODING(String),
+  OBJECTIDLETIME(String),
+  MEMORYSTATS,
+  MEMORYPURGE,
+  CLIENT(String, Vec<String>),
+  CLUSTER(String, Vec<String>),
+  ASKING,
+  ACL(String, Vec<String>),
+  AUTH(Vec<String>),
+  HELLO(Vec<String>),
+  TIME,
+  LOLWUT(Vec<String>),
+  RESET,
+  SHUTDOWN(Option<bool>),
+  DEBUG(String, Vec<String>),
+  MONITOR,
+  CONFIGRESETSTAT,
+  CONFIGSET(Vec<String>),
+  SLOWLOG(String, Vec<String>),
+  BGSAVE,
+  DUMP(String),
+  /// Key, TTL, payload, REPLACE and ABSTTL
+  RESTORE(String, i64, Vec<u8>, bool, bool),
 }
 
+/// Largest bulk argument a client may send, Redis's default
+/// `proto-max-bulk-len`
+pub const PROTO_MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+
+/// Commands whose first argument is a subcommand (`CONFIG GET`, `CLIENT LIST`, ...)
+const CONTAINER_COMMANDS: [&str; 7] = [
+  "CONFIG", "MEMORY", "OBJECT", "CLIENT", "CLUSTER", "ACL", "SLOWLOG",
+];
+
+impl Command {
+  /** Lowercase command name as reported by CLIENT LIST, e.g. `client|list` */
+  pub fn name(&self) -> String {
+    let name = match self {
+      Command::PING(_) => "ping",
+      Command::ECHO(_) => "echo",
+      Command::SET(..) => "set",
+      Command::GET(_) => "get",
+      Command::CONFIGGET(_) => "config|get",
+      Command::CONFIGRESETSTAT => "config|resetstat",
+      Command::CONFIGSET(_) => "config|set",
+      Command::KEYS(_) => "keys",
+      Command::SCAN(..) => "scan",
+      Command::INFO(_) => "info",
+      Command::MEMORYUSAGE(_) => "memory|usage",
+      Command::MEMORYSTATS => "memory|stats",
+      Command::MEMORYPURGE => "memory|purge",
+      Command::DEL(_) => "del",
+      Command::UNLINK(_) => "unlink",
+      Command::FLUSHALL(_) => "flushall",
+      Command::EXPIRE(..) => "expire",
+      Command::PEXPIRE(..) => "pexpire",
+      Command::TTL(_) => "ttl",
+      Command::PTTL(_) => "pttl",
+      Command::PERSIST(_) => "persist",
+      Command::INCR(_) => "incr",
+      Command::DECR(_) => "decr",
+      Command::INCRBY(..) => "incrby",
+      Command::DECRBY(..) => "decrby",
+      Command::OBJECTENCODING(_) => "object|encoding",
+      Command::OBJECTIDLETIME(_) => "object|idletime",
+      Command::CLIENT(subcommand, _) => return format!("client|{}", subcommand.to_lowercase()),
+      Command::CLUSTER(subcommand, _) => return format!("cluster|{}", subcommand.to_lowercase()),
+      Command::ACL(subcommand, _) => return format!("acl|{}", subcommand.to_lowercase()),
+      Command::SLOWLOG(subcommand, _) => return format!("slowlog|{}", subcommand.to_lowercase()),
+      Command::ASKING => "asking",
+      Command::AUTH(_) => "auth",
+      Command::HELLO(_) => "hello",
+      Command::TIME => "time",
+      Command::LOLWUT(_) => "lolwut",
+      Command::RESET => "reset",
+      Command::SHUTDOWN(_) => "shutdown",
+      Command::DEBUG(..) => "debug",
+      Command::MONITOR => "monitor",
+      Command::BGSAVE => "bgsave",
+      Command::DUMP(_) => "dump",
+      Command::RESTORE(..) => "restore",
+      Command::UNKNOWN(command) => return command.to_lowercase().replace(' ', "|"),
+    };
+    name.to_string()
+  }
+
+  /** Keys the command accesses, checked against the ACL key patterns */
+  pub fn keys(&self) -> Vec<&str> {
+    match self {
+      Command::SET(key, ..)
+      | Command::GET(key)
+      | Command::MEMORYUSAGE(key)
+      | Command::EXPIRE(key, _)
+      | Command::PEXPIRE(key, _)
+      | Command::TTL(key)
+      | Command::PTTL(key)
+      | Command::PERSIST(key)
+      | Command::INCR(key)
+      | Command::DECR(key)
+      | Command::INCRBY(key, _)
+      | Command::DECRBY(key, _)
+      | Command::OBJECTENCODING(key)
+      | Command::OBJECTIDLETIME(key)
+      | Command::DUMP(key)
+      | Command::RESTORE(key, ..) => vec![key.as_str()],
+      Command::DEL(keys) | Command::UNLINK(keys) => keys.iter().map(String::as_str).collect(),
+      _ => vec![],
+    }
+  }
+
+  /** Whether the command may modify the dataset; these are held back by CLIENT PAUSE WRITE */
+  pub fn is_write(&self) -> bool {
+    matches!(
+      self,
+      Command::SET(..)
+        | Command::DEL(_)
+        | Command::UNLINK(_)
+        | Command::FLUSHALL(_)
+        | Command::EXPIRE(..)
+        | Command::PEXPIRE(..)
+        | Command::PERSIST(_)
+        | Command::INCR(_)
+        | Command::DECR(_)
+        | Command::INCRBY(..)
+        | Command::DECRBY(..)
+        | Command::RESTORE(..)
+    )
+  }
+
+  /** Whether the command may grow the dataset; refused past `maxmemory` when no key can be evicted */
+  pub fn is_denyoom(&self) -> bool {
+    matches!(
+      self,
+      Command::SET(..)
+        | Command::INCR(_)
+        | Command::DECR(_)
+        | Command::INCRBY(..)
+        | Command::DECRBY(..)
+        | Command::RESTORE(..)
+    )
+  }
+}
+
+#[derive(Clone)]
 pub enum RedisValue {
   SimpleString(String),
   BulkString(Option<String>),
+  /// Bulk string of a stored value, sharing its buffer with the keyspace
+  BulkBytes(Bytes),
   Array(Vec<String>),
   Error(String),
+  Integer(i64),
+  /// Array whose elements can be of any type, including other arrays
+  Nested(Vec<RedisValue>),
+  /// Map, a flat array of keys and values in RESP2
+  Map(Vec<(RedisValue, RedisValue)>),
+  /// Out-of-band message, an array in RESP2
+  Push(Vec<RedisValue>),
 }
 
 /** Parses Redis command */
 pub fn parse_command(command_input: &[u8]) -> Result<Command, String> {
-  let input =
-    str::from_utf8(command_input).map_err(|e| format!("Invalid UTF-8 sequence: {}", e))?;
-
-  let parts: Vec<&str> = input.split("\r\n").collect();
-
-  if parts.len() < 4 || !parts[0].starts_with("*") {
-    return Err("Invalid RESP format".to_string());
+  // RESTORE's payload is binary, so it is read off the frame rather than text
+  if frame_arguments(command_input)
+    .next()
+    .is_some_and(|name| name.eq_ignore_ascii_case(b"RESTORE"))
+  {
+    return parse_restore(frame_arguments(command_input).skip(1).collect());
   }
+  let arguments = frame_arguments(command_input)
+    .map(|argument| str::from_utf8(argument).map_err(|e| format!("Invalid UTF-8 sequence: {}", e)))
+    .collect::<Result<Vec<&str>, String>>()?;
 
-  let mut command = parts[2].to_uppercase();
+  let Some(name) = arguments.first() else {
+    return Err("Invalid RESP format".to_string());
+  };
+  let mut command = name.to_uppercase();
 
-  // Check if the command is CONFIG
-  if command.starts_with("CONFIG") {
-    command = format!("{} {}", command, parts[4].to_uppercase());
+  // Check if the command is a container command such as CONFIG or MEMORY
+  if CONTAINER_COMMANDS.contains(&command.as_str()) && arguments.len() > 1 {
+    command = format!("{} {}", command, arguments[1].to_uppercase());
   }
 
   match command.as_str() {
-    "ECHO" => {
-      if parts.len() < 6 {
-        return Err("Invalid ECHO command format".to_string());
-      } else {
-        Ok(Command::ECHO(parts[4].to_string()))
+    "ECHO" => match arguments.get(1) {
+      Some(message) => Ok(Command::ECHO(message.to_string())),
+      None => Err("Invalid ECHO command format".to_string()),
+    },
+    "PING" => Ok(Command::PING(arguments.get(1).map(|s| s.to_string()))),
+    "SET" => match &arguments[1..] {
+      [] => Err("Invalid SET command format".to_string()),
+      [_] => Err("Invalid SET command format: value not provided".to_string()),
+      [key, value] => Ok(Command::SET(key.to_string(), value.to_string(), None)),
+      [key, value, options @ ..] => {
+        let options = options.iter().map(|s| s.to_string()).collect();
+        Ok(Command::SET(
+          key.to_string(),
+          value.to_string(),
+          Some(group_redis_optional_arguments(options)),
+        ))
+      }
+    },
+    // SET with EX or PX
+    "SETEX" | "PSETEX" => {
+      let arguments = command_arguments(&arguments);
+      let [key, expire, value] = arguments.as_slice() else {
+        return Err(format!(
+          "wrong number of arguments for '{}' command",
+          command.to_lowercase()
+        ));
+      };
+      if !expire.parse::<u64>().is_ok_and(|expire| expire > 0) {
+        return Err(format!(
+          "invalid expire time in '{}' command",
+          command.to_lowercase()
+        ));
       }
+      let unit = if command == "SETEX" { "EX" } else { "PX" };
+      Ok(Command::SET(
+        key.clone(),
+        value.clone(),
+        Some(vec![(unit.to_string(), expire.clone())]),
+      ))
     }
-    "PING" => {
-      if parts.len() < 4 {
-        return Err("Invalid PING command format".to_string());
-      } else if parts.len() >= 6 {
-        Ok(Command::PING(Some(parts[4].to_string())))
-      } else {
-        Ok(Command::PING(None))
+    "GET" => match arguments.get(1) {
+      Some(key) => Ok(Command::GET(key.to_string())),
+      None => Err("Invalid GET command format".to_string()),
+    },
+    "CONFIG GET" => {
+      let patterns = command_arguments(&arguments)[1..].to_vec();
+      if patterns.is_empty() {
+        return Err("Invalid CONFIG GET command format".to_string());
       }
+      Ok(Command::CONFIGGET(patterns))
     }
-    "SET" => {
-      if parts.len() < 7 {
-        if parts.len() < 6 {
-          return Err("Invalid SET command format".to_string());
-        } else {
-          return Err("Invalid SET command format: value not provided".to_string());
-        }
-      } else {
-        // Check if the optional arguments are provided
-        if parts.len() == 8 {
-          Ok(Command::SET(
-            parts[4].to_string(),
-            parts[6].to_string(),
-            None,
-          ))
-        } else if parts.len() > 8 {
-          let mut optional_args: Vec<String> = Vec::with_capacity(parts.len() - 8);
-          for i in 8..parts.len() {
-            optional_args.push(parts[i].to_string());
+    "CONFIG RESETSTAT" => Ok(Command::CONFIGRESETSTAT),
+    "CONFIG SET" => Ok(Command::CONFIGSET(
+      command_arguments(&arguments)[1..].to_vec(),
+    )),
+    "KEYS" => match arguments.get(1) {
+      Some(pattern) => Ok(Command::KEYS(pattern.to_string())),
+      None => Err("Invalid KEYS command format".to_string()),
+    },
+    "SCAN" => {
+      let arguments = command_arguments(&arguments);
+      let Some((cursor, options)) = arguments.split_first() else {
+        return Err("Invalid SCAN command format".to_string());
+      };
+      let cursor = cursor
+        .parse::<u64>()
+        .map_err(|_| "invalid cursor".to_string())?;
+      let (mut pattern, mut count) = (None, 10);
+      for option in options.chunks(2) {
+        match (option[0].to_uppercase().as_str(), option.get(1)) {
+          ("MATCH", Some(value)) => pattern = Some(value.clone()),
+          ("COUNT", Some(value)) => {
+            count = value
+              .parse::<usize>()
+              .ok()
+              .filter(|count| *count > 0)
+              .ok_or_else(|| "syntax error".to_string())?
           }
-
-          let options: Vec<String> = optional_args
-            .iter()
-            .filter(|s| !s.starts_with("$"))
-            .map(|f| f.clone())
-            .collect();
-
-          let processed_optional_arguments = group_redis_optional_arguments(options);
-
-          Ok(Command::SET(
-            parts[4].to_string(),
-            parts[6].to_string(),
-            Some(processed_optional_arguments),
-          ))
-        } else {
-          return Err("Invalid SET command format: Unknown optional parameters".to_string());
+          _ => return Err("syntax error".to_string()),
         }
       }
+      Ok(Command::SCAN(cursor, pattern, count))
     }
-    "GET" => {
-      if parts.len() < 6 {
-        if parts.len() < 5 {
-          return Err("Invalid GET command format".to_string());
-        } else {
-          return Err("Invalid GET command format: key not provided".to_string());
-        }
+    "INFO" => Ok(Command::INFO(command_arguments(&arguments))),
+    "DUMP" => match command_arguments(&arguments).into_iter().next() {
+      Some(key) => Ok(Command::DUMP(key)),
+      None => Err("Invalid DUMP command format".to_string()),
+    },
+    "DEL" | "UNLINK" => {
+      let keys = command_arguments(&arguments);
+      if keys.is_empty() {
+        Err(format!("Invalid {} command format", command))
+      } else if command == "DEL" {
+        Ok(Command::DEL(keys))
       } else {
-        Ok(Command::GET(parts[4].to_string()))
+        Ok(Command::UNLINK(keys))
       }
     }
-    "CONFIG GET" => {
-      if parts.len() < 5 {
-        return Err("Invalid CONFIG GET command format".to_string());
-      } else {
-        Ok(Command::CONFIGGET(parts[6].to_string()))
+    "FLUSHALL" => {
+      let arguments = command_arguments(&arguments);
+      match arguments.first().map(|a| a.to_uppercase()).as_deref() {
+        None => Ok(Command::FLUSHALL(None)),
+        Some("ASYNC") => Ok(Command::FLUSHALL(Some(true))),
+        Some("SYNC") => Ok(Command::FLUSHALL(Some(false))),
+        Some(_) => Err("Invalid FLUSHALL command format".to_string()),
       }
     }
-    "KEYS" => {
-      if parts.len() < 5 {
-        return Err("Invalid KEYS command format".to_string());
+    "EXPIRE" | "PEXPIRE" => {
+      let arguments = command_arguments(&arguments);
+      if arguments.len() < 2 {
+        return Err(format!("Invalid {} command format", command));
+      }
+      let timeout = arguments[1]
+        .parse::<i64>()
+        .map_err(|_| "value is not an integer or out of range".to_string())?;
+      if command == "EXPIRE" {
+        Ok(Command::EXPIRE(arguments[0].clone(), timeout))
       } else {
-        Ok(Command::KEYS(parts[4].to_string()))
+        Ok(Command::PEXPIRE(arguments[0].clone(), timeout))
       }
     }
-    "INFO" => {
-      let options = parts[4..]
-        .iter()
-        .filter(|o| !o.is_empty())
-        .collect::<Vec<&&str>>();
-      info!("Options: {:?}", options);
-
-      if parts.len() < 4 {
-        return Err("Invalid INFO command format".to_string());
+    "TTL" | "PTTL" | "PERSIST" => {
+      let arguments = command_arguments(&arguments);
+      match arguments.into_iter().next() {
+        None => Err(format!("Invalid {} command format", command)),
+        Some(key) if command == "TTL" => Ok(Command::TTL(key)),
+        Some(key) if command == "PTTL" => Ok(Command::PTTL(key)),
+        Some(key) => Ok(Command::PERSIST(key)),
+      }
+    }
+    "INCR" | "DECR" => match command_arguments(&arguments).into_iter().next() {
+      None => Err(format!("Invalid {} command format", command)),
+      Some(key) if command == "INCR" => Ok(Command::INCR(key)),
+      Some(key) => Ok(Command::DECR(key)),
+    },
+    "INCRBY" | "DECRBY" => {
+      let arguments = command_arguments(&arguments);
+      if arguments.len() < 2 {
+        return Err(format!("Invalid {} command format", command));
+      }
+      let amount = arguments[1]
+        .parse::<i64>()
+        .map_err(|_| "value is not an integer or out of range".to_string())?;
+      if command == "INCRBY" {
+        Ok(Command::INCRBY(arguments[0].clone(), amount))
+      } else if amount == i64::MIN {
+        Err("decrement would overflow".to_string())
       } else {
-        Ok(Command::INFO(parts[4].to_string()))
+        Ok(Command::DECRBY(arguments[0].clone(), amount))
+      }
+    }
+    "OBJECT ENCODING" => match arguments.get(2) {
+      Some(key) => Ok(Command::OBJECTENCODING(key.to_string())),
+      None => Err("Invalid OBJECT ENCODING command format".to_string()),
+    },
+    "OBJECT IDLETIME" => match command_arguments(&arguments).get(1) {
+      Some(key) => Ok(Command::OBJECTIDLETIME(key.clone())),
+      None => Err("Invalid OBJECT IDLETIME command format".to_string()),
+    },
+    "MEMORY STATS" => Ok(Command::MEMORYSTATS),
+    "MEMORY PURGE" => Ok(Command::MEMORYPURGE),
+    "AUTH" => Ok(Command::AUTH(command_arguments(&arguments))),
+    "HELLO" => Ok(Command::HELLO(command_arguments(&arguments))),
+    "TIME" => Ok(Command::TIME),
+    "DEBUG" => {
+      let arguments = command_arguments(&arguments);
+      match arguments.split_first() {
+        Some((subcommand, rest)) => Ok(Command::DEBUG(subcommand.to_uppercase(), rest.to_vec())),
+        None => Err("Invalid DEBUG command format".to_string()),
+      }
+    }
+    "RESET" => Ok(Command::RESET),
+    "ASKING" => Ok(Command::ASKING),
+    "BGSAVE" => Ok(Command::BGSAVE),
+    "MONITOR" => Ok(Command::MONITOR),
+    "SHUTDOWN" => {
+      let arguments = command_arguments(&arguments);
+      match arguments.first().map(|a| a.to_uppercase()).as_deref() {
+        None => Ok(Command::SHUTDOWN(None)),
+        Some("SAVE") => Ok(Command::SHUTDOWN(Some(true))),
+        Some("NOSAVE") => Ok(Command::SHUTDOWN(Some(false))),
+        Some(_) => Err("Invalid SHUTDOWN command format".to_string()),
+      }
+    }
+    "LOLWUT" => Ok(Command::LOLWUT(command_arguments(&arguments))),
+    _ if command.starts_with("ACL ") => {
+      let arguments = command_arguments(&arguments);
+      match arguments.split_first() {
+        Some((subcommand, rest)) => Ok(Command::ACL(subcommand.to_uppercase(), rest.to_vec())),
+        None => Err("Invalid ACL command format".to_string()),
+      }
+    }
+    _ if command.starts_with("CLIENT ") => {
+      let arguments = command_arguments(&arguments);
+      match arguments.split_first() {
+        Some((subcommand, rest)) => Ok(Command::CLIENT(subcommand.to_uppercase(), rest.to_vec())),
+        None => Err("Invalid CLIENT command format".to_string()),
       }
     }
+    _ if command.starts_with("SLOWLOG ") => {
+      let arguments = command_arguments(&arguments);
+      match arguments.split_first() {
+        Some((subcommand, rest)) => Ok(Command::SLOWLOG(subcommand.to_uppercase(), rest.to_vec())),
+        None => Err("Invalid SLOWLOG command format".to_string()),
+      }
+    }
+    _ if command.starts_with("CLUSTER ") => {
+      let arguments = command_arguments(&arguments);
+      match arguments.split_first() {
+        Some((subcommand, rest)) => Ok(Command::CLUSTER(subcommand.to_uppercase(), rest.to_vec())),
+        None => Err("Invalid CLUSTER command format".to_string()),
+      }
+    }
+    "MEMORY USAGE" => match arguments.get(2) {
+      Some(key) => Ok(Command::MEMORYUSAGE(key.to_string())),
+      None => Err("Invalid MEMORY USAGE command format".to_string()),
+    },
     _ => Ok(Command::UNKNOWN(command)),
   }
 }
 
+/** Length of the first complete command in `buffer`, or None until more bytes
+ * arrive: pipelined commands come several to a read, large ones over several
+ * reads. Inline commands span a line. */
+pub fn frame_length(buffer: &[u8]) -> Result<Option<usize>, String> {
+  let line_end = |start: usize| {
+    buffer[start..]
+      .windows(2)
+      .position(|window| window == b"\r\n")
+      .map(|end| start + end)
+  };
+  if buffer.first() != Some(&b'*') {
+    return Ok(buffer.iter().position(|&b| b == b'\n').map(|end| end + 1));
+  }
+  let Some(end) = line_end(0) else {
+    return Ok(None);
+  };
+  let count = parse_length(&buffer[1..end])
+    .ok_or_else(|| "ERR Protocol error: invalid multibulk length".to_string())?;
+  let mut position = end + 2;
+  for _ in 0..count {
+    let Some(end) = line_end(position) else {
+      return Ok(None);
+    };
+    if buffer[position] != b'$' {
+      return Err(format!(
+        "ERR Protocol error: expected '$', got '{}'",
+        buffer[position] as char
+      ));
+    }
+    position = parse_length(&buffer[position + 1..end])
+      .filter(|&length| length <= PROTO_MAX_BULK_LEN)
+      .and_then(|length| (end + 2 + 2).checked_add(length))
+      .ok_or_else(|| "ERR Protocol error: invalid bulk length".to_string())?;
+    if position > buffer.len() {
+      return Ok(None);
+    }
+  }
+  Ok(Some(position))
+}
+
+fn parse_length(digits: &[u8]) -> Option<usize> {
+  str::from_utf8(digits).ok()?.parse::<usize>().ok()
+}
+
+/** The bulk arguments of an array frame, binary safe unlike the text parsing */
+fn frame_arguments(frame: &[u8]) -> impl Iterator<Item = &[u8]> {
+  let line_end = |start: usize| {
+    frame
+      .get(start..)?
+      .windows(2)
+      .position(|window| window == b"\r\n")
+      .map(|end| start + end)
+  };
+  let header = (frame.first() == Some(&b'*'))
+    .then(|| line_end(0))
+    .flatten()
+    .and_then(|end| Some((parse_length(&frame[1..end])?, end + 2)));
+  let (count, mut position) = header.unwrap_or((0, 0));
+  (0..count).map_while(move |_| {
+    let end = line_end(position)?;
+    if frame[position] != b'$' {
+      return None;
+    }
+    let length = parse_length(&frame[position + 1..end])?;
+    let argument = frame.get(end + 2..(end + 2).checked_add(length)?)?;
+    position = end + 2 + length + 2;
+    Some(argument)
+  })
+}
+
+fn parse_restore(arguments: Vec<&[u8]>) -> Result<Command, String> {
+  let [key, ttl, payload, options @ ..] = &arguments[..] else {
+    return Err("Invalid RESTORE command format".to_string());
+  };
+  let key = str::from_utf8(key)
+    .map_err(|e| format!("Invalid UTF-8 sequence: {}", e))?
+    .to_string();
+  let ttl = str::from_utf8(ttl)
+    .ok()
+    .and_then(|ttl| ttl.parse::<i64>().ok())
+    .ok_or_else(|| "value is not an integer or out of range".to_string())?;
+  let (mut replace, mut absttl) = (false, false);
+  for option in options {
+    match option.to_ascii_uppercase().as_slice() {
+      b"REPLACE" => replace = true,
+      b"ABSTTL" => absttl = true,
+      _ => return Err("syntax error".to_string()),
+    }
+  }
+  Ok(Command::RESTORE(
+    key,
+    ttl,
+    payload.to_vec(),
+    replace,
+    absttl,
+  ))
+}
+
+/** Every argument of the command, starting with its name as sent by the client */
+pub fn command_argv(command_input: &[u8]) -> Vec<String> {
+  frame_arguments(command_input)
+    .map(|argument| String::from_utf8_lossy(argument).into_owned())
+    .collect()
+}
+
+/** Extracts the arguments that follow the command name */
+fn command_arguments(arguments: &[&str]) -> Vec<String> {
+  arguments[1..].iter().map(|s| s.to_string()).collect()
+}
+
 /** Serializes response to match RESP format */
-pub fn serialize_response(value: RedisValue) -> String {
+pub fn serialize_response(value: RedisValue) -> Vec<u8> {
   match value {
-    RedisValue::SimpleString(s) => format!("+{}\r\n", s),
-    RedisValue::BulkString(Some(s)) => format!("${}\r\n{}\r\n", s.len(), s),
-    RedisValue::BulkString(None) => "$-1\r\n".to_string(),
-    RedisValue::Error(s) => format!("-{}\r\n", s),
+    RedisValue::SimpleString(s) => format!("+{}\r\n", s).into_bytes(),
+    RedisValue::BulkString(Some(s)) => format!("${}\r\n{}\r\n", s.len(), s).into_bytes(),
+    RedisValue::BulkString(None) => b"$-1\r\n".to_vec(),
+    RedisValue::BulkBytes(bytes) => {
+      let mut response = format!("${}\r\n", bytes.len()).into_bytes();
+      response.extend_from_slice(&bytes);
+      response.extend_from_slice(b"\r\n");
+      response
+    }
+    RedisValue::Error(s) => format!("-{}\r\n", s).into_bytes(),
+    RedisValue::Integer(i) => format!(":{}\r\n", i).into_bytes(),
     RedisValue::Array(values) => {
-      let mut response = format!("*{}\r\n", values.len());
+      let mut response = format!("*{}\r\n", values.len()).into_bytes();
       for value in values {
-        response.push_str(&serialize_response(RedisValue::BulkString(Some(value))));
+        response.extend(serialize_response(RedisValue::BulkString(Some(value))));
+      }
+      response
+    }
+    RedisValue::Nested(values) | RedisValue::Push(values) => {
+      let mut response = format!("*{}\r\n", values.len()).into_bytes();
+      for value in values {
+        response.extend(serialize_response(value));
+      }
+      response
+    }
+    RedisValue::Map(entries) => {
+      let mut response = format!("*{}\r\n", entries.len() * 2).into_bytes();
+      for (key, value) in entries {
+        response.extend(serialize_response(key));
+        response.extend(serialize_response(value));
+      }
+      response
+    }
+  }
+}
+
+/** Serializes a reply to a client that switched to RESP3 with HELLO 3, where
+ * nulls, maps and pushes have types of their own */
+pub fn serialize_resp3(value: RedisValue) -> Vec<u8> {
+  let aggregate = |kind: char, values: Vec<RedisValue>| {
+    let mut response = format!("{}{}\r\n", kind, values.len()).into_bytes();
+    for value in values {
+      response.extend(serialize_resp3(value));
+    }
+    response
+  };
+  match value {
+    RedisValue::BulkString(None) => b"_\r\n".to_vec(),
+    RedisValue::Nested(values) => aggregate('*', values),
+    RedisValue::Push(values) => aggregate('>', values),
+    RedisValue::Map(entries) => {
+      let mut response = format!("%{}\r\n", entries.len()).into_bytes();
+      for (key, value) in entries {
+        response.extend(serialize_resp3(key));
+        response.extend(serialize_resp3(value));
       }
       response
     }
+    value => serialize_response(value),
   }
 }
 
@@ -0,0 +1,147 @@
+/**
+ * The PROXY protocol of HAProxy, versions 1 and 2, for servers behind a TCP
+ * load balancer.
+ *
+ * With `proxy-protocol yes` (plain port) or `tls-proxy-protocol yes` (TLS
+ * port), every connection on the listener must start with a PROXY header
+ * naming the client the balancer is forwarding; connections without one are
+ * dropped. The addresses in the header replace the socket's, so CLIENT LIST,
+ * the ACL log and MONITOR show the real client. `LOCAL` and `UNKNOWN` headers,
+ * sent by health checks, keep the socket's addresses.
+ *
+ * Only enable it on listeners reachable through the balancer alone: anyone
+ * else could claim any address.
+ */
+use crate::config::Config;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+
+/// Signature starting a version 2 header
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Longest version 1 header, CRLF included
+const V1_MAX_LENGTH: usize = 107;
+
+/// How long a balancer has to send the header
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `proxy-protocol` (or `tls-proxy-protocol` for TLS listeners) from the
+/// configuration, off unless set to `yes`
+pub fn enabled(config: &Config, tls: bool) -> bool {
+  let parameter = if tls {
+    "tls-proxy-protocol"
+  } else {
+    "proxy-protocol"
+  };
+  config
+    .get(parameter)
+    .is_some_and(|value| value.eq_ignore_ascii_case("yes"))
+}
+
+fn invalid(message: &str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Reads the PROXY header at the start of `stream`, returning the client and
+/// destination addresses it names, if any
+pub async fn read_header(stream: &mut TcpStream) -> io::Result<Option<(SocketAddr, SocketAddr)>> {
+  tokio::time::timeout(HEADER_TIMEOUT, async {
+    let mut start = [0u8; 12];
+    stream.read_exact(&mut start).await?;
+    if start == V2_SIGNATURE {
+      read_v2(stream).await
+    } else if start.starts_with(b"PROXY ") {
+      read_v1(stream, &start).await
+    } else {
+      Err(invalid("missing PROXY protocol header"))
+    }
+  })
+  .await
+  .map_err(|_| {
+    io::Error::new(
+      io::ErrorKind::TimedOut,
+      "timed out reading the PROXY header",
+    )
+  })?
+}
+
+/// `PROXY TCP4|TCP6|UNKNOWN <source> <destination> <source port> <destination port>\r\n`
+async fn read_v1(
+  stream: &mut TcpStream,
+  start: &[u8],
+) -> io::Result<Option<(SocketAddr, SocketAddr)>> {
+  let mut line = start.to_vec();
+  while !line.ends_with(b"\r\n") {
+    if line.len() >= V1_MAX_LENGTH {
+      return Err(invalid("PROXY header too long"));
+    }
+    line.push(stream.read_u8().await?);
+  }
+  let line = std::str::from_utf8(&line[..line.len() - 2])
+    .map_err(|_| invalid("PROXY header is not ASCII"))?;
+  match line.split(' ').collect::<Vec<&str>>()[..] {
+    ["PROXY", "UNKNOWN", ..] => Ok(None),
+    ["PROXY", "TCP4" | "TCP6", source, destination, source_port, destination_port] => {
+      let address = |ip: &str, port: &str| -> io::Result<SocketAddr> {
+        let ip = ip
+          .parse::<IpAddr>()
+          .map_err(|_| invalid("invalid address in PROXY header"))?;
+        let port = port
+          .parse::<u16>()
+          .map_err(|_| invalid("invalid port in PROXY header"))?;
+        Ok(SocketAddr::new(ip, port))
+      };
+      Ok(Some((
+        address(source, source_port)?,
+        address(destination, destination_port)?,
+      )))
+    }
+    _ => Err(invalid("malformed PROXY header")),
+  }
+}
+
+/// The binary header: version and command, family, length, then addresses
+async fn read_v2(stream: &mut TcpStream) -> io::Result<Option<(SocketAddr, SocketAddr)>> {
+  let version_command = stream.read_u8().await?;
+  let family = stream.read_u8().await?;
+  let length = stream.read_u16().await? as usize;
+  let mut payload = vec![0u8; length];
+  stream.read_exact(&mut payload).await?;
+
+  if version_command >> 4 != 2 {
+    return Err(invalid("unsupported PROXY protocol version"));
+  }
+  // LOCAL: the balancer's own connection, e.g. a health check
+  if version_command & 0x0f == 0 {
+    return Ok(None);
+  }
+  let port = |offset: usize| u16::from_be_bytes([payload[offset], payload[offset + 1]]);
+  match family >> 4 {
+    1 if length >= 12 => {
+      let ip = |offset: usize| {
+        let octets: [u8; 4] = payload[offset..offset + 4].try_into().unwrap();
+        IpAddr::V4(Ipv4Addr::from(octets))
+      };
+      Ok(Some((
+        SocketAddr::new(ip(0), port(8)),
+        SocketAddr::new(ip(4), port(10)),
+      )))
+    }
+    2 if length >= 36 => {
+      let ip = |offset: usize| {
+        let octets: [u8; 16] = payload[offset..offset + 16].try_into().unwrap();
+        IpAddr::V6(Ipv6Addr::from(octets))
+      };
+      Ok(Some((
+        SocketAddr::new(ip(0), port(32)),
+        SocketAddr::new(ip(16), port(34)),
+      )))
+    }
+    1 | 2 => Err(invalid("truncated PROXY header")),
+    // UNSPEC and Unix sockets carry no address to show
+    _ => Ok(None),
+  }
+}
@@ -0,0 +1,183 @@
+/**
+ * Per-client command rate limiting. This is not something Redis has, it keeps a
+ * noisy client from hogging a shared instance.
+ *
+ * Every client gets a token bucket that refills at `ratelimit` commands per
+ * second and holds up to `ratelimit-burst` tokens. Each command takes a token;
+ * when there is none left the command is either held back until one is available
+ * (`ratelimit-policy delay`, the default) or rejected with a `-RATELIMIT` error
+ * (`ratelimit-policy reject`). With `ratelimit-scope user` the connections of an
+ * ACL user share a single bucket instead.
+ *
+ * `ratelimit 0`, the default, turns rate limiting off. CONFIG SET of any of
+ * these settings starts every bucket afresh.
+ */
+use crate::config::Config;
+use dashmap::DashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+use std::time::Duration;
+use tokio::time::Instant;
+
+pub const RATE_LIMITED_ERROR: &str = "RATELIMIT command rate limit exceeded, try again later";
+
+/// What happens to commands over the limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitPolicy {
+  Delay,
+  Reject,
+}
+
+/// Who shares a bucket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitScope {
+  Client,
+  User,
+}
+
+/// Outcome of asking for a token
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+  Allowed,
+  /// Run the command once this has elapsed
+  Delayed(Duration),
+  Rejected,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TokenBucket {
+  tokens: f64,
+  refilled_at: Instant,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct RateLimitSettings {
+  /// Commands per second, 0 when rate limiting is off
+  rate: f64,
+  burst: f64,
+  policy: RateLimitPolicy,
+  scope: RateLimitScope,
+}
+
+impl RateLimitSettings {
+  /// Reads `ratelimit`, `ratelimit-burst`, `ratelimit-policy` and `ratelimit-scope`
+  fn from_config(config: &Config) -> Result<Self, String> {
+    let rate = match config.get("ratelimit") {
+      Some(rate) => rate
+        .parse::<f64>()
+        .ok()
+        .filter(|rate| rate.is_finite() && *rate >= 0.0)
+        .ok_or_else(|| format!("Invalid ratelimit '{}'", rate))?,
+      None => 0.0,
+    };
+    // The burst defaults to a second worth of commands, which 0 also asks for
+    let burst = match config.get("ratelimit-burst").filter(|burst| burst != "0") {
+      Some(burst) => burst
+        .parse::<f64>()
+        .ok()
+        .filter(|burst| burst.is_finite() && *burst >= 1.0)
+        .ok_or_else(|| format!("Invalid ratelimit-burst '{}'", burst))?,
+      None => rate.max(1.0),
+    };
+    let policy = match config.get("ratelimit-policy").as_deref() {
+      None | Some("delay") => RateLimitPolicy::Delay,
+      Some("reject") => RateLimitPolicy::Reject,
+      Some(policy) => return Err(format!("Invalid ratelimit-policy '{}'", policy)),
+    };
+    let scope = match config.get("ratelimit-scope").as_deref() {
+      None | Some("client") => RateLimitScope::Client,
+      Some("user") => RateLimitScope::User,
+      Some(scope) => return Err(format!("Invalid ratelimit-scope '{}'", scope)),
+    };
+
+    Ok(Self {
+      rate,
+      burst,
+      policy,
+      scope,
+    })
+  }
+}
+
+pub struct RateLimiter {
+  settings: RwLock<RateLimitSettings>,
+  buckets: DashMap<String, TokenBucket>,
+  limited: AtomicU64,
+}
+
+impl RateLimiter {
+  pub fn from_config(config: &Config) -> Result<Self, String> {
+    Ok(Self {
+      settings: RwLock::new(RateLimitSettings::from_config(config)?),
+      buckets: DashMap::new(),
+      limited: AtomicU64::new(0),
+    })
+  }
+
+  /// Applies changed `ratelimit*` settings, leaving the current ones in place
+  /// when they are invalid
+  pub fn reconfigure(&self, config: &Config) -> Result<(), String> {
+    let settings = RateLimitSettings::from_config(config)?;
+    *self.settings.write().unwrap() = settings;
+    self.buckets.clear();
+    Ok(())
+  }
+
+  fn settings(&self) -> RateLimitSettings {
+    *self.settings.read().unwrap()
+  }
+
+  pub fn enabled(&self) -> bool {
+    self.settings().rate > 0.0
+  }
+
+  /// Takes a token for a command of client `id`, authenticated as `user`
+  pub fn acquire(&self, id: u64, user: &str) -> Admission {
+    let settings = self.settings();
+    if settings.rate <= 0.0 {
+      return Admission::Allowed;
+    }
+
+    let key = match settings.scope {
+      RateLimitScope::Client => format!("client:{}", id),
+      RateLimitScope::User => format!("user:{}", user),
+    };
+    let now = Instant::now();
+    let mut bucket = self.buckets.entry(key).or_insert(TokenBucket {
+      tokens: settings.burst,
+      refilled_at: now,
+    });
+
+    let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+    bucket.tokens = (bucket.tokens + elapsed * settings.rate).min(settings.burst);
+    bucket.refilled_at = now;
+
+    if bucket.tokens >= 1.0 {
+      bucket.tokens -= 1.0;
+      return Admission::Allowed;
+    }
+
+    self.limited.fetch_add(1, Ordering::Relaxed);
+    match settings.policy {
+      RateLimitPolicy::Reject => Admission::Rejected,
+      RateLimitPolicy::Delay => {
+        // The token is taken now, so commands queued behind it wait their turn
+        let wait = (1.0 - bucket.tokens) / settings.rate;
+        bucket.tokens -= 1.0;
+        Admission::Delayed(Duration::from_secs_f64(wait))
+      }
+    }
+  }
+
+  /// Drops the bucket of a client that disconnected
+  pub fn forget_client(&self, id: u64) {
+    if self.settings().scope == RateLimitScope::Client {
+      self.buckets.remove(&format!("client:{}", id));
+    }
+  }
+
+  /// Number of commands delayed or rejected since startup
+  pub fn limited_commands(&self) -> u64 {
+    self.limited.load(Ordering::Relaxed)
+  }
+}
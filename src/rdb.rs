@@ -0,0 +1,379 @@
+/**
+ * Writes the keyspace as an RDB file that `database.rs` (and Redis) can load.
+ *
+ * Only what the server stores is written: string values, with their absolute
+ * expiry time in milliseconds. Strings are written as length prefixed bytes,
+ * LZF compressed with `rdbcompression yes` when longer than
+ * `COMPRESSION_MIN_LENGTH` and compression saves room, as Redis does; the
+ * integer encoding is not used. With `rdbchecksum yes` the file ends with a
+ * CRC64 of its content, otherwise with zeros, which readers take as "checksum
+ * disabled".
+ *
+ * The file is written to a temporary path first and renamed over the target, so
+ * a crash mid-save never leaves a truncated snapshot behind. With an encryption
+ * key the encoded snapshot is sealed before being written, see `encryption.rs`.
+ *
+ * BGSAVE only holds the storage lock to open a copy-on-write snapshot of the
+ * keyspace, see `snapshot.rs`, then encodes and writes it on a blocking
+ * thread while clients keep writing.
+ */
+use crate::config::Config;
+use crate::dump::crc64;
+use crate::encryption::{self, EncryptionKey};
+use crate::lzf;
+use crate::state::ServerState;
+use crate::stats::Stats;
+use crate::storage::Storage;
+use crate::storagelock::StorageLock;
+use bytes::Bytes;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{error, info};
+
+const RDB_VERSION: &str = "0011";
+
+const OPCODE_AUX: u8 = 0xFA;
+const OPCODE_RESIZEDB: u8 = 0xFB;
+const OPCODE_EXPIRETIME_MS: u8 = 0xFC;
+const OPCODE_SELECTDB: u8 = 0xFE;
+const OPCODE_EOF: u8 = 0xFF;
+
+const TYPE_STRING: u8 = 0;
+
+const ENCODING_LZF: u8 = 0xC3;
+
+/// Strings up to this length aren't worth compressing, as in Redis
+const COMPRESSION_MIN_LENGTH: usize = 20;
+
+/// The saves of a server, for INFO persistence
+pub struct Saves {
+  /// Unix time of the last successful save, the startup time until then
+  last_save: AtomicU64,
+  last_save_failed: AtomicBool,
+  bgsave_in_progress: AtomicBool,
+}
+
+impl Saves {
+  pub fn new() -> Self {
+    Self {
+      last_save: AtomicU64::new(0),
+      last_save_failed: AtomicBool::new(false),
+      bgsave_in_progress: AtomicBool::new(false),
+    }
+  }
+
+  /// Records the outcome of a save. `dirty` is the number of changes the
+  /// snapshot includes, changes made since are still to be saved.
+  fn saved(&self, stats: &Stats, result: &io::Result<usize>, dirty: u64) {
+    self
+      .last_save_failed
+      .store(result.is_err(), Ordering::Relaxed);
+    if result.is_ok() {
+      self.last_save.store(unix_time(), Ordering::Relaxed);
+      let _ = stats
+        .dirty
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |now| {
+          Some(now.saturating_sub(dirty))
+        });
+    }
+  }
+}
+
+impl Default for Saves {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+/// How snapshots are encoded: `rdbcompression` and `rdbchecksum`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RdbOptions {
+  pub compression: bool,
+  pub checksum: bool,
+}
+
+impl Default for RdbOptions {
+  fn default() -> Self {
+    Self {
+      compression: true,
+      checksum: true,
+    }
+  }
+}
+
+impl RdbOptions {
+  pub fn from_config(config: &Config) -> Self {
+    let enabled = |name: &str| config.get(name).as_deref() != Some("no");
+    Self {
+      compression: enabled("rdbcompression"),
+      checksum: enabled("rdbchecksum"),
+    }
+  }
+}
+
+/// Where snapshots are written: `dir`/`dbfilename`, defaulting like Redis
+pub fn snapshot_path(config: &Config) -> PathBuf {
+  let dir = config.get("dir").unwrap_or_else(|| ".".to_string());
+  let dbfilename = config
+    .get("dbfilename")
+    .unwrap_or_else(|| "dump.rdb".to_string());
+  PathBuf::from(dir).join(dbfilename)
+}
+
+fn write_length(out: &mut Vec<u8>, length: usize) {
+  if length < 1 << 6 {
+    out.push(length as u8);
+  } else if length < 1 << 14 {
+    out.push(0x40 | (length >> 8) as u8);
+    out.push(length as u8);
+  } else if length <= u32::MAX as usize {
+    out.push(0x80);
+    out.extend_from_slice(&(length as u32).to_be_bytes());
+  } else {
+    out.push(0x81);
+    out.extend_from_slice(&(length as u64).to_be_bytes());
+  }
+}
+
+/// Bytes a string takes once serialized, as reported by DEBUG OBJECT
+pub fn serialized_length(value: &[u8]) -> usize {
+  let mut prefix = Vec::with_capacity(9);
+  write_length(&mut prefix, value.len());
+  prefix.len() + value.len()
+}
+
+fn write_string(out: &mut Vec<u8>, value: &[u8]) {
+  write_length(out, value.len());
+  out.extend_from_slice(value);
+}
+
+/// Writes a string LZF compressed when that makes it shorter, plainly
+/// otherwise
+fn write_compressed_string(out: &mut Vec<u8>, value: &[u8], compressor: &mut lzf::Compressor) {
+  // Compressed, the string takes its two lengths on top of the data
+  let compressed = (value.len() > COMPRESSION_MIN_LENGTH)
+    .then(|| compressor.compress(value, value.len() - 4))
+    .flatten();
+  match compressed {
+    Some(compressed) => {
+      out.push(ENCODING_LZF);
+      write_length(out, compressed.len());
+      write_length(out, value.len());
+      out.extend_from_slice(&compressed);
+    }
+    None => write_string(out, value),
+  }
+}
+
+fn write_aux(out: &mut Vec<u8>, key: &str, value: &str) {
+  out.push(OPCODE_AUX);
+  write_string(out, key.as_bytes());
+  write_string(out, value.as_bytes());
+}
+
+/// Serializes the keyspace in RDB format
+pub fn encode(storage: &Storage, options: RdbOptions) -> Vec<u8> {
+  encode_entries(storage.entries(), options)
+}
+
+/// Serializes entries taken out of the keyspace in RDB format
+pub fn encode_entries(
+  entries: Vec<(String, Bytes, Option<SystemTime>)>,
+  options: RdbOptions,
+) -> Vec<u8> {
+  let ctime = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap_or_default();
+
+  // Each entry adds its type, lengths and deadline to the key and value
+  let size: usize = entries
+    .iter()
+    .map(|(key, value, _)| key.len() + value.len() + 16)
+    .sum();
+  let mut out = Vec::with_capacity(size + 64);
+  out.extend_from_slice(b"REDIS");
+  out.extend_from_slice(RDB_VERSION.as_bytes());
+  write_aux(&mut out, "redis-ver", env!("CARGO_PKG_VERSION"));
+  write_aux(&mut out, "redis-bits", &(usize::BITS).to_string());
+  write_aux(&mut out, "ctime", &ctime.as_secs().to_string());
+
+  out.push(OPCODE_SELECTDB);
+  out.push(0);
+  out.push(OPCODE_RESIZEDB);
+  write_length(&mut out, entries.len());
+  write_length(
+    &mut out,
+    entries
+      .iter()
+      .filter(|(_, _, expires_at)| expires_at.is_some())
+      .count(),
+  );
+
+  let mut compressor = options.compression.then(lzf::Compressor::new);
+  for (key, value, expires_at) in entries {
+    if let Some(expires_at) = expires_at {
+      let millis = expires_at
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+      out.push(OPCODE_EXPIRETIME_MS);
+      out.extend_from_slice(&millis.to_le_bytes());
+    }
+    out.push(TYPE_STRING);
+    match &mut compressor {
+      Some(compressor) => {
+        write_compressed_string(&mut out, key.as_bytes(), compressor);
+        write_compressed_string(&mut out, &value, compressor);
+      }
+      None => {
+        write_string(&mut out, key.as_bytes());
+        write_string(&mut out, &value);
+      }
+    }
+  }
+
+  out.push(OPCODE_EOF);
+  let checksum = if options.checksum { crc64(&out) } else { 0 };
+  out.extend_from_slice(&checksum.to_le_bytes());
+  out
+}
+
+fn unix_time() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap_or_default()
+    .as_secs()
+}
+
+/// Encrypts the encoded snapshot when there is a key
+fn seal(data: Vec<u8>, key: Option<&EncryptionKey>) -> io::Result<Vec<u8>> {
+  match key {
+    Some(key) => encryption::seal(key, &data).map_err(io::Error::other),
+    None => Ok(data),
+  }
+}
+
+/// Writes a snapshot of the keyspace to `path`, atomically replacing it and
+/// encrypting it with `key`. Returns the number of bytes written.
+pub fn save(
+  storage: &Storage,
+  path: &PathBuf,
+  key: Option<&EncryptionKey>,
+  options: RdbOptions,
+) -> io::Result<usize> {
+  seal(encode(storage, options), key).and_then(|data| write_snapshot(&data, path))
+}
+
+/// SAVE for a server: `save`, failing while the RDB-WRITE-ERROR fault is
+/// injected, with the outcome recorded for INFO persistence
+pub fn server_save(
+  state: &ServerState,
+  storage: &Storage,
+  path: &PathBuf,
+  key: Option<&EncryptionKey>,
+  options: RdbOptions,
+) -> io::Result<usize> {
+  let dirty = state.stats.dirty.load(Ordering::Relaxed);
+  let result = if state.faults.rdb_write_error() {
+    Err(write_error_fault())
+  } else {
+    save(storage, path, key, options)
+  };
+  state.saves.saved(&state.stats, &result, dirty);
+  result
+}
+
+/// BGSAVE: saves a snapshot of the keyspace to `path` in the background,
+/// encrypted with `key` and encoded with `options`
+pub fn bgsave(
+  state: Arc<ServerState>,
+  storage: Arc<StorageLock>,
+  path: PathBuf,
+  key: Option<EncryptionKey>,
+  options: RdbOptions,
+) -> Result<(), String> {
+  if state.saves.bgsave_in_progress.swap(true, Ordering::SeqCst) {
+    return Err("ERR Background save already in progress".to_string());
+  }
+  tokio::spawn(async move {
+    let (snapshot, dirty) = {
+      let storage = storage.lock().await;
+      (
+        storage.begin_snapshot(),
+        state.stats.dirty.load(Ordering::Relaxed),
+      )
+    };
+    let fault = state.faults.rdb_write_error();
+    let result = tokio::task::spawn_blocking(move || {
+      if fault {
+        return Err(write_error_fault());
+      }
+      let data = encode_entries(snapshot.entries(), options);
+      seal(data, key.as_ref()).and_then(|data| write_snapshot(&data, &path))
+    })
+    .await
+    .unwrap_or_else(|e| Err(io::Error::other(e.to_string())));
+    match &result {
+      Ok(bytes) => info!(
+        "Background saving terminated with success ({} bytes)",
+        bytes
+      ),
+      Err(e) => error!("Background saving error: {}", e),
+    }
+    state.saves.saved(&state.stats, &result, dirty);
+    state
+      .saves
+      .bgsave_in_progress
+      .store(false, Ordering::SeqCst);
+  });
+  Ok(())
+}
+
+fn write_error_fault() -> io::Error {
+  io::Error::other("injected fault: RDB write error")
+}
+
+fn write_snapshot(data: &[u8], path: &PathBuf) -> io::Result<usize> {
+  let temporary = path.with_file_name(format!("temp-{}.rdb", std::process::id()));
+
+  let mut file = fs::File::create(&temporary)?;
+  file.write_all(data)?;
+  file.sync_all()?;
+  drop(file);
+  fs::rename(&temporary, path)?;
+
+  Ok(data.len())
+}
+
+/// Lines of the `# Persistence` INFO section of a server
+pub fn persistence_info(state: &ServerState) -> Vec<String> {
+  let last_save = match state.saves.last_save.load(Ordering::Relaxed) {
+    0 => crate::info::started_at(),
+    last_save => last_save,
+  };
+  let status = if state.saves.last_save_failed.load(Ordering::Relaxed) {
+    "err"
+  } else {
+    "ok"
+  };
+  vec![
+    "loading:0".to_string(),
+    "async_loading:0".to_string(),
+    format!(
+      "rdb_changes_since_last_save:{}",
+      state.stats.dirty.load(Ordering::Relaxed)
+    ),
+    format!(
+      "rdb_bgsave_in_progress:{}",
+      state.saves.bgsave_in_progress.load(Ordering::Relaxed) as u8
+    ),
+    format!("rdb_last_save_time:{}", last_save),
+    format!("rdb_last_bgsave_status:{}", status),
+    "aof_enabled:0".to_string(),
+    "aof_rewrite_in_progress:0".to_string(),
+  ]
+}
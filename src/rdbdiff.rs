@@ -0,0 +1,139 @@
+/**
+ * `redis-server --diff-rdb a.rdb b.rdb` compares two RDB dumps, to validate
+ * a migration or check a backup, and exits without starting the server.
+ *
+ * The report is a JSON document on stdout listing the keys only in A, the
+ * keys only in B, and the keys in both whose value or deadline differs, each
+ * sorted by name:
+ *
+ * ```json
+ * {
+ *   "only_in_a": ["session:1"],
+ *   "only_in_b": [],
+ *   "changed": [
+ *     { "key": "user:1", "a": { "value": "alice", "expires_at": null }, "b": { "value": "bob", "expires_at": null } }
+ *   ]
+ * }
+ * ```
+ *
+ * `expires_at` is the Unix time in milliseconds the key expires at. Like
+ * diff(1) the exit status is 0 when the dumps hold the same keys, 1 when they
+ * differ and 2 when one can't be read. Encrypted dumps are decrypted with the
+ * key of the `REDIS_ENCRYPTION_KEY` environment variable.
+ */
+use crate::database::RDBParser;
+use crate::encryption;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::time::UNIX_EPOCH;
+
+/// A key's value and deadline in a dump
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+  pub value: Vec<u8>,
+  /// Unix time in milliseconds
+  pub expires_at: Option<u64>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RdbDiff {
+  pub only_in_a: Vec<String>,
+  pub only_in_b: Vec<String>,
+  /// Keys with their entry in A and in B
+  pub changed: Vec<(String, Entry, Entry)>,
+}
+
+impl RdbDiff {
+  pub fn is_empty(&self) -> bool {
+    self.only_in_a.is_empty() && self.only_in_b.is_empty() && self.changed.is_empty()
+  }
+
+  pub fn to_json(&self) -> String {
+    let entry = |entry: &Entry| {
+      json!({
+        "value": RDBParser::stringify(&entry.value),
+        "expires_at": entry.expires_at,
+      })
+    };
+    let changed: Vec<Value> = self
+      .changed
+      .iter()
+      .map(|(key, a, b)| json!({ "key": key, "a": entry(a), "b": entry(b) }))
+      .collect();
+    let document = json!({
+      "only_in_a": self.only_in_a,
+      "only_in_b": self.only_in_b,
+      "changed": changed,
+    });
+    serde_json::to_string_pretty(&document).unwrap_or_default()
+  }
+}
+
+/// The keys of the dump at `path`
+pub fn load(path: &str) -> Result<BTreeMap<String, Entry>, String> {
+  let data = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+  let key = encryption::env_key()?;
+  let data = encryption::decrypt(data, key.as_ref()).map_err(|e| format!("{}: {}", path, e))?;
+  let mut parser = RDBParser::new(data);
+  parser.parse().map_err(|e| format!("{}: {}", path, e))?;
+
+  let mut keys = BTreeMap::new();
+  for (key, value) in parser.entries() {
+    let entry = Entry {
+      value: value.clone(),
+      expires_at: None,
+    };
+    keys.insert(RDBParser::stringify(key), entry);
+  }
+  for (key, value, deadline) in parser.expiry_entries() {
+    let expires_at = deadline
+      .duration_since(UNIX_EPOCH)
+      .unwrap_or_default()
+      .as_millis() as u64;
+    let entry = Entry {
+      value: value.clone(),
+      expires_at: Some(expires_at),
+    };
+    keys.insert(RDBParser::stringify(key), entry);
+  }
+  Ok(keys)
+}
+
+pub fn diff(a: &BTreeMap<String, Entry>, b: &BTreeMap<String, Entry>) -> RdbDiff {
+  let mut diff = RdbDiff::default();
+  for (key, entry) in a {
+    match b.get(key) {
+      None => diff.only_in_a.push(key.clone()),
+      Some(other) if other != entry => {
+        diff
+          .changed
+          .push((key.clone(), entry.clone(), other.clone()))
+      }
+      Some(_) => {}
+    }
+  }
+  diff.only_in_b = b
+    .keys()
+    .filter(|key| !a.contains_key(*key))
+    .cloned()
+    .collect();
+  diff
+}
+
+/// `--diff-rdb`: prints the report and returns the exit status
+pub fn run(a: &str, b: &str) -> i32 {
+  let (a, b) = match (load(a), load(b)) {
+    (Ok(a), Ok(b)) => (a, b),
+    (Err(e), _) | (_, Err(e)) => {
+      eprintln!("Failed to read {}", e);
+      return 2;
+    }
+  };
+  let diff = diff(&a, &b);
+  println!("{}", diff.to_json());
+  if diff.is_empty() {
+    0
+  } else {
+    1
+  }
+}
@@ -0,0 +1,128 @@
+/**
+ * Read-through caching: when GET misses, the value is asked from the system
+ * of record, stored with a TTL and returned, so that the server can stand in
+ * front of a database without the clients filling it.
+ *
+ * The miss handler is either a `MissHandler` given to the builder by an
+ * application embedding the server, or `read-through-command`: a program run
+ * with its arguments and the key appended. The program prints the value on
+ * stdout and exits with 0, one trailing newline being dropped, or exits with 1
+ * when the key doesn't exist either; other statuses are errors. A handler set
+ * on the builder wins over the command.
+ *
+ * Loaded values expire after `read-through-ttl` seconds, 0 keeping them for
+ * good. Handlers running longer than `read-through-timeout` milliseconds fail
+ * the GET with an error, as do handler errors; keys that don't exist stay
+ * misses and are asked again next time. Neither the keyspace nor the key is
+ * locked while a handler runs, and a key written meanwhile wins over the
+ * loaded value.
+ */
+use crate::config::Config;
+use std::future::Future;
+use std::pin::Pin;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::sync::Mutex as AsyncMutex;
+use tracing::debug;
+
+/// The value of a missing key, `None` when it doesn't exist either
+pub type MissFuture<'a> = Pin<Box<dyn Future<Output = Result<Option<String>, String>> + Send + 'a>>;
+
+pub trait MissHandler: Send + Sync {
+  fn load<'a>(&'a self, key: &'a str) -> MissFuture<'a>;
+}
+
+/// A key loaded by the miss handler, to store and return
+pub struct Loaded {
+  pub value: String,
+  pub ttl: Option<Duration>,
+}
+
+/// The miss handler of a server
+#[derive(Default)]
+pub struct ReadThrough {
+  handler: Option<Arc<dyn MissHandler>>,
+}
+
+impl ReadThrough {
+  pub fn new(handler: Option<Arc<dyn MissHandler>>) -> Self {
+    Self { handler }
+  }
+
+  /// Asks the miss handler for `key`. `Ok(None)` when there is no handler or
+  /// the key doesn't exist.
+  pub async fn load(
+    &self,
+    key: &str,
+    config: &Arc<AsyncMutex<Config>>,
+  ) -> Result<Option<Loaded>, String> {
+    let (command, ttl, timeout) = {
+      let config = config.lock().await;
+      let number = |name: &str| {
+        config
+          .get_or_default(name)
+          .and_then(|value| value.parse::<u64>().ok())
+          .unwrap_or(0)
+      };
+      (
+        config.get("read-through-command").unwrap_or_default(),
+        number("read-through-ttl"),
+        Duration::from_millis(number("read-through-timeout")),
+      )
+    };
+    let loading = match &self.handler {
+      Some(handler) => handler.load(key),
+      None if !command.is_empty() => Box::pin(run_command(command, key)),
+      None => return Ok(None),
+    };
+
+    debug!("Read-through load of {}", key);
+    let value = tokio::time::timeout(timeout, loading)
+      .await
+      .map_err(|_| "ERR read-through handler timed out".to_string())?
+      .map_err(|e| format!("ERR read-through handler failed: {}", e))?;
+    Ok(value.map(|value| Loaded {
+      value,
+      ttl: (ttl > 0).then(|| Duration::from_secs(ttl)),
+    }))
+  }
+}
+
+/// Runs `read-through-command` for `key`
+async fn run_command(command: String, key: &str) -> Result<Option<String>, String> {
+  let mut words = command.split_whitespace();
+  let program = words.next().unwrap_or_default();
+  let output = Command::new(program)
+    .args(words)
+    .arg(key)
+    .stdin(Stdio::null())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    // Timing out drops the future, which must not leave the process behind
+    .kill_on_drop(true)
+    .output()
+    .await
+    .map_err(|e| format!("{}: {}", program, e))?;
+  match output.status.code() {
+    Some(0) => {
+      let mut value = String::from_utf8(output.stdout)
+        .map_err(|_| format!("{} printed invalid UTF-8", program))?;
+      if value.ends_with('\n') {
+        value.pop();
+        if value.ends_with('\r') {
+          value.pop();
+        }
+      }
+      Ok(Some(value))
+    }
+    Some(1) => Ok(None),
+    _ => Err(format!(
+      "{} exited with {}: {}",
+      program,
+      output.status,
+      String::from_utf8_lossy(&output.stderr).trim()
+    )),
+  }
+}
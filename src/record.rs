@@ -0,0 +1,320 @@
+/**
+ * Recording what clients send, to reproduce a bug elsewhere: `record-file
+ * <file>` writes every frame read from every connection to the file, byte for
+ * byte as it arrived, and `replay-file <file>` runs such a recording again at
+ * startup. A recording attached to a bug report brings another server to the
+ * same state, through the same commands in the same order.
+ *
+ * The file starts with a version line, then holds one entry per frame: the
+ * microseconds since the recording started, the client ID and the frame
+ * length on a line, then the frame itself and a CRLF.
+ *
+ * ```text
+ * REDIS-RS-RECORD 1
+ * 1520 7 31
+ * *3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nvalue\r\n
+ * ```
+ *
+ * Every frame is recorded, including those refused or that fail to parse, and
+ * passwords sent with AUTH or HELLO are kept in clear, so recordings are as
+ * sensitive as the data. Setting `record-file` starts the file over, an empty
+ * value stops recording.
+ *
+ * Replaying gives each recorded client an in-process connection, like
+ * `import-protocol` does, and sends the frames in the order they were
+ * recorded, each once the previous one was answered, so that the clients
+ * interleave as they did. With `replay-timing yes` the recorded gaps between
+ * frames are kept as well. A frame answered with nothing, such as after
+ * CLIENT REPLY OFF, holds the next one back for a second at most.
+ */
+use crate::acl::Acl;
+use crate::clients::ClientRegistry;
+use crate::cluster::Cluster;
+use crate::config::Config;
+use crate::connection::Connection;
+use crate::import::reply_length;
+use crate::module::ModuleCommands;
+use crate::ratelimit::RateLimiter;
+use crate::readthrough::ReadThrough;
+use crate::server::serve_client;
+use crate::state::ServerState;
+use crate::storagelock::StorageLock;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream, ReadHalf, WriteHalf};
+use tokio::sync::Mutex as AsyncMutex;
+use tokio::task::JoinHandle;
+use tracing::{info, warn, Instrument};
+
+/// First line of a recording
+const HEADER: &str = "REDIS-RS-RECORD 1";
+
+/// Bytes in flight between the replay and each of its clients
+const PIPE_SIZE: usize = 64 * 1024;
+
+/// How long a replayed frame waits for its reply before the next one is sent
+const REPLY_TIMEOUT: Duration = Duration::from_secs(1);
+
+struct Output {
+  path: PathBuf,
+  file: File,
+  started: Instant,
+}
+
+pub struct Recorder {
+  output: Mutex<Option<Output>>,
+}
+
+impl Recorder {
+  pub fn new() -> Self {
+    Self {
+      output: Mutex::new(None),
+    }
+  }
+
+  /// Starts or stops recording after `record-file`
+  pub fn apply_config(&self, config: &Config) -> Result<(), String> {
+    let path = config.get("record-file").unwrap_or_default();
+    let mut output = self.output.lock().unwrap();
+    if path.is_empty() {
+      *output = None;
+    } else if output.as_ref().map(|output| &output.path) != Some(&PathBuf::from(&path)) {
+      *output = Some(create(PathBuf::from(&path))?);
+    }
+    Ok(())
+  }
+
+  pub fn enabled(&self) -> bool {
+    self.output.lock().unwrap().is_some()
+  }
+
+  /// Records a frame read from client `client_id`
+  pub fn record(&self, client_id: u64, frame: &[u8]) {
+    let mut guard = self.output.lock().unwrap();
+    let Some(output) = guard.as_mut() else {
+      return;
+    };
+    let micros = output.started.elapsed().as_micros() as u64;
+    let mut entry = format!("{} {} {}\r\n", micros, client_id, frame.len()).into_bytes();
+    entry.extend_from_slice(frame);
+    entry.extend_from_slice(b"\r\n");
+    if let Err(e) = output.file.write_all(&entry) {
+      warn!(
+        "Failed to write to the recording {}, stopping it: {}",
+        output.path.display(),
+        e
+      );
+      *guard = None;
+    }
+  }
+}
+
+impl Default for Recorder {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+fn create(path: PathBuf) -> Result<Output, String> {
+  let mut file = OpenOptions::new()
+    .create(true)
+    .write(true)
+    .truncate(true)
+    .open(&path)
+    .map_err(|e| format!("Can't create the recording {}: {}", path.display(), e))?;
+  file
+    .write_all(format!("{}\r\n", HEADER).as_bytes())
+    .map_err(|e| format!("Can't write to the recording {}: {}", path.display(), e))?;
+  Ok(Output {
+    path,
+    file,
+    started: Instant::now(),
+  })
+}
+
+/// A recorded frame
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+  /// Microseconds since the recording started
+  pub micros: u64,
+  pub client_id: u64,
+  pub frame: Vec<u8>,
+}
+
+/// The entries of a recording
+pub fn parse(recording: &[u8]) -> Result<Vec<Entry>, String> {
+  let line_end = |from: usize| {
+    recording[from..]
+      .windows(2)
+      .position(|window| window == b"\r\n")
+      .map(|end| from + end)
+  };
+  let header_end = line_end(0).ok_or("Not a recording")?;
+  if &recording[..header_end] != HEADER.as_bytes() {
+    return Err("Not a recording, or one of another version".to_string());
+  }
+  let mut entries = Vec::new();
+  let mut position = header_end + 2;
+  while position < recording.len() {
+    let invalid = || format!("Invalid recording entry at byte {}", position);
+    let end = line_end(position).ok_or_else(invalid)?;
+    let line = std::str::from_utf8(&recording[position..end]).map_err(|_| invalid())?;
+    let fields = line
+      .split(' ')
+      .map(|field| field.parse::<u64>())
+      .collect::<Result<Vec<u64>, _>>()
+      .map_err(|_| invalid())?;
+    let [micros, client_id, length] = fields[..] else {
+      return Err(invalid());
+    };
+    let start = end + 2;
+    let frame_end = start + length as usize;
+    if recording.get(frame_end..frame_end + 2) != Some(&b"\r\n"[..]) {
+      return Err(invalid());
+    }
+    entries.push(Entry {
+      micros,
+      client_id,
+      frame: recording[start..frame_end].to_vec(),
+    });
+    position = frame_end + 2;
+  }
+  Ok(entries)
+}
+
+/// What a replay ran
+#[derive(Debug, Default, PartialEq)]
+pub struct ReplaySummary {
+  pub clients: u64,
+  pub frames: u64,
+  pub errors: u64,
+}
+
+/// The in-process connection of a recorded client
+struct Session {
+  commands: WriteHalf<DuplexStream>,
+  replies: ReadHalf<DuplexStream>,
+  pending: Vec<u8>,
+  task: JoinHandle<()>,
+}
+
+impl Session {
+  /// Reads the replies to the last frame, waiting `REPLY_TIMEOUT` at most for
+  /// the first one, and counts the errors among them
+  async fn read_replies(&mut self, summary: &mut ReplaySummary) {
+    let mut chunk = [0; PIPE_SIZE];
+    let mut answered = false;
+    loop {
+      let mut position = 0;
+      while let Some(length) = reply_length(&self.pending[position..]) {
+        if self.pending[position] == b'-' {
+          if summary.errors == 0 {
+            let line = String::from_utf8_lossy(&self.pending[position + 1..position + length - 2]);
+            warn!("First error while replaying: {}", line);
+          }
+          summary.errors += 1;
+        }
+        answered = true;
+        position += length;
+      }
+      self.pending.drain(..position);
+      if answered {
+        return;
+      }
+      match tokio::time::timeout(REPLY_TIMEOUT, self.replies.read(&mut chunk)).await {
+        Ok(Ok(n)) if n > 0 => self.pending.extend_from_slice(&chunk[..n]),
+        _ => return,
+      }
+    }
+  }
+}
+
+/// Runs the recording at `path`, keeping its timing with `timing`
+#[allow(clippy::too_many_arguments)]
+pub async fn replay(
+  path: &str,
+  timing: bool,
+  storage: Arc<StorageLock>,
+  config: Arc<AsyncMutex<Config>>,
+  clients: Arc<ClientRegistry>,
+  acl: Arc<Acl>,
+  rate_limiter: Arc<RateLimiter>,
+  cluster: Arc<Cluster>,
+  modules: Arc<ModuleCommands>,
+  read_through: Arc<ReadThrough>,
+  state: Arc<ServerState>,
+) -> Result<ReplaySummary, String> {
+  let recording = tokio::fs::read(path)
+    .await
+    .map_err(|e| format!("Can't open the recording {}: {}", path, e))?;
+  let entries = parse(&recording).map_err(|e| format!("{}: {}", path, e))?;
+  info!("Replaying {} frames from {}", entries.len(), path);
+  let started = tokio::time::Instant::now();
+
+  let mut summary = ReplaySummary::default();
+  let mut sessions: HashMap<u64, Session> = HashMap::new();
+  for entry in entries {
+    if timing {
+      let due = started + Duration::from_micros(entry.micros);
+      tokio::time::sleep_until(due).await;
+    }
+    let session = sessions.entry(entry.client_id).or_insert_with(|| {
+      let (client, server) = tokio::io::duplex(PIPE_SIZE);
+      let (replies, commands) = tokio::io::split(client);
+      let addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0));
+      let task = tokio::spawn(
+        serve_client(
+          Connection::new(server),
+          addr,
+          addr,
+          -1,
+          storage.clone(),
+          config.clone(),
+          clients.clone(),
+          acl.clone(),
+          rate_limiter.clone(),
+          cluster.clone(),
+          modules.clone(),
+          read_through.clone(),
+          state.clone(),
+        )
+        .instrument(tracing::info_span!("replay", recorded_id = entry.client_id)),
+      );
+      summary.clients += 1;
+      Session {
+        commands,
+        replies,
+        pending: Vec::new(),
+        task,
+      }
+    });
+    // A client that hung up, with QUIT for instance, has nothing left to run
+    if session.commands.write_all(&entry.frame).await.is_err() {
+      continue;
+    }
+    summary.frames += 1;
+    session.read_replies(&mut summary).await;
+  }
+
+  // Hanging up ends the sessions once their last command ran
+  for (_, mut session) in sessions {
+    let _ = session.commands.shutdown().await;
+    let _ = tokio::io::copy(&mut session.replies, &mut tokio::io::sink()).await;
+    let _ = session.task.await;
+  }
+
+  info!(
+    "Replayed {} frames of {} clients from {} in {:.3} seconds, {} errors",
+    summary.frames,
+    summary.clients,
+    path,
+    started.elapsed().as_secs_f64(),
+    summary.errors
+  );
+  Ok(summary)
+}
@@ -0,0 +1,159 @@
+/**
+ * The SCAN cursor. Like Redis, the keyspace is walked as a hash table of a
+ * power of two buckets, a key living in the bucket of the low bits of its
+ * hash, and the cursor is the next bucket to visit. Buckets are visited in
+ * reverse binary order: the cursor is incremented from its highest bit down,
+ * so that the buckets a bucket splits into when the table doubles, or merges
+ * with when it halves, are visited together.
+ *
+ * The table grows and shrinks with the keyspace between the calls of a scan,
+ * and a cursor keeps its meaning across sizes: every key present for the whole
+ * scan is returned, at least once. A key may be returned twice when the table
+ * shrank meanwhile, keys added or deleted during the scan may or may not be.
+ *
+ * The table is virtual: the keys are indexed by their hash with its bits
+ * reversed, which orders them the way the cursor visits the buckets, and a
+ * bucket is a range of that index.
+ *
+ * The index holds a second copy of every key. DashMap doesn't expose its
+ * buckets, which a cursor could otherwise be derived from, so that memory is
+ * the price of a stable cursor; it is counted by `memory`, and reported by
+ * INFO and MEMORY STATS.
+ */
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeSet;
+use std::hash::{BuildHasher, Hasher};
+use std::mem::size_of;
+use std::ops::Bound;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Smallest table, as in Redis
+const MIN_TABLE_BITS: u32 = 2;
+
+/// Empty buckets a call visits per key asked for, bounding a call over a
+/// sparse range like Redis does
+const EMPTY_VISITS_PER_KEY: usize = 10;
+
+/// Share of a B-tree node an entry costs on top of itself, its parent and
+/// sibling pointers and the slack of nodes not full
+const NODE_OVERHEAD: usize = 16;
+
+/// Keys ordered by bit-reversed hash, see the module documentation
+#[derive(Default)]
+pub struct ScanIndex {
+  hasher: RandomState,
+  keys: Mutex<BTreeSet<(u64, String)>>,
+  /// Approximate bytes used by `keys`
+  memory: AtomicUsize,
+}
+
+/// Approximate bytes an indexed key uses
+fn entry_size(key: &str) -> usize {
+  size_of::<(u64, String)>() + key.len() + NODE_OVERHEAD
+}
+
+impl ScanIndex {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  fn position(&self, key: &str) -> u64 {
+    self.hasher.hash_one(key).reverse_bits()
+  }
+
+  pub fn insert(&self, key: &str) {
+    let position = self.position(key);
+    if self
+      .keys
+      .lock()
+      .unwrap()
+      .insert((position, key.to_string()))
+    {
+      self.memory.fetch_add(entry_size(key), Ordering::Relaxed);
+    }
+  }
+
+  pub fn remove(&self, key: &str) {
+    let position = self.position(key);
+    if self
+      .keys
+      .lock()
+      .unwrap()
+      .remove(&(position, key.to_string()))
+    {
+      self.memory.fetch_sub(entry_size(key), Ordering::Relaxed);
+    }
+  }
+
+  pub fn clear(&self) {
+    let mut keys = self.keys.lock().unwrap();
+    keys.clear();
+    self.memory.store(0, Ordering::Relaxed);
+  }
+
+  /// Approximate bytes used by the index
+  pub fn memory(&self) -> usize {
+    self.memory.load(Ordering::Relaxed)
+  }
+
+  /// Up to `count` keys taken at random: the ones following a random
+  /// position, in the random order of their hashes
+  pub fn sample(&self, count: usize) -> Vec<String> {
+    let start = RandomState::new().build_hasher().finish();
+    let keys = self.keys.lock().unwrap();
+    keys
+      .range((Bound::Included((start, String::new())), Bound::Unbounded))
+      .chain(keys.range(..(start, String::new())))
+      .take(count)
+      .map(|(_, key)| key.clone())
+      .collect()
+  }
+
+  /// The keys of the buckets from `cursor` on, until at least `count` keys
+  /// were found or the table was walked, and the cursor to continue from, 0
+  /// once done
+  pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<String>) {
+    let keys = self.keys.lock().unwrap();
+    let bits = table_bits(keys.len());
+    let mask = (1u64 << bits) - 1;
+    let mut cursor = cursor;
+    let mut found = Vec::new();
+    let mut visited = 0;
+    loop {
+      // Keys whose hash ends with the bucket's bits, reversed: the positions
+      // starting with them
+      let first = (cursor & mask).reverse_bits();
+      let last = first | (u64::MAX >> bits);
+      found.extend(
+        keys
+          .range((Bound::Included((first, String::new())), Bound::Unbounded))
+          .take_while(|(position, _)| *position <= last)
+          .map(|(_, key)| key.clone()),
+      );
+      cursor = next_cursor(cursor, mask);
+      visited += 1;
+      if cursor == 0 || found.len() >= count || visited >= count * EMPTY_VISITS_PER_KEY {
+        return (cursor, found);
+      }
+    }
+  }
+}
+
+/// Bits of the table holding `len` keys: the smallest power of two fitting
+/// them
+fn table_bits(len: usize) -> u32 {
+  len
+    .next_power_of_two()
+    .trailing_zeros()
+    .clamp(MIN_TABLE_BITS, u64::BITS - 1)
+}
+
+/// The reverse binary increment of Redis's `dictScan`: sets the bits above the
+/// mask so that adding one to the reversed cursor carries into the masked ones
+fn next_cursor(cursor: u64, mask: u64) -> u64 {
+  (cursor | !mask)
+    .reverse_bits()
+    .wrapping_add(1)
+    .reverse_bits()
+}
@@ -0,0 +1,1114 @@
+/**
+ * The server itself, embeddable in other Rust applications: tests and edge
+ * caches can run an in-process Redis-compatible server and reach its keyspace
+ * directly.
+ *
+ * ```ignore
+ * let mut server = RedisServer::builder()
+ *   .bind("127.0.0.1:7000")
+ *   .config("maxmemory", "100mb")
+ *   .build();
+ * server.start().await?;
+ * server.storage().lock().await.set(..);
+ * server.shutdown();
+ * ```
+ *
+ * Process level concerns stay with the binary: logging, daemonizing, the
+ * pidfile, and saving then exiting on SIGTERM/SIGINT. The SHUTDOWN command
+ * only stops the server it's sent to, `stopped` resolves then; whether the
+ * process exits is up to the application.
+ */
+use crate::acl::{Acl, DEFAULT_USER};
+use crate::address::parse_host_port;
+use crate::arguments::{process_configuration_arguments, CLIArguments};
+use crate::clients::{
+  register_clients_cron, set_tcp_keepalive, set_tcp_nodelay, ClientRegistry, DEFAULT_TCP_KEEPALIVE,
+  QUERY_BUFFER_LIMIT, QUERY_BUFFER_SIZE,
+};
+use crate::cluster::{register_cluster_gossip, Cluster};
+use crate::commands::{self, Outcome};
+use crate::config::{find_parameter, parse_log_level, Config};
+use crate::connection::Connection;
+use crate::cron::Scheduler;
+use crate::database::populate_hot_storage;
+use crate::expiry::register_active_expire;
+use crate::iothreads::{self, IoThreads};
+use crate::keyevents::KeyEventHandler;
+use crate::listener::{self, bind_addresses, is_loopback, protected_mode, spawn_acceptors};
+use crate::module::{CommandHandler, ModuleCommands};
+use crate::parser::{
+  command_argv, frame_length, parse_command, serialize_response, Command, RedisValue,
+};
+use crate::ratelimit::{Admission, RateLimiter, RATE_LIMITED_ERROR};
+use crate::readthrough::{MissHandler, ReadThrough};
+use crate::state::ServerState;
+use crate::stats::{register_stats_sampler, Stats};
+use crate::statsd::register_statsd_exporter;
+use crate::storage::Storage;
+use crate::storagelock::StorageLock;
+use crate::ttlhistogram::register_ttl_sampler;
+use crate::{
+  address, admin, allocator, clients, configfile, csv, diagnostics, encryption, import, info, json,
+  logging, proxy, record, telemetry, tls, tracking,
+};
+use bytes::BytesMut;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio::sync::Mutex as AsyncMutex;
+use tokio::task::{AbortHandle, JoinHandle};
+use tokio::time::Instant;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, info, warn, Instrument};
+
+/// Port served when none is configured
+pub const DEFAULT_PORT: u16 = 6379;
+
+/// Settings of a server to build, applied in order: later ones win
+#[derive(Default)]
+pub struct RedisServerBuilder {
+  arguments: CLIArguments,
+  /// Parameters set with `config`, checked when the server starts
+  overrides: CLIArguments,
+  bind: Option<String>,
+  storage: Option<Arc<StorageLock>>,
+  commands: Vec<Arc<dyn CommandHandler>>,
+  miss_handler: Option<Arc<dyn MissHandler>>,
+  key_event_handlers: Vec<Arc<dyn KeyEventHandler>>,
+}
+
+impl RedisServerBuilder {
+  /// Listens on `address`, as `host:port`, `[ipv6]:port` or `host port`
+  pub fn bind(mut self, address: &str) -> Self {
+    self.bind = Some(address.to_string());
+    self
+  }
+
+  /// Sets a configuration parameter, as in the configuration file
+  pub fn config(mut self, name: &str, value: &str) -> Self {
+    self
+      .overrides
+      .push((name.to_lowercase(), vec![value.to_string()]));
+    self
+  }
+
+  /// Takes already checked directives, as parsed from the command line and
+  /// the configuration file
+  pub fn arguments(mut self, arguments: CLIArguments) -> Self {
+    self.arguments.extend(arguments);
+    self
+  }
+
+  /// Serves an existing keyspace instead of an empty one
+  pub fn storage(mut self, storage: Arc<StorageLock>) -> Self {
+    self.storage = Some(storage);
+    self
+  }
+
+  /// Adds a custom command, checked when the server starts
+  pub fn command(mut self, handler: impl CommandHandler + 'static) -> Self {
+    self.commands.push(Arc::new(handler));
+    self
+  }
+
+  /// Loads the keys GET misses, see `readthrough`
+  pub fn miss_handler(mut self, handler: impl MissHandler + 'static) -> Self {
+    self.miss_handler = Some(Arc::new(handler));
+    self
+  }
+
+  /// Calls `handler` with the keys that expire, see `keyevents`
+  pub fn key_event_handler(mut self, handler: impl KeyEventHandler + 'static) -> Self {
+    self.key_event_handlers.push(Arc::new(handler));
+    self
+  }
+
+  pub fn build(self) -> RedisServer {
+    let mut arguments = self.arguments;
+    arguments.extend(self.overrides.iter().cloned());
+    RedisServer {
+      arguments,
+      overrides: self.overrides,
+      bind: self.bind,
+      storage: self
+        .storage
+        .unwrap_or_else(|| Arc::new(StorageLock::new(Storage::new()))),
+      config: Arc::new(AsyncMutex::new(Config::new())),
+      commands: self.commands,
+      miss_handler: self.miss_handler,
+      key_event_handlers: self.key_event_handlers,
+      state: Arc::new(ServerState::new()),
+      running: None,
+    }
+  }
+}
+
+/// What a started server runs with, dropped on shutdown
+struct Running {
+  clients: Arc<ClientRegistry>,
+  acl: Arc<Acl>,
+  rate_limiter: Arc<RateLimiter>,
+  local_addrs: Vec<SocketAddr>,
+  tasks: Vec<JoinHandle<()>>,
+}
+
+pub struct RedisServer {
+  arguments: CLIArguments,
+  overrides: CLIArguments,
+  bind: Option<String>,
+  storage: Arc<StorageLock>,
+  config: Arc<AsyncMutex<Config>>,
+  commands: Vec<Arc<dyn CommandHandler>>,
+  miss_handler: Option<Arc<dyn MissHandler>>,
+  key_event_handlers: Vec<Arc<dyn KeyEventHandler>>,
+  state: Arc<ServerState>,
+  running: Option<Running>,
+}
+
+impl RedisServer {
+  pub fn builder() -> RedisServerBuilder {
+    RedisServerBuilder::default()
+  }
+
+  /// The keyspace, shared with the connections
+  pub fn storage(&self) -> Arc<StorageLock> {
+    self.storage.clone()
+  }
+
+  /// The configuration, as CONFIG GET and CONFIG SET see it
+  pub fn config(&self) -> Arc<AsyncMutex<Config>> {
+    self.config.clone()
+  }
+
+  /// The counters and logs of the server: INFO stats, the slow log...
+  pub fn state(&self) -> Arc<ServerState> {
+    self.state.clone()
+  }
+
+  /// Addresses the server listens on, once started
+  pub fn local_addrs(&self) -> Vec<SocketAddr> {
+    self
+      .running
+      .as_ref()
+      .map(|running| running.local_addrs.clone())
+      .unwrap_or_default()
+  }
+
+  pub fn is_running(&self) -> bool {
+    self.running.is_some()
+  }
+
+  /// Applies the configuration, loads the dataset and starts listening.
+  /// Connections are served in the background until `shutdown`.
+  pub async fn start(&mut self) -> Result<(), String> {
+    if self.running.is_some() {
+      return Err("The server is already running".to_string());
+    }
+    info!("Starting Redis Server!");
+    info::record_startup();
+
+    for (name, values) in &self.overrides {
+      let parameter =
+        find_parameter(name).ok_or_else(|| format!("Unknown parameter '{}'", name))?;
+      parameter
+        .kind
+        .validate(&values.join(" "))
+        .map_err(|e| format!("Invalid {}: {}", name, e))?;
+    }
+    let mut modules = ModuleCommands::new();
+    for handler in &self.commands {
+      modules.register(handler.clone())?;
+    }
+    let modules = Arc::new(modules);
+    let read_through = Arc::new(ReadThrough::new(self.miss_handler.clone()));
+    let mut arguments = self.arguments.clone();
+    if let Some(address) = &self.bind {
+      let (host, port) = parse_host_port(address)?;
+      arguments.push(("bind".to_string(), vec![host]));
+      arguments.push(("port".to_string(), vec![port.to_string()]));
+    }
+    let port = arguments
+      .iter()
+      .rev()
+      .find(|(argument, _)| argument == "port")
+      .map(|(_, values)| values.join(" "))
+      .unwrap_or_else(|| DEFAULT_PORT.to_string());
+
+    let config = self.config.clone();
+    let storage = self.storage.clone();
+    let state = self.state.clone();
+    state.clear_shutdown();
+    process_configuration_arguments(arguments, config.clone()).await;
+    config.lock().await.set("port".to_string(), port.clone());
+    if let Some(loglevel) = config.lock().await.get("loglevel") {
+      match parse_log_level(&loglevel) {
+        Some(level) => logging::set_level(level),
+        None => return Err(format!("Invalid loglevel: {}", loglevel)),
+      }
+    }
+
+    let Ok(port) = port.parse::<u16>() else {
+      return Err(format!("Invalid port: {}", port));
+    };
+    let addresses = bind_addresses(&*config.lock().await);
+    let tls_port = tls::tls_port(&*config.lock().await)?;
+    let tls = match tls_port {
+      Some(tls_port) => match tls::load_acceptor(&*config.lock().await) {
+        Ok(acceptor) => Some((tls_port, acceptor)),
+        Err(e) => return Err(format!("Failed to configure TLS: {}", e)),
+      },
+      None => None,
+    };
+    let backlog = listener::tcp_backlog(&*config.lock().await);
+    let listeners = listener::listen(&addresses, port, tls, backlog).await?;
+    let local_addrs = listeners
+      .iter()
+      .filter_map(|(listener, _)| listener.local_addr().ok())
+      .collect();
+    {
+      let config = config.lock().await;
+      let mut storage = storage.lock().await;
+      storage.set_stats(state.stats.clone());
+      storage.lazyfree().options.apply_config(&config);
+      storage.hotkeys().apply_config(&config);
+      storage.apply_negative_cache_config(&config);
+      storage.eviction().apply_config(&config);
+    }
+
+    // Only populate hot storage if the configuration is set
+    let encryption_key = encryption::key(&*config.lock().await)
+      .map_err(|e| format!("Failed to get the encryption key: {}", e))?;
+    populate_hot_storage(&storage, &config, encryption_key.as_ref()).await?;
+    let json_file = config
+      .lock()
+      .await
+      .get("import-json")
+      .filter(|path| !path.is_empty());
+    if let Some(path) = json_file {
+      let loaded = json::import_file(&*storage.lock().await, Path::new(&path))?;
+      info!("Loaded {} keys from {}", loaded, path);
+    }
+    let csv_file = config
+      .lock()
+      .await
+      .get("import-csv")
+      .filter(|path| !path.is_empty());
+    if let Some(path) = csv_file {
+      let columns = config
+        .lock()
+        .await
+        .get("import-csv-columns")
+        .unwrap_or_default();
+      csv::import_csv(&path, &columns, &storage).await?;
+    }
+    // Loading the dataset isn't accessing it
+    storage.lock().await.hotkeys().reset();
+
+    let mut scheduler = Scheduler::new();
+    register_active_expire(&mut scheduler, storage.clone());
+    allocator::register_memory_sampler(&mut scheduler);
+    register_stats_sampler(&mut scheduler, state.stats.clone());
+    register_ttl_sampler(&mut scheduler, storage.clone());
+
+    let mut cluster = Cluster::new(&*config.lock().await);
+    cluster
+      .load()
+      .map_err(|e| format!("Failed to load the cluster configuration: {}", e))?;
+    let cluster = Arc::new(cluster);
+    register_cluster_gossip(&mut scheduler, cluster.clone());
+    let clients = Arc::new(ClientRegistry::new(state.stats.clone()));
+    if let Some(limits) = config.lock().await.get("client-output-buffer-limit") {
+      let limits = clients
+        .output_limits()
+        .parse(&limits)
+        .map_err(|e| format!("Invalid client-output-buffer-limit: {}", e))?;
+      clients.set_output_limits(limits);
+    }
+    clients.apply_max_memory(&*config.lock().await)?;
+    let registry = Arc::downgrade(&clients);
+    storage
+      .lock()
+      .await
+      .set_invalidation_hook(Box::new(move |key| {
+        if let Some(clients) = registry.upgrade() {
+          clients.key_modified(key);
+        }
+      }));
+    register_clients_cron(&mut scheduler, clients.clone(), config.clone());
+    register_statsd_exporter(
+      &mut scheduler,
+      storage.clone(),
+      config.clone(),
+      clients.clone(),
+      state.stats.clone(),
+    );
+    let mut tasks = vec![scheduler.spawn()];
+    for handler in &self.key_event_handlers {
+      let mut events = storage.lock().await.subscribe_key_events();
+      let handler = handler.clone();
+      tasks.push(tokio::spawn(async move {
+        while let Some(event) = events.recv().await {
+          handler.on_event(&event).await;
+        }
+      }));
+    }
+
+    let rate_limiter = Arc::new(RateLimiter::from_config(&*config.lock().await)?);
+
+    let acl = Arc::new(Acl::new(config.lock().await.get("aclfile")));
+    if let Some(max_len) = config
+      .lock()
+      .await
+      .get("acllog-max-len")
+      .and_then(|max_len| max_len.parse::<usize>().ok())
+    {
+      acl.log.set_max_len(max_len);
+    }
+    acl
+      .load()
+      .map_err(|e| format!("Failed to load the ACL file: {}", e))?;
+
+    state.slowlog.apply_config(&*config.lock().await);
+    state.audit_log.apply_config(&*config.lock().await)?;
+    state.recorder.apply_config(&*config.lock().await)?;
+    let protocol_file = config
+      .lock()
+      .await
+      .get("import-protocol")
+      .filter(|path| !path.is_empty());
+    if let Some(path) = protocol_file {
+      import::import_protocol(
+        &path,
+        storage.clone(),
+        config.clone(),
+        clients.clone(),
+        acl.clone(),
+        rate_limiter.clone(),
+        cluster.clone(),
+        modules.clone(),
+        read_through.clone(),
+        state.clone(),
+      )
+      .await?;
+    }
+    let (replay_file, replay_timing) = {
+      let config = config.lock().await;
+      (
+        config.get("replay-file").filter(|path| !path.is_empty()),
+        config.get("replay-timing").as_deref() == Some("yes"),
+      )
+    };
+    if let Some(path) = replay_file {
+      record::replay(
+        &path,
+        replay_timing,
+        storage.clone(),
+        config.clone(),
+        clients.clone(),
+        acl.clone(),
+        rate_limiter.clone(),
+        cluster.clone(),
+        modules.clone(),
+        read_through.clone(),
+        state.clone(),
+      )
+      .await?;
+    }
+    let admin = admin::spawn(admin::AdminState {
+      storage: storage.clone(),
+      config: config.clone(),
+      clients: clients.clone(),
+      acl: acl.clone(),
+      rate_limiter: rate_limiter.clone(),
+      server: state.clone(),
+    })
+    .await?;
+    tasks.extend(admin);
+
+    let io_threads = iothreads::io_threads(&*config.lock().await);
+    let io_threads = match io_threads {
+      1 => None,
+      count => {
+        info!("Serving connections on {} I/O threads", count);
+        Some(Arc::new(IoThreads::start(count)?))
+      }
+    };
+
+    // Only now that the dataset is ready are clients accepted
+    tasks.push(spawn_accept_loop(
+      spawn_acceptors(listeners),
+      io_threads,
+      storage,
+      config,
+      clients.clone(),
+      acl.clone(),
+      rate_limiter.clone(),
+      cluster,
+      modules,
+      read_through,
+      state.clone(),
+    ));
+    tasks.push(spawn_stop_on_shutdown(&tasks, clients.clone(), state));
+    self.running = Some(Running {
+      clients,
+      acl,
+      rate_limiter,
+      local_addrs,
+      tasks,
+    });
+    Ok(())
+  }
+
+  /// Reloads the configuration file and ACL file on SIGHUP, like Redis.
+  /// `cli_arguments` are the command line directives, which win over the file.
+  pub fn reload_on_sighup(&self, cli_arguments: CLIArguments) {
+    let Some(running) = &self.running else {
+      return;
+    };
+    configfile::spawn_reload_on_sighup(
+      cli_arguments,
+      self.storage.clone(),
+      self.config.clone(),
+      running.clients.clone(),
+      running.acl.clone(),
+      running.rate_limiter.clone(),
+      self.state.clone(),
+    );
+  }
+
+  /// Logs a diagnostics report on SIGUSR1, see `diagnostics`
+  pub fn report_on_sigusr1(&self) {
+    let Some(running) = &self.running else {
+      return;
+    };
+    diagnostics::spawn_report_on_sigusr1(
+      self.storage.clone(),
+      self.config.clone(),
+      running.clients.clone(),
+      self.state.clone(),
+      running.tasks.len(),
+    );
+  }
+
+  /// The lines of the report SIGUSR1 logs, empty until the server is started
+  pub async fn diagnostics(&self) -> Vec<String> {
+    let Some(running) = &self.running else {
+      return vec![];
+    };
+    diagnostics::report(
+      &self.storage,
+      &self.config,
+      &running.clients,
+      &self.state.slowlog,
+      running.tasks.len(),
+    )
+    .await
+  }
+
+  /// Resolves once a client stopped the server with SHUTDOWN, which saved
+  /// the dataset first. The server no longer runs by then.
+  pub async fn stopped(&mut self) {
+    if self.running.is_none() {
+      return;
+    }
+    self.state.shutdown_requested().await;
+    self.shutdown();
+  }
+
+  /// Stops listening and closes every connection. The dataset isn't saved:
+  /// `shutdown::prepare_shutdown` does that, as SHUTDOWN does.
+  pub fn shutdown(&mut self) {
+    let Some(running) = self.running.take() else {
+      return;
+    };
+    for task in &running.tasks {
+      task.abort();
+    }
+    for client in running.clients.all() {
+      running.clients.kill_client(client.id);
+    }
+    info!("Redis Server stopped");
+  }
+}
+
+impl Drop for RedisServer {
+  fn drop(&mut self) {
+    self.shutdown();
+  }
+}
+
+/// Serves the connections accepted by the listeners
+#[allow(clippy::too_many_arguments)]
+fn spawn_accept_loop(
+  mut incoming: tokio::sync::mpsc::UnboundedReceiver<listener::Accepted>,
+  io_threads: Option<Arc<IoThreads>>,
+  storage: Arc<StorageLock>,
+  config: Arc<AsyncMutex<Config>>,
+  clients: Arc<ClientRegistry>,
+  acl: Arc<Acl>,
+  rate_limiter: Arc<RateLimiter>,
+  cluster: Arc<Cluster>,
+  modules: Arc<ModuleCommands>,
+  read_through: Arc<ReadThrough>,
+  state: Arc<ServerState>,
+) -> JoinHandle<()> {
+  tokio::spawn(async move {
+    while let Some(stream) = incoming.recv().await {
+      match stream {
+        Ok((stream, addr, tls)) if denied_by_protected_mode(&addr, &tls, &config, &acl).await => {
+          deny_connection(stream, tls, &state.stats)
+        }
+        Ok((stream, _, tls)) if clients.len() >= max_clients(&config).await => {
+          reject_connection(stream, tls, &state.stats)
+        }
+        Ok((stream, addr, tls)) => {
+          Stats::incr(&state.stats.total_connections_received);
+          set_tcp_keepalive(&stream, tcp_keepalive(&config).await);
+          set_tcp_nodelay(&stream, tcp_nodelay(&config).await);
+          let serve = {
+            let storage = storage.clone();
+            let config = config.clone();
+            let clients = clients.clone();
+            let acl = acl.clone();
+            let rate_limiter = rate_limiter.clone();
+            let cluster = cluster.clone();
+            let modules = modules.clone();
+            let read_through = read_through.clone();
+            let state = state.clone();
+            move |stream| {
+              handle_connection(
+                stream,
+                tls,
+                addr,
+                storage,
+                config,
+                clients,
+                acl,
+                rate_limiter,
+                cluster,
+                modules,
+                read_through,
+                state,
+              )
+            }
+          };
+          match &io_threads {
+            Some(io_threads) => io_threads.serve(stream, serve),
+            None => serve(stream),
+          }
+        }
+        Err(e) => {
+          warn!("Failed to accept a connection: {}", e);
+        }
+      };
+    }
+  })
+}
+
+/** `maxclients` from the configuration */
+async fn max_clients(config: &Arc<AsyncMutex<Config>>) -> usize {
+  clients::max_clients(&*config.lock().await)
+}
+
+/** `tcp-nodelay` from the configuration, on unless set to `no` */
+async fn tcp_nodelay(config: &Arc<AsyncMutex<Config>>) -> bool {
+  !config
+    .lock()
+    .await
+    .get("tcp-nodelay")
+    .is_some_and(|value| value.eq_ignore_ascii_case("no"))
+}
+
+/** `tcp-keepalive` from the configuration */
+async fn tcp_keepalive(config: &Arc<AsyncMutex<Config>>) -> u64 {
+  config
+    .lock()
+    .await
+    .get("tcp-keepalive")
+    .and_then(|seconds| seconds.parse::<u64>().ok())
+    .unwrap_or(DEFAULT_TCP_KEEPALIVE)
+}
+
+/** Whether protected-mode refuses a connection from `addr`. TLS listeners
+ * requiring client certificates authenticate everyone, so they are exempt. */
+async fn denied_by_protected_mode(
+  addr: &SocketAddr,
+  tls: &Option<TlsAcceptor>,
+  config: &Arc<AsyncMutex<Config>>,
+  acl: &Acl,
+) -> bool {
+  let config = config.lock().await;
+  let mutual_tls =
+    tls.is_some() && tls::ClientAuth::from_config(&config) == Ok(tls::ClientAuth::Required);
+  protected_mode(&config) && !mutual_tls && acl.default_user_nopass() && !is_loopback(addr)
+}
+
+/// Stops the `tasks` of the server and closes every connection once SHUTDOWN
+/// is called, whether or not the application waits on `stopped`
+fn spawn_stop_on_shutdown(
+  tasks: &[JoinHandle<()>],
+  clients: Arc<ClientRegistry>,
+  state: Arc<ServerState>,
+) -> JoinHandle<()> {
+  let tasks: Vec<AbortHandle> = tasks.iter().map(JoinHandle::abort_handle).collect();
+  tokio::spawn(async move {
+    state.shutdown_requested().await;
+    for task in &tasks {
+      task.abort();
+    }
+    for client in clients.all() {
+      clients.kill_client(client.id);
+    }
+  })
+}
+
+/** Turns away a non-loopback connection while in protected-mode */
+fn deny_connection(stream: TcpStream, tls: Option<TlsAcceptor>, stats: &Arc<Stats>) {
+  warn!("Denying connection: protected mode is enabled");
+  Stats::incr(&stats.rejected_connections);
+  let stats = stats.clone();
+  tokio::spawn(async move {
+    let Ok(mut stream) = Connection::accept(stream, tls).await else {
+      return;
+    };
+    stream.set_stats(stats);
+    let response = serialize_response(RedisValue::Error(
+      listener::PROTECTED_MODE_ERROR.to_string(),
+    ));
+    if let Err(e) = stream.write_response(&response).await {
+      debug!("Failed to write to stream: {}", e);
+    }
+    let _ = stream.flush().await;
+  });
+}
+
+/** Turns away a connection over the `maxclients` limit with an error, like Redis */
+fn reject_connection(stream: TcpStream, tls: Option<TlsAcceptor>, stats: &Arc<Stats>) {
+  warn!("Rejecting connection: max number of clients reached");
+  Stats::incr(&stats.rejected_connections);
+  let stats = stats.clone();
+  tokio::spawn(async move {
+    let Ok(mut stream) = Connection::accept(stream, tls).await else {
+      return;
+    };
+    stream.set_stats(stats);
+    let response = serialize_response(RedisValue::Error(
+      "ERR max number of clients reached".to_string(),
+    ));
+    if let Err(e) = stream.write_response(&response).await {
+      debug!("Failed to write to stream: {}", e);
+    }
+    let _ = stream.flush().await;
+  });
+}
+
+/** Handles TCP connections to Redis Server */
+#[allow(clippy::too_many_arguments)]
+fn handle_connection(
+  mut stream: TcpStream,
+  tls: Option<TlsAcceptor>,
+  addr: SocketAddr,
+  storage: Arc<StorageLock>,
+  config: Arc<AsyncMutex<Config>>,
+  clients: Arc<ClientRegistry>,
+  acl: Arc<Acl>,
+  rate_limiter: Arc<RateLimiter>,
+  cluster: Arc<Cluster>,
+  modules: Arc<ModuleCommands>,
+  read_through: Arc<ReadThrough>,
+  state: Arc<ServerState>,
+) {
+  let laddr = stream.local_addr().unwrap_or(addr);
+  #[cfg(unix)]
+  let fd = {
+    use std::os::unix::io::AsRawFd;
+    stream.as_raw_fd() as i64
+  };
+  #[cfg(not(unix))]
+  let fd = -1;
+
+  let span = tracing::info_span!(
+    "connection",
+    id = tracing::field::Empty,
+    addr = tracing::field::Empty
+  );
+  tokio::spawn(
+    async move {
+      // Behind a load balancer the PROXY header names the real client
+      let (addr, laddr) = if proxy::enabled(&*config.lock().await, tls.is_some()) {
+        match proxy::read_header(&mut stream).await {
+          Ok(Some(addresses)) => addresses,
+          Ok(None) => (addr, laddr),
+          Err(e) => {
+            debug!("Dropping connection from {}: {}", addr, e);
+            return;
+          }
+        }
+      } else {
+        (addr, laddr)
+      };
+      let (addr, laddr) = (address::canonical(addr), address::canonical(laddr));
+      tracing::Span::current().record("addr", tracing::field::display(addr));
+
+      let stream = match Connection::accept(stream, tls).await {
+        Ok(stream) => stream,
+        Err(e) => {
+          debug!("TLS handshake failed: {}", e);
+          return;
+        }
+      };
+      serve_client(
+        stream,
+        addr,
+        laddr,
+        fd,
+        storage,
+        config,
+        clients,
+        acl,
+        rate_limiter,
+        cluster,
+        modules,
+        read_through,
+        state,
+      )
+      .await;
+    }
+    .instrument(span),
+  );
+}
+
+/// Runs the commands of a client until it disconnects or is killed. Any
+/// stream will do: besides sockets, a protocol file being imported.
+#[allow(clippy::too_many_arguments)]
+pub async fn serve_client(
+  mut stream: Connection,
+  addr: SocketAddr,
+  laddr: SocketAddr,
+  fd: i64,
+  storage: Arc<StorageLock>,
+  config: Arc<AsyncMutex<Config>>,
+  clients: Arc<ClientRegistry>,
+  acl: Arc<Acl>,
+  rate_limiter: Arc<RateLimiter>,
+  cluster: Arc<Cluster>,
+  modules: Arc<ModuleCommands>,
+  read_through: Arc<ReadThrough>,
+  state: Arc<ServerState>,
+) {
+  let (client_id, kill, mut pushed) = clients.register(addr, laddr, fd);
+  stream.set_stats(state.stats.clone());
+  tracing::Span::current().record("id", client_id);
+  debug!("Accepted new connection");
+  // TLS clients may be logged in by their certificate, other connections are
+  // logged in as the default user when it needs no password
+  let certificate_user = match stream.peer_certificate() {
+    Some(certificate) => tls::certificate_user(certificate, &*config.lock().await, &acl),
+    None => None,
+  };
+  match certificate_user {
+    Some(user) => {
+      debug!("Authenticated as {} by certificate", user);
+      clients.set_user(client_id, &user);
+    }
+    None if acl.authenticate(DEFAULT_USER, None) => clients.set_user(client_id, DEFAULT_USER),
+    None => {}
+  }
+
+  // Bytes read but not run yet: the rest of a pipeline, or part of a command
+  let mut pending = BytesMut::with_capacity(QUERY_BUFFER_SIZE);
+  loop {
+    stream.set_command_span(None);
+    let n = match frame_length(&pending) {
+      Ok(Some(n)) => n,
+      Ok(None) if pending.len() > QUERY_BUFFER_LIMIT => {
+        warn!("Closing client {}: query buffer limit reached", client_id);
+        break;
+      }
+      Ok(None) => {
+        // Every complete command ran, send their replies before waiting
+        if let Err(e) = stream.flush().await {
+          debug!("Failed to write to stream: {}", e);
+          break;
+        }
+        let read = tokio::select! {
+          read = stream.read_buf(&mut pending) => read,
+          _ = kill.notified() => {
+            debug!("Client killed");
+            break;
+          }
+          Some(message) = pushed.recv() => {
+            if let Err(e) = stream.write_response(&message).await {
+              debug!("Failed to write to stream: {}", e);
+              break;
+            }
+            if let Err(e) = stream.flush().await {
+              debug!("Failed to write to stream: {}", e);
+              break;
+            }
+            clients.written(client_id, message.len());
+            continue;
+          }
+        };
+        match read {
+          Ok(0) => break,
+          Ok(n) => {
+            debug!("Received {} bytes", n);
+            clients.read_query(client_id, pending.len());
+            continue;
+          }
+          Err(e) => {
+            debug!("Failed to read from stream: {}", e);
+            break;
+          }
+        }
+      }
+      // Like Redis, reply to a malformed request and hang up
+      Err(e) => {
+        let response = serialize_response(RedisValue::Error(e));
+        if let Err(e) = stream.write_response(&response).await {
+          debug!("Failed to write to stream: {}", e);
+        }
+        break;
+      }
+    };
+    // Split off without copying, the allocation is reused once `buf` is dropped
+    let buf = pending.split_to(n).freeze();
+    state.recorder.record(client_id, &buf);
+    clients.read_query(client_id, pending.len());
+    let command_span =
+      telemetry::command_span(client_id, clients.traceparent(client_id).as_deref());
+    let command =
+      command_span.in_scope(|| tracing::info_span!("parse").in_scope(|| parse_command(&buf[..n])));
+    if let Ok(command) = &command {
+      command_span.record("otel.name", command.name().as_str());
+    }
+    stream.set_command_span(Some(command_span.clone()));
+    if let Ok(command) = &command {
+      // Hold the command back while clients are paused, unless killed meanwhile
+      if clients.is_paused(modules.is_write(command)) {
+        if let Err(e) = stream.flush().await {
+          debug!("Failed to write to stream: {}", e);
+          break;
+        }
+      }
+      tokio::select! {
+        _ = clients.wait_while_paused(modules.is_write(command)) => {}
+        _ = kill.notified() => {
+          debug!("Client killed");
+          break;
+        }
+      }
+      clients.touch(client_id, &command.name());
+
+      // Injected faults, sparing DEBUG so that they can be turned off
+      if !matches!(command, Command::DEBUG(..)) {
+        if state.faults.reset_connection() {
+          debug!("Injected fault: resetting the connection");
+          break;
+        }
+        if let Some(latency) = state.faults.latency() {
+          tokio::time::sleep(latency).await;
+        }
+      }
+
+      // Monitors only watch, the one way out is RESET
+      if clients.is_monitor(client_id) && !matches!(command, Command::RESET) {
+        state.stats.commands.rejected(&command.name());
+        let response = serialize_response(RedisValue::Error(
+          "ERR Command not allowed in MONITOR mode, use RESET to leave it".to_string(),
+        ));
+        if let Err(e) = stream.write_response(&response).await {
+          debug!("Failed to write to stream: {}", e);
+          break;
+        }
+        continue;
+      }
+
+      if let Err(e) = acl.check(&clients, client_id, command) {
+        state.stats.commands.rejected(&command.name());
+        let response = serialize_response(RedisValue::Error(e));
+        if let Err(e) = stream.write_response(&response).await {
+          debug!("Failed to write to stream: {}", e);
+          break;
+        }
+        continue;
+      }
+
+      // In a cluster the keys must belong to a slot served here
+      if cluster.enabled() {
+        let asking = clients.take_asking(client_id);
+        let redirect = {
+          let storage = storage.share().await;
+          cluster.redirect(&command.keys(), asking, |key| storage.exists(key))
+        };
+        if let Some(e) = redirect {
+          state.stats.commands.rejected(&command.name());
+          let response = serialize_response(RedisValue::Error(e));
+          if let Err(e) = stream.write_response(&response).await {
+            debug!("Failed to write to stream: {}", e);
+            break;
+          }
+          continue;
+        }
+      }
+
+      let (user, _) = clients.session(client_id);
+      match rate_limiter.acquire(client_id, &user) {
+        Admission::Allowed => {}
+        Admission::Delayed(wait) => {
+          if let Err(e) = stream.flush().await {
+            debug!("Failed to write to stream: {}", e);
+            break;
+          }
+          tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = kill.notified() => {
+              debug!("Client killed");
+              break;
+            }
+          }
+        }
+        Admission::Rejected => {
+          state.stats.commands.rejected(&command.name());
+          let response = serialize_response(RedisValue::Error(RATE_LIMITED_ERROR.to_string()));
+          if let Err(e) = stream.write_response(&response).await {
+            debug!("Failed to write to stream: {}", e);
+            break;
+          }
+          continue;
+        }
+      }
+
+      // Past maxmemory keys are evicted before a write, and when none can be
+      // the writes that may grow the dataset are refused
+      if modules.is_write(command) {
+        let evicted = storage.share().await.perform_evictions();
+        match evicted {
+          Err(e) if modules.is_denyoom(command) => {
+            state.stats.commands.rejected(&command.name());
+            let response = serialize_response(RedisValue::Error(e));
+            if let Err(e) = stream.write_response(&response).await {
+              debug!("Failed to write to stream: {}", e);
+              break;
+            }
+            continue;
+          }
+          _ => {}
+        }
+      }
+
+      if clients.has_monitors() {
+        clients.feed_monitors(client_id, &command_argv(&buf[..n]));
+      }
+      Stats::incr(&state.stats.total_commands_processed);
+      if modules.is_write(command) {
+        Stats::incr(&state.stats.dirty);
+      }
+    }
+
+    // Timing hook: every command that runs is accounted in commandstats
+    let started = Instant::now();
+    let name = command.as_ref().ok().map(Command::name);
+    let audited: Option<Vec<String>> = match &command {
+      Ok(command) if modules.is_write(command) && state.audit_log.enabled() => {
+        Some(command.keys().into_iter().map(str::to_string).collect())
+      }
+      _ => None,
+    };
+    stream.take_error_replies();
+
+    // Keys read to remember for CLIENT TRACKING, the keyspace invalidates the
+    // ones written
+    let tracked: Option<Vec<String>> = match &command {
+      Ok(command) if !clients.tracking_table().is_empty() && !modules.is_write(command) => {
+        Some(command.keys().iter().map(|key| key.to_string()).collect())
+      }
+      _ => None,
+    };
+
+    let dispatch = tracing::info_span!(parent: &command_span, "dispatch");
+    let outcome = match command {
+      Ok(command) => {
+        let context = commands::Context {
+          client_id,
+          addr,
+          frame: &buf[..n],
+          storage: &storage,
+          config: &config,
+          clients: &clients,
+          acl: &acl,
+          rate_limiter: &rate_limiter,
+          cluster: &cluster,
+          modules: &modules,
+          read_through: &read_through,
+          state: &state,
+          span: &dispatch,
+          holds_keys: false,
+        };
+        tracking::run_as(client_id, commands::dispatch(&context, command)).await
+      }
+      Err(e) => {
+        debug!("Failed to parse command: {}", e);
+        Outcome::Reply(RedisValue::Error(format!("ERR {}", e)))
+      }
+    };
+    match outcome {
+      Outcome::Reply(response) => {
+        let resp = clients.resp(client_id);
+        if let Err(e) = stream.write_value(response, resp).await {
+          debug!("Failed to write to stream: {}", e);
+          break;
+        }
+      }
+      Outcome::Shutdown => {
+        let _ = stream.flush().await;
+        state.request_shutdown();
+        break;
+      }
+    }
+
+    drop(dispatch);
+
+    if let Some(name) = name {
+      let failed = stream.take_error_replies() > 0;
+      if failed {
+        telemetry::record_error(&command_span);
+      }
+      let elapsed = started.elapsed();
+      state.stats.commands.record(&name, elapsed, failed);
+      if state.slowlog.is_slow(elapsed) {
+        if let Some(client) = clients.get(client_id) {
+          state.slowlog.record(
+            elapsed,
+            &command_argv(&buf[..n]),
+            &client.addr.to_string(),
+            &client.name,
+          );
+        }
+      }
+
+      if let (Some(keys), Some(client)) = (audited, clients.get(client_id)) {
+        state.audit_log.record(
+          &command_argv(&buf[..n]),
+          &keys,
+          &client.addr.to_string(),
+          &clients.session(client_id).0,
+        );
+      }
+
+      if let Some(keys) = tracked {
+        clients.track_reads(client_id, &name, &keys);
+      }
+    }
+  }
+
+  // Replies to the last commands, such as QUIT's or a protocol error
+  if let Err(e) = stream.flush().await {
+    debug!("Failed to write to stream: {}", e);
+  }
+  clients.unregister(client_id);
+  rate_limiter.forget_client(client_id);
+  debug!("Connection closed");
+}
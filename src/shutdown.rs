@@ -0,0 +1,95 @@
+/**
+ * Graceful shutdown, shared by the SHUTDOWN command and the SIGTERM/SIGINT
+ * handlers.
+ *
+ * Before exiting, the keyspace is saved to the RDB file when save points are
+ * configured (`--save "3600 1 300 100"`), or when SHUTDOWN SAVE asks for it. If
+ * that save fails the server keeps running, like Redis, so no data is lost
+ * silently; SHUTDOWN NOSAVE skips it.
+ */
+use crate::config::Config;
+use crate::daemon;
+use crate::encryption;
+use crate::rdb::{self, RdbOptions};
+use crate::state::ServerState;
+use crate::storagelock::StorageLock;
+use crate::telemetry;
+use std::sync::Arc;
+use tokio::sync::Mutex as AsyncMutex;
+use tracing::{error, info};
+
+/// Whether at least one `save <seconds> <changes>` point is configured
+pub fn save_points_configured(config: &Config) -> bool {
+  config
+    .get("save")
+    .map(|save| !save.trim().is_empty())
+    .unwrap_or(false)
+}
+
+/// Runs everything that must happen before the process exits. `save` follows
+/// SHUTDOWN: `Some(true)` forces a save, `Some(false)` skips it and `None`
+/// saves only when save points are configured.
+pub async fn prepare_shutdown(
+  storage: &Arc<StorageLock>,
+  config: &Arc<AsyncMutex<Config>>,
+  state: &ServerState,
+  save: Option<bool>,
+) -> Result<(), String> {
+  info!("User requested shutdown...");
+  let config = config.lock().await;
+  let save = save.unwrap_or_else(|| save_points_configured(&config));
+
+  if save {
+    let path = rdb::snapshot_path(&config);
+    info!("Saving the final RDB snapshot before exiting.");
+    let key = encryption::key(&config).map_err(|e| {
+      error!("Error trying to save the DB, can't exit: {}", e);
+      "ERR Errors trying to SHUTDOWN. Check logs.".to_string()
+    })?;
+    let storage = storage.lock().await;
+    match rdb::server_save(
+      state,
+      &storage,
+      &path,
+      key.as_ref(),
+      RdbOptions::from_config(&config),
+    ) {
+      Ok(bytes) => info!("DB saved on disk ({} bytes)", bytes),
+      Err(e) => {
+        error!("Error trying to save the DB, can't exit: {}", e);
+        return Err("ERR Errors trying to SHUTDOWN. Check logs.".to_string());
+      }
+    }
+  }
+
+  daemon::remove_pidfile();
+  telemetry::shutdown();
+  info!("Redis is now ready to exit, bye bye...");
+  Ok(())
+}
+
+/// Resolves once SIGTERM or SIGINT is received
+pub async fn wait_for_signal() {
+  #[cfg(unix)]
+  {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut terminate = match signal(SignalKind::terminate()) {
+      Ok(terminate) => terminate,
+      Err(e) => {
+        error!("Failed to install the SIGTERM handler: {}", e);
+        let _ = tokio::signal::ctrl_c().await;
+        return;
+      }
+    };
+    tokio::select! {
+      _ = terminate.recv() => info!("Received SIGTERM scheduling shutdown..."),
+      _ = tokio::signal::ctrl_c() => info!("Received SIGINT scheduling shutdown..."),
+    }
+  }
+
+  #[cfg(not(unix))]
+  {
+    let _ = tokio::signal::ctrl_c().await;
+    info!("Received SIGINT scheduling shutdown...");
+  }
+}
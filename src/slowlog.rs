@@ -0,0 +1,204 @@
+/**
+ * SLOWLOG: the last commands that took longer than `slowlog-log-slower-than`
+ * microseconds to run, kept in memory.
+ *
+ * Commands are timed by the same hook as INFO commandstats, from dispatch to
+ * reply, so time spent waiting for the client or in the network is not
+ * counted. At most `slowlog-max-len` entries are kept, newest first. As in
+ * Redis, a negative threshold disables the log and 0 logs every command;
+ * arguments are cut to `SLOWLOG_MAX_ARGC` and each to `SLOWLOG_MAX_ARGLEN`
+ * bytes so that a huge value can't blow the log up.
+ */
+use crate::config::Config;
+use crate::parser::RedisValue;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Default `slowlog-log-slower-than`, in microseconds
+pub const SLOWLOG_SLOWER_THAN: i64 = 10_000;
+
+/// Default `slowlog-max-len`
+pub const SLOWLOG_MAX_LEN: usize = 128;
+
+/// Arguments kept per entry, the last one standing for the rest
+const SLOWLOG_MAX_ARGC: usize = 32;
+
+/// Bytes kept per argument
+const SLOWLOG_MAX_ARGLEN: usize = 128;
+
+#[derive(Debug, Clone)]
+pub struct SlowLogEntry {
+  pub id: u64,
+  /// Unix time the command was logged at
+  pub timestamp: u64,
+  /// Execution time in microseconds
+  pub duration: u64,
+  pub argv: Vec<String>,
+  /// Address and name of the client that ran the command
+  pub addr: String,
+  pub name: String,
+}
+
+impl SlowLogEntry {
+  fn to_value(&self) -> RedisValue {
+    RedisValue::Nested(vec![
+      RedisValue::Integer(self.id as i64),
+      RedisValue::Integer(self.timestamp as i64),
+      RedisValue::Integer(self.duration as i64),
+      RedisValue::Array(self.argv.clone()),
+      RedisValue::BulkString(Some(self.addr.clone())),
+      RedisValue::BulkString(Some(self.name.clone())),
+    ])
+  }
+}
+
+pub struct SlowLog {
+  /// Newest entries first
+  entries: Mutex<VecDeque<SlowLogEntry>>,
+  next_id: AtomicU64,
+  slower_than: AtomicI64,
+  max_len: AtomicUsize,
+}
+
+impl SlowLog {
+  pub fn new() -> Self {
+    Self {
+      entries: Mutex::new(VecDeque::new()),
+      next_id: AtomicU64::new(0),
+      slower_than: AtomicI64::new(SLOWLOG_SLOWER_THAN),
+      max_len: AtomicUsize::new(SLOWLOG_MAX_LEN),
+    }
+  }
+
+  /// Takes `slowlog-log-slower-than` and `slowlog-max-len` from the
+  /// configuration, trimming the log right away
+  pub fn apply_config(&self, config: &Config) {
+    if let Some(slower_than) = config
+      .get("slowlog-log-slower-than")
+      .and_then(|value| value.parse::<i64>().ok())
+    {
+      self.slower_than.store(slower_than, Ordering::Relaxed);
+    }
+    if let Some(max_len) = config
+      .get("slowlog-max-len")
+      .and_then(|value| value.parse::<usize>().ok())
+    {
+      self.max_len.store(max_len, Ordering::Relaxed);
+      self.entries.lock().unwrap().truncate(max_len);
+    }
+  }
+
+  /// Whether a command that ran for `elapsed` goes to the log
+  pub fn is_slow(&self, elapsed: Duration) -> bool {
+    let slower_than = self.slower_than.load(Ordering::Relaxed);
+    slower_than >= 0 && elapsed.as_micros() >= slower_than as u128
+  }
+
+  /// Logs a command that ran for `elapsed`, if it is slow enough
+  pub fn record(&self, elapsed: Duration, argv: &[String], addr: &str, name: &str) {
+    if !self.is_slow(elapsed) {
+      return;
+    }
+    let mut kept: Vec<String> = argv
+      .iter()
+      .take(SLOWLOG_MAX_ARGC)
+      .map(|argument| truncate(argument))
+      .collect();
+    if argv.len() > SLOWLOG_MAX_ARGC {
+      kept[SLOWLOG_MAX_ARGC - 1] =
+        format!("... ({} more arguments)", argv.len() - SLOWLOG_MAX_ARGC + 1);
+    }
+    let entry = SlowLogEntry {
+      id: self.next_id.fetch_add(1, Ordering::Relaxed),
+      timestamp: SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs(),
+      duration: elapsed.as_micros() as u64,
+      argv: kept,
+      addr: addr.to_string(),
+      name: name.to_string(),
+    };
+    let mut entries = self.entries.lock().unwrap();
+    entries.push_front(entry);
+    entries.truncate(self.max_len.load(Ordering::Relaxed));
+  }
+
+  /// The `count` newest entries
+  pub fn entries(&self, count: usize) -> Vec<SlowLogEntry> {
+    let entries = self.entries.lock().unwrap();
+    entries.iter().take(count).cloned().collect()
+  }
+
+  pub fn len(&self) -> usize {
+    self.entries.lock().unwrap().len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  pub fn reset(&self) {
+    self.entries.lock().unwrap().clear();
+  }
+
+  /// SLOWLOG GET [count] | LEN | RESET
+  pub fn handle_command(&self, subcommand: &str, args: &[String]) -> RedisValue {
+    match (subcommand, args) {
+      ("GET", []) => self.get(10),
+      // -1 returns every entry, like Redis
+      ("GET", [count]) if count == "-1" => self.get(usize::MAX),
+      ("GET", [count]) => match count.parse::<usize>() {
+        Ok(count) => self.get(count),
+        Err(_) => RedisValue::Error("ERR count should be greater than or equal to -1".to_string()),
+      },
+      ("LEN", []) => RedisValue::Integer(self.len() as i64),
+      ("RESET", []) => {
+        self.reset();
+        RedisValue::SimpleString("OK".to_string())
+      }
+      ("GET" | "LEN" | "RESET", _) => RedisValue::Error(format!(
+        "ERR wrong number of arguments for 'slowlog|{}' command",
+        subcommand.to_lowercase()
+      )),
+      _ => RedisValue::Error(format!(
+        "ERR unknown subcommand '{}'. Try SLOWLOG HELP.",
+        subcommand.to_lowercase()
+      )),
+    }
+  }
+
+  fn get(&self, count: usize) -> RedisValue {
+    RedisValue::Nested(
+      self
+        .entries(count)
+        .iter()
+        .map(SlowLogEntry::to_value)
+        .collect(),
+    )
+  }
+}
+
+impl Default for SlowLog {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+/// `argument` cut to `SLOWLOG_MAX_ARGLEN` bytes, on a character boundary
+fn truncate(argument: &str) -> String {
+  if argument.len() <= SLOWLOG_MAX_ARGLEN {
+    return argument.to_string();
+  }
+  let mut end = SLOWLOG_MAX_ARGLEN;
+  while !argument.is_char_boundary(end) {
+    end -= 1;
+  }
+  format!(
+    "{}... ({} more bytes)",
+    &argument[..end],
+    argument.len() - end
+  )
+}
@@ -0,0 +1,237 @@
+/**
+ * Copy-on-write snapshots of the keyspace, the point-in-time view BGSAVE
+ * serializes while clients keep writing. KEYS and DEBUG DIGEST walk one too
+ * on large keyspaces, see `offload`.
+ *
+ * Opening a snapshot copies nothing: it takes a handle to the keyspace and an
+ * empty overlay. From then on, the first write to a key records in the overlay
+ * what the key was before (its value, shared rather than copied, and its
+ * deadline, or that it didn't exist). The serializer walks the live keyspace,
+ * marking every key it reads in the overlay, and takes the keys written since
+ * the snapshot from the overlay instead, so each key is seen exactly once, as
+ * it was when the snapshot was opened. Keys deleted since only remain in the
+ * overlay, which is walked last.
+ *
+ * FLUSHALL swaps the keyspace out rather than clearing it: open snapshots keep
+ * the old one, which nothing writes anymore, and stop following writes.
+ *
+ * The overlay ends up holding every key name once walked, it is dropped with
+ * the snapshot.
+ *
+ * `DatasetSnapshot` is the in-memory kind, for applications embedding the
+ * server, test suites mostly, that go back to a known dataset between cases:
+ * `Storage::snapshot()` takes one and `Storage::restore()` puts the keyspace
+ * back as it was, any number of times. It is a snapshot like BGSAVE's, so
+ * taking one copies nothing and writes feed its overlay for as long as it is
+ * kept. Restoring reads it without marking keys as read, which would stop the
+ * overlay from following them, and costs a walk over the key names.
+ */
+use crate::clock::Clock;
+use crate::encoding::Value;
+use crate::storage::StorageValue;
+use bytes::Bytes;
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::time::Instant;
+
+pub type Keyspace = DashMap<String, StorageValue>;
+
+/// A key as snapshots see it: its value and deadline
+type Frozen = (Value, Option<Instant>);
+
+enum Preserved {
+  /// The snapshot read the key from the keyspace, unchanged since it opened
+  Read,
+  /// The key before its first write since the snapshot opened, `None` when it
+  /// didn't exist
+  Before(Option<Frozen>),
+}
+
+#[derive(Default)]
+struct Overlay {
+  keys: DashMap<String, Preserved>,
+}
+
+/// The snapshots in progress, which every write to the keyspace feeds
+#[derive(Default)]
+pub struct Snapshots {
+  open: Mutex<Vec<Arc<Overlay>>>,
+}
+
+impl Snapshots {
+  /// Opens a snapshot of `keyspace` as it is now. The caller holds the storage
+  /// lock, so nothing writes to it meanwhile.
+  pub fn open(self: &Arc<Self>, keyspace: Arc<Keyspace>, clock: &dyn Clock) -> Snapshot {
+    let overlay = Arc::new(Overlay::default());
+    self.open.lock().unwrap().push(overlay.clone());
+    Snapshot {
+      keyspace,
+      overlay,
+      snapshots: self.clone(),
+      taken_at: clock.now(),
+      wall_clock: clock.system_time(),
+    }
+  }
+
+  /// Records `key` as it is before a write, for the snapshots that haven't
+  /// seen it yet
+  pub fn preserve(&self, keyspace: &Keyspace, key: &str) {
+    let open = self.open.lock().unwrap();
+    if open.is_empty() {
+      return;
+    }
+    let before = keyspace
+      .get(key)
+      .map(|entry| (entry.encoded().clone(), entry.expires_at()));
+    for overlay in open.iter() {
+      overlay
+        .keys
+        .entry(key.to_string())
+        .or_insert_with(|| Preserved::Before(before.clone()));
+    }
+  }
+
+  /// Stops the open snapshots from following writes, once their keyspace was
+  /// swapped out and won't change anymore
+  pub fn detach(&self) {
+    self.open.lock().unwrap().clear();
+  }
+
+  fn close(&self, overlay: &Arc<Overlay>) {
+    self
+      .open
+      .lock()
+      .unwrap()
+      .retain(|open| !Arc::ptr_eq(open, overlay));
+  }
+}
+
+/// A point-in-time view of the keyspace, see the module documentation
+pub struct Snapshot {
+  keyspace: Arc<Keyspace>,
+  overlay: Arc<Overlay>,
+  snapshots: Arc<Snapshots>,
+  taken_at: Instant,
+  wall_clock: SystemTime,
+}
+
+impl Snapshot {
+  /// The keys live when the snapshot was opened, with their values and
+  /// absolute deadlines
+  pub fn entries(self) -> Vec<(String, Bytes, Option<SystemTime>)> {
+    let mut entries = Vec::with_capacity(self.keyspace.len());
+    self.walk(|key, value, expires_at| {
+      if let Some(expires_at) = self.deadline(expires_at) {
+        entries.push((key, value.to_bytes(), expires_at));
+      }
+    });
+    entries
+  }
+
+  /// The names of the keys live when the snapshot was opened
+  pub fn keys(self) -> Vec<String> {
+    let mut keys = Vec::with_capacity(self.keyspace.len());
+    self.walk(|key, _, expires_at| {
+      if self.deadline(expires_at).is_some() {
+        keys.push(key);
+      }
+    });
+    keys
+  }
+
+  /// Visits every key as it was when the snapshot was opened, expired ones
+  /// included, marking the keys read in the overlay
+  fn walk(&self, mut visit: impl FnMut(String, &Value, Option<Instant>)) {
+    for entry in self.keyspace.iter() {
+      match self.overlay.keys.entry(entry.key().clone()) {
+        // Written since the snapshot opened, the overlay has it as it was
+        Entry::Occupied(_) => continue,
+        Entry::Vacant(vacant) => {
+          vacant.insert(Preserved::Read);
+        }
+      }
+      visit(entry.key().clone(), entry.encoded(), entry.expires_at());
+    }
+    for preserved in self.overlay.keys.iter() {
+      if let Preserved::Before(Some((value, expires_at))) = preserved.value() {
+        visit(preserved.key().clone(), value, *expires_at);
+      }
+    }
+  }
+
+  /// The absolute deadline of a key, `None` when it had already expired when
+  /// the snapshot was opened
+  fn deadline(&self, expires_at: Option<Instant>) -> Option<Option<SystemTime>> {
+    match expires_at {
+      Some(deadline) if deadline <= self.taken_at => None,
+      Some(deadline) => Some(Some(
+        self.wall_clock + deadline.duration_since(self.taken_at),
+      )),
+      None => Some(None),
+    }
+  }
+
+  /// The keys live when the snapshot was opened, like `entries` but leaving
+  /// the overlay as it is, so that the snapshot can be read again
+  fn frozen(&self) -> Vec<(String, Value, Option<Instant>)> {
+    let live =
+      |expires_at: &Option<Instant>| expires_at.is_none_or(|deadline| deadline > self.taken_at);
+    let mut entries = Vec::with_capacity(self.keyspace.len());
+    for entry in self.keyspace.iter() {
+      if self.overlay.keys.contains_key(entry.key()) {
+        continue;
+      }
+      if live(&entry.expires_at()) {
+        entries.push((
+          entry.key().clone(),
+          entry.encoded().clone(),
+          entry.expires_at(),
+        ));
+      }
+    }
+    for preserved in self.overlay.keys.iter() {
+      if let Preserved::Before(Some((value, expires_at))) = preserved.value() {
+        if live(expires_at) {
+          entries.push((preserved.key().clone(), value.clone(), *expires_at));
+        }
+      }
+    }
+    entries
+  }
+}
+
+impl Drop for Snapshot {
+  fn drop(&mut self) {
+    self.snapshots.close(&self.overlay);
+  }
+}
+
+/// A keyspace as it was, to restore it with, see the module documentation
+#[derive(Clone)]
+pub struct DatasetSnapshot {
+  snapshot: Arc<Snapshot>,
+}
+
+impl DatasetSnapshot {
+  pub(crate) fn new(snapshot: Snapshot) -> Self {
+    Self {
+      snapshot: Arc::new(snapshot),
+    }
+  }
+
+  /// The keys with their values and deadlines
+  pub(crate) fn entries(&self) -> Vec<(String, Value, Option<Instant>)> {
+    self.snapshot.frozen()
+  }
+
+  /// Number of keys, counted with a walk over them
+  pub fn len(&self) -> usize {
+    self.entries().len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+}
@@ -0,0 +1,65 @@
+/**
+ * What each server keeps to itself, so that servers embedded in the same
+ * process don't mix their counters and logs: the INFO stats and
+ * commandstats, the slow log, the audit log, the recorder, the injected
+ * faults, the state of RDB saves and whether SHUTDOWN was called.
+ *
+ * The statistics are shared with the keyspace and the client registry,
+ * which bump them too. What describes the process rather than a server (the
+ * allocator, cron stats, the startup time, the log level) stays process wide.
+ */
+use crate::audit::AuditLog;
+use crate::fault::Faults;
+use crate::rdb::Saves;
+use crate::record::Recorder;
+use crate::slowlog::SlowLog;
+use crate::stats::Stats;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+pub struct ServerState {
+  pub stats: Arc<Stats>,
+  pub slowlog: SlowLog,
+  pub audit_log: AuditLog,
+  pub recorder: Recorder,
+  pub faults: Faults,
+  pub saves: Saves,
+  /// Raised by SHUTDOWN, once the dataset is saved
+  shutdown: watch::Sender<bool>,
+}
+
+impl ServerState {
+  pub fn new() -> Self {
+    Self {
+      stats: Arc::new(Stats::new()),
+      slowlog: SlowLog::new(),
+      audit_log: AuditLog::new(),
+      recorder: Recorder::new(),
+      faults: Faults::new(),
+      saves: Saves::new(),
+      shutdown: watch::Sender::new(false),
+    }
+  }
+
+  /// Asks the server to stop, as SHUTDOWN does
+  pub fn request_shutdown(&self) {
+    self.shutdown.send_replace(true);
+  }
+
+  /// Forgets a past SHUTDOWN, for a server started again
+  pub fn clear_shutdown(&self) {
+    self.shutdown.send_replace(false);
+  }
+
+  /// Resolves once SHUTDOWN was called
+  pub async fn shutdown_requested(&self) {
+    let mut requested = self.shutdown.subscribe();
+    let _ = requested.wait_for(|requested| *requested).await;
+  }
+}
+
+impl Default for ServerState {
+  fn default() -> Self {
+    Self::new()
+  }
+}
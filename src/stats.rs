@@ -0,0 +1,205 @@
+/**
+ * Server statistics reported by INFO stats.
+ *
+ * Like Redis's `server.stat_*` fields these are the counters of a server (see
+ * `ServerState`), bumped from wherever the event happens (the accept loop, the
+ * connection tasks, the keyspace) without any locking. The instantaneous rates
+ * are computed the way Redis does: the counters are sampled every 100ms and the
+ * rate is averaged over the last 16 samples.
+ */
+use crate::commandstats::CommandStats;
+use crate::cron::Scheduler;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+const SAMPLE_BUDGET: Duration = Duration::from_millis(1);
+const SAMPLES: usize = 16;
+
+/// Rolling window of per-second rates computed from consecutive samples
+struct Rate {
+  last_value: u64,
+  last_sample: Option<Instant>,
+  samples: [f64; SAMPLES],
+  index: usize,
+}
+
+impl Rate {
+  const fn new() -> Self {
+    Self {
+      last_value: 0,
+      last_sample: None,
+      samples: [0.0; SAMPLES],
+      index: 0,
+    }
+  }
+
+  fn sample(&mut self, value: u64, now: Instant) {
+    if let Some(last_sample) = self.last_sample {
+      let elapsed = now.duration_since(last_sample).as_secs_f64();
+      if elapsed > 0.0 {
+        self.samples[self.index] = value.saturating_sub(self.last_value) as f64 / elapsed;
+        self.index = (self.index + 1) % SAMPLES;
+      }
+    }
+    self.last_value = value;
+    self.last_sample = Some(now);
+  }
+
+  /// Average rate per second over the window
+  fn average(&self) -> f64 {
+    self.samples.iter().sum::<f64>() / SAMPLES as f64
+  }
+}
+
+struct Rates {
+  ops: Rate,
+  net_input: Rate,
+  net_output: Rate,
+}
+
+pub struct Stats {
+  pub total_connections_received: AtomicU64,
+  pub rejected_connections: AtomicU64,
+  pub total_commands_processed: AtomicU64,
+  pub total_net_input_bytes: AtomicU64,
+  pub total_net_output_bytes: AtomicU64,
+  pub keyspace_hits: AtomicU64,
+  pub keyspace_misses: AtomicU64,
+  pub expired_keys: AtomicU64,
+  pub evicted_keys: AtomicU64,
+  pub evicted_clients: AtomicU64,
+  pub total_error_replies: AtomicU64,
+  /// Writes since the last save, like Redis's `server.dirty`
+  pub dirty: AtomicU64,
+  /// Per-command calls and latencies
+  pub commands: CommandStats,
+  rates: Mutex<Rates>,
+}
+
+impl Stats {
+  pub fn new() -> Self {
+    Self {
+      total_connections_received: AtomicU64::new(0),
+      rejected_connections: AtomicU64::new(0),
+      total_commands_processed: AtomicU64::new(0),
+      total_net_input_bytes: AtomicU64::new(0),
+      total_net_output_bytes: AtomicU64::new(0),
+      keyspace_hits: AtomicU64::new(0),
+      keyspace_misses: AtomicU64::new(0),
+      expired_keys: AtomicU64::new(0),
+      evicted_keys: AtomicU64::new(0),
+      evicted_clients: AtomicU64::new(0),
+      total_error_replies: AtomicU64::new(0),
+      dirty: AtomicU64::new(0),
+      commands: CommandStats::new(),
+      rates: Mutex::new(Rates {
+        ops: Rate::new(),
+        net_input: Rate::new(),
+        net_output: Rate::new(),
+      }),
+    }
+  }
+
+  /// Bumps a counter by one
+  pub fn incr(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+  }
+
+  /// Bumps a counter by `amount`
+  pub fn add(counter: &AtomicU64, amount: usize) {
+    counter.fetch_add(amount as u64, Ordering::Relaxed);
+  }
+
+  /// CONFIG RESETSTAT: zeroes every counter, like Redis
+  pub fn reset(&self) {
+    for counter in [
+      &self.total_connections_received,
+      &self.rejected_connections,
+      &self.total_commands_processed,
+      &self.total_net_input_bytes,
+      &self.total_net_output_bytes,
+      &self.keyspace_hits,
+      &self.keyspace_misses,
+      &self.expired_keys,
+      &self.evicted_keys,
+      &self.evicted_clients,
+      &self.total_error_replies,
+    ] {
+      counter.store(0, Ordering::Relaxed);
+    }
+    self.commands.reset();
+    let mut rates = self.rates.lock().unwrap();
+    *rates = Rates {
+      ops: Rate::new(),
+      net_input: Rate::new(),
+      net_output: Rate::new(),
+    };
+  }
+
+  fn sample(&self) {
+    let now = Instant::now();
+    let mut rates = self.rates.lock().unwrap();
+    rates
+      .ops
+      .sample(self.total_commands_processed.load(Ordering::Relaxed), now);
+    rates
+      .net_input
+      .sample(self.total_net_input_bytes.load(Ordering::Relaxed), now);
+    rates
+      .net_output
+      .sample(self.total_net_output_bytes.load(Ordering::Relaxed), now);
+  }
+
+  /// Lines of the `# Stats` INFO section
+  pub fn info(&self) -> Vec<String> {
+    let (ops, input, output) = {
+      let rates = self.rates.lock().unwrap();
+      (
+        rates.ops.average(),
+        rates.net_input.average(),
+        rates.net_output.average(),
+      )
+    };
+    let counter =
+      |name: &str, counter: &AtomicU64| format!("{}:{}", name, counter.load(Ordering::Relaxed));
+
+    vec![
+      counter(
+        "total_connections_received",
+        &self.total_connections_received,
+      ),
+      counter("total_commands_processed", &self.total_commands_processed),
+      format!("instantaneous_ops_per_sec:{}", ops.round() as u64),
+      counter("total_net_input_bytes", &self.total_net_input_bytes),
+      counter("total_net_output_bytes", &self.total_net_output_bytes),
+      format!("instantaneous_input_kbps:{:.2}", input / 1024.0),
+      format!("instantaneous_output_kbps:{:.2}", output / 1024.0),
+      counter("rejected_connections", &self.rejected_connections),
+      counter("expired_keys", &self.expired_keys),
+      counter("evicted_keys", &self.evicted_keys),
+      counter("evicted_clients", &self.evicted_clients),
+      counter("keyspace_hits", &self.keyspace_hits),
+      counter("keyspace_misses", &self.keyspace_misses),
+      counter("total_error_replies", &self.total_error_replies),
+    ]
+  }
+}
+
+impl Default for Stats {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+/// Schedules the job that samples the counters behind the instantaneous rates
+pub fn register_stats_sampler(scheduler: &mut Scheduler, stats: Arc<Stats>) {
+  scheduler.every("stats-sampler", SAMPLE_INTERVAL, SAMPLE_BUDGET, move || {
+    let stats = stats.clone();
+    async move {
+      stats.sample();
+    }
+  });
+}
@@ -0,0 +1,319 @@
+/**
+ * The StatsD exporter: pushes the server's metrics over UDP to a StatsD
+ * server or a Datadog agent, for setups collecting metrics that way rather
+ * than by scraping the `/metrics` endpoint of the HTTP admin API.
+ *
+ * `statsd-address host:port` turns it on. Every `statsd-flush-interval`
+ * seconds it sends, under `statsd-prefix`:
+ *
+ * - counters of what happened since the previous flush: commands processed,
+ *   connections, network bytes, keyspace hits and misses, expired and evicted
+ *   keys, error replies, and the calls of each command
+ * - the mean latency of each command that ran since then, as a timer
+ * - gauges of the memory used (allocated, peak, resident, dataset, clients),
+ *   the connected clients and the keys
+ *
+ * ```text
+ * redis.commands.total:1520|c
+ * redis.commands.get.calls:1200|c
+ * redis.commands.get.latency:0.004|ms
+ * redis.memory.used:3250176|g
+ * ```
+ *
+ * With `statsd-datadog-tags yes` the command is a DogStatsD tag instead
+ * (`redis.commands.calls:1200|c|#command:get`), so that one metric covers
+ * every command. The metrics are sent several per packet, one per line. The
+ * first flush after the exporter is turned on only takes the counters as a
+ * baseline.
+ */
+use crate::address::parse_host_port;
+use crate::allocator;
+use crate::clients::ClientRegistry;
+use crate::config::Config;
+use crate::cron::Scheduler;
+use crate::stats::Stats;
+use crate::storagelock::StorageLock;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::Mutex as AsyncMutex;
+use tokio::time::Instant;
+use tracing::warn;
+
+/// Default `statsd-prefix`
+pub const DEFAULT_PREFIX: &str = "redis";
+
+/// Default `statsd-flush-interval`, in seconds
+pub const DEFAULT_FLUSH_INTERVAL: u64 = 10;
+
+/// How often the exporter checks whether a flush is due
+const FLUSH_TICK: Duration = Duration::from_secs(1);
+
+/// Time a flush may take before it counts as an overrun
+const FLUSH_BUDGET: Duration = Duration::from_millis(10);
+
+/// Largest packet sent, to fit in the MTU of most networks
+pub const MAX_PACKET: usize = 1432;
+
+/// Where and how the metrics are sent, from the configuration
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatsdOptions {
+  pub host: String,
+  pub port: u16,
+  /// Prepended to every metric name, with a dot
+  pub prefix: String,
+  pub interval: Duration,
+  /// Whether commands are DogStatsD tags rather than part of the name
+  pub tags: bool,
+}
+
+impl StatsdOptions {
+  /// The options of `config`, `None` while `statsd-address` isn't set
+  pub fn from_config(config: &Config) -> Option<Self> {
+    let (host, port) = parse_host_port(&config.get("statsd-address")?).ok()?;
+    let prefix = config
+      .get("statsd-prefix")
+      .unwrap_or_else(|| DEFAULT_PREFIX.to_string());
+    Some(Self {
+      host,
+      port,
+      prefix: match prefix.is_empty() {
+        true => prefix,
+        false => format!("{}.", prefix.trim_end_matches('.')),
+      },
+      interval: Duration::from_secs(
+        config
+          .get("statsd-flush-interval")
+          .and_then(|interval| interval.parse().ok())
+          .unwrap_or(DEFAULT_FLUSH_INTERVAL),
+      ),
+      tags: config.get("statsd-datadog-tags").as_deref() == Some("yes"),
+    })
+  }
+}
+
+/// The exporter of a server, with the counters as of the last flush
+#[derive(Default)]
+pub struct StatsdExporter {
+  socket: Option<UdpSocket>,
+  last_flush: Option<Instant>,
+  counters: HashMap<&'static str, u64>,
+  /// Calls and microseconds of each command
+  commands: HashMap<String, (u64, u64)>,
+  /// Counters of the server
+  stats: Arc<Stats>,
+}
+
+impl StatsdExporter {
+  pub fn new(stats: Arc<Stats>) -> Self {
+    Self {
+      stats,
+      ..Self::default()
+    }
+  }
+
+  /// The metrics of a flush, the counters being the change since the
+  /// previous one. `gauges` are the values measured by the caller.
+  pub fn metrics(&mut self, options: &StatsdOptions, gauges: &[(&str, usize)]) -> Vec<String> {
+    let prefix = &options.prefix;
+    let stats = self.stats.clone();
+    let mut metrics = Vec::new();
+    for (name, counter) in [
+      ("commands.total", &stats.total_commands_processed),
+      ("connections.received", &stats.total_connections_received),
+      ("connections.rejected", &stats.rejected_connections),
+      ("net.input_bytes", &stats.total_net_input_bytes),
+      ("net.output_bytes", &stats.total_net_output_bytes),
+      ("keyspace.hits", &stats.keyspace_hits),
+      ("keyspace.misses", &stats.keyspace_misses),
+      ("keys.expired", &stats.expired_keys),
+      ("keys.evicted", &stats.evicted_keys),
+      ("errors", &stats.total_error_replies),
+    ] {
+      let value = counter.load(Ordering::Relaxed);
+      let previous = self.counters.insert(name, value).unwrap_or(0);
+      metrics.push(format!("{}{}:{}|c", prefix, name, since(value, previous)));
+    }
+
+    for (command, calls, usec) in stats.commands.totals() {
+      let (previous_calls, previous_usec) = self
+        .commands
+        .insert(command.clone(), (calls, usec))
+        .unwrap_or((0, 0));
+      let (calls, usec) = match calls < previous_calls {
+        // CONFIG RESETSTAT
+        true => (calls, usec),
+        false => (calls - previous_calls, usec.saturating_sub(previous_usec)),
+      };
+      if calls == 0 {
+        continue;
+      }
+      let latency = usec as f64 / calls as f64 / 1000.0;
+      let command = metric_name(&command);
+      match options.tags {
+        true => metrics.extend([
+          format!("{}commands.calls:{}|c|#command:{}", prefix, calls, command),
+          format!(
+            "{}commands.latency:{:.3}|ms|#command:{}",
+            prefix, latency, command
+          ),
+        ]),
+        false => metrics.extend([
+          format!("{}commands.{}.calls:{}|c", prefix, command, calls),
+          format!("{}commands.{}.latency:{:.3}|ms", prefix, command, latency),
+        ]),
+      }
+    }
+
+    let memory = allocator::stats();
+    for (name, value) in [
+      ("memory.used", memory.allocated),
+      ("memory.peak", memory.peak_allocated),
+      ("memory.rss", memory.resident),
+    ]
+    .iter()
+    .chain(gauges)
+    {
+      metrics.push(format!("{}{}:{}|g", prefix, name, value));
+    }
+    metrics
+  }
+
+  /// Sends the metrics if `options.interval` passed since the last flush
+  pub async fn flush(&mut self, options: &StatsdOptions, gauges: &[(&str, usize)]) {
+    let now = Instant::now();
+    let Some(last_flush) = self.last_flush else {
+      self.metrics(options, gauges);
+      self.last_flush = Some(now);
+      return;
+    };
+    if now.duration_since(last_flush) < options.interval {
+      return;
+    }
+    self.last_flush = Some(now);
+    let metrics = self.metrics(options, gauges);
+    let target = match tokio::net::lookup_host((options.host.as_str(), options.port)).await {
+      Ok(mut addresses) => addresses.next(),
+      Err(e) => {
+        warn!("StatsD: failed to resolve {}: {}", options.host, e);
+        return;
+      }
+    };
+    let Some(target) = target else {
+      warn!("StatsD: no address for {}", options.host);
+      return;
+    };
+    let socket = match self.socket(target).await {
+      Ok(socket) => socket,
+      Err(e) => {
+        warn!("StatsD: failed to open a socket: {}", e);
+        return;
+      }
+    };
+    for packet in packets(&metrics) {
+      if let Err(e) = socket.send_to(packet.as_bytes(), target).await {
+        warn!("StatsD: failed to send the metrics to {}: {}", target, e);
+        return;
+      }
+    }
+  }
+
+  /// Forgets the last flush, the exporter being turned off
+  pub fn stop(&mut self) {
+    self.last_flush = None;
+    self.socket = None;
+  }
+
+  /// A socket of the family of `target`
+  async fn socket(&mut self, target: SocketAddr) -> std::io::Result<&UdpSocket> {
+    let family_changed = self
+      .socket
+      .as_ref()
+      .and_then(|socket| socket.local_addr().ok())
+      .is_none_or(|local| local.is_ipv6() != target.is_ipv6());
+    if family_changed {
+      let local = match target {
+        SocketAddr::V4(_) => "0.0.0.0:0",
+        SocketAddr::V6(_) => "[::]:0",
+      };
+      self.socket = Some(UdpSocket::bind(local).await?);
+    }
+    Ok(self.socket.as_ref().unwrap())
+  }
+}
+
+/// The change of a counter, all of it when it was reset since
+fn since(value: u64, previous: u64) -> u64 {
+  value.checked_sub(previous).unwrap_or(value)
+}
+
+/// A command name as a metric name, `client|list` as `client_list`
+fn metric_name(command: &str) -> String {
+  command
+    .chars()
+    .map(|c| match c.is_ascii_alphanumeric() || c == '-' {
+      true => c,
+      false => '_',
+    })
+    .collect()
+}
+
+/// The metrics, one per line, in as few packets of at most `MAX_PACKET`
+/// bytes as they fit in
+pub fn packets(metrics: &[String]) -> Vec<String> {
+  let mut packets = Vec::new();
+  let mut packet = String::new();
+  for metric in metrics {
+    if !packet.is_empty() && packet.len() + 1 + metric.len() > MAX_PACKET {
+      packets.push(std::mem::take(&mut packet));
+    }
+    if !packet.is_empty() {
+      packet.push('\n');
+    }
+    packet.push_str(metric);
+  }
+  if !packet.is_empty() {
+    packets.push(packet);
+  }
+  packets
+}
+
+/// Schedules the job flushing the metrics, which reads the `statsd-*`
+/// parameters every time so that CONFIG SET applies them
+pub fn register_statsd_exporter(
+  scheduler: &mut Scheduler,
+  storage: Arc<StorageLock>,
+  config: Arc<AsyncMutex<Config>>,
+  clients: Arc<ClientRegistry>,
+  stats: Arc<Stats>,
+) {
+  let exporter = Arc::new(AsyncMutex::new(StatsdExporter::new(stats)));
+  scheduler.every("statsd", FLUSH_TICK, FLUSH_BUDGET, move || {
+    let storage = storage.clone();
+    let config = config.clone();
+    let clients = clients.clone();
+    let exporter = exporter.clone();
+    async move {
+      let options = StatsdOptions::from_config(&*config.lock().await);
+      let mut exporter = exporter.lock().await;
+      let Some(options) = options else {
+        exporter.stop();
+        return;
+      };
+      let (dataset, keys) = {
+        let storage = storage.lock().await;
+        (storage.used_memory(), storage.len())
+      };
+      let gauges = [
+        ("memory.dataset", dataset),
+        ("memory.clients", clients.memory()),
+        ("clients.connected", clients.len()),
+        ("keys", keys),
+      ];
+      exporter.flush(&options, &gauges).await;
+    }
+  });
+}
@@ -1,54 +1,271 @@
+use crate::changes::{Change, ChangeKind, KeyState, CHANGE_FEED_CAPACITY};
+use crate::clock::{Clock, SystemClock};
+use crate::config::Config;
+use crate::encoding::Value;
+use crate::eviction::{Eviction, EvictionPolicy, OOM_ERROR};
+use crate::expiry::ExpiryIndex;
+use crate::glob::glob_match;
+use crate::hotkeys::HotKeys;
+use crate::keyevents::{KeyEvent, KeyEventReason, KeyEvents};
+use crate::lazyfree::LazyFree;
+use crate::memory::{entry_size, MemoryCounter};
+use crate::negativecache::NegativeCache;
+use crate::rdb;
+use crate::scan::ScanIndex;
+use crate::snapshot::{DatasetSnapshot, Keyspace, Snapshot, Snapshots};
+use crate::stats::Stats;
+use crate::ttlhistogram::{TtlHistogram, TtlSample};
+use bytes::Bytes;
 use dashmap::DashMap;
-use log::info;
-use std::time::Duration;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, mpsc};
 use tokio::time::Instant;
+use tracing::debug;
+
+/// Told about every key modified in the keyspace, `None` for all of them,
+/// see `tracking`
+pub type InvalidationHook = Box<dyn Fn(Option<&str>) + Send + Sync>;
 
 #[derive(Debug)]
 pub struct StorageValue {
   created_at: Instant,
-  value: String,
+  /// Last time the value was read or written, backs OBJECT IDLETIME
+  accessed_at: Instant,
+  value: Value,
   expires_at: Option<Instant>,
+  /// Approximate bytes used by the entry (key, value and overhead)
+  size: usize,
 }
 
 impl StorageValue {
   pub fn new(value: String) -> Self {
+    let now = Instant::now();
     Self {
-      created_at: Instant::now(),
-      value,
+      created_at: now,
+      accessed_at: now,
+      value: Value::new(value),
       expires_at: None,
+      size: 0,
     }
   }
+
+  /// Approximate bytes used by the entry, as last accounted by `Storage`
+  pub fn size(&self) -> usize {
+    self.size
+  }
+
+  /// The value, sharing the stored buffer
+  pub fn bytes(&self) -> Bytes {
+    self.value.to_bytes()
+  }
+
+  /// The value in its encoding, see `encoding`
+  pub(crate) fn encoded(&self) -> &Value {
+    &self.value
+  }
+
+  pub fn expires_at(&self) -> Option<Instant> {
+    self.expires_at
+  }
 }
 
 pub struct Storage {
-  storage: DashMap<String, StorageValue>,
+  storage: Arc<Keyspace>,
+  clock: Arc<dyn Clock>,
+  snapshots: Arc<Snapshots>,
+  memory: MemoryCounter,
+  lazyfree: LazyFree,
+  eviction: Eviction,
+  expiry: ExpiryIndex,
+  scan: ScanIndex,
+  changes: broadcast::Sender<Change>,
+  key_events: KeyEvents,
+  hotkeys: HotKeys,
+  negative: NegativeCache,
+  ttl_histogram: TtlHistogram,
+  invalidation: RwLock<Option<InvalidationHook>>,
+  /// Counters of the server serving the keyspace
+  stats: Arc<Stats>,
 }
 
 impl Storage {
   // Creates a new instance of the Storage struct
   pub fn new() -> Self {
+    Self::with_clock(Arc::new(SystemClock))
+  }
+
+  /// A keyspace reading time from `clock`, see `clock`
+  pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
     Self {
-      storage: DashMap::new(),
+      storage: Arc::new(DashMap::new()),
+      clock,
+      snapshots: Arc::new(Snapshots::default()),
+      memory: MemoryCounter::new(),
+      lazyfree: LazyFree::new(),
+      eviction: Eviction::new(),
+      expiry: ExpiryIndex::new(),
+      scan: ScanIndex::new(),
+      changes: broadcast::channel(CHANGE_FEED_CAPACITY).0,
+      key_events: KeyEvents::new(),
+      hotkeys: HotKeys::new(),
+      negative: NegativeCache::new(),
+      ttl_histogram: TtlHistogram::new(),
+      invalidation: RwLock::new(None),
+      stats: Arc::new(Stats::new()),
+    }
+  }
+
+  /// Counts hits, misses, expired and evicted keys in `stats` from now on
+  pub fn set_stats(&mut self, stats: Arc<Stats>) {
+    self.stats = stats;
+  }
+
+  /// Change feed of the keyspace, see `changes`
+  pub fn subscribe_changes(&self) -> broadcast::Receiver<Change> {
+    self.changes.subscribe()
+  }
+
+  /// Keys the server removes by itself, see `keyevents`
+  pub fn subscribe_key_events(&self) -> mpsc::UnboundedReceiver<KeyEvent> {
+    self.key_events.subscribe()
+  }
+
+  /// Calls `hook` with every key modified from now on, by commands or by the
+  /// server itself, in place of the previous hook
+  pub fn set_invalidation_hook(&self, hook: InvalidationHook) {
+    *self.invalidation.write().unwrap() = Some(hook);
+  }
+
+  fn invalidate(&self, key: Option<&str>) {
+    if let Some(hook) = self.invalidation.read().unwrap().as_ref() {
+      hook(key);
+    }
+  }
+
+  /// Whether anyone subscribed to the change feed, so that changes are only
+  /// built when they'll be read
+  fn watched(&self) -> bool {
+    self.changes.receiver_count() > 0
+  }
+
+  fn publish(
+    &self,
+    kind: ChangeKind,
+    key: &str,
+    before: Option<KeyState>,
+    after: Option<KeyState>,
+  ) {
+    let _ = self.changes.send(Change {
+      kind,
+      key: key.to_string(),
+      before,
+      after,
+    });
+  }
+
+  /// Deadline index of the keys that carry a TTL
+  pub fn expiry_index(&self) -> &ExpiryIndex {
+    &self.expiry
+  }
+
+  /// Current time of the keyspace's clock
+  pub fn now(&self) -> Instant {
+    self.clock.now()
+  }
+
+  /// Wall clock time of a deadline
+  fn wall_clock(&self, deadline: Instant) -> SystemTime {
+    self.clock.system_time() + deadline.saturating_duration_since(self.clock.now())
+  }
+
+  /// Value and deadline of an entry, for the change feed
+  fn state(&self, entry: &StorageValue) -> KeyState {
+    let expires_at = entry.expires_at.map(|deadline| self.wall_clock(deadline));
+    KeyState::new(entry.value.to_string(), expires_at)
+  }
+
+  /// Opens a copy-on-write snapshot of the keyspace, see `snapshot`
+  pub fn begin_snapshot(&self) -> Snapshot {
+    self
+      .snapshots
+      .open(self.storage.clone(), self.clock.as_ref())
+  }
+
+  /// Background freeing of detached values
+  pub fn lazyfree(&self) -> &LazyFree {
+    &self.lazyfree
+  }
+
+  /// The `maxmemory` settings, see `eviction`
+  pub fn eviction(&self) -> &Eviction {
+    &self.eviction
+  }
+
+  /// The most accessed keys, see `hotkeys`
+  pub fn hotkeys(&self) -> &HotKeys {
+    &self.hotkeys
+  }
+
+  /// The filter answering GET misses, see `negativecache`
+  pub fn negative_cache(&self) -> &NegativeCache {
+    &self.negative
+  }
+
+  /// The latest sample of the TTLs, see `ttlhistogram`
+  pub fn ttl_histogram(&self) -> &TtlHistogram {
+    &self.ttl_histogram
+  }
+
+  /// Samples the TTLs of up to `count` keys taken at random, keeping the
+  /// sample as the latest
+  pub fn sample_ttls(&self, count: usize) -> TtlSample {
+    let now = self.clock.now();
+    let mut sample = TtlSample::default();
+    for key in self.scan.sample(count) {
+      if let Some(entry) = self.storage.get(&key) {
+        sample.record(
+          entry
+            .expires_at
+            .map(|deadline| deadline.saturating_duration_since(now)),
+        );
+      }
+    }
+    self.ttl_histogram.update(sample.clone());
+    sample
+  }
+
+  /// Reads `negative-lookup-cache`
+  pub fn apply_negative_cache_config(&self, config: &Config) {
+    self.negative.apply_config(config, &self.storage);
+  }
+
+  /// Adds a key just written to the negative lookup cache
+  fn remember_key(&self, key: &str) {
+    if self.negative.insert(key) {
+      self.negative.rebuild(&self.storage);
     }
   }
 
   /** Creates a new entry to storage */
   pub fn set(&self, key: String, value: String, options: Vec<(String, String)>) {
+    let now = self.clock.now();
     let mut value = StorageValue {
-      value,
-      created_at: Instant::now(),
+      value: Value::new(value),
+      created_at: now,
+      accessed_at: now,
       expires_at: None,
+      size: 0,
     };
 
-    println!("Filtered Options: {:?}", options);
-
     for (argument, argument_value) in options {
       match argument.as_str() {
         "EX" => {
           let duration = match argument_value.parse::<u64>() {
             Ok(d) => d,
             Err(e) => {
-              eprintln!("Failed to parse duration: {}", e);
+              debug!("Failed to parse duration: {}", e);
               continue;
             }
           };
@@ -59,7 +276,7 @@ impl Storage {
           let duration = match argument_value.parse::<u64>() {
             Ok(d) => d,
             Err(e) => {
-              eprintln!("Failed to parse duration: {}", e);
+              debug!("Failed to parse duration: {}", e);
               continue;
             }
           };
@@ -67,62 +284,575 @@ impl Storage {
           value.expires_at = Some(value.created_at + Duration::from_millis(duration));
         }
         _ => {
-          eprintln!("Unknown option: {}", argument);
+          debug!("Unknown option: {}", argument);
         }
       }
     }
 
-    self.storage.insert(key, value);
+    value.size = entry_size(&key, &value.value);
+    let new_size = value.size;
+    let watched = self.watched();
+    let after = watched.then(|| self.state(&value));
+    self.memory.add(&key, new_size);
+
+    match value.expires_at {
+      Some(deadline) => self.expiry.insert(&key, deadline),
+      None => self.expiry.remove(&key),
+    }
+
+    self.hotkeys.record(&key);
+    self.snapshots.preserve(&self.storage, &key);
+    let previous = self.storage.insert(key.clone(), value);
+    if watched {
+      self.publish(
+        ChangeKind::Set,
+        &key,
+        previous.as_ref().map(|previous| self.state(previous)),
+        after,
+      );
+    }
+    self.invalidate(Some(&key));
+    if previous.is_none() {
+      self.scan.insert(&key);
+      self.remember_key(&key);
+    }
+    if let Some(previous) = previous {
+      self.memory.sub(&key, previous.size);
+      let lazy = self
+        .lazyfree
+        .options
+        .lazy_server_del
+        .load(Ordering::Relaxed);
+      let size = previous.size;
+      self.lazyfree.free_sized(previous, size, lazy);
+    }
   }
 
   pub fn remove(&self, key: &str) {
-    self.storage.remove(key);
+    self.delete(key, false, ChangeKind::Del);
   }
 
-  /** Retrieves a value from storage */
-  pub fn get(&self, key: &str) -> Option<String> {
-    self.storage.get(key).and_then(|result| {
-      let now = Instant::now();
-      if let Some(expires_at) = result.expires_at {
-        if expires_at < now {
-          drop(result);
-          self.remove(key);
-          None
+  /// Removes a key, handing its value to the lazyfree thread when `lazy` is set
+  /// and the value is large. Returns whether the key existed.
+  fn delete(&self, key: &str, lazy: bool, kind: ChangeKind) -> bool {
+    self.snapshots.preserve(&self.storage, key);
+    let generation = self.negative.generation();
+    match self.storage.remove(key) {
+      Some((key, value)) => {
+        if self.watched() {
+          self.publish(kind, &key, Some(self.state(&value)), None);
+        }
+        let reason = match kind {
+          ChangeKind::Expired => Some(KeyEventReason::Expired),
+          ChangeKind::Evicted => Some(KeyEventReason::Evicted),
+          _ => None,
+        };
+        if let Some(reason) = reason.filter(|_| self.key_events.watched()) {
+          self.key_events.send(KeyEvent {
+            key: key.clone(),
+            value: self.state(&value),
+            reason,
+          });
+        }
+        self.memory.sub(&key, value.size);
+        self.expiry.remove(&key);
+        self.scan.remove(&key);
+        self.negative.remove(&key, generation);
+        self.invalidate(Some(&key));
+        let size = value.size;
+        self.lazyfree.free_sized(value, size, lazy);
+        true
+      }
+      None => false,
+    }
+  }
+
+  /// Removes an expired key, honoring `lazyfree-lazy-expire`
+  fn expire(&self, key: &str) {
+    let lazy = self.lazyfree.options.lazy_expire.load(Ordering::Relaxed);
+    if self.delete(key, lazy, ChangeKind::Expired) {
+      Stats::incr(&self.stats.expired_keys);
+    }
+  }
+
+  /// Evicts keys while the dataset uses more than `maxmemory`, see
+  /// `eviction`, returning how many. Fails with the OOM error when it's still
+  /// over with nothing left to evict.
+  pub fn perform_evictions(&self) -> Result<usize, String> {
+    let max_memory = self.eviction.max_memory();
+    if max_memory == 0 {
+      return Ok(0);
+    }
+    let _evicting = self.eviction.begin();
+    let lazy = self.lazyfree.options.lazy_eviction.load(Ordering::Relaxed);
+    let mut evicted = 0;
+    while self.used_memory() > max_memory {
+      let Some(key) = self.eviction_candidate() else {
+        return Err(OOM_ERROR.to_string());
+      };
+      if !self.delete(&key, lazy, ChangeKind::Evicted) {
+        return Err(OOM_ERROR.to_string());
+      }
+      Stats::incr(&self.stats.evicted_keys);
+      evicted += 1;
+    }
+    Ok(evicted)
+  }
+
+  /// The next key to evict under `maxmemory-policy`, None when there is none
+  fn eviction_candidate(&self) -> Option<String> {
+    let policy = self.eviction.policy();
+    let samples = self.eviction.samples();
+    match policy {
+      EvictionPolicy::NoEviction => return None,
+      EvictionPolicy::AllKeysRandom => return self.scan.sample(1).pop(),
+      EvictionPolicy::VolatileTtl => return self.expiry.soonest(1).pop().map(|(key, _)| key),
+      _ => {}
+    }
+    // Sampled keys with their last access, only the ones with a deadline for
+    // the volatile policies
+    let accessed = |keys: Vec<String>| -> Vec<(String, Instant)> {
+      keys
+        .into_iter()
+        .filter_map(|key| {
+          let entry = self.storage.get(&key)?;
+          let accessed_at = entry.accessed_at;
+          let eligible = !policy.volatile() || entry.expires_at.is_some();
+          drop(entry);
+          eligible.then_some((key, accessed_at))
+        })
+        .collect()
+    };
+    let mut candidates = accessed(self.scan.sample(samples));
+    if candidates.is_empty() && policy.volatile() {
+      let soonest = self.expiry.soonest(samples);
+      candidates = accessed(soonest.into_iter().map(|(key, _)| key).collect());
+    }
+    match policy {
+      EvictionPolicy::VolatileRandom => candidates.into_iter().next(),
+      _ => candidates
+        .into_iter()
+        .min_by_key(|(_, accessed_at)| *accessed_at),
+    }
+    .map(|(key, _)| key)
+  }
+
+  /// DEL: removes the keys, lazily only when `lazyfree-lazy-user-del` is set
+  pub fn del(&self, keys: &[String]) -> usize {
+    let lazy = self.lazyfree.options.lazy_user_del.load(Ordering::Relaxed);
+    keys
+      .iter()
+      .filter(|key| self.delete(key, lazy, ChangeKind::Del))
+      .count()
+  }
+
+  /// UNLINK: removes the keys and reclaims large values in the background
+  pub fn unlink(&self, keys: &[String]) -> usize {
+    keys
+      .iter()
+      .filter(|key| self.delete(key, true, ChangeKind::Del))
+      .count()
+  }
+
+  /// FLUSHALL: drops the whole keyspace. When `lazy` is set the old keyspace is
+  /// swapped out and freed by the lazyfree thread.
+  pub fn flushall(&mut self, lazy: Option<bool>) {
+    let lazy = lazy.unwrap_or_else(|| {
+      self
+        .lazyfree
+        .options
+        .lazy_user_flush
+        .load(Ordering::Relaxed)
+    });
+    // Open snapshots keep the old keyspace, which won't change anymore
+    self.snapshots.detach();
+    let keyspace = std::mem::take(&mut self.storage);
+    self.memory.reset();
+    self.expiry.clear();
+    self.scan.clear();
+    self.negative.clear();
+    self.invalidate(None);
+    if self.watched() {
+      for entry in keyspace.iter() {
+        self.publish(ChangeKind::Del, entry.key(), Some(self.state(&entry)), None);
+      }
+    }
+
+    if lazy {
+      self.lazyfree.free(keyspace);
+    } else {
+      drop(keyspace);
+    }
+  }
+
+  /// The live keys, to `restore` later: a copy-on-write snapshot that
+  /// copies nothing, see `snapshot`
+  pub fn snapshot(&self) -> DatasetSnapshot {
+    DatasetSnapshot::new(self.begin_snapshot())
+  }
+
+  /// Replaces the keyspace with `snapshot`, as FLUSHALL then writing its keys
+  /// back would. Keys whose deadline passed since are restored, and expire.
+  pub fn restore(&mut self, snapshot: &DatasetSnapshot) {
+    let entries = snapshot.entries();
+    self.flushall(None);
+    let now = self.clock.now();
+    let watched = self.watched();
+    for (key, value, expires_at) in &entries {
+      let size = entry_size(key, value);
+      let entry = StorageValue {
+        created_at: now,
+        accessed_at: now,
+        value: value.clone(),
+        expires_at: *expires_at,
+        size,
+      };
+      if watched {
+        self.publish(ChangeKind::Set, key, None, Some(self.state(&entry)));
+      }
+      self.memory.add(key, size);
+      if let Some(deadline) = expires_at {
+        self.expiry.insert(key, *deadline);
+      }
+      self.scan.insert(key);
+      self.storage.insert(key.clone(), entry);
+      self.remember_key(key);
+    }
+  }
+
+  /// Sets (or clears, with `None`) the deadline of an existing key.
+  /// Returns false when the key does not exist.
+  pub fn set_expiry(&self, key: &str, deadline: Option<Instant>) -> bool {
+    if self.is_expired(key) {
+      self.expire(key);
+      return false;
+    }
+
+    self.snapshots.preserve(&self.storage, key);
+    match self.storage.get_mut(key) {
+      Some(mut entry) => {
+        let before = self.watched().then(|| self.state(&entry));
+        entry.expires_at = deadline;
+        if let Some(before) = before {
+          let after = self.state(&entry);
+          drop(entry);
+          self.publish(ChangeKind::Expire, key, Some(before), Some(after));
+        }
+        match deadline {
+          Some(deadline) => self.expiry.insert(key, deadline),
+          None => self.expiry.remove(key),
+        }
+        self.invalidate(Some(key));
+        true
+      }
+      None => false,
+    }
+  }
+
+  /// The value of a live key, without counting a hit or miss or touching it,
+  /// for inspection
+  pub fn peek(&self, key: &str) -> Option<String> {
+    if !self.exists(key) {
+      return None;
+    }
+    self.storage.get(key).map(|entry| entry.value.to_string())
+  }
+
+  /// Whether a live key exists, without counting a hit or miss
+  pub fn exists(&self, key: &str) -> bool {
+    self.ttl(key).is_some()
+  }
+
+  /// Remaining time to live of a key: `None` when the key does not exist,
+  /// `Some(None)` when it exists without a deadline.
+  pub fn ttl(&self, key: &str) -> Option<Option<Duration>> {
+    if self.is_expired(key) {
+      self.expire(key);
+      return None;
+    }
+
+    self.storage.get(key).map(|entry| {
+      entry
+        .expires_at
+        .map(|deadline| deadline.saturating_duration_since(self.clock.now()))
+    })
+  }
+
+  fn is_expired(&self, key: &str) -> bool {
+    self
+      .storage
+      .get(key)
+      .and_then(|entry| entry.expires_at)
+      .map(|deadline| deadline <= self.clock.now())
+      .unwrap_or(false)
+  }
+
+  /// Reclaims up to `limit` keys whose deadline has passed, returning how many
+  /// were removed
+  pub fn active_expire_cycle(&self, limit: usize) -> usize {
+    let now = self.clock.now();
+    let candidates = self.expiry.pop_expired(now, limit);
+    let mut expired = 0;
+
+    for key in candidates {
+      // The index is only a hint: the key may have been re-set since
+      if self.is_expired(&key) {
+        self.expire(&key);
+        expired += 1;
+      }
+    }
+
+    expired
+  }
+
+  /// Internal encoding of the value stored at `key`
+  pub fn encoding(&self, key: &str) -> Option<&'static str> {
+    if self.is_expired(key) {
+      self.expire(key);
+      return None;
+    }
+    self.storage.get(key).map(|entry| entry.value.encoding())
+  }
+
+  /// DEBUG OBJECT description of the value stored at `key`
+  pub fn debug_object(&self, key: &str) -> Option<String> {
+    if self.is_expired(key) {
+      self.expire(key);
+      return None;
+    }
+    self.storage.get(key).map(|entry| {
+      let value = entry.value.to_string();
+      // LRU clock of the last access: unix seconds on 24 bits, like Redis
+      let idle = self
+        .clock
+        .now()
+        .saturating_duration_since(entry.accessed_at);
+      let lru = self
+        .clock
+        .system_time()
+        .checked_sub(idle)
+        .and_then(|accessed| accessed.duration_since(UNIX_EPOCH).ok())
+        .map(|accessed| accessed.as_secs() % (1 << 24))
+        .unwrap_or(0);
+      format!(
+        "Value at:{:p} refcount:1 encoding:{} serializedlength:{} lru:{} lru_seconds_idle:{}",
+        &entry.value,
+        entry.value.encoding(),
+        rdb::serialized_length(value.as_bytes()),
+        lru,
+        idle.as_secs()
+      )
+    })
+  }
+
+  /// INCRBY: adds `delta` to the integer stored at `key`, creating it when
+  /// missing. The deadline of an existing key is preserved.
+  pub fn incr_by(&self, key: &str, delta: i64) -> Result<i64, String> {
+    if self.is_expired(key) {
+      self.expire(key);
+    }
+
+    // A failed increment leaves the key as preserved
+    self.snapshots.preserve(&self.storage, key);
+    match self.storage.get_mut(key) {
+      Some(mut entry) => {
+        // Canonical integers are always stored with the `int` encoding, so
+        // anything else is not a valid counter
+        let current = entry
+          .value
+          .as_integer()
+          .ok_or_else(|| "value is not an integer or out of range".to_string())?;
+        let next = current
+          .checked_add(delta)
+          .ok_or_else(|| "increment or decrement would overflow".to_string())?;
+
+        let before = self.watched().then(|| self.state(&entry));
+        let old_size = entry.size;
+        entry.value = Value::Int(next);
+        entry.accessed_at = self.clock.now();
+        self.hotkeys.record(key);
+        let new_size = entry_size(key, &entry.value);
+        entry.size = new_size;
+        self.memory.resize(key, old_size, new_size);
+        if let Some(before) = before {
+          let after = self.state(&entry);
+          drop(entry);
+          self.publish(ChangeKind::Set, key, Some(before), Some(after));
         } else {
-          Some(result.value.clone())
+          drop(entry);
         }
-      } else {
-        Some(result.value.clone())
+        self.invalidate(Some(key));
+        Ok(next)
       }
+      None => {
+        self.set(key.to_string(), delta.to_string(), vec![]);
+        Ok(delta)
+      }
+    }
+  }
+
+  /// Time since the key was last accessed. Inspecting it doesn't count as an access.
+  pub fn idle_time(&self, key: &str) -> Option<Duration> {
+    if self.is_expired(key) {
+      self.expire(key);
+      return None;
+    }
+    self.storage.get(key).map(|entry| {
+      self
+        .clock
+        .now()
+        .saturating_duration_since(entry.accessed_at)
     })
   }
 
-  /// Retrieve all the keys that match the pattern
-  pub fn keys(&self, pattern: &str) -> Vec<String> {
-    info!("Extracting keys that match the pattern: {}", pattern);
-
-    match pattern {
-      "" => return vec![],
-      "*" => {
-        return self
-          .storage
-          .iter()
-          .map(|entry| entry.key().clone())
-          .collect();
+  /// Approximate bytes used by a single key, if present
+  pub fn memory_usage(&self, key: &str) -> Option<usize> {
+    self.storage.get(key).map(|entry| entry.size)
+  }
+
+  /// Lines of the `# Keyspace` INFO section, empty while there are no keys
+  pub fn keyspace_info(&self) -> Vec<String> {
+    if self.is_empty() {
+      return vec![];
+    }
+    vec![format!(
+      "db0:keys={},expires={},avg_ttl={}",
+      self.len(),
+      self.expiry.len(),
+      self.expiry.average_ttl(self.clock.now()).as_millis()
+    )]
+  }
+
+  /// Number of keys in the keyspace, including expired keys not reclaimed yet
+  pub fn len(&self) -> usize {
+    self.storage.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.storage.is_empty()
+  }
+
+  /// Approximate bytes used by the whole keyspace
+  pub fn used_memory(&self) -> usize {
+    self.memory.used()
+  }
+
+  /// Approximate bytes used by the SCAN cursor index, on top of
+  /// `used_memory`, see `scan`
+  pub fn scan_index_memory(&self) -> usize {
+    self.scan.memory()
+  }
+
+  /// Per-shard breakdown of the bytes used by the keyspace
+  pub fn used_memory_per_shard(&self) -> Vec<usize> {
+    self.memory.shard_totals()
+  }
+
+  /// Keys of each shard of `used_memory_per_shard`. Walks the whole keyspace,
+  /// for diagnostics only.
+  pub fn keys_per_shard(&self) -> Vec<usize> {
+    let mut keys = vec![0; self.memory.shard_count()];
+    for entry in self.storage.iter() {
+      keys[self.memory.shard_index(entry.key())] += 1;
+    }
+    keys
+  }
+
+  /** Retrieves a value from storage, shared with the keyspace rather than copied */
+  pub fn get(&self, key: &str) -> Option<Bytes> {
+    self.lookup(key, true)
+  }
+
+  /// Retrieves a value, only refreshing its access time when `touch` is set so
+  /// that scans (CLIENT NO-TOUCH) don't disturb the idle time of keys
+  pub fn lookup(&self, key: &str, touch: bool) -> Option<Bytes> {
+    if self.negative.absent(key) {
+      Stats::incr(&self.stats.keyspace_misses);
+      return None;
+    }
+    let entry = self.storage.get_mut(key);
+    if entry.is_none() {
+      self.negative.false_positive();
+    }
+    let value = entry.and_then(|mut result| {
+      let now = self.clock.now();
+      if let Some(expires_at) = result.expires_at {
+        if expires_at <= now {
+          drop(result);
+          self.expire(key);
+          return None;
+        }
       }
-      _ => {
-        return self
-          .storage
-          .iter()
-          .filter_map(|entry| {
-            if entry.key().contains(pattern) {
-              Some(entry.key().clone())
-            } else {
-              None
-            }
-          })
-          .collect();
+      if touch {
+        result.accessed_at = now;
+        self.hotkeys.record(key);
       }
+      Some(result.value.to_bytes())
+    });
+    match value {
+      Some(_) => Stats::incr(&self.stats.keyspace_hits),
+      None => Stats::incr(&self.stats.keyspace_misses),
     }
+    value
+  }
+
+  /// Every live key with its value and absolute deadline, for persistence
+  pub fn string_entries(&self) -> Vec<(String, String, Option<SystemTime>)> {
+    self
+      .entries()
+      .into_iter()
+      .map(|(key, value, expires_at)| {
+        (
+          key,
+          String::from_utf8_lossy(&value).into_owned(),
+          expires_at,
+        )
+      })
+      .collect()
+  }
+
+  /// Like `string_entries`, with handles to the values rather than copies of
+  /// them
+  pub fn entries(&self) -> Vec<(String, Bytes, Option<SystemTime>)> {
+    let now = self.clock.now();
+    let wall_clock = self.clock.system_time();
+    self
+      .storage
+      .iter()
+      .filter(|entry| entry.expires_at.is_none_or(|deadline| deadline > now))
+      .map(|entry| {
+        let expires_at = entry
+          .expires_at
+          .map(|deadline| wall_clock + deadline.duration_since(now));
+        (entry.key().clone(), entry.value.to_bytes(), expires_at)
+      })
+      .collect()
+  }
+
+  /// SCAN: the live keys of the buckets from `cursor` on matching `pattern`,
+  /// and the cursor to continue from, see `scan`
+  pub fn scan(&self, cursor: u64, pattern: Option<&str>, count: usize) -> (u64, Vec<String>) {
+    let (cursor, keys) = self.scan.scan(cursor, count);
+    let keys = keys
+      .into_iter()
+      .filter(|key| self.exists(key) && pattern.is_none_or(|pattern| glob_match(pattern, key)))
+      .collect();
+    (cursor, keys)
+  }
+
+  /// Retrieve all the keys that match the pattern, leaving out the expired
+  /// ones not reclaimed yet
+  pub fn keys(&self, pattern: &str) -> Vec<String> {
+    debug!("Extracting keys that match the pattern: {}", pattern);
+    let keys: Vec<String> = self
+      .storage
+      .iter()
+      .filter(|entry| glob_match(pattern, entry.key()))
+      .map(|entry| entry.key().clone())
+      .collect();
+    keys
+      .into_iter()
+      .filter(|key| !self.is_expired(key))
+      .collect()
   }
 }
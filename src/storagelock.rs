@@ -0,0 +1,73 @@
+/**
+ * The keyspace lock, striped by key so that commands on different keys run
+ * in parallel, across the I/O threads for instance (see `iothreads`).
+ *
+ * `lock` takes the whole keyspace, as the cron jobs, SAVE, FLUSHALL and the
+ * commands without keys do. A command on keys takes the stripes its keys hash
+ * to with `lock_keys` for as long as it runs, and `share`s the keyspace with
+ * the commands on other keys (GET lets go of its key while read-through loads
+ * it). Two commands on the same key still run one after the other. The keyspace is a DashMap and its indexes (memory, expiry,
+ * SCAN...) are updated under locks of their own, which is what makes sharing
+ * it safe.
+ *
+ * Stripes are taken in ascending order, and always before the keyspace: a
+ * command never waits for a stripe while it holds the keyspace, so a pending
+ * `lock` can't deadlock with the commands ahead of it.
+ */
+use crate::storage::Storage;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use tokio::sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Number of stripes, many more than cores so that unrelated keys rarely
+/// share one
+const STRIPES: usize = 1024;
+
+pub struct StorageLock {
+  storage: RwLock<Storage>,
+  stripes: Vec<Mutex<()>>,
+}
+
+/// The stripes of the keys of a command, released when dropped
+pub struct KeyLocks<'a> {
+  _stripes: Vec<MutexGuard<'a, ()>>,
+}
+
+impl StorageLock {
+  pub fn new(storage: Storage) -> Self {
+    Self {
+      storage: RwLock::new(storage),
+      stripes: (0..STRIPES).map(|_| Mutex::new(())).collect(),
+    }
+  }
+
+  /// Locks the whole keyspace
+  pub async fn lock(&self) -> RwLockWriteGuard<'_, Storage> {
+    self.storage.write().await
+  }
+
+  /// The keyspace, shared with the commands on other keys. Keys are only
+  /// read or written under their `lock_keys`, unless it is safe next to any
+  /// command, as evicting keys is.
+  pub async fn share(&self) -> RwLockReadGuard<'_, Storage> {
+    self.storage.read().await
+  }
+
+  /// Locks `keys` against the other commands on them
+  pub async fn lock_keys(&self, keys: &[&str]) -> KeyLocks<'_> {
+    let mut stripes: Vec<usize> = keys.iter().map(|key| stripe(key)).collect();
+    stripes.sort_unstable();
+    stripes.dedup();
+    let mut held = Vec::with_capacity(stripes.len());
+    for index in stripes {
+      held.push(self.stripes[index].lock().await);
+    }
+    KeyLocks { _stripes: held }
+  }
+}
+
+fn stripe(key: &str) -> usize {
+  let mut hasher = DefaultHasher::new();
+  key.hash(&mut hasher);
+  hasher.finish() as usize % STRIPES
+}
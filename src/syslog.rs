@@ -0,0 +1,173 @@
+/**
+ * Logging to the local syslog daemon, for hosts collecting logs only that way.
+ *
+ * With `syslog-enabled yes` every log line is also sent, as an RFC 3164
+ * message tagged `syslog-ident[pid]`, to the daemon's socket (/dev/log, or
+ * /var/run/syslog on macOS) with the `syslog-facility` facility: `user` or
+ * `local0` to `local7`, like Redis. Levels map to syslog severities the way
+ * Redis's do: `debug` and `verbose` are debug and info, `notice` is notice,
+ * warnings are warning and errors err.
+ */
+use crate::arguments::CLIArguments;
+use std::io;
+use tracing::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Sockets the syslog daemon may listen on
+#[cfg(unix)]
+const SYSLOG_SOCKETS: [&str; 3] = ["/dev/log", "/var/run/syslog", "/var/run/log"];
+
+pub const DEFAULT_IDENT: &str = "redis";
+
+/// Values `syslog-facility` accepts
+pub const FACILITIES: [&str; 9] = [
+  "user", "local0", "local1", "local2", "local3", "local4", "local5", "local6", "local7",
+];
+
+/// `syslog-facility`, as its syslog code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Facility(u8);
+
+impl Facility {
+  pub fn parse(value: &str) -> Option<Self> {
+    match value.to_lowercase().as_str() {
+      "user" => Some(Facility(1)),
+      local => {
+        let n = local.strip_prefix("local")?.parse::<u8>().ok()?;
+        (n <= 7).then_some(Facility(16 + n))
+      }
+    }
+  }
+}
+
+impl Default for Facility {
+  fn default() -> Self {
+    Facility(16)
+  }
+}
+
+/// The `syslog-*` settings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyslogOptions {
+  pub ident: String,
+  pub facility: Facility,
+}
+
+impl SyslogOptions {
+  /// The settings when `syslog-enabled` is on. Later directives win, like in
+  /// the configuration.
+  pub fn from_arguments(arguments: &CLIArguments) -> Option<Self> {
+    let directive = |name: &str| {
+      arguments
+        .iter()
+        .rev()
+        .find(|(argument, _)| argument == name)
+        .map(|(_, values)| values.join(" "))
+    };
+    if !directive("syslog-enabled").is_some_and(|value| value.eq_ignore_ascii_case("yes")) {
+      return None;
+    }
+    Some(Self {
+      ident: directive("syslog-ident")
+        .filter(|ident| !ident.is_empty())
+        .unwrap_or_else(|| DEFAULT_IDENT.to_string()),
+      facility: directive("syslog-facility")
+        .and_then(|facility| Facility::parse(&facility))
+        .unwrap_or_default(),
+    })
+  }
+}
+
+/// Syslog severity of a log level
+fn severity(level: &Level) -> u8 {
+  match *level {
+    Level::ERROR => 3,
+    Level::WARN => 4,
+    Level::INFO => 5,
+    Level::DEBUG => 6,
+    _ => 7,
+  }
+}
+
+/// Hands formatted log lines to the syslog daemon, one datagram each
+pub struct Syslog {
+  #[cfg(unix)]
+  socket: std::os::unix::net::UnixDatagram,
+  /// `ident[pid]: `, the tag starting every message
+  tag: String,
+  facility: Facility,
+}
+
+impl Syslog {
+  #[cfg(unix)]
+  pub fn connect(options: &SyslogOptions) -> io::Result<Self> {
+    let socket = std::os::unix::net::UnixDatagram::unbound()?;
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no syslog socket found");
+    for path in SYSLOG_SOCKETS {
+      match socket.connect(path) {
+        Ok(()) => {
+          return Ok(Self {
+            socket,
+            tag: format!("{}[{}]: ", options.ident, std::process::id()),
+            facility: options.facility,
+          })
+        }
+        Err(e) => last_error = e,
+      }
+    }
+    Err(last_error)
+  }
+
+  #[cfg(not(unix))]
+  pub fn connect(_options: &SyslogOptions) -> io::Result<Self> {
+    Err(io::Error::new(
+      io::ErrorKind::Unsupported,
+      "syslog is only supported on Unix",
+    ))
+  }
+
+  fn make_writer_for_level(&self, level: &Level) -> SyslogWriter<'_> {
+    SyslogWriter {
+      syslog: self,
+      priority: self.facility.0 * 8 + severity(level),
+    }
+  }
+
+  fn send(&self, priority: u8, line: &[u8]) {
+    let mut message = format!("<{}>{}", priority, self.tag).into_bytes();
+    message.extend_from_slice(line.trim_ascii_end());
+    // A syslog daemon being restarted must not take the server down, the
+    // line is lost
+    #[cfg(unix)]
+    let _ = self.socket.send(&message);
+  }
+}
+
+/// Writes a single log line with the priority of its event
+pub struct SyslogWriter<'a> {
+  syslog: &'a Syslog,
+  priority: u8,
+}
+
+impl io::Write for SyslogWriter<'_> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.syslog.send(self.priority, buf);
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+impl<'a> MakeWriter<'a> for Syslog {
+  type Writer = SyslogWriter<'a>;
+
+  fn make_writer(&'a self) -> Self::Writer {
+    self.make_writer_for_level(&Level::INFO)
+  }
+
+  fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+    self.make_writer_for_level(meta.level())
+  }
+}
@@ -0,0 +1,183 @@
+/**
+ * Tracing of the command lifecycle, exported with OpenTelemetry.
+ *
+ * Every command runs in a `command` span, child of the `connection` span,
+ * named after the command and carrying the client id. It covers the whole
+ * round trip, broken down in `parse`, `dispatch`, `storage` (waiting for and
+ * holding the keyspace lock) and `reply` (writing the response) spans, so a
+ * slow command shows where its time went. The spans are at info level: they
+ * are recorded unless `loglevel` is `warning` or `nothing`.
+ *
+ * With `otlp-endpoint` set, e.g. `--otlp-endpoint http://localhost:4317`, the
+ * spans are exported over OTLP/gRPC to an OpenTelemetry collector. The
+ * exporter is behind the `otlp` cargo feature, off by default:
+ * `cargo build --features otlp`.
+ *
+ * Applications can tie their commands to their own traces by handing their
+ * W3C trace context to the connection with
+ * `CLIENT SETINFO TRACEPARENT 00-<trace-id>-<span-id>-<flags>`: the commands
+ * that follow are recorded with that `trace_id`, and exported as children of
+ * the application's span.
+ */
+use crate::storage::Storage;
+use crate::storagelock::StorageLock;
+use std::ops::{Deref, DerefMut};
+use tokio::sync::{RwLockReadGuard, RwLockWriteGuard};
+use tracing::{Instrument, Span};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// `service.name` of the exported spans
+#[cfg(feature = "otlp")]
+const SERVICE_NAME: &str = "redis";
+
+/// The trace id of a W3C `traceparent`: `<version>-<trace-id>-<parent-id>-<flags>`,
+/// in lowercase hex, with neither id all zeros
+pub fn parse_traceparent(traceparent: &str) -> Option<&str> {
+  let hex = |part: &str, len: usize| {
+    part.len() == len && part.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+  };
+  let zero = |part: &str| part.bytes().all(|b| b == b'0');
+  match traceparent.split('-').collect::<Vec<&str>>()[..] {
+    [version, trace_id, parent_id, flags]
+      if hex(version, 2)
+        && version != "ff"
+        && hex(trace_id, 32)
+        && !zero(trace_id)
+        && hex(parent_id, 16)
+        && !zero(parent_id)
+        && hex(flags, 2) =>
+    {
+      Some(trace_id)
+    }
+    _ => None,
+  }
+}
+
+/// The span of a command from client `client_id`, a child of the application
+/// span given with CLIENT SETINFO TRACEPARENT, if any. The command name is
+/// recorded once parsed.
+pub fn command_span(client_id: u64, traceparent: Option<&str>) -> Span {
+  let span = tracing::info_span!(
+    "command",
+    otel.name = tracing::field::Empty,
+    otel.kind = "server",
+    otel.status_code = tracing::field::Empty,
+    db.system = "redis",
+    client.id = client_id,
+    trace_id = tracing::field::Empty,
+  );
+  if let Some(traceparent) = traceparent {
+    if let Some(trace_id) = parse_traceparent(traceparent) {
+      span.record("trace_id", trace_id);
+    }
+    #[cfg(feature = "otlp")]
+    set_parent(&span, traceparent);
+  }
+  span
+}
+
+/// Marks the command of `span` as failed, when it replied with an error
+pub fn record_error(span: &Span) {
+  span.record("otel.status_code", "ERROR");
+}
+
+#[cfg(feature = "otlp")]
+fn set_parent(span: &Span, traceparent: &str) {
+  use opentelemetry::propagation::TextMapPropagator;
+  use opentelemetry_sdk::propagation::TraceContextPropagator;
+  use std::collections::HashMap;
+  use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+  let carrier = HashMap::from([("traceparent".to_string(), traceparent.to_string())]);
+  span.set_parent(TraceContextPropagator::new().extract(&carrier));
+}
+
+/// A lock guard recording the time it is held in a span
+pub struct Traced<G> {
+  guard: G,
+  _span: Span,
+}
+
+impl<G: Deref> Deref for Traced<G> {
+  type Target = G::Target;
+
+  fn deref(&self) -> &Self::Target {
+    &self.guard
+  }
+}
+
+impl<G: DerefMut> DerefMut for Traced<G> {
+  fn deref_mut(&mut self) -> &mut Self::Target {
+    &mut self.guard
+  }
+}
+
+/// Locks the keyspace for the command of `command`, in a `storage` span
+/// lasting until the lock is released
+pub async fn lock_storage<'a>(
+  storage: &'a StorageLock,
+  command: &Span,
+) -> Traced<RwLockWriteGuard<'a, Storage>> {
+  let span = tracing::info_span!(parent: command, "storage");
+  let guard = storage.lock().instrument(span.clone()).await;
+  Traced { guard, _span: span }
+}
+
+/// `lock_storage` for a command holding its keys, sharing the keyspace with
+/// the commands on other keys (see `StorageLock`)
+pub async fn share_storage<'a>(
+  storage: &'a StorageLock,
+  command: &Span,
+) -> Traced<RwLockReadGuard<'a, Storage>> {
+  let span = tracing::info_span!(parent: command, "storage");
+  let guard = storage.share().instrument(span.clone()).await;
+  Traced { guard, _span: span }
+}
+
+/// The layer exporting spans to the OTLP collector at `endpoint`, when set.
+/// Must be called within the Tokio runtime, which runs the exporter.
+#[cfg(feature = "otlp")]
+pub fn layer<S>(endpoint: Option<&str>) -> Result<Option<impl Layer<S>>, String>
+where
+  S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+{
+  use opentelemetry::KeyValue;
+  use opentelemetry_otlp::WithExportConfig;
+  use opentelemetry_sdk::Resource;
+
+  let Some(endpoint) = endpoint else {
+    return Ok(None);
+  };
+  let tracer = opentelemetry_otlp::new_pipeline()
+    .tracing()
+    .with_exporter(
+      opentelemetry_otlp::new_exporter()
+        .tonic()
+        .with_endpoint(endpoint),
+    )
+    .with_trace_config(
+      opentelemetry_sdk::trace::config()
+        .with_resource(Resource::new([KeyValue::new("service.name", SERVICE_NAME)])),
+    )
+    .install_batch(opentelemetry_sdk::runtime::Tokio)
+    .map_err(|e| format!("Can't set up the OTLP exporter: {}", e))?;
+  Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+}
+
+#[cfg(not(feature = "otlp"))]
+pub fn layer<S>(endpoint: Option<&str>) -> Result<Option<impl Layer<S>>, String>
+where
+  S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+{
+  match endpoint {
+    Some(_) => Err("otlp-endpoint needs a server built with the otlp feature".to_string()),
+    None => Ok(None::<tracing_subscriber::layer::Identity>),
+  }
+}
+
+/// Sends the spans still buffered before exiting
+pub fn shutdown() {
+  #[cfg(feature = "otlp")]
+  opentelemetry::global::shutdown_tracer_provider();
+}
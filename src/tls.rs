@@ -0,0 +1,183 @@
+/**
+ * TLS for client connections, with rustls.
+ *
+ * Setting `tls-port` opens TLS listeners on the `bind` addresses next to the
+ * plain ones (`port 0` leaves only TLS). The server presents `tls-cert-file`
+ * with the key in `tls-key-file`, both PEM encoded.
+ *
+ * Client certificates are verified against `tls-ca-cert-file`. Like in Redis,
+ * `tls-auth-clients` decides whether they are required (`yes`, the default once
+ * a CA is configured), checked when presented (`optional`) or not asked for
+ * (`no`). With `tls-auth-clients-user` set to `CN` or `SAN`, a client whose
+ * certificate names an enabled ACL user is logged in as that user, without
+ * AUTH, so mTLS deployments don't need passwords.
+ */
+use crate::acl::Acl;
+use crate::config::Config;
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{RootCertStore, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+use x509_parser::extensions::GeneralName;
+
+/// `tls-auth-clients`: how client certificates are handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientAuth {
+  No,
+  Optional,
+  Required,
+}
+
+impl ClientAuth {
+  pub fn from_config(config: &Config) -> Result<Self, String> {
+    match config.get("tls-auth-clients") {
+      None if config.has("tls-ca-cert-file") => Ok(ClientAuth::Required),
+      None => Ok(ClientAuth::No),
+      Some(value) => match value.to_lowercase().as_str() {
+        "yes" => Ok(ClientAuth::Required),
+        "optional" => Ok(ClientAuth::Optional),
+        "no" => Ok(ClientAuth::No),
+        _ => Err(format!("Invalid tls-auth-clients: {}", value)),
+      },
+    }
+  }
+}
+
+/// `tls-auth-clients-user`: the certificate field naming the ACL user
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CertificateUser {
+  Off,
+  CommonName,
+  SubjectAltName,
+}
+
+impl CertificateUser {
+  pub fn from_config(config: &Config) -> Result<Self, String> {
+    match config.get("tls-auth-clients-user") {
+      None => Ok(CertificateUser::Off),
+      Some(value) => match value.to_uppercase().as_str() {
+        "OFF" => Ok(CertificateUser::Off),
+        "CN" => Ok(CertificateUser::CommonName),
+        "SAN" => Ok(CertificateUser::SubjectAltName),
+        _ => Err(format!("Invalid tls-auth-clients-user: {}", value)),
+      },
+    }
+  }
+
+  /// User names the certificate offers, in order of preference
+  fn candidates(&self, certificate: &CertificateDer) -> Vec<String> {
+    let Ok((_, certificate)) = x509_parser::parse_x509_certificate(certificate) else {
+      return vec![];
+    };
+    match self {
+      CertificateUser::Off => vec![],
+      CertificateUser::CommonName => certificate
+        .subject()
+        .iter_common_name()
+        .filter_map(|name| name.as_str().ok())
+        .map(str::to_string)
+        .collect(),
+      CertificateUser::SubjectAltName => match certificate.subject_alternative_name() {
+        Ok(Some(names)) => names
+          .value
+          .general_names
+          .iter()
+          .filter_map(|name| match name {
+            GeneralName::DNSName(name) | GeneralName::RFC822Name(name) | GeneralName::URI(name) => {
+              Some(name.to_string())
+            }
+            _ => None,
+          })
+          .collect(),
+        _ => vec![],
+      },
+    }
+  }
+}
+
+/// The ACL user a verified client certificate logs in as, if any
+pub fn certificate_user(
+  certificate: &CertificateDer,
+  config: &Config,
+  acl: &Acl,
+) -> Option<String> {
+  let field = CertificateUser::from_config(config).ok()?;
+  field
+    .candidates(certificate)
+    .into_iter()
+    .find(|user| acl.user_enabled(user))
+}
+
+/// `tls-port` from the configuration, `None` when TLS is off
+pub fn tls_port(config: &Config) -> Result<Option<u16>, String> {
+  match config.get("tls-port") {
+    None => Ok(None),
+    Some(port) => match port.parse::<u16>() {
+      Ok(0) => Ok(None),
+      Ok(port) => Ok(Some(port)),
+      Err(_) => Err(format!("Invalid tls-port: {}", port)),
+    },
+  }
+}
+
+fn load_certificates(path: &str) -> Result<Vec<CertificateDer<'static>>, String> {
+  let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+  let certificates = rustls_pemfile::certs(&mut BufReader::new(file))
+    .collect::<Result<Vec<_>, _>>()
+    .map_err(|e| format!("{}: {}", path, e))?;
+  if certificates.is_empty() {
+    return Err(format!("{}: no certificate found", path));
+  }
+  Ok(certificates)
+}
+
+fn load_private_key(path: &str) -> Result<PrivateKeyDer<'static>, String> {
+  let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+  rustls_pemfile::private_key(&mut BufReader::new(file))
+    .map_err(|e| format!("{}: {}", path, e))?
+    .ok_or_else(|| format!("{}: no private key found", path))
+}
+
+/// Builds the acceptor wrapping TLS connections from the `tls-*` settings
+pub fn load_acceptor(config: &Config) -> Result<TlsAcceptor, String> {
+  let certificate_file = config
+    .get("tls-cert-file")
+    .ok_or("tls-port requires tls-cert-file")?;
+  let key_file = config
+    .get("tls-key-file")
+    .ok_or("tls-port requires tls-key-file")?;
+  let certificates = load_certificates(&certificate_file)?;
+  let key = load_private_key(&key_file)?;
+
+  let client_auth = ClientAuth::from_config(config)?;
+  CertificateUser::from_config(config)?;
+
+  let builder = ServerConfig::builder();
+  let builder = match (client_auth, config.get("tls-ca-cert-file")) {
+    (ClientAuth::No, _) => builder.with_no_client_auth(),
+    (_, None) => return Err("tls-auth-clients requires tls-ca-cert-file".to_string()),
+    (client_auth, Some(ca_file)) => {
+      let mut roots = RootCertStore::empty();
+      for certificate in load_certificates(&ca_file)? {
+        roots
+          .add(certificate)
+          .map_err(|e| format!("{}: {}", ca_file, e))?;
+      }
+      let mut verifier = WebPkiClientVerifier::builder(Arc::new(roots));
+      if client_auth == ClientAuth::Optional {
+        verifier = verifier.allow_unauthenticated();
+      }
+      let verifier = verifier
+        .build()
+        .map_err(|e| format!("{}: {}", ca_file, e))?;
+      builder.with_client_cert_verifier(verifier)
+    }
+  };
+  let server_config = builder
+    .with_single_cert(certificates, key)
+    .map_err(|e| format!("Invalid TLS certificate or key: {}", e))?;
+  Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
@@ -0,0 +1,448 @@
+/**
+ * CLIENT TRACKING: server-assisted client-side caching.
+ *
+ * In the default mode the server remembers which keys each tracking client read,
+ * and when one of them is modified every client that may have it cached gets an
+ * invalidation message, after which the key is forgotten until it is read again.
+ * In BCAST mode nothing is remembered: clients subscribe to key prefixes and are
+ * told about every modified key that starts with one of them.
+ *
+ * A connection that switched to RESP3 with HELLO 3 gets its invalidations as
+ * `invalidate` push messages, on the connection itself or on the client named
+ * by REDIRECT. A RESP2 connection can only get them through a redirection, as
+ * `__redis__:invalidate` pub/sub messages to the client named by REDIRECT, like
+ * Redis does; without one there is no way to deliver them and they are
+ * dropped.
+ *
+ * The keyspace reports every key it modifies (see
+ * `Storage::set_invalidation_hook`): written by a command, expired, evicted,
+ * flushed, or changed through the admin API. The client whose command is
+ * running is known from `run_as`, for NOLOOP; changes the server makes by
+ * itself are announced to every client.
+ */
+use crate::clients::ClientRegistry;
+use crate::parser::{serialize_resp3, serialize_response, RedisValue};
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+pub const INVALIDATE_CHANNEL: &str = "__redis__:invalidate";
+
+/// Client id of the changes the server makes by itself
+const SERVER: u64 = 0;
+
+tokio::task_local! {
+  /// The client whose command is running
+  static CURRENT_CLIENT: u64;
+}
+
+/// Runs `command`, a command of client `id`, so that the keys it modifies are
+/// known to be modified by that client
+pub async fn run_as<F: Future>(id: u64, command: F) -> F::Output {
+  CURRENT_CLIENT.scope(id, command).await
+}
+
+/// How a client tracks keys, as set by CLIENT TRACKING ON
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrackingOptions {
+  /// Client receiving the invalidation messages
+  pub redirect: Option<u64>,
+  pub bcast: bool,
+  pub prefixes: Vec<String>,
+  /// Only keys read right after CLIENT CACHING YES are tracked
+  pub optin: bool,
+  /// Keys read right after CLIENT CACHING NO are not tracked
+  pub optout: bool,
+  /// Don't notify the client about keys it modified itself
+  pub noloop: bool,
+  /// CLIENT CACHING answer for the next command
+  pub caching: Option<bool>,
+  /// The redirection client went away
+  pub broken_redirect: bool,
+}
+
+impl TrackingOptions {
+  /// Parses CLIENT TRACKING ON|OFF [REDIRECT id] [PREFIX prefix ...] [BCAST]
+  /// [OPTIN] [OPTOUT] [NOLOOP]. Returns `None` for OFF.
+  pub fn parse(args: &[String]) -> Result<Option<Self>, String> {
+    let Some((state, options)) = args.split_first() else {
+      return Err("ERR wrong number of arguments for 'client|tracking' command".to_string());
+    };
+    let on = match state.to_uppercase().as_str() {
+      "ON" => true,
+      "OFF" => false,
+      _ => return Err("ERR syntax error".to_string()),
+    };
+
+    let mut tracking = TrackingOptions::default();
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+      match option.to_uppercase().as_str() {
+        "REDIRECT" => match options.next().map(|id| id.parse::<u64>()) {
+          Some(Ok(id)) => tracking.redirect = Some(id),
+          Some(Err(_)) => return Err("ERR Invalid client ID".to_string()),
+          None => return Err("ERR syntax error".to_string()),
+        },
+        "PREFIX" => match options.next() {
+          Some(prefix) => tracking.prefixes.push(prefix.clone()),
+          None => return Err("ERR syntax error".to_string()),
+        },
+        "BCAST" => tracking.bcast = true,
+        "OPTIN" => tracking.optin = true,
+        "OPTOUT" => tracking.optout = true,
+        "NOLOOP" => tracking.noloop = true,
+        _ => return Err("ERR syntax error".to_string()),
+      }
+    }
+
+    if !on {
+      return Ok(None);
+    }
+    if !tracking.bcast && !tracking.prefixes.is_empty() {
+      return Err("ERR PREFIX option requires BCAST mode to be enabled".to_string());
+    }
+    if tracking.optin && tracking.optout {
+      return Err("ERR You can't use both OPTIN and OPTOUT".to_string());
+    }
+    if tracking.bcast && (tracking.optin || tracking.optout) {
+      return Err("ERR OPTIN and OPTOUT are not compatible with BCAST".to_string());
+    }
+    for (i, prefix) in tracking.prefixes.iter().enumerate() {
+      for other in &tracking.prefixes[i + 1..] {
+        if prefix.starts_with(other.as_str()) || other.starts_with(prefix.as_str()) {
+          return Err(format!(
+            "ERR Prefix '{}' overlaps with another provided prefix '{}'. Prefixes for a single client must not overlap.",
+            prefix, other
+          ));
+        }
+      }
+    }
+    // BCAST without prefixes means every key
+    if tracking.bcast && tracking.prefixes.is_empty() {
+      tracking.prefixes.push(String::new());
+    }
+    Ok(Some(tracking))
+  }
+
+  /// Whether keys read by the current command should be remembered
+  fn tracks_reads(&self) -> bool {
+    if self.bcast {
+      return false;
+    }
+    if self.optin {
+      return self.caching == Some(true);
+    }
+    if self.optout {
+      return self.caching != Some(false);
+    }
+    true
+  }
+
+  /// Flags reported by CLIENT TRACKINGINFO
+  fn flags(&self) -> Vec<String> {
+    let mut flags = vec!["on".to_string()];
+    if self.bcast {
+      flags.push("bcast".to_string());
+    }
+    if self.optin {
+      flags.push("optin".to_string());
+    }
+    if self.optout {
+      flags.push("optout".to_string());
+    }
+    match self.caching {
+      Some(true) => flags.push("caching-yes".to_string()),
+      Some(false) => flags.push("caching-no".to_string()),
+      None => {}
+    }
+    if self.noloop {
+      flags.push("noloop".to_string());
+    }
+    if self.broken_redirect {
+      flags.push("broken_redirect".to_string());
+    }
+    flags
+  }
+}
+
+/// Which clients may have cached which keys
+#[derive(Default)]
+pub struct TrackingTable {
+  /// Keys read by clients in the default mode
+  keys: Mutex<HashMap<String, HashSet<u64>>>,
+  /// BCAST prefixes and the clients registered for them
+  prefixes: Mutex<HashMap<String, HashSet<u64>>>,
+  /// Number of clients with tracking on, to skip all of this when there is none
+  tracking_clients: AtomicUsize,
+}
+
+impl TrackingTable {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Number of clients with tracking turned on
+  pub fn len(&self) -> usize {
+    self.tracking_clients.load(Ordering::Relaxed)
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// Number of keys remembered in the default mode
+  pub fn tracked_keys(&self) -> usize {
+    self.keys.lock().unwrap().len()
+  }
+
+  /// Forgets everything about a client
+  fn forget(&self, id: u64) {
+    self.keys.lock().unwrap().retain(|_, clients| {
+      clients.remove(&id);
+      !clients.is_empty()
+    });
+    self.prefixes.lock().unwrap().retain(|_, clients| {
+      clients.remove(&id);
+      !clients.is_empty()
+    });
+  }
+}
+
+/// The invalidation message in protocol version `resp`, `None` invalidating
+/// every key
+fn invalidation(keys: Option<&[String]>, resp: u8) -> Vec<u8> {
+  let payload = match keys {
+    Some(keys) => RedisValue::Array(keys.to_vec()),
+    None => RedisValue::BulkString(None),
+  };
+  match resp {
+    3 => serialize_resp3(RedisValue::Push(vec![
+      RedisValue::BulkString(Some("invalidate".to_string())),
+      payload,
+    ])),
+    _ => serialize_response(RedisValue::Nested(vec![
+      RedisValue::BulkString(Some("message".to_string())),
+      RedisValue::BulkString(Some(INVALIDATE_CHANNEL.to_string())),
+      payload,
+    ])),
+  }
+}
+
+impl ClientRegistry {
+  /// CLIENT TRACKING ON|OFF ...
+  pub fn tracking_command(&self, id: u64, args: &[String]) -> RedisValue {
+    let options = match TrackingOptions::parse(args) {
+      Ok(options) => options,
+      Err(e) => return RedisValue::Error(e),
+    };
+    if let Some(redirect) = options.as_ref().and_then(|options| options.redirect) {
+      if redirect != id && self.get(redirect).is_none() {
+        return RedisValue::Error(
+          "ERR The client ID you want redirect to does not exist".to_string(),
+        );
+      }
+    }
+
+    let table = self.tracking_table();
+    let was_tracking = self.update(id, |client| client.tracking.is_some()) == Some(true);
+    table.forget(id);
+    match options {
+      Some(options) => {
+        let mut prefixes = table.prefixes.lock().unwrap();
+        for prefix in &options.prefixes {
+          prefixes.entry(prefix.clone()).or_default().insert(id);
+        }
+        drop(prefixes);
+        self.update(id, |client| client.tracking = Some(options));
+        if !was_tracking {
+          table.tracking_clients.fetch_add(1, Ordering::Relaxed);
+        }
+      }
+      None => {
+        self.update(id, |client| client.tracking = None);
+        if was_tracking {
+          table.tracking_clients.fetch_sub(1, Ordering::Relaxed);
+        }
+      }
+    }
+    RedisValue::SimpleString("OK".to_string())
+  }
+
+  /// CLIENT CACHING YES|NO
+  pub fn caching_command(&self, id: u64, args: &[String]) -> RedisValue {
+    let caching = match args {
+      [value] if value.eq_ignore_ascii_case("yes") => true,
+      [value] if value.eq_ignore_ascii_case("no") => false,
+      [_] => return RedisValue::Error("ERR syntax error".to_string()),
+      _ => {
+        return RedisValue::Error(
+          "ERR wrong number of arguments for 'client|caching' command".to_string(),
+        )
+      }
+    };
+    let applied = self.update(id, |client| match client.tracking.as_mut() {
+      Some(tracking) if (caching && tracking.optin) || (!caching && tracking.optout) => {
+        tracking.caching = Some(caching);
+        true
+      }
+      _ => false,
+    });
+    if applied == Some(true) {
+      RedisValue::SimpleString("OK".to_string())
+    } else if caching {
+      RedisValue::Error(
+        "ERR CLIENT CACHING YES is only valid when tracking is enabled in OPTIN mode.".to_string(),
+      )
+    } else {
+      RedisValue::Error(
+        "ERR CLIENT CACHING NO is only valid when tracking is enabled in OPTOUT mode.".to_string(),
+      )
+    }
+  }
+
+  /// CLIENT GETREDIR: -1 when not tracking, 0 when not redirecting
+  pub fn getredir_command(&self, id: u64) -> RedisValue {
+    let redirect = self
+      .get(id)
+      .and_then(|client| client.tracking)
+      .map(|tracking| tracking.redirect.unwrap_or(0) as i64)
+      .unwrap_or(-1);
+    RedisValue::Integer(redirect)
+  }
+
+  /// CLIENT TRACKINGINFO
+  pub fn trackinginfo_command(&self, id: u64) -> RedisValue {
+    let tracking = self.get(id).and_then(|client| client.tracking);
+    let bulk = |s: &str| RedisValue::BulkString(Some(s.to_string()));
+    let (flags, redirect, prefixes) = match tracking {
+      Some(tracking) => {
+        let prefixes = if tracking.bcast {
+          tracking.prefixes.clone()
+        } else {
+          vec![]
+        };
+        (
+          tracking.flags(),
+          tracking.redirect.map(|id| id as i64).unwrap_or(0),
+          prefixes,
+        )
+      }
+      None => (vec!["off".to_string()], -1, vec![]),
+    };
+    RedisValue::Nested(vec![
+      bulk("flags"),
+      RedisValue::Array(flags),
+      bulk("redirect"),
+      RedisValue::Integer(redirect),
+      bulk("prefixes"),
+      RedisValue::Array(prefixes),
+    ])
+  }
+
+  /// Remembers the keys a tracking client just read, and consumes its CLIENT
+  /// CACHING answer unless the command was CLIENT CACHING itself
+  pub fn track_reads(&self, id: u64, command: &str, keys: &[String]) {
+    let tracked = self.update(id, |client| {
+      let tracking = client.tracking.as_mut()?;
+      let tracked = tracking.tracks_reads();
+      if command != "client|caching" {
+        tracking.caching = None;
+      }
+      Some(tracked)
+    });
+    if tracked.flatten() != Some(true) {
+      return;
+    }
+    let mut table = self.tracking_table().keys.lock().unwrap();
+    for key in keys {
+      table.entry(key.clone()).or_default().insert(id);
+    }
+  }
+
+  /// Tells the clients that may have cached `keys` that they were modified by
+  /// client `writer`. `None` means every key, after a flush.
+  pub fn invalidate(&self, writer: u64, keys: Option<&[String]>) {
+    let table = self.tracking_table();
+    let mut recipients: HashMap<u64, Vec<String>> = HashMap::new();
+    let mut everyone: HashSet<u64> = HashSet::new();
+
+    match keys {
+      Some(keys) => {
+        let mut tracked = table.keys.lock().unwrap();
+        for key in keys {
+          for client in tracked.remove(key).unwrap_or_default() {
+            recipients.entry(client).or_default().push(key.clone());
+          }
+        }
+        drop(tracked);
+        let prefixes = table.prefixes.lock().unwrap();
+        for (prefix, clients) in prefixes.iter() {
+          for key in keys.iter().filter(|key| key.starts_with(prefix.as_str())) {
+            for client in clients {
+              recipients.entry(*client).or_default().push(key.clone());
+            }
+          }
+        }
+      }
+      None => {
+        let mut tracked = table.keys.lock().unwrap();
+        everyone.extend(tracked.values().flatten());
+        tracked.clear();
+        drop(tracked);
+        everyone.extend(table.prefixes.lock().unwrap().values().flatten());
+      }
+    }
+
+    let messages = recipients
+      .into_iter()
+      .map(|(client, mut keys)| {
+        keys.dedup();
+        (client, Some(keys))
+      })
+      .chain(everyone.into_iter().map(|client| (client, None)));
+
+    for (client, keys) in messages {
+      let Some(tracking) = self.get(client).and_then(|client| client.tracking) else {
+        continue;
+      };
+      if tracking.noloop && client == writer {
+        continue;
+      }
+      // RESP2 connections can only get invalidations through a redirection
+      let recipient = tracking.redirect.unwrap_or(client);
+      let resp = self.resp(recipient);
+      if tracking.redirect.is_none() && resp != 3 {
+        continue;
+      }
+      let message = invalidation(keys.as_deref(), resp);
+      if !self.push(recipient, message) && tracking.redirect.is_some() {
+        self.update(client, |client| {
+          if let Some(tracking) = client.tracking.as_mut() {
+            tracking.broken_redirect = true;
+          }
+        });
+      }
+    }
+  }
+
+  /// Invalidates a key the keyspace modified, `None` for all of them, on
+  /// behalf of the client whose command is running if any
+  pub fn key_modified(&self, key: Option<&str>) {
+    if self.tracking_table().is_empty() {
+      return;
+    }
+    let writer = CURRENT_CLIENT.try_with(|id| *id).unwrap_or(SERVER);
+    let keys = key.map(|key| [key.to_string()]);
+    self.invalidate(writer, keys.as_ref().map(|keys| &keys[..]));
+  }
+
+  /// Drops the tracking state of a client that turned tracking off or left
+  pub fn stop_tracking(&self, id: u64) {
+    let table = self.tracking_table();
+    table.forget(id);
+    if self.update(id, |client| client.tracking.take().is_some()) == Some(true) {
+      table.tracking_clients.fetch_sub(1, Ordering::Relaxed);
+    }
+  }
+}
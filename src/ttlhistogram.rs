@@ -0,0 +1,149 @@
+/**
+ * The TTL histogram: how long the keys have left to live, for capacity
+ * planning, to tell a dataset about to expire in bulk from one full of keys
+ * that never will.
+ *
+ * Every `SAMPLE_INTERVAL` a job samples up to `SAMPLE_KEYS` keys at random,
+ * all of them in a smaller keyspace, and sorts the ones with a TTL into
+ * buckets of time left, from under a minute to over 30 days. The latest
+ * sample is reported by the `ttl` INFO section and by the `/metrics` endpoint
+ * of the HTTP admin API as a Prometheus histogram, its buckets cumulative in
+ * both:
+ *
+ * ```text
+ * ttl_sampled_keys:1000
+ * ttl_persistent_keys:250
+ * ttl_histogram:le_60=12,le_600=40,le_3600=310,le_21600=600,le_86400=700,le_604800=745,le_2592000=750,le_inf=750
+ * ttl_sum_seconds:40392000
+ * ```
+ *
+ * A key past its deadline but not reclaimed yet counts as expiring now.
+ */
+use crate::cron::Scheduler;
+use crate::storagelock::StorageLock;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How often the keys are sampled
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Keys a sample looks at
+pub const SAMPLE_KEYS: usize = 1000;
+
+/// Time a sample may take before it counts as an overrun
+const SAMPLE_BUDGET: Duration = Duration::from_millis(10);
+
+/// Upper bounds of the buckets in seconds: a minute, ten minutes, an hour, six
+/// hours, a day, a week and 30 days, the last bucket being unbounded
+pub const BUCKETS: [u64; 7] = [60, 600, 3600, 21_600, 86_400, 604_800, 2_592_000];
+
+/// The keys of a sample, by time left
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TtlSample {
+  /// Keys sampled, with or without a TTL
+  pub keys: u64,
+  /// Keys without a TTL
+  pub persistent: u64,
+  /// Keys with a TTL of at most each of `BUCKETS`
+  pub buckets: [u64; BUCKETS.len()],
+  /// Keys with a TTL
+  pub volatile: u64,
+  /// Sum of the TTLs
+  pub sum: Duration,
+}
+
+impl TtlSample {
+  /// Counts a key, with its time left if it has a TTL
+  pub fn record(&mut self, ttl: Option<Duration>) {
+    self.keys += 1;
+    let Some(ttl) = ttl else {
+      self.persistent += 1;
+      return;
+    };
+    self.volatile += 1;
+    self.sum += ttl;
+    for (bound, count) in BUCKETS.iter().zip(self.buckets.iter_mut()) {
+      if ttl <= Duration::from_secs(*bound) {
+        *count += 1;
+      }
+    }
+  }
+
+  /// Lines of the `# Ttl` INFO section
+  pub fn info(&self) -> Vec<String> {
+    let buckets: Vec<String> = BUCKETS
+      .iter()
+      .zip(self.buckets)
+      .map(|(bound, count)| format!("le_{}={}", bound, count))
+      .chain([format!("le_inf={}", self.volatile)])
+      .collect();
+    vec![
+      format!("ttl_sampled_keys:{}", self.keys),
+      format!("ttl_persistent_keys:{}", self.persistent),
+      format!("ttl_histogram:{}", buckets.join(",")),
+      format!("ttl_sum_seconds:{}", self.sum.as_secs()),
+    ]
+  }
+
+  /// The sample in the Prometheus text format
+  pub fn prometheus(&self) -> String {
+    let mut metrics = String::from(
+      "# HELP redis_key_ttl_seconds Time left before the sampled keys with a TTL expire\n\
+       # TYPE redis_key_ttl_seconds histogram\n",
+    );
+    for (bound, count) in BUCKETS.iter().zip(self.buckets) {
+      metrics.push_str(&format!(
+        "redis_key_ttl_seconds_bucket{{le=\"{}\"}} {}\n",
+        bound, count
+      ));
+    }
+    metrics.push_str(&format!(
+      "redis_key_ttl_seconds_bucket{{le=\"+Inf\"}} {}\n\
+       redis_key_ttl_seconds_sum {}\n\
+       redis_key_ttl_seconds_count {}\n",
+      self.volatile,
+      self.sum.as_secs_f64(),
+      self.volatile
+    ));
+    metrics.push_str(&format!(
+      "# HELP redis_key_ttl_sampled_keys Keys of the last TTL sample\n\
+       # TYPE redis_key_ttl_sampled_keys gauge\n\
+       redis_key_ttl_sampled_keys {}\n\
+       # HELP redis_key_ttl_persistent_keys Keys without a TTL in the last TTL sample\n\
+       # TYPE redis_key_ttl_persistent_keys gauge\n\
+       redis_key_ttl_persistent_keys {}\n",
+      self.keys, self.persistent
+    ));
+    metrics
+  }
+}
+
+/// The latest sample of a keyspace
+#[derive(Default)]
+pub struct TtlHistogram {
+  latest: Mutex<TtlSample>,
+}
+
+impl TtlHistogram {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn latest(&self) -> TtlSample {
+    self.latest.lock().unwrap().clone()
+  }
+
+  pub fn update(&self, sample: TtlSample) {
+    *self.latest.lock().unwrap() = sample;
+  }
+}
+
+/// Schedules the job sampling the TTLs of the keyspace
+pub fn register_ttl_sampler(scheduler: &mut Scheduler, storage: Arc<StorageLock>) {
+  scheduler.every("ttl-sampler", SAMPLE_INTERVAL, SAMPLE_BUDGET, move || {
+    let storage = storage.clone();
+    async move {
+      storage.lock().await.sample_ttls(SAMPLE_KEYS);
+    }
+  });
+}
@@ -0,0 +1,74 @@
+/**
+ * The audit log of write commands, see `audit`.
+ */
+mod common;
+
+use common::TestServer;
+use redis::AsyncCommands;
+use serde_json::Value;
+
+fn entries(path: &std::path::Path) -> Vec<Value> {
+  std::fs::read_to_string(path)
+    .unwrap_or_default()
+    .lines()
+    .map(|line| serde_json::from_str(line).unwrap())
+    .collect()
+}
+
+fn argv(entry: &Value) -> Vec<&str> {
+  entry["argv"]
+    .as_array()
+    .unwrap()
+    .iter()
+    .map(|argument| argument.as_str().unwrap())
+    .collect()
+}
+
+#[tokio::test]
+async fn write_commands_are_audited() {
+  let dir = common::scratch_dir();
+  let file = dir.join("audit.log");
+  let server = TestServer::with_config(&[("audit-log", file.to_str().unwrap())]).await;
+  let mut connection = server.connection().await;
+
+  let _: () = connection.set("user:1", "alice").await.unwrap();
+  let _: Option<String> = connection.get("user:1").await.unwrap();
+  let _: i64 = connection.incr("visits", 1).await.unwrap();
+  let logged = entries(&file);
+  assert_eq!(logged.len(), 2, "{:?}", logged);
+  assert_eq!(argv(&logged[0]), ["SET", "user:1", "alice"]);
+  assert_eq!(argv(&logged[1]), ["INCRBY", "visits", "1"]);
+  assert_eq!(logged[0]["user"].as_str(), Some("default"));
+  assert!(logged[0]["addr"]
+    .as_str()
+    .unwrap()
+    .starts_with("127.0.0.1:"));
+  assert!(logged[0]["time"].as_u64().is_some());
+
+  // Values redacted, then rotation on every line
+  let _: () = redis::cmd("CONFIG")
+    .arg("SET")
+    .arg("audit-log-redact-values")
+    .arg("yes")
+    .arg("audit-log-max-size")
+    .arg("1")
+    .arg("audit-log-max-files")
+    .arg("2")
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  for i in 0..3 {
+    let _: () = connection
+      .set_ex(format!("session:{}", i), "secret", 100)
+      .await
+      .unwrap();
+  }
+  let numbered = |n: usize| dir.join(format!("audit.log.{}", n));
+  assert_eq!(
+    argv(&entries(&numbered(1))[0]),
+    ["SETEX", "session:2", "(redacted)", "(redacted)"]
+  );
+  assert_eq!(argv(&entries(&numbered(2))[0])[1], "session:1");
+  assert!(!numbered(3).exists());
+  assert!(entries(&file).is_empty());
+}
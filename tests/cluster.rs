@@ -0,0 +1,253 @@
+/**
+ * Multi-node tests: in-process clusters checking slot ownership, MOVED
+ * redirections, slot migration with ASK, and assembling a cluster with the
+ * node administration commands.
+ */
+mod common;
+
+use common::cluster::TestCluster;
+use common::{wait_until, TestServer};
+use redis::aio::MultiplexedConnection;
+use redis::AsyncCommands;
+use redis_starter_rust::cluster::key_slot;
+
+/// Runs CLUSTER with `args`
+async fn cluster_command(
+  connection: &mut MultiplexedConnection,
+  args: &[&str],
+) -> redis::RedisResult<redis::Value> {
+  redis::cmd("CLUSTER")
+    .arg(args)
+    .query_async(connection)
+    .await
+}
+
+#[tokio::test]
+async fn keys_live_on_their_slot_owner() {
+  let cluster = TestCluster::start(3).await;
+
+  for key in ["alpha", "beta", "gamma", "{user1}.name", "{user1}.email"] {
+    let owner = cluster.node_for(key);
+    let mut connection = owner.connection().await;
+    let _: () = connection.set(key, "value").await.unwrap();
+
+    for node in &cluster.nodes {
+      let value = node.storage().lock().await.get(key);
+      if node.id == owner.id {
+        assert_eq!(
+          value.as_deref(),
+          Some(&b"value"[..]),
+          "{} on its owner",
+          key
+        );
+      } else {
+        assert_eq!(value, None, "{} on {}", key, node.id);
+      }
+    }
+  }
+}
+
+#[tokio::test]
+async fn other_nodes_redirect_with_moved() {
+  let cluster = TestCluster::start(3).await;
+  let key = "alpha";
+  let owner = cluster.node_for(key);
+  let mut connection = cluster.other_node(key).connection().await;
+
+  let error = connection.set::<_, _, ()>(key, "value").await.unwrap_err();
+  assert_eq!(error.code(), Some("MOVED"));
+  assert_eq!(
+    error.detail(),
+    Some(format!("{} 127.0.0.1:{}", key_slot(key), owner.port).as_str())
+  );
+}
+
+#[tokio::test]
+async fn migrating_a_slot_redirects_with_ask() {
+  let cluster = TestCluster::start(2).await;
+  let key = "alpha";
+  let slot = key_slot(key).to_string();
+  let source = cluster.node_for(key);
+  let target = cluster.other_node(key);
+  let mut from = source.connection().await;
+  let mut to = target.connection().await;
+  let _: () = from.set(key, "moving").await.unwrap();
+
+  let set_slot = |action: &str, node: &str| {
+    let mut command = redis::cmd("CLUSTER");
+    command.arg("SETSLOT").arg(&slot).arg(action).arg(node);
+    command
+  };
+  let _: () = set_slot("IMPORTING", &source.id)
+    .query_async(&mut to)
+    .await
+    .unwrap();
+  let _: () = set_slot("MIGRATING", &target.id)
+    .query_async(&mut from)
+    .await
+    .unwrap();
+
+  // Keys still on the source are served there
+  let value: String = from.get(key).await.unwrap();
+  assert_eq!(value, "moving");
+
+  // Move the key behind the servers' back, as MIGRATE would
+  let value = source.storage().lock().await.peek(key).unwrap();
+  source.storage().lock().await.del(&[key.to_string()]);
+  target
+    .storage()
+    .lock()
+    .await
+    .set(key.to_string(), value, Vec::new());
+
+  let error = from.get::<_, String>(key).await.unwrap_err();
+  assert_eq!(error.code(), Some("ASK"));
+  let error = to.get::<_, String>(key).await.unwrap_err();
+  assert_eq!(error.code(), Some("MOVED"));
+  let (value,): (String,) = redis::pipe()
+    .cmd("ASKING")
+    .ignore()
+    .get(key)
+    .query_async(&mut to)
+    .await
+    .unwrap();
+  assert_eq!(value, "moving");
+
+  // Once the slot is handed over, the source sends clients to the target
+  for connection in [&mut to, &mut from] {
+    let _: () = set_slot("NODE", &target.id)
+      .query_async(connection)
+      .await
+      .unwrap();
+  }
+  let error = from.get::<_, String>(key).await.unwrap_err();
+  assert_eq!(error.code(), Some("MOVED"));
+  let value: String = to.get(key).await.unwrap();
+  assert_eq!(value, "moving");
+}
+
+#[tokio::test]
+async fn assembling_a_cluster() {
+  let mut nodes = Vec::new();
+  for _ in 0..4 {
+    let server = TestServer::with_config(&[("cluster-enabled", "yes")]).await;
+    let connection = server.connection().await;
+    nodes.push((server, connection));
+  }
+  let ports: Vec<String> = nodes
+    .iter()
+    .map(|(server, _)| server.addr.port().to_string())
+    .collect();
+  let mut ids = Vec::new();
+  for (_, connection) in &mut nodes {
+    let id: String = redis::cmd("CLUSTER")
+      .arg("MYID")
+      .query_async(connection)
+      .await
+      .unwrap();
+    ids.push(id);
+  }
+
+  // Three masters take their slots and epochs, as redis-cli --cluster create
+  // has them do
+  let ranges = [("0", "5460"), ("5461", "10922"), ("10923", "16383")];
+  for (index, (start, end)) in ranges.iter().enumerate() {
+    let connection = &mut nodes[index].1;
+    cluster_command(connection, &["ADDSLOTSRANGE", start, end])
+      .await
+      .unwrap();
+    let epoch = (index + 1).to_string();
+    cluster_command(connection, &["SET-CONFIG-EPOCH", &epoch])
+      .await
+      .unwrap();
+  }
+  let error = cluster_command(&mut nodes[0].1, &["ADDSLOTS", "0"])
+    .await
+    .unwrap_err();
+  assert_eq!(error.detail(), Some("Slot 0 is already busy"));
+  let error = cluster_command(&mut nodes[3].1, &["DELSLOTS", "0"])
+    .await
+    .unwrap_err();
+  assert_eq!(error.detail(), Some("Slot 0 is already unassigned"));
+
+  for port in &ports[1..] {
+    cluster_command(&mut nodes[0].1, &["MEET", "127.0.0.1", port])
+      .await
+      .unwrap();
+  }
+  // Every node learns every other from the gossip
+  for (_, connection) in &nodes {
+    wait_until("the nodes know each other", || {
+      let mut connection = connection.clone();
+      async move {
+        let info: String = redis::cmd("CLUSTER")
+          .arg("INFO")
+          .query_async(&mut connection)
+          .await
+          .unwrap();
+        info.contains("cluster_state:ok") && info.contains("cluster_known_nodes:4")
+      }
+    })
+    .await;
+  }
+
+  // The fourth node replicates the first master
+  cluster_command(&mut nodes[3].1, &["REPLICATE", &ids[0]])
+    .await
+    .unwrap();
+  let replica_line = format!("{} 127.0.0.1:{}@", ids[3], ports[3]);
+  let connection = nodes[1].1.clone();
+  wait_until("the replica is known", || {
+    let mut connection = connection.clone();
+    let (replica_line, master) = (replica_line.clone(), ids[0].clone());
+    async move {
+      let nodes: String = redis::cmd("CLUSTER")
+        .arg("NODES")
+        .query_async(&mut connection)
+        .await
+        .unwrap();
+      nodes.lines().any(|line| {
+        line.starts_with(&replica_line) && line.contains(&format!(" slave {} ", master))
+      })
+    }
+  })
+  .await;
+
+  // Keys are served by the masters of their slots
+  let owner = ranges
+    .iter()
+    .position(|(_, end)| key_slot("alpha") <= end.parse().unwrap())
+    .unwrap();
+  let _: () = nodes[owner].1.set("alpha", "1").await.unwrap();
+  let error = nodes[3].1.get::<_, String>("alpha").await.unwrap_err();
+  assert_eq!(error.code(), Some("MOVED"));
+
+  // Removing the replica: the others forget it, then it resets
+  for (_, connection) in &mut nodes[..3] {
+    cluster_command(connection, &["FORGET", &ids[3]])
+      .await
+      .unwrap();
+  }
+  cluster_command(&mut nodes[3].1, &["RESET", "HARD"])
+    .await
+    .unwrap();
+  let id: String = redis::cmd("CLUSTER")
+    .arg("MYID")
+    .query_async(&mut nodes[3].1)
+    .await
+    .unwrap();
+  assert_ne!(id, ids[3]);
+  let info: String = redis::cmd("CLUSTER")
+    .arg("INFO")
+    .query_async(&mut nodes[0].1)
+    .await
+    .unwrap();
+  assert!(info.contains("cluster_known_nodes:3"), "{}", info);
+  let error = cluster_command(&mut nodes[owner].1, &["RESET"])
+    .await
+    .unwrap_err();
+  assert_eq!(
+    error.detail(),
+    Some("CLUSTER RESET can't be called with master nodes containing keys")
+  );
+}
@@ -0,0 +1,141 @@
+/**
+ * In-process Redis Cluster for tests: every node is a `TestServer`, started
+ * with a nodes.conf naming all the others, so the topology is known from the
+ * first command and tests don't depend on gossip timing.
+ *
+ * The server doesn't replicate yet, so clusters are made of masters only.
+ */
+use super::{free_port, scratch_dir, wait_until, TestServer};
+use redis::aio::MultiplexedConnection;
+use redis_starter_rust::cluster::{generate_node_id, key_slot, CLUSTER_SLOTS};
+use redis_starter_rust::StorageLock;
+use std::sync::Arc;
+
+/// The cluster bus port of a node, like the server derives it
+const BUS_PORT_OFFSET: u16 = 10000;
+
+pub struct TestNode {
+  pub id: String,
+  pub port: u16,
+  /// First and last slot the node serves
+  pub slots: (u16, u16),
+  pub server: TestServer,
+}
+
+impl TestNode {
+  pub fn storage(&self) -> Arc<StorageLock> {
+    self.server.server.storage()
+  }
+
+  pub async fn connection(&self) -> MultiplexedConnection {
+    self.server.connection().await
+  }
+
+  pub fn serves(&self, slot: u16) -> bool {
+    (self.slots.0..=self.slots.1).contains(&slot)
+  }
+}
+
+pub struct TestCluster {
+  pub nodes: Vec<TestNode>,
+}
+
+impl TestCluster {
+  /// `masters` nodes sharing the slots in equal consecutive ranges
+  pub async fn start(masters: usize) -> Self {
+    assert!(masters > 0, "a cluster needs a node");
+    let layout: Vec<(String, u16, (u16, u16))> = (0..masters)
+      .map(|index| {
+        let first = CLUSTER_SLOTS * index / masters;
+        let last = CLUSTER_SLOTS * (index + 1) / masters - 1;
+        (generate_node_id(), free_port(), (first as u16, last as u16))
+      })
+      .collect();
+
+    let mut nodes = Vec::new();
+    for (index, (id, port, slots)) in layout.iter().enumerate() {
+      let dir = scratch_dir();
+      std::fs::write(dir.join("nodes.conf"), nodes_file(&layout, index))
+        .expect("failed to write nodes.conf");
+      let server = TestServer::launch(dir, *port, &[("cluster-enabled", "yes")]).await;
+      nodes.push(TestNode {
+        id: id.clone(),
+        port: *port,
+        slots: *slots,
+        server,
+      });
+    }
+    let cluster = Self { nodes };
+    cluster.wait_for_ok().await;
+    cluster
+  }
+
+  /// The node serving `key`
+  pub fn node_for(&self, key: &str) -> &TestNode {
+    let slot = key_slot(key);
+    self
+      .nodes
+      .iter()
+      .find(|node| node.serves(slot))
+      .expect("every slot is served")
+  }
+
+  /// A node not serving `key`, to be redirected from
+  pub fn other_node(&self, key: &str) -> &TestNode {
+    let slot = key_slot(key);
+    self
+      .nodes
+      .iter()
+      .find(|node| !node.serves(slot))
+      .expect("the cluster has a single node")
+  }
+
+  pub fn storages(&self) -> Vec<Arc<StorageLock>> {
+    self.nodes.iter().map(TestNode::storage).collect()
+  }
+
+  /// Waits until every node reports `cluster_state:ok`
+  pub async fn wait_for_ok(&self) {
+    for node in &self.nodes {
+      let connection = node.connection().await;
+      wait_until("the cluster is up", || {
+        let mut connection = connection.clone();
+        async move {
+          redis::cmd("CLUSTER")
+            .arg("INFO")
+            .query_async::<_, String>(&mut connection)
+            .await
+            .is_ok_and(|info| info.contains("cluster_state:ok"))
+        }
+      })
+      .await;
+    }
+  }
+}
+
+/// The nodes.conf of node `myself` in `layout`, in the CLUSTER NODES format
+fn nodes_file(layout: &[(String, u16, (u16, u16))], myself: usize) -> String {
+  let mut content = String::new();
+  for (index, (id, port, (first, last))) in layout.iter().enumerate() {
+    let flags = if index == myself {
+      "myself,master"
+    } else {
+      "master"
+    };
+    content.push_str(&format!(
+      "{} 127.0.0.1:{}@{} {} - 0 0 {} connected {}-{}\n",
+      id,
+      port,
+      port.wrapping_add(BUS_PORT_OFFSET),
+      flags,
+      index + 1,
+      first,
+      last
+    ));
+  }
+  content.push_str(&format!(
+    "vars currentEpoch {} lastVoteEpoch 0\n",
+    layout.len()
+  ));
+  content
+}
@@ -0,0 +1,122 @@
+#![allow(dead_code)]
+/**
+ * Boots in-process servers for the integration tests, each on its own
+ * ephemeral port and scratch directory, and connects `redis` crate clients
+ * to them.
+ */
+pub mod cluster;
+
+use redis::aio::MultiplexedConnection;
+use redis_starter_rust::clock::Clock;
+use redis_starter_rust::{RedisServer, RedisServerBuilder, Storage, StorageLock};
+use std::net::{SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+pub struct TestServer {
+  pub server: RedisServer,
+  pub addr: SocketAddr,
+  dir: PathBuf,
+}
+
+impl TestServer {
+  pub async fn start() -> Self {
+    Self::with_config(&[]).await
+  }
+
+  /// A server with configuration parameters set, as in the configuration file
+  pub async fn with_config(config: &[(&str, &str)]) -> Self {
+    Self::launch(scratch_dir(), free_port(), config).await
+  }
+
+  /// A server on `port` keeping its files in `dir`, which it removes when dropped
+  pub async fn launch(dir: PathBuf, port: u16, config: &[(&str, &str)]) -> Self {
+    let mut builder = RedisServer::builder();
+    for (name, value) in config {
+      builder = builder.config(name, value);
+    }
+    Self::launch_builder(dir, port, builder).await
+  }
+
+  /// A server from a builder carrying more than configuration, such as
+  /// custom commands
+  pub async fn with_builder(builder: RedisServerBuilder) -> Self {
+    Self::launch_builder(scratch_dir(), free_port(), builder).await
+  }
+
+  /// A server whose keyspace reads time from `clock`
+  pub async fn with_clock(clock: Arc<dyn Clock>) -> Self {
+    let storage = Arc::new(StorageLock::new(Storage::with_clock(clock)));
+    Self::with_builder(RedisServer::builder().storage(storage)).await
+  }
+
+  async fn launch_builder(dir: PathBuf, port: u16, builder: RedisServerBuilder) -> Self {
+    let mut server = builder
+      .bind(&format!("127.0.0.1:{}", port))
+      .config("dir", dir.to_str().expect("scratch dir isn't UTF-8"))
+      .build();
+    server.start().await.expect("failed to start the server");
+    let addr = server.local_addrs()[0];
+    Self { server, addr, dir }
+  }
+
+  pub fn client(&self) -> redis::Client {
+    redis::Client::open(format!("redis://{}/", self.addr)).expect("invalid server address")
+  }
+
+  pub async fn connection(&self) -> MultiplexedConnection {
+    self
+      .client()
+      .get_multiplexed_async_connection()
+      .await
+      .expect("failed to connect to the server")
+  }
+}
+
+impl Drop for TestServer {
+  fn drop(&mut self) {
+    self.server.shutdown();
+    let _ = std::fs::remove_dir_all(&self.dir);
+  }
+}
+
+/// A port nothing listens on. Port 0 turns the plain listener off, so the
+/// kernel is asked for a free port first.
+pub fn free_port() -> u16 {
+  TcpListener::bind("127.0.0.1:0")
+    .and_then(|listener| listener.local_addr())
+    .expect("no free port")
+    .port()
+}
+
+/// An empty directory for the server's files, so tests never load or write
+/// a dump in the working directory
+pub fn scratch_dir() -> PathBuf {
+  let dir = std::env::temp_dir().join(format!(
+    "redis-rs-test-{}-{}",
+    std::process::id(),
+    NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+  ));
+  let _ = std::fs::remove_dir_all(&dir);
+  std::fs::create_dir_all(&dir).expect("failed to create a scratch dir");
+  dir
+}
+
+/// Polls `check` until it holds, failing the test after 5 seconds
+pub async fn wait_until<F, Fut>(what: &str, mut check: F)
+where
+  F: FnMut() -> Fut,
+  Fut: std::future::Future<Output = bool>,
+{
+  for _ in 0..100 {
+    if check().await {
+      return;
+    }
+    tokio::time::sleep(Duration::from_millis(50)).await;
+  }
+  panic!("timed out waiting until {}", what);
+}
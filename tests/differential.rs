@@ -0,0 +1,243 @@
+/**
+ * Differential testing: random command sequences run against this server and
+ * a real `redis-server`, comparing every reply and the final DEBUG DIGEST, so
+ * that semantic divergences from Redis show up.
+ *
+ * The reference server is `redis-server` from the PATH, or the binary named
+ * by `REDIS_SERVER`; it needs Redis 7 for `enable-debug-command`. Without it
+ * the test is skipped. `DIFFERENTIAL_SEED` replays a failing run,
+ * `DIFFERENTIAL_ROUNDS` sets how many sequences are tried.
+ */
+mod common;
+
+use common::{free_port, TestServer};
+use redis::aio::MultiplexedConnection;
+use redis::Value;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DEFAULT_ROUNDS: usize = 20;
+
+/// Commands per sequence
+const SEQUENCE_LENGTH: usize = 200;
+
+/// Few keys, so that commands keep running into each other's data
+const KEYS: usize = 8;
+
+/// A `redis-server` child process, killed on drop
+struct ReferenceServer {
+  child: Child,
+  port: u16,
+}
+
+impl ReferenceServer {
+  fn spawn() -> Option<Self> {
+    let binary = std::env::var("REDIS_SERVER").unwrap_or_else(|_| "redis-server".to_string());
+    let port = free_port();
+    let child = Command::new(binary)
+      .args(["--port", &port.to_string(), "--bind", "127.0.0.1"])
+      .args(["--save", "", "--appendonly", "no"])
+      .args(["--enable-debug-command", "yes"])
+      .stdout(Stdio::null())
+      .stderr(Stdio::null())
+      .spawn()
+      .ok()?;
+    Some(Self { child, port })
+  }
+
+  async fn connection(&self) -> MultiplexedConnection {
+    let client = redis::Client::open(format!("redis://127.0.0.1:{}/", self.port)).unwrap();
+    for _ in 0..100 {
+      if let Ok(connection) = client.get_multiplexed_async_connection().await {
+        return connection;
+      }
+      tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("redis-server didn't start on port {}", self.port);
+  }
+}
+
+impl Drop for ReferenceServer {
+  fn drop(&mut self) {
+    let _ = self.child.kill();
+    let _ = self.child.wait();
+  }
+}
+
+/// xorshift, reproducible from its seed
+struct Random(u64);
+
+impl Random {
+  fn below(&mut self, bound: usize) -> usize {
+    self.0 ^= self.0 << 13;
+    self.0 ^= self.0 >> 7;
+    self.0 ^= self.0 << 17;
+    (self.0 % bound as u64) as usize
+  }
+
+  fn between(&mut self, min: i64, max: i64) -> i64 {
+    min + self.below((max - min + 1) as usize) as i64
+  }
+
+  fn key(&mut self) -> String {
+    format!("key:{}", self.below(KEYS))
+  }
+
+  fn value(&mut self) -> String {
+    match self.below(6) {
+      0 => "abc".to_string(),
+      1 => format!("{}", i64::MAX - self.between(0, 2)),
+      2 => format!("{}", i64::MIN + self.between(0, 2)),
+      3 => "3.5".to_string(),
+      _ => self.between(-100, 100).to_string(),
+    }
+  }
+}
+
+/// A random command over the supported string and keyspace commands.
+/// Timeouts are long enough that nothing expires during a run.
+fn random_command(random: &mut Random) -> Vec<String> {
+  let key = random.key();
+  match random.below(15) {
+    0 | 1 => vec!["SET".into(), key, random.value()],
+    2 => vec![
+      "SET".into(),
+      key,
+      random.value(),
+      "EX".into(),
+      random.between(100, 10_000).to_string(),
+    ],
+    3 => vec![
+      "SET".into(),
+      key,
+      random.value(),
+      "PX".into(),
+      random.between(100_000, 10_000_000).to_string(),
+    ],
+    4 | 5 => vec!["GET".into(), key],
+    6 => {
+      let mut command = vec!["DEL".into(), key];
+      command.extend((0..random.below(3)).map(|_| random.key()));
+      command
+    }
+    7 => vec!["UNLINK".into(), key],
+    8 => vec!["INCR".into(), key],
+    9 => vec!["DECR".into(), key],
+    10 => vec![
+      "INCRBY".into(),
+      key,
+      random.between(-1000, 1000).to_string(),
+    ],
+    11 => vec![
+      "DECRBY".into(),
+      key,
+      random.between(-1000, 1000).to_string(),
+    ],
+    12 => match random.below(3) {
+      0 => vec!["EXPIRE".into(), key, random.between(-10, 0).to_string()],
+      1 => vec![
+        "EXPIRE".into(),
+        key,
+        random.between(100, 10_000).to_string(),
+      ],
+      _ => vec![
+        "PEXPIRE".into(),
+        key,
+        random.between(100_000, 10_000_000).to_string(),
+      ],
+    },
+    13 => match random.below(3) {
+      0 => vec!["TTL".into(), key],
+      1 => vec!["PTTL".into(), key],
+      _ => vec!["PERSIST".into(), key],
+    },
+    _ => vec!["KEYS".into(), "key:*".into()],
+  }
+}
+
+/// A reply in a form both servers should agree on: errors by their code
+/// only, remaining times by whether there is one, KEYS in sorted order
+#[derive(Debug, PartialEq)]
+enum Reply {
+  Value(Value),
+  Error(Option<String>),
+}
+
+async fn run(connection: &mut MultiplexedConnection, command: &[String]) -> Reply {
+  let mut cmd = redis::cmd(&command[0]);
+  for argument in &command[1..] {
+    cmd.arg(argument);
+  }
+  match cmd.query_async::<_, Value>(connection).await {
+    Ok(Value::Int(ttl)) if matches!(command[0].as_str(), "TTL" | "PTTL") => {
+      Reply::Value(Value::Int(ttl.signum()))
+    }
+    Ok(Value::Bulk(mut keys)) if command[0] == "KEYS" => {
+      keys.sort_by_key(|key| format!("{:?}", key));
+      Reply::Value(Value::Bulk(keys))
+    }
+    Ok(value) => Reply::Value(value),
+    Err(e) => Reply::Error(e.code().map(str::to_string)),
+  }
+}
+
+async fn digest(connection: &mut MultiplexedConnection) -> Value {
+  redis::cmd("DEBUG")
+    .arg("DIGEST")
+    .query_async(connection)
+    .await
+    .expect("DEBUG DIGEST failed")
+}
+
+#[tokio::test]
+async fn replies_match_redis() {
+  let Some(reference) = ReferenceServer::spawn() else {
+    eprintln!("redis-server not found, skipping the differential test");
+    return;
+  };
+  let server = TestServer::start().await;
+  let mut expected = reference.connection().await;
+  let mut actual = server.connection().await;
+
+  let rounds = std::env::var("DIFFERENTIAL_ROUNDS")
+    .ok()
+    .and_then(|rounds| rounds.parse::<usize>().ok())
+    .unwrap_or(DEFAULT_ROUNDS);
+  let first_seed = std::env::var("DIFFERENTIAL_SEED")
+    .ok()
+    .and_then(|seed| seed.parse::<u64>().ok())
+    .unwrap_or_else(|| {
+      SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64
+    });
+
+  for round in 0..rounds as u64 {
+    let seed = first_seed.wrapping_add(round) | 1;
+    let mut random = Random(seed);
+    for connection in [&mut expected, &mut actual] {
+      let _: () = redis::cmd("FLUSHALL")
+        .query_async(connection)
+        .await
+        .unwrap();
+    }
+
+    for index in 0..SEQUENCE_LENGTH {
+      let command = random_command(&mut random);
+      let want = run(&mut expected, &command).await;
+      let got = run(&mut actual, &command).await;
+      assert_eq!(
+        got, want,
+        "DIFFERENTIAL_SEED={} command #{}: {:?}",
+        seed, index, command
+      );
+    }
+    assert_eq!(
+      digest(&mut actual).await,
+      digest(&mut expected).await,
+      "DIFFERENTIAL_SEED={}: datasets differ",
+      seed
+    );
+  }
+}
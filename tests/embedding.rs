@@ -0,0 +1,30 @@
+/**
+ * An application embedding the server keeps its own global allocator, which
+ * this test binary declares.
+ */
+mod common;
+
+use redis::AsyncCommands;
+use redis_starter_rust::RedisServer;
+use std::alloc::System;
+
+#[global_allocator]
+static GLOBAL: System = System;
+
+#[tokio::test]
+async fn embedding_with_own_allocator() {
+  let port = common::free_port();
+  let mut server = RedisServer::builder()
+    .bind(&format!("127.0.0.1:{}", port))
+    .build();
+  server.start().await.expect("failed to start the server");
+  let mut connection = redis::Client::open(format!("redis://127.0.0.1:{}/", port))
+    .unwrap()
+    .get_multiplexed_async_connection()
+    .await
+    .unwrap();
+  let _: () = connection.set("key", "value").await.unwrap();
+  let value: Option<String> = connection.get("key").await.unwrap();
+  assert_eq!(value.as_deref(), Some("value"));
+  server.shutdown();
+}
@@ -0,0 +1,74 @@
+/**
+ * Faults injected with DEBUG FAULT, see `fault`.
+ */
+mod common;
+
+use common::TestServer;
+use redis::aio::MultiplexedConnection;
+use redis::AsyncCommands;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+async fn fault(connection: &mut MultiplexedConnection, args: &[&str]) {
+  let _: () = redis::cmd("DEBUG")
+    .arg("FAULT")
+    .arg(args)
+    .query_async(connection)
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn injected_faults() {
+  let dir = common::scratch_dir();
+  let server = TestServer::launch(dir.clone(), common::free_port(), &[]).await;
+  let mut connection = server.connection().await;
+
+  fault(&mut connection, &["LATENCY", "200"]).await;
+  let started = Instant::now();
+  let _: () = connection.set("key", "value").await.unwrap();
+  assert!(started.elapsed() >= Duration::from_millis(200));
+
+  fault(&mut connection, &["RDB-WRITE-ERROR", "1"]).await;
+  let _: () = redis::cmd("BGSAVE")
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  common::wait_until("the failed save", || async {
+    let info: String = redis::cmd("INFO")
+      .arg("persistence")
+      .query_async(&mut server.connection().await)
+      .await
+      .unwrap();
+    info.contains("rdb_last_bgsave_status:err\r\n")
+  })
+  .await;
+  assert!(!dir.join("dump.rdb").exists());
+
+  let faults: Vec<String> = redis::cmd("DEBUG")
+    .arg("FAULT")
+    .arg("LIST")
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  assert_eq!(
+    faults,
+    [
+      "latency:200ms,100%",
+      "connection-reset:0%",
+      "rdb-write-error:1"
+    ]
+  );
+
+  // Every connection is dropped at its next command, DEBUG aside
+  fault(&mut connection, &["CONNECTION-RESET", "100"]).await;
+  let mut doomed = TcpStream::connect(server.addr).await.unwrap();
+  doomed.write_all(b"*1\r\n$4\r\nPING\r\n").await.unwrap();
+  let mut reply = Vec::new();
+  doomed.read_to_end(&mut reply).await.unwrap();
+  assert!(reply.is_empty(), "{:?}", reply);
+  fault(&mut connection, &["OFF"]).await;
+  let value: String = connection.get("key").await.unwrap();
+  assert_eq!(value, "value");
+}
@@ -0,0 +1,2000 @@
+/**
+ * End-to-end tests: a real server on a real socket, driven by the `redis`
+ * crate client, so the wire protocol is covered as clients see it.
+ */
+mod common;
+
+use common::TestServer;
+use redis::AsyncCommands;
+use redis_starter_rust::changes::ChangeKind;
+use redis_starter_rust::cli::{self, Reply};
+use redis_starter_rust::clock::MockClock;
+use redis_starter_rust::commands;
+use redis_starter_rust::configfile::split_arguments;
+use redis_starter_rust::database::RDBParser;
+use redis_starter_rust::debug::dataset_digest;
+use redis_starter_rust::dump;
+use redis_starter_rust::encryption::{self, EncryptionKey};
+use redis_starter_rust::keyevents::{KeyEvent, KeyEventFuture, KeyEventHandler, KeyEventReason};
+use redis_starter_rust::module::{CommandFuture, CommandHandler, Session};
+use redis_starter_rust::parser::{serialize_resp3, serialize_response, RedisValue};
+use redis_starter_rust::rdbdiff;
+use redis_starter_rust::readthrough::{MissFuture, MissHandler};
+use redis_starter_rust::statsd;
+use redis_starter_rust::{rdb, RedisServer, Storage};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+#[tokio::test]
+async fn ping_and_echo() {
+  let server = TestServer::start().await;
+  let mut connection = server.connection().await;
+
+  let pong: String = redis::cmd("PING")
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  assert_eq!(pong, "PONG");
+  let echo: String = redis::cmd("ECHO")
+    .arg("hello world")
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  assert_eq!(echo, "hello world");
+}
+
+#[tokio::test]
+async fn set_and_get() {
+  let server = TestServer::start().await;
+  let mut connection = server.connection().await;
+
+  let _: () = connection.set("greeting", "hello").await.unwrap();
+  let value: String = connection.get("greeting").await.unwrap();
+  assert_eq!(value, "hello");
+
+  let missing: Option<String> = connection.get("missing").await.unwrap();
+  assert_eq!(missing, None);
+
+  let deleted: i64 = connection.del("greeting").await.unwrap();
+  assert_eq!(deleted, 1);
+  let value: Option<String> = connection.get("greeting").await.unwrap();
+  assert_eq!(value, None);
+}
+
+#[tokio::test]
+async fn values_with_spaces_and_unicode() {
+  let server = TestServer::start().await;
+  let mut connection = server.connection().await;
+
+  let value = "spaces, tabs\tand ünïcödé";
+  let _: () = connection.set("text", value).await.unwrap();
+  let read: String = connection.get("text").await.unwrap();
+  assert_eq!(read, value);
+}
+
+#[tokio::test]
+async fn counters() {
+  let server = TestServer::start().await;
+  let mut connection = server.connection().await;
+
+  let value: i64 = connection.incr("counter", 1).await.unwrap();
+  assert_eq!(value, 1);
+  let value: i64 = connection.incr("counter", 10).await.unwrap();
+  assert_eq!(value, 11);
+  let value: i64 = connection.decr("counter", 1).await.unwrap();
+  assert_eq!(value, 10);
+
+  let _: () = connection.set("text", "abc").await.unwrap();
+  let error = connection.incr::<_, _, i64>("text", 1).await.unwrap_err();
+  assert_eq!(error.kind(), redis::ErrorKind::ResponseError);
+}
+
+#[tokio::test]
+async fn expiration() {
+  let server = TestServer::start().await;
+  let mut connection = server.connection().await;
+
+  let _: () = connection.set("key", "value").await.unwrap();
+  let ttl: i64 = connection.ttl("key").await.unwrap();
+  assert_eq!(ttl, -1);
+  let ttl: i64 = connection.ttl("missing").await.unwrap();
+  assert_eq!(ttl, -2);
+
+  let set: bool = connection.expire("key", 100).await.unwrap();
+  assert!(set);
+  let ttl: i64 = connection.ttl("key").await.unwrap();
+  assert!((99..=100).contains(&ttl), "TTL {}", ttl);
+  let pttl: i64 = connection.pttl("key").await.unwrap();
+  assert!((99_000..=100_000).contains(&pttl), "PTTL {}", pttl);
+
+  let persisted: bool = connection.persist("key").await.unwrap();
+  assert!(persisted);
+  let ttl: i64 = connection.ttl("key").await.unwrap();
+  assert_eq!(ttl, -1);
+}
+
+#[tokio::test]
+async fn keys_expire() {
+  let clock = Arc::new(MockClock::new());
+  let server = TestServer::with_clock(clock.clone()).await;
+  let mut connection = server.connection().await;
+
+  let _: () = redis::cmd("SET")
+    .arg("short")
+    .arg("lived")
+    .arg("PX")
+    .arg(50)
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  let _: () = connection.set_ex("volatile", "value", 100).await.unwrap();
+  let value: Option<String> = connection.get("short").await.unwrap();
+  assert_eq!(value.as_deref(), Some("lived"));
+
+  clock.advance(Duration::from_millis(100));
+  let value: Option<String> = connection.get("short").await.unwrap();
+  assert_eq!(value, None);
+
+  // TTLs count down with the clock
+  clock.advance(Duration::from_secs(30));
+  let ttl: i64 = connection.ttl("volatile").await.unwrap();
+  assert_eq!(ttl, 70);
+
+  // The active cycle reclaims the keys nobody reads
+  clock.advance(Duration::from_secs(70));
+  common::wait_until("the active expiry", || async {
+    server.server.storage().lock().await.is_empty()
+  })
+  .await;
+}
+
+#[test]
+fn mock_clock_drives_the_keyspace() {
+  let clock = Arc::new(MockClock::new());
+  let storage = Storage::with_clock(clock.clone());
+  storage.set(
+    "key".to_string(),
+    "value".to_string(),
+    vec![("EX".to_string(), "10".to_string())],
+  );
+
+  clock.advance(Duration::from_secs(4));
+  assert_eq!(storage.idle_time("key"), Some(Duration::from_secs(4)));
+  assert_eq!(storage.ttl("key"), Some(Some(Duration::from_secs(6))));
+
+  clock.advance(Duration::from_secs(6));
+  assert_eq!(storage.active_expire_cycle(10), 1);
+  assert!(storage.is_empty());
+}
+
+#[test]
+fn keys_expire_at_their_deadline() {
+  let clock = Arc::new(MockClock::new());
+  let storage = Storage::with_clock(clock.clone());
+  storage.set(
+    "key".to_string(),
+    "value".to_string(),
+    vec![("PX".to_string(), "10".to_string())],
+  );
+
+  clock.advance(Duration::from_millis(10));
+  assert_eq!(storage.get("key"), None);
+  assert!(storage.is_empty());
+}
+
+#[test]
+fn keys_leave_out_expired_keys() {
+  let clock = Arc::new(MockClock::new());
+  let storage = Storage::with_clock(clock.clone());
+  storage.set("live".to_string(), "1".to_string(), vec![]);
+  storage.set(
+    "expiring".to_string(),
+    "2".to_string(),
+    vec![("PX".to_string(), "10".to_string())],
+  );
+  let snapshot = storage.begin_snapshot();
+
+  clock.advance(Duration::from_millis(10));
+  assert_eq!(storage.len(), 2);
+  assert_eq!(storage.keys("*"), vec!["live".to_string()]);
+  // The snapshot walks the keys as they were when it was opened
+  let mut keys = snapshot.keys();
+  keys.sort();
+  assert_eq!(keys, vec!["expiring".to_string(), "live".to_string()]);
+}
+
+#[test]
+fn scan_survives_resizing() {
+  let storage = Storage::new();
+  for i in 0..1000 {
+    storage.set(format!("stable:{}", i), i.to_string(), vec![]);
+  }
+
+  // The table grows, then shrinks below its size when the scan started
+  let mut seen = std::collections::HashSet::new();
+  let mut cursor = 0;
+  let mut calls = 0;
+  loop {
+    let (next, keys) = storage.scan(cursor, None, 7);
+    seen.extend(keys);
+    calls += 1;
+    match calls {
+      10 => (0..3000).for_each(|i| storage.set(format!("added:{}", i), i.to_string(), vec![])),
+      50 => {
+        let added: Vec<String> = (0..3000).map(|i| format!("added:{}", i)).collect();
+        let stable: Vec<String> = (500..1000).map(|i| format!("stable:{}", i)).collect();
+        storage.del(&added);
+        storage.del(&stable);
+      }
+      _ => {}
+    }
+    if next == 0 {
+      break;
+    }
+    cursor = next;
+  }
+  assert!((0..500).all(|i| seen.contains(&format!("stable:{}", i))));
+
+  // The index memory follows the keys, overwrites aside
+  let memory = storage.scan_index_memory();
+  assert!(memory > 500 * "stable:0".len());
+  storage.set("stable:0".to_string(), "again".to_string(), vec![]);
+  assert_eq!(storage.scan_index_memory(), memory);
+  let stable: Vec<String> = (0..500).map(|i| format!("stable:{}", i)).collect();
+  storage.del(&stable);
+  assert_eq!(storage.scan_index_memory(), 0);
+}
+
+#[tokio::test]
+async fn scan() {
+  let server = TestServer::start().await;
+  let mut connection = server.connection().await;
+  for i in 0..100 {
+    let _: () = connection.set(format!("user:{}", i), i).await.unwrap();
+    let _: () = connection.set(format!("session:{}", i), i).await.unwrap();
+  }
+
+  let mut users = Vec::new();
+  let mut cursor = 0;
+  loop {
+    let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+      .arg(cursor)
+      .arg("MATCH")
+      .arg("user:*")
+      .arg("COUNT")
+      .arg(20)
+      .query_async(&mut connection)
+      .await
+      .unwrap();
+    users.extend(keys);
+    if next == 0 {
+      break;
+    }
+    cursor = next;
+  }
+  users.sort();
+  users.dedup();
+  assert_eq!(users.len(), 100);
+  assert!(users.iter().all(|key| key.starts_with("user:")));
+
+  let error = redis::cmd("SCAN")
+    .arg("nope")
+    .query_async::<_, ()>(&mut connection)
+    .await
+    .unwrap_err();
+  assert!(error.to_string().contains("invalid cursor"), "{}", error);
+}
+
+#[tokio::test]
+async fn pipelines() {
+  let server = TestServer::start().await;
+  let mut connection = server.connection().await;
+
+  let (value, counter, missing): (String, i64, Option<String>) = redis::pipe()
+    .set("a", "1")
+    .ignore()
+    .incr("a", 5)
+    .ignore()
+    .get("a")
+    .incr("b", 2)
+    .get("c")
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  assert_eq!(value, "6");
+  assert_eq!(counter, 2);
+  assert_eq!(missing, None);
+
+  // A long pipeline is answered in order, across several reads
+  let mut pipeline = redis::pipe();
+  for i in 0..1000 {
+    pipeline.set(format!("key:{}", i), i).ignore();
+  }
+  for i in 0..1000 {
+    pipeline.get(format!("key:{}", i));
+  }
+  let values: Vec<i64> = pipeline.query_async(&mut connection).await.unwrap();
+  assert_eq!(values, (0..1000).collect::<Vec<i64>>());
+
+  // Replies outgrowing the write buffer are written before the pipeline ends
+  let large = "x".repeat(10_000);
+  let mut pipeline = redis::pipe();
+  pipeline.set("large", &large).ignore();
+  for _ in 0..20 {
+    pipeline.get("large");
+  }
+  let values: Vec<String> = pipeline.query_async(&mut connection).await.unwrap();
+  assert_eq!(values, vec![large; 20]);
+
+  // Replies larger than the buffer bypass it, still in order
+  let huge = "y".repeat(200_000);
+  let (small, value, after): (String, String, String) = redis::pipe()
+    .set("huge", &huge)
+    .ignore()
+    .get("key:1")
+    .get("huge")
+    .get("key:2")
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  assert_eq!((small.as_str(), after.as_str()), ("1", "2"));
+  assert_eq!(value, huge);
+}
+
+#[test]
+fn snapshots_are_point_in_time() {
+  let mut storage = Storage::new();
+  for (key, value) in [
+    ("kept", "1"),
+    ("changed", "2"),
+    ("deleted", "3"),
+    ("counter", "4"),
+  ] {
+    storage.set(key.to_string(), value.to_string(), Vec::new());
+  }
+  storage.set(
+    "volatile".to_string(),
+    "5".to_string(),
+    vec![("EX".to_string(), "100".to_string())],
+  );
+  let sorted = |mut entries: Vec<(String, bytes::Bytes, Option<std::time::SystemTime>)>| {
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    entries
+      .into_iter()
+      .map(|(key, value, expires_at)| (key, value, expires_at.is_some()))
+      .collect::<Vec<_>>()
+  };
+  let before = sorted(storage.entries());
+
+  // Writes after the snapshot opened aren't part of it
+  let snapshot = storage.begin_snapshot();
+  storage.set("changed".to_string(), "two".to_string(), Vec::new());
+  storage.del(&["deleted".to_string()]);
+  storage.incr_by("counter", 10).unwrap();
+  storage.set_expiry("volatile", None);
+  storage.set("added".to_string(), "6".to_string(), Vec::new());
+  assert_eq!(sorted(snapshot.entries()), before);
+
+  // Nor the keyspace that replaced it on FLUSHALL
+  let snapshot = storage.begin_snapshot();
+  let now = sorted(storage.entries());
+  storage.flushall(Some(false));
+  storage.set("kept".to_string(), "new".to_string(), Vec::new());
+  assert_eq!(sorted(snapshot.entries()), now);
+}
+
+#[test]
+fn dataset_snapshots_restore() {
+  let mut storage = Storage::new();
+  storage.set("fixture".to_string(), "1".to_string(), Vec::new());
+  storage.set(
+    "session".to_string(),
+    "token".to_string(),
+    vec![("EX".to_string(), "100".to_string())],
+  );
+  let snapshot = storage.snapshot();
+  assert_eq!(snapshot.len(), 2);
+  let before = storage.entries();
+
+  // Each case starts from the same dataset
+  for case in 0..3 {
+    storage.incr_by("fixture", 10).unwrap();
+    storage.del(&["session".to_string()]);
+    storage.set(format!("case:{}", case), "written".to_string(), Vec::new());
+    if case == 2 {
+      storage.flushall(None);
+    }
+    storage.restore(&snapshot);
+
+    let mut entries = storage.entries();
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut expected = before.clone();
+    expected.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(entries.len(), expected.len());
+    for (entry, expected) in entries.iter().zip(&expected) {
+      assert_eq!((&entry.0, &entry.1), (&expected.0, &expected.1));
+      assert_eq!(entry.2.is_some(), expected.2.is_some());
+    }
+    assert!(storage.ttl("session").unwrap().unwrap() > Duration::from_secs(90));
+    assert_eq!(storage.scan(0, Some("case:*"), 100).1, Vec::<String>::new());
+  }
+}
+
+#[test]
+fn dataset_snapshots_are_copy_on_write() {
+  let mut storage = Storage::new();
+  storage.set("large".to_string(), "z".repeat(1 << 20), Vec::new());
+  let stored = storage.get("large").unwrap();
+
+  // Overwritten after the snapshot, the value comes back without a copy
+  let snapshot = storage.snapshot();
+  storage.set("large".to_string(), "small".to_string(), Vec::new());
+  storage.set("later".to_string(), "written".to_string(), Vec::new());
+  storage.restore(&snapshot);
+  assert_eq!(storage.get("large").unwrap().as_ptr(), stored.as_ptr());
+  assert_eq!(storage.get("later"), None);
+}
+
+#[test]
+fn reads_share_stored_values() {
+  let storage = Storage::new();
+  let large = "z".repeat(1 << 20);
+  storage.set("large".to_string(), large.clone(), Vec::new());
+  storage.set("small".to_string(), "42".to_string(), Vec::new());
+
+  let first = storage.get("large").unwrap();
+  let second = storage.get("large").unwrap();
+  assert_eq!(first, large.as_bytes());
+  assert_eq!(first.as_ptr(), second.as_ptr());
+  assert_eq!(storage.get("small").unwrap(), "42");
+}
+
+#[test]
+fn binary_replies_are_written_as_is() {
+  let payload = bytes::Bytes::from_static(b"\xff\x00\r\n\xfe");
+  let reply = RedisValue::Nested(vec![RedisValue::BulkBytes(payload.clone())]);
+  assert_eq!(
+    serialize_response(reply.clone()),
+    b"*1\r\n$5\r\n\xff\x00\r\n\xfe\r\n"
+  );
+  assert_eq!(serialize_resp3(reply), b"*1\r\n$5\r\n\xff\x00\r\n\xfe\r\n");
+}
+
+#[test]
+fn command_registry() {
+  let names = [
+    "get",
+    "SET",
+    "config|get",
+    "CONFIG SET",
+    "client|list",
+    "cluster info",
+    "memory|purge",
+  ];
+  for name in names {
+    assert!(commands::handler(name).is_some(), "no handler for {}", name);
+  }
+  assert!(commands::handler("config|nope").is_none());
+  assert!(commands::handler("nope").is_none());
+}
+
+#[tokio::test]
+async fn concurrent_clients() {
+  let server = TestServer::start().await;
+
+  let mut tasks = Vec::new();
+  for client in 0..8 {
+    let mut connection = server.connection().await;
+    tasks.push(tokio::spawn(async move {
+      for i in 0..100 {
+        let _: i64 = connection.incr("shared", 1).await.unwrap();
+        let _: () = connection
+          .set(format!("client:{}", client), i)
+          .await
+          .unwrap();
+      }
+    }));
+  }
+  for task in tasks {
+    task.await.unwrap();
+  }
+
+  let mut connection = server.connection().await;
+  let shared: i64 = connection.get("shared").await.unwrap();
+  assert_eq!(shared, 800);
+}
+
+#[tokio::test]
+async fn io_threads() {
+  let server = TestServer::with_config(&[("io-threads", "4")]).await;
+
+  // Commands on a shared key, on keys of their own, and on the whole
+  // keyspace, all at once
+  let mut tasks = Vec::new();
+  for client in 0..8 {
+    let mut connection = server.connection().await;
+    tasks.push(tokio::spawn(async move {
+      for i in 0..100 {
+        let _: i64 = connection.incr("shared", 1).await.unwrap();
+        let _: i64 = connection
+          .incr(format!("client:{}", client), 1)
+          .await
+          .unwrap();
+        let _: () = connection.set("scratch", client).await.unwrap();
+        let _: i64 = connection.del("scratch").await.unwrap();
+        if i % 10 == 0 {
+          let _: Vec<String> = connection.keys("client:*").await.unwrap();
+        }
+      }
+    }));
+  }
+  for task in tasks {
+    task.await.unwrap();
+  }
+
+  let mut connection = server.connection().await;
+  let shared: i64 = connection.get("shared").await.unwrap();
+  assert_eq!(shared, 800);
+  for client in 0..8 {
+    let count: i64 = connection.get(format!("client:{}", client)).await.unwrap();
+    assert_eq!(count, 100);
+  }
+  let info: String = redis::cmd("INFO")
+    .arg("stats")
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  assert!(info.contains("io_threads_active:1\r\n"), "{}", info);
+}
+
+#[tokio::test]
+async fn commands_lock_their_keys_only() {
+  let server = TestServer::with_config(&[("io-threads", "2")]).await;
+  let mut connection = server.connection().await;
+  let storage = server.server.storage();
+
+  // While a key is locked, its commands wait and the others run
+  let locked = storage.lock_keys(&["busy"]).await;
+  let mut blocked = server.connection().await;
+  let waiting = tokio::spawn(async move {
+    let _: () = blocked.set("busy", "finally").await.unwrap();
+  });
+  let _: () = tokio::time::timeout(Duration::from_secs(1), connection.set("free", "now"))
+    .await
+    .expect("a command on another key waited")
+    .unwrap();
+  tokio::time::sleep(Duration::from_millis(50)).await;
+  assert!(!waiting.is_finished());
+
+  drop(locked);
+  tokio::time::timeout(Duration::from_secs(1), waiting)
+    .await
+    .expect("the command on the locked key never ran")
+    .unwrap();
+  let value: String = connection.get("busy").await.unwrap();
+  assert_eq!(value, "finally");
+
+  // Commands on keys share the keyspace, the others wait for all of it
+  let shared = storage.share().await;
+  let _: () = tokio::time::timeout(Duration::from_secs(1), connection.set("free", "again"))
+    .await
+    .expect("a command on a key waited for the shared keyspace")
+    .unwrap();
+  let mut blocked = server.connection().await;
+  let waiting = tokio::spawn(async move {
+    let _: Vec<String> = blocked.keys("*").await.unwrap();
+  });
+  tokio::time::sleep(Duration::from_millis(50)).await;
+  assert!(!waiting.is_finished());
+  drop(shared);
+  tokio::time::timeout(Duration::from_secs(1), waiting)
+    .await
+    .expect("KEYS never ran")
+    .unwrap();
+}
+
+#[tokio::test]
+async fn unknown_commands_are_errors() {
+  let server = TestServer::start().await;
+  let mut connection = server.connection().await;
+
+  let error = redis::cmd("NOSUCHCOMMAND")
+    .query_async::<_, ()>(&mut connection)
+    .await
+    .unwrap_err();
+  assert_eq!(error.kind(), redis::ErrorKind::ResponseError);
+
+  // The connection is still usable afterwards
+  let pong: String = redis::cmd("PING")
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  assert_eq!(pong, "PONG");
+}
+
+#[tokio::test]
+async fn oversized_bulk_lengths_are_protocol_errors() {
+  let server = TestServer::start().await;
+  for length in ["18446744073709551615", "536870913"] {
+    let mut stream = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+    stream
+      .write_all(format!("*1\r\n${}\r\n", length).as_bytes())
+      .await
+      .unwrap();
+    // The server replies and hangs up
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply).await.unwrap();
+    assert_eq!(reply, "-ERR Protocol error: invalid bulk length\r\n");
+  }
+}
+
+/// Reads from `stream` until what was read contains `expected`
+async fn read_until(stream: &mut tokio::net::TcpStream, expected: &str) -> String {
+  let mut read = Vec::new();
+  let mut buffer = [0; 1024];
+  while !String::from_utf8_lossy(&read).contains(expected) {
+    let n = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buffer))
+      .await
+      .unwrap_or_else(|_| panic!("no {:?} in {:?}", expected, String::from_utf8_lossy(&read)))
+      .unwrap();
+    assert!(n > 0, "connection closed");
+    read.extend_from_slice(&buffer[..n]);
+  }
+  String::from_utf8_lossy(&read).into_owned()
+}
+
+#[tokio::test]
+async fn client_tracking_invalidations() {
+  let server = TestServer::start().await;
+  let mut connection = server.connection().await;
+  // The invalidations are redirected to a connection of their own
+  let mut redirect = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+  redirect
+    .write_all(b"*2\r\n$6\r\nCLIENT\r\n$2\r\nID\r\n")
+    .await
+    .unwrap();
+  let reply = read_until(&mut redirect, "\r\n").await;
+  let redirect_id: u64 = reply.trim_start_matches(':').trim_end().parse().unwrap();
+  let _: () = redis::cmd("CLIENT")
+    .arg("TRACKING")
+    .arg("ON")
+    .arg("REDIRECT")
+    .arg(redirect_id)
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  let invalidation = |key: &str| {
+    format!(
+      "__redis__:invalidate\r\n*1\r\n${}\r\n{}\r\n",
+      key.len(),
+      key
+    )
+  };
+
+  // A key that expires
+  let _: () = redis::cmd("SET")
+    .arg("session")
+    .arg("token")
+    .arg("PX")
+    .arg(50)
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  let _: Option<String> = connection.get("session").await.unwrap();
+  read_until(&mut redirect, &invalidation("session")).await;
+
+  // A key written through the keyspace rather than by a command
+  let _: () = connection.set("page", "v1").await.unwrap();
+  let _: Option<String> = connection.get("page").await.unwrap();
+  let storage = server.server.storage();
+  storage
+    .lock()
+    .await
+    .set("page".to_string(), "v2".to_string(), vec![]);
+  read_until(&mut redirect, &invalidation("page")).await;
+
+  // A flush invalidates everything
+  let _: Option<String> = connection.get("page").await.unwrap();
+  storage.lock().await.flushall(None);
+  read_until(&mut redirect, "__redis__:invalidate\r\n$-1\r\n").await;
+}
+
+#[tokio::test]
+async fn hello_switches_to_resp3() {
+  let server = TestServer::start().await;
+  let mut connection = server.connection().await;
+  let mut client = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+
+  client
+    .write_all(&redis::cmd("HELLO").arg(4).get_packed_command())
+    .await
+    .unwrap();
+  read_until(&mut client, "-NOPROTO unsupported protocol version\r\n").await;
+
+  client
+    .write_all(
+      &redis::cmd("HELLO")
+        .arg(3)
+        .arg("SETNAME")
+        .arg("cache")
+        .get_packed_command(),
+    )
+    .await
+    .unwrap();
+  let reply = read_until(&mut client, "$7\r\nmodules\r\n*0\r\n").await;
+  assert!(
+    reply.starts_with("%7\r\n$6\r\nserver\r\n$5\r\nredis\r\n"),
+    "{:?}",
+    reply
+  );
+  assert!(reply.contains("$5\r\nproto\r\n:3\r\n"), "{:?}", reply);
+  let list: String = redis::cmd("CLIENT")
+    .arg("LIST")
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  assert!(list.contains("name=cache"), "{}", list);
+  assert!(list.contains("resp=3"), "{}", list);
+
+  // Nulls have a type of their own
+  client
+    .write_all(&redis::cmd("GET").arg("missing").get_packed_command())
+    .await
+    .unwrap();
+  read_until(&mut client, "_\r\n").await;
+
+  // Invalidations are pushed on the connection itself
+  client
+    .write_all(
+      &redis::pipe()
+        .cmd("CLIENT")
+        .arg("TRACKING")
+        .arg("ON")
+        .cmd("GET")
+        .arg("page")
+        .get_packed_pipeline(),
+    )
+    .await
+    .unwrap();
+  read_until(&mut client, "+OK\r\n_\r\n").await;
+  let _: () = connection.set("page", "v1").await.unwrap();
+  read_until(
+    &mut client,
+    ">2\r\n$10\r\ninvalidate\r\n*1\r\n$4\r\npage\r\n",
+  )
+  .await;
+
+  // RESP2 gets the same reply as a flat array
+  let reply: Vec<redis::Value> = redis::cmd("HELLO")
+    .arg(2)
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  assert_eq!(reply.len(), 14);
+  assert_eq!(reply[4], redis::Value::Data(b"proto".to_vec()));
+  assert_eq!(reply[5], redis::Value::Int(2));
+}
+
+#[tokio::test]
+async fn config_from_the_builder() {
+  let server = TestServer::with_config(&[("maxclients", "50")]).await;
+  let mut connection = server.connection().await;
+
+  let config: Vec<String> = redis::cmd("CONFIG")
+    .arg("GET")
+    .arg("maxclients")
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  assert_eq!(config[0], "maxclients");
+  assert_eq!(config[1], "50");
+}
+
+#[tokio::test]
+async fn secrets_are_redacted() {
+  const KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+  let admin_port = common::free_port();
+  let server = TestServer::with_config(&[
+    ("encryption-key", KEY),
+    ("http-admin-port", &admin_port.to_string()),
+    ("http-admin-token", "s3cret-token"),
+  ])
+  .await;
+  let mut connection = server.connection().await;
+
+  let config: Vec<String> = redis::cmd("CONFIG")
+    .arg("GET")
+    .arg("encryption-key*")
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  assert_eq!(
+    config,
+    vec!["encryption-key", "(redacted)", "encryption-key-command", ""]
+  );
+
+  let mut admin = tokio::net::TcpStream::connect(("127.0.0.1", admin_port))
+    .await
+    .unwrap();
+  admin
+    .write_all(b"GET /config HTTP/1.1\r\nHost: localhost\r\n\r\n")
+    .await
+    .unwrap();
+  let mut response = String::new();
+  admin.read_to_string(&mut response).await.unwrap();
+  assert!(response.contains("\"encryption-key\":\"(redacted)\""));
+  assert!(!response.contains(KEY));
+  assert!(!response.contains("s3cret-token"));
+}
+
+#[tokio::test]
+async fn storage_is_shared_with_clients() {
+  let server = TestServer::start().await;
+  let mut connection = server.connection().await;
+
+  server.server.storage().lock().await.set(
+    "seeded".to_string(),
+    "from rust".to_string(),
+    Vec::new(),
+  );
+  let value: String = connection.get("seeded").await.unwrap();
+  assert_eq!(value, "from rust");
+
+  let _: () = connection.set("written", "by a client").await.unwrap();
+  let value = server.server.storage().lock().await.get("written");
+  assert_eq!(value.as_deref(), Some(&b"by a client"[..]));
+}
+
+#[tokio::test]
+async fn shutdown_closes_the_listener() {
+  let admin_port = common::free_port();
+  let mut server = TestServer::with_config(&[("http-admin-port", &admin_port.to_string())]).await;
+  let addr = server.addr;
+  let admin_addr = SocketAddr::from(([127, 0, 0, 1], admin_port));
+  assert!(tokio::net::TcpStream::connect(admin_addr).await.is_ok());
+  server.server.shutdown();
+  assert!(!server.server.is_running());
+
+  // Give the acceptors a moment to drop their listeners
+  tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+  assert!(tokio::net::TcpStream::connect(addr).await.is_err());
+  assert!(tokio::net::TcpStream::connect(admin_addr).await.is_err());
+}
+
+#[tokio::test]
+async fn shutdown_command_stops_the_server() {
+  let mut server = TestServer::start().await;
+  let addr = server.addr;
+  let mut client = tokio::net::TcpStream::connect(addr).await.unwrap();
+  client
+    .write_all(b"*2\r\n$8\r\nSHUTDOWN\r\n$6\r\nNOSAVE\r\n")
+    .await
+    .unwrap();
+
+  // The server stops, the process embedding it carries on
+  tokio::time::timeout(Duration::from_secs(5), server.server.stopped())
+    .await
+    .expect("SHUTDOWN didn't stop the server");
+  assert!(!server.server.is_running());
+  let mut reply = Vec::new();
+  let _ = client.read_to_end(&mut reply).await;
+  assert!(reply.is_empty());
+  tokio::time::sleep(Duration::from_millis(50)).await;
+  assert!(tokio::net::TcpStream::connect(addr).await.is_err());
+}
+
+#[tokio::test]
+async fn import_protocol_at_startup() {
+  let dir = common::scratch_dir();
+  let file = dir.join("import.resp");
+  let mut protocol = String::new();
+  for i in 0..1000 {
+    let (key, value) = (format!("key:{}", i), i.to_string());
+    protocol.push_str(&format!(
+      "*3\r\n$3\r\nSET\r\n${}\r\n{}\r\n${}\r\n{}\r\n",
+      key.len(),
+      key,
+      value.len(),
+      value
+    ));
+  }
+  // Errors are counted, the commands after them still run
+  protocol.push_str("*3\r\n$3\r\nSET\r\n$4\r\ntext\r\n$3\r\nabc\r\n");
+  protocol.push_str("*2\r\n$4\r\nINCR\r\n$4\r\ntext\r\n");
+  protocol.push_str("*2\r\n$4\r\nINCR\r\n$7\r\nkey:999\r\n*2\r\n$4\r\nINCR\r\n$5\r\nkey:1\r\n");
+  std::fs::write(&file, protocol).unwrap();
+
+  let server = TestServer::with_config(&[("import-protocol", file.to_str().unwrap())]).await;
+  let mut connection = server.connection().await;
+  let value: i64 = connection.get("key:999").await.unwrap();
+  assert_eq!(value, 1000);
+  let value: i64 = connection.get("key:1").await.unwrap();
+  assert_eq!(value, 2);
+  let keys: Vec<String> = connection.keys("key:*").await.unwrap();
+  assert_eq!(keys.len(), 1000);
+  std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn json_export_round_trip() {
+  let server = TestServer::start().await;
+  let mut connection = server.connection().await;
+  let _: () = connection.set("plain", "value").await.unwrap();
+  let _: () = connection.set_ex("session", "token", 1000).await.unwrap();
+
+  let dir = common::scratch_dir();
+  let file = dir.join("dump.json");
+  let _: () = redis::cmd("DEBUG")
+    .arg("EXPORT-JSON")
+    .arg(file.to_str().unwrap())
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+
+  let copy = TestServer::with_config(&[("import-json", file.to_str().unwrap())]).await;
+  let mut connection = copy.connection().await;
+  let value: String = connection.get("plain").await.unwrap();
+  assert_eq!(value, "value");
+  let value: String = connection.get("session").await.unwrap();
+  assert_eq!(value, "token");
+  let ttl: i64 = connection.ttl("session").await.unwrap();
+  assert!(ttl > 990 && ttl <= 1000, "ttl {}", ttl);
+  let ttl: i64 = connection.ttl("plain").await.unwrap();
+  assert_eq!(ttl, -1);
+  std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn import_csv_at_startup() {
+  let dir = common::scratch_dir();
+  let file = dir.join("seed.csv");
+  let mut rows = String::from("id,email,name,expires_in\n");
+  for i in 0..25_000 {
+    rows.push_str(&format!("{},user{}@example.com,User {},\n", i, i, i));
+  }
+  rows.push_str("session,user@example.com,\"Doe, \"\"Jane\"\"\nline two\",600\n");
+  // Skipped: no key, then a TTL that isn't a number of seconds
+  rows.push_str(",nobody@example.com,Nobody,\n");
+  rows.push_str("bad,bad@example.com,Bad,soon\n");
+  std::fs::write(&file, rows).unwrap();
+
+  let server = TestServer::with_config(&[
+    ("import-csv", file.to_str().unwrap()),
+    ("import-csv-columns", "email name expires_in"),
+  ])
+  .await;
+  let mut connection = server.connection().await;
+  let value: String = connection.get("user24999@example.com").await.unwrap();
+  assert_eq!(value, "User 24999");
+  let ttl: i64 = connection.ttl("user0@example.com").await.unwrap();
+  assert_eq!(ttl, -1);
+  let value: String = connection.get("user@example.com").await.unwrap();
+  assert_eq!(value, "Doe, \"Jane\"\nline two");
+  let ttl: i64 = connection.ttl("user@example.com").await.unwrap();
+  assert!(ttl > 590 && ttl <= 600, "ttl {}", ttl);
+  let value: Option<String> = connection.get("bad@example.com").await.unwrap();
+  assert_eq!(value, None);
+  std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn change_feed() {
+  let server = TestServer::start().await;
+  let mut changes = server.server.storage().lock().await.subscribe_changes();
+  let mut connection = server.connection().await;
+
+  let _: () = connection.set("feed", "one").await.unwrap();
+  let _: () = connection.set("feed", "two").await.unwrap();
+  let _: bool = connection.expire("feed", 100).await.unwrap();
+  let _: i64 = connection.del("feed").await.unwrap();
+
+  let change = changes.recv().await.unwrap();
+  assert_eq!(
+    (change.kind, change.key.as_str()),
+    (ChangeKind::Set, "feed")
+  );
+  assert_eq!(change.before, None);
+  assert_eq!(change.after.unwrap().value, "one");
+
+  let change = changes.recv().await.unwrap();
+  assert_eq!(change.kind, ChangeKind::Set);
+  assert_eq!(change.before.unwrap().value, "one");
+  assert_eq!(change.after.unwrap().value, "two");
+
+  let change = changes.recv().await.unwrap();
+  assert_eq!(change.kind, ChangeKind::Expire);
+  assert_eq!(change.before.unwrap().expires_at, None);
+  assert!(change.after.unwrap().expires_at.is_some());
+
+  let change = changes.recv().await.unwrap();
+  assert_eq!(change.kind, ChangeKind::Del);
+  assert_eq!(change.before.unwrap().value, "two");
+  assert_eq!(change.after, None);
+}
+
+/// APPENDUSER key suffix: appends the user running it and a suffix to a value
+struct AppendUser;
+
+impl CommandHandler for AppendUser {
+  fn name(&self) -> &str {
+    "appenduser"
+  }
+
+  fn arity(&self) -> i64 {
+    3
+  }
+
+  fn flags(&self) -> &[&str] {
+    &["write"]
+  }
+
+  fn execute<'a>(
+    &'a self,
+    session: &'a Session,
+    storage: &'a Storage,
+    args: &'a [String],
+  ) -> CommandFuture<'a> {
+    Box::pin(async move {
+      let value = format!(
+        "{}{}{}",
+        String::from_utf8_lossy(&storage.get(&args[0]).unwrap_or_default()),
+        session.user,
+        args[1]
+      );
+      storage.set(args[0].clone(), value.clone(), vec![]);
+      RedisValue::BulkString(Some(value))
+    })
+  }
+}
+
+#[tokio::test]
+async fn custom_commands() {
+  let server = TestServer::with_builder(RedisServer::builder().command(AppendUser)).await;
+  let mut connection = server.connection().await;
+
+  let value: String = redis::cmd("APPENDUSER")
+    .arg("greeting")
+    .arg("!")
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  assert_eq!(value, "default!");
+  let value: String = redis::cmd("appendUser")
+    .arg("greeting")
+    .arg("?")
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  assert_eq!(value, "default!default?");
+  let value: String = connection.get("greeting").await.unwrap();
+  assert_eq!(value, "default!default?");
+
+  let error = redis::cmd("APPENDUSER")
+    .arg("greeting")
+    .query_async::<_, String>(&mut connection)
+    .await
+    .unwrap_err();
+  assert_eq!(
+    error.detail(),
+    Some("wrong number of arguments for 'appenduser' command")
+  );
+}
+
+/// A custom command taking the name of a built-in one
+struct Get;
+
+impl CommandHandler for Get {
+  fn name(&self) -> &str {
+    "GET"
+  }
+
+  fn arity(&self) -> i64 {
+    2
+  }
+
+  fn execute<'a>(&'a self, _: &'a Session, _: &'a Storage, _: &'a [String]) -> CommandFuture<'a> {
+    Box::pin(async { RedisValue::BulkString(None) })
+  }
+}
+
+#[tokio::test]
+async fn custom_commands_cant_replace_built_in_ones() {
+  let mut server = RedisServer::builder()
+    .bind(&format!("127.0.0.1:{}", common::free_port()))
+    .command(Get)
+    .build();
+  assert_eq!(
+    server.start().await,
+    Err("Command 'get' already exists".to_string())
+  );
+}
+
+/// A database with the users, counting the lookups
+struct Users(std::sync::atomic::AtomicUsize);
+
+impl MissHandler for Users {
+  fn load<'a>(&'a self, key: &'a str) -> MissFuture<'a> {
+    Box::pin(async move {
+      self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+      match key.strip_prefix("user:") {
+        Some("failing") => Err("connection refused".to_string()),
+        Some(id) => Ok(Some(format!("User {}", id))),
+        None => Ok(None),
+      }
+    })
+  }
+}
+
+#[tokio::test]
+async fn read_through_handler() {
+  let users = Users(std::sync::atomic::AtomicUsize::new(0));
+  let server = TestServer::with_builder(
+    RedisServer::builder()
+      .miss_handler(users)
+      .config("read-through-ttl", "60"),
+  )
+  .await;
+  let mut connection = server.connection().await;
+
+  let value: String = connection.get("user:7").await.unwrap();
+  assert_eq!(value, "User 7");
+  let ttl: i64 = connection.ttl("user:7").await.unwrap();
+  assert!(ttl > 50 && ttl <= 60, "ttl {}", ttl);
+  let value: Option<String> = connection.get("order:1").await.unwrap();
+  assert_eq!(value, None);
+  let error = connection
+    .get::<_, Option<String>>("user:failing")
+    .await
+    .unwrap_err();
+  assert_eq!(
+    error.detail(),
+    Some("read-through handler failed: connection refused")
+  );
+
+  // Cached: the handler isn't asked again
+  let value: String = connection.get("user:7").await.unwrap();
+  assert_eq!(value, "User 7");
+  let _: () = connection.set("user:8", "cached").await.unwrap();
+  let value: String = connection.get("user:8").await.unwrap();
+  assert_eq!(value, "cached");
+}
+
+/// A system of record answering once told to
+struct Slow {
+  asked: Arc<tokio::sync::Notify>,
+  answer: Arc<tokio::sync::Notify>,
+}
+
+impl MissHandler for Slow {
+  fn load<'a>(&'a self, _: &'a str) -> MissFuture<'a> {
+    Box::pin(async move {
+      self.asked.notify_one();
+      self.answer.notified().await;
+      Ok(Some("loaded".to_string()))
+    })
+  }
+}
+
+#[tokio::test]
+async fn read_through_lets_go_of_the_key() {
+  let asked = Arc::new(tokio::sync::Notify::new());
+  let answer = Arc::new(tokio::sync::Notify::new());
+  let server = TestServer::with_builder(
+    RedisServer::builder()
+      .miss_handler(Slow {
+        asked: asked.clone(),
+        answer: answer.clone(),
+      })
+      .config("read-through-timeout", "10000"),
+  )
+  .await;
+  let mut reader = server.connection().await;
+  let mut writer = server.connection().await;
+
+  let get = tokio::spawn(async move { reader.get::<_, String>("key").await.unwrap() });
+  asked.notified().await;
+  let written: redis::RedisResult<()> =
+    tokio::time::timeout(Duration::from_secs(5), writer.set("key", "written"))
+      .await
+      .expect("SET waited for the miss handler");
+  written.unwrap();
+
+  // A key written while loading wins
+  answer.notify_one();
+  assert_eq!(get.await.unwrap(), "written");
+}
+
+#[tokio::test]
+async fn read_through_command() {
+  use std::os::unix::fs::PermissionsExt;
+
+  let dir = common::scratch_dir();
+  let script = dir.join("lookup.sh");
+  std::fs::write(
+    &script,
+    "#!/bin/sh\ncase \"$2\" in\n  user:*) echo \"$1 ${2#user:}\" ;;\n  *) exit 1 ;;\nesac\n",
+  )
+  .unwrap();
+  std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+  let command = format!("{} Hello", script.display());
+
+  let server = TestServer::with_config(&[
+    ("read-through-command", &command),
+    ("read-through-ttl", "0"),
+  ])
+  .await;
+  let mut connection = server.connection().await;
+  let value: String = connection.get("user:alice").await.unwrap();
+  assert_eq!(value, "Hello alice");
+  let ttl: i64 = connection.ttl("user:alice").await.unwrap();
+  assert_eq!(ttl, -1);
+  let value: Option<String> = connection.get("order:1").await.unwrap();
+  assert_eq!(value, None);
+  std::fs::remove_dir_all(dir).unwrap();
+}
+
+/// A write-behind store, keeping the keys that expired
+struct Expired(Arc<std::sync::Mutex<Vec<KeyEvent>>>);
+
+impl KeyEventHandler for Expired {
+  fn on_event<'a>(&'a self, event: &'a KeyEvent) -> KeyEventFuture<'a> {
+    Box::pin(async move { self.0.lock().unwrap().push(event.clone()) })
+  }
+}
+
+#[tokio::test]
+async fn key_event_handler() {
+  let expired = Arc::new(std::sync::Mutex::new(Vec::new()));
+  let server =
+    TestServer::with_builder(RedisServer::builder().key_event_handler(Expired(expired.clone())))
+      .await;
+  let mut connection = server.connection().await;
+
+  let _: () = redis::cmd("SET")
+    .arg("session")
+    .arg("token")
+    .arg("PX")
+    .arg(50)
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  let _: () = connection.set("deleted", "value").await.unwrap();
+  let _: () = connection.del("deleted").await.unwrap();
+  common::wait_until("the expiration", || async {
+    !expired.lock().unwrap().is_empty()
+  })
+  .await;
+
+  let events = expired.lock().unwrap().clone();
+  assert_eq!(events.len(), 1, "{:?}", events);
+  assert_eq!(events[0].key, "session");
+  assert_eq!(events[0].value.value, "token");
+  assert!(events[0].value.expires_at.is_some());
+  assert_eq!(events[0].reason, KeyEventReason::Expired);
+}
+
+#[test]
+fn rdb_diff() {
+  let dir = common::scratch_dir();
+  let (a, b) = (Storage::new(), Storage::new());
+  a.set("same".to_string(), "1".to_string(), vec![]);
+  b.set("same".to_string(), "1".to_string(), vec![]);
+  a.set("gone".to_string(), "x".to_string(), vec![]);
+  b.set("new".to_string(), "y".to_string(), vec![]);
+  a.set("value".to_string(), "before".to_string(), vec![]);
+  b.set("value".to_string(), "after".to_string(), vec![]);
+  a.set("ttl".to_string(), "t".to_string(), vec![]);
+  let px = vec![("PX".to_string(), "100000".to_string())];
+  b.set("ttl".to_string(), "t".to_string(), px);
+
+  let (path_a, path_b) = (dir.join("a.rdb"), dir.join("b.rdb"));
+  redis_starter_rust::rdb::save(&a, &path_a, None, rdb::RdbOptions::default()).unwrap();
+  redis_starter_rust::rdb::save(&b, &path_b, None, rdb::RdbOptions::default()).unwrap();
+  let load = |path: &std::path::Path| rdbdiff::load(path.to_str().unwrap()).unwrap();
+  let diff = rdbdiff::diff(&load(&path_a), &load(&path_b));
+  assert_eq!(diff.only_in_a, ["gone"]);
+  assert_eq!(diff.only_in_b, ["new"]);
+  let changed: Vec<&str> = diff.changed.iter().map(|(key, ..)| key.as_str()).collect();
+  assert_eq!(changed, ["ttl", "value"]);
+  assert_eq!(diff.changed[0].1.expires_at, None);
+  assert!(diff.changed[0].2.expires_at.is_some());
+
+  assert!(rdbdiff::diff(&load(&path_a), &load(&path_a)).is_empty());
+  std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn interactive_client() {
+  let server = TestServer::start().await;
+  let port = server.addr.port();
+  let replies = tokio::task::spawn_blocking(move || {
+    let mut client = cli::Client::new("127.0.0.1", port);
+    let mut call = |line: &str| {
+      let reply = client.call(&split_arguments(line).unwrap()).unwrap();
+      cli::format_reply(&reply)
+    };
+    vec![
+      call("SET greeting \"hello\\nworld\""),
+      call("GET greeting"),
+      call("GET missing"),
+      call("INCR counter"),
+      call("KEYS greeting"),
+      call("KEYS nothing*"),
+      call("NOPE"),
+    ]
+  })
+  .await
+  .unwrap();
+  assert_eq!(
+    replies[..6],
+    [
+      "OK",
+      "\"hello\\nworld\"",
+      "(nil)",
+      "(integer) 1",
+      "1) \"greeting\"",
+      "(empty array)"
+    ]
+  );
+  assert!(replies[6].starts_with("(error) "), "{}", replies[6]);
+
+  let mut nested: &[u8] = b"*3\r\n*2\r\n:1\r\n$-1\r\n%1\r\n+key\r\n,1.5\r\n#t\r\n";
+  let reply = cli::read_reply(&mut nested).unwrap();
+  assert_eq!(
+    reply,
+    Reply::Array(vec![
+      Reply::Array(vec![Reply::Integer(1), Reply::Nil]),
+      Reply::Map(vec![(
+        Reply::Status("key".to_string()),
+        Reply::Double("1.5".to_string())
+      )]),
+      Reply::Boolean(true),
+    ])
+  );
+  assert_eq!(
+    cli::format_reply(&reply),
+    "1) 1) (integer) 1\n   2) (nil)\n2) 1# key => (double) 1.5\n3) (true)"
+  );
+}
+
+#[test]
+fn parallel_rdb_loading() {
+  let storage = Storage::new();
+  for i in 0..10_000 {
+    let options = if i % 4 == 0 {
+      vec![("EX".to_string(), "3600".to_string())]
+    } else {
+      Vec::new()
+    };
+    storage.set(format!("key:{}", i), format!("value:{}", i), options);
+  }
+  let data = rdb::encode(&storage, rdb::RdbOptions::default());
+
+  for threads in [1, 4] {
+    let loaded = Storage::new();
+    let keys = RDBParser::new(data.clone()).load(&loaded, threads).unwrap();
+    assert_eq!(keys, 10_000);
+    assert_eq!(loaded.expiry_index().len(), 2_500);
+    assert_eq!(dataset_digest(&loaded), dataset_digest(&storage));
+  }
+
+  // A file cut in its last record is an error, whatever the threads loaded
+  let truncated = data[..data.len() - 20].to_vec();
+  assert!(RDBParser::new(truncated).load(&Storage::new(), 4).is_err());
+}
+
+#[test]
+fn rdb_compression_and_checksum() {
+  let storage = Storage::new();
+  for i in 0..100 {
+    storage.set(format!("long:{}", i), "abc".repeat(100 + i), Vec::new());
+    storage.set(format!("short:{}", i), i.to_string(), Vec::new());
+  }
+  let encode = |compression, checksum| {
+    rdb::encode(
+      &storage,
+      rdb::RdbOptions {
+        compression,
+        checksum,
+      },
+    )
+  };
+  let plain = encode(false, false);
+  let compressed = encode(true, true);
+  assert!(compressed.len() * 2 < plain.len());
+  assert_eq!(plain[plain.len() - 8..], [0; 8]);
+  let (data, footer) = compressed.split_at(compressed.len() - 8);
+  assert_eq!(footer, dump::crc64(data).to_le_bytes());
+
+  for data in [plain, compressed] {
+    let loaded = Storage::new();
+    let keys = RDBParser::new(data.clone()).load(&loaded, 4).unwrap();
+    assert_eq!(keys, 200);
+    assert_eq!(dataset_digest(&loaded), dataset_digest(&storage));
+    let mut parser = RDBParser::new(data);
+    parser.parse().unwrap();
+    assert_eq!(parser.entries().len(), 200);
+  }
+}
+
+#[tokio::test]
+async fn encrypted_rdb() {
+  const KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+  let key = EncryptionKey::parse(KEY).unwrap();
+  let dir = common::scratch_dir();
+  let path = dir.join("dump.rdb");
+  let storage = Storage::new();
+  storage.set("secret".to_string(), "hunter2".to_string(), vec![]);
+  redis_starter_rust::rdb::save(&storage, &path, Some(&key), rdb::RdbOptions::default()).unwrap();
+  let data = std::fs::read(&path).unwrap();
+  assert!(encryption::is_encrypted(&data));
+  assert!(!data.windows(7).any(|window| window == b"hunter2"));
+
+  // Without the key the dump can't be loaded, and must not be overwritten
+  let mut server = RedisServer::builder()
+    .bind(&format!("127.0.0.1:{}", common::free_port()))
+    .config("dir", dir.to_str().unwrap())
+    .build();
+  assert!(server.start().await.is_err());
+
+  let command = format!("echo {}", KEY);
+  let server = TestServer::launch(
+    dir.clone(),
+    common::free_port(),
+    &[("encryption-key-command", &command)],
+  )
+  .await;
+  let mut connection = server.connection().await;
+  let value: String = connection.get("secret").await.unwrap();
+  assert_eq!(value, "hunter2");
+
+  // The command runs a program, clients can't change it
+  let reply: redis::RedisResult<String> = redis::cmd("CONFIG")
+    .arg("SET")
+    .arg("encryption-key-command")
+    .arg("touch /tmp/pwned")
+    .query_async(&mut connection)
+    .await;
+  assert!(reply.is_err());
+
+  let _: () = connection.set("more", "data").await.unwrap();
+  let _: String = redis::cmd("BGSAVE")
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  common::wait_until("the encrypted snapshot", || async {
+    let data = std::fs::read(&path).unwrap_or_default();
+    encryption::open(&key, &data)
+      .is_ok_and(|plain| plain.windows(4).any(|window| window == b"more"))
+  })
+  .await;
+}
+
+#[tokio::test]
+async fn memory_purge() {
+  let server = TestServer::start().await;
+  let mut connection = server.connection().await;
+  let reply: String = redis::cmd("MEMORY")
+    .arg("PURGE")
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  assert_eq!(reply, "OK");
+
+  let info: String = redis::cmd("INFO")
+    .arg("memory")
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  let allocator = if cfg!(feature = "jemalloc") {
+    "jemalloc"
+  } else if cfg!(feature = "mimalloc") {
+    "mimalloc"
+  } else {
+    "libc"
+  };
+  assert!(info.contains(&format!("mem_allocator:{}\r\n", allocator)));
+  assert_eq!(
+    info.contains("allocator_retained:"),
+    cfg!(feature = "jemalloc")
+  );
+}
+
+async fn info_field(connection: &mut redis::aio::MultiplexedConnection, field: &str) -> u64 {
+  let info: String = redis::cmd("INFO").query_async(connection).await.unwrap();
+  info
+    .lines()
+    .find_map(|line| line.strip_prefix(&format!("{}:", field)))
+    .and_then(|value| value.parse().ok())
+    .unwrap_or_else(|| panic!("no {} in INFO", field))
+}
+
+#[tokio::test]
+async fn maxmemory_eviction() {
+  let server = TestServer::start().await;
+  let mut connection = server.connection().await;
+  let config_set = |parameter: &str, value: String| {
+    let mut command = redis::cmd("CONFIG");
+    command.arg("SET").arg(parameter).arg(value);
+    command
+  };
+
+  // Without an eviction policy, writes growing the dataset are refused
+  let _: () = connection.set("first", "value").await.unwrap();
+  let _: () = config_set("maxmemory", "1".to_string())
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  let error = connection
+    .set::<_, _, ()>("second", "value")
+    .await
+    .unwrap_err();
+  assert_eq!(error.code(), Some("OOM"));
+  let deleted: i64 = connection.del("first").await.unwrap();
+  assert_eq!(deleted, 1);
+  let _: () = connection.set("second", "value").await.unwrap();
+  let _: () = config_set("maxmemory", "0".to_string())
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+
+  // Lowering maxmemory evicts right away
+  for i in 0..100 {
+    let _: () = connection.set(format!("key:{}", i), i).await.unwrap();
+  }
+  let dataset = info_field(&mut connection, "used_memory_dataset").await;
+  let _: () = config_set("maxmemory-policy", "allkeys-lru".to_string())
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  let _: () = config_set("maxmemory", (dataset / 2).to_string())
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  let keys = connection.keys::<_, Vec<String>>("*").await.unwrap().len() as u64;
+  assert!((40..60).contains(&keys), "{} keys left", keys);
+  assert!(info_field(&mut connection, "used_memory_dataset").await <= dataset / 2);
+  assert_eq!(
+    info_field(&mut connection, "evicted_keys").await,
+    101 - keys
+  );
+
+  // volatile-ttl evicts the key closest to expiring, and only keys with a
+  // deadline
+  let _: () = config_set("maxmemory", "0".to_string())
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  let _: () = connection.set_ex("soon", "value", 100).await.unwrap();
+  let _: () = connection.set_ex("late", "value", 1000).await.unwrap();
+  let dataset = info_field(&mut connection, "used_memory_dataset").await;
+  let _: () = config_set("maxmemory-policy", "volatile-ttl".to_string())
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  let _: () = config_set("maxmemory", dataset.to_string())
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  let _: () = connection.set("more", "value").await.unwrap();
+  let _: () = connection.set("more", "value").await.unwrap();
+  let soon: Option<String> = connection.get("soon").await.unwrap();
+  let late: Option<String> = connection.get("late").await.unwrap();
+  assert_eq!((soon, late), (None, Some("value".to_string())));
+  let _: () = connection.del("late").await.unwrap();
+  let _: () = config_set("maxmemory", "1".to_string())
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  let error = connection
+    .set::<_, _, ()>("more", "value")
+    .await
+    .unwrap_err();
+  assert_eq!(error.code(), Some("OOM"));
+}
+
+#[tokio::test]
+async fn cron_stats() {
+  let server = TestServer::start().await;
+  let mut connection = server.connection().await;
+  common::wait_until("the background jobs to run", || {
+    let mut connection = connection.clone();
+    async move {
+      let info: String = redis::cmd("INFO")
+        .arg("cronstats")
+        .query_async(&mut connection)
+        .await
+        .unwrap();
+      ["active-expire", "clients-cron", "stats-sampler"]
+        .iter()
+        .all(|job| info.contains(&format!("cronstat_{}:runs=", job)))
+    }
+  })
+  .await;
+
+  let info: String = redis::cmd("INFO")
+    .arg("all")
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  assert!(info.contains("# Cronstats\r\n"), "{}", info);
+  assert!(info.contains("overruns="), "{}", info);
+}
+
+#[tokio::test]
+async fn offloaded_commands() {
+  let server = TestServer::start().await;
+  let mut connection = server.connection().await;
+  for i in 0..100 {
+    let _: () = connection.set(format!("key:{}", i), i).await.unwrap();
+  }
+  let _: () = connection.pset_ex("key:1expired", 1, 1).await.unwrap();
+  tokio::time::sleep(Duration::from_millis(5)).await;
+
+  // The same replies whether the keyspace is walked in place or offloaded
+  let mut replies = Vec::new();
+  for min_keys in ["0", "1"] {
+    let _: String = redis::cmd("CONFIG")
+      .arg("SET")
+      .arg("offload-min-keys")
+      .arg(min_keys)
+      .query_async(&mut connection)
+      .await
+      .unwrap();
+    let mut keys: Vec<String> = connection.keys("key:1*").await.unwrap();
+    keys.sort();
+    let digest: String = redis::cmd("DEBUG")
+      .arg("DIGEST")
+      .query_async(&mut connection)
+      .await
+      .unwrap();
+    replies.push((keys, digest));
+  }
+  assert_eq!(replies[0], replies[1]);
+  assert_eq!(replies[0].0.len(), 11);
+  assert_ne!(replies[0].1, "0".repeat(40));
+}
+
+/// Keys and their counts, from a reply of `[key, count]` pairs
+fn ranked(reply: redis::Value) -> Vec<(String, i64)> {
+  let pairs: Vec<redis::Value> = redis::from_redis_value(&reply).unwrap();
+  pairs
+    .iter()
+    .map(|pair| redis::from_redis_value(pair).unwrap())
+    .collect()
+}
+
+#[tokio::test]
+async fn bigkeys() {
+  let server = TestServer::start().await;
+  let mut connection = server.connection().await;
+  for i in 0..2500 {
+    let _: () = connection.set(format!("small:{}", i), i).await.unwrap();
+  }
+  let _: () = connection.set("large", "x".repeat(5000)).await.unwrap();
+  let _: () = connection.set("medium", "x".repeat(500)).await.unwrap();
+
+  let report: Vec<Vec<redis::Value>> = redis::cmd("DEBUG")
+    .arg("BIGKEYS")
+    .arg(2)
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  assert_eq!(report.len(), 1);
+  let field = |index: usize| report[0][index].clone();
+  let kind: String = redis::from_redis_value(&field(1)).unwrap();
+  let keys: i64 = redis::from_redis_value(&field(3)).unwrap();
+  let (by_length, by_memory) = (ranked(field(5)), ranked(field(7)));
+  assert_eq!(kind, "string");
+  assert_eq!(keys, 2502);
+  assert_eq!(
+    by_length,
+    [("large".to_string(), 5000), ("medium".to_string(), 500)]
+  );
+  assert_eq!(by_memory[0].0, "large");
+  assert!(by_memory[0].1 > 5000);
+}
+
+#[tokio::test]
+async fn hotkeys() {
+  let server = TestServer::with_config(&[("hotkeys-capacity", "4")]).await;
+  let mut connection = server.connection().await;
+  let _: () = connection.set("hot", "1").await.unwrap();
+  let _: () = connection.set("warm", "2").await.unwrap();
+  for _ in 0..100 {
+    let _: String = connection.get("hot").await.unwrap();
+  }
+  for _ in 0..50 {
+    let _: String = connection.get("warm").await.unwrap();
+  }
+  // Keys accessed once churn through the counters left
+  for i in 0..20 {
+    let _: () = connection.set(format!("cold:{}", i), i).await.unwrap();
+  }
+
+  let top = redis::cmd("DEBUG")
+    .arg("HOTKEYS")
+    .arg(2)
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  assert_eq!(
+    ranked(top),
+    [("hot".to_string(), 101), ("warm".to_string(), 51)]
+  );
+  let all = redis::cmd("DEBUG")
+    .arg("HOTKEYS")
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  assert_eq!(ranked(all).len(), 4);
+
+  let info: String = redis::cmd("INFO")
+    .arg("hotkeys")
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  assert!(info.contains("hotkeys_capacity:4\r\n"), "{}", info);
+  assert!(
+    info.contains("hotkey0:key=hot,accesses=101,error=0\r\n"),
+    "{}",
+    info
+  );
+
+  let _: () = redis::cmd("DEBUG")
+    .arg("HOTKEYS")
+    .arg("RESET")
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  let top: Vec<(String, i64)> = redis::cmd("DEBUG")
+    .arg("HOTKEYS")
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  assert!(top.is_empty());
+}
+
+#[tokio::test]
+async fn ttl_histogram() {
+  let server = TestServer::start().await;
+  let mut connection = server.connection().await;
+  for key in ["a", "b", "c"] {
+    let _: () = connection.set(key, "1").await.unwrap();
+  }
+  for (key, seconds) in [("d", 30), ("e", 30), ("f", 7200), ("g", 8_640_000)] {
+    let _: () = connection.set_ex(key, "1", seconds).await.unwrap();
+  }
+
+  let sample = server.server.storage().lock().await.sample_ttls(1000);
+  assert_eq!((sample.keys, sample.persistent, sample.volatile), (7, 3, 4));
+  assert_eq!(sample.buckets, [2, 2, 2, 3, 3, 3, 3]);
+
+  let info: String = redis::cmd("INFO")
+    .arg("ttl")
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  assert!(info.contains("ttl_sampled_keys:7\r\n"), "{}", info);
+  assert!(info.contains("ttl_persistent_keys:3\r\n"), "{}", info);
+  assert!(
+    info.contains(
+      "ttl_histogram:le_60=2,le_600=2,le_3600=2,le_21600=3,le_86400=3,le_604800=3,le_2592000=3,le_inf=4\r\n"
+    ),
+    "{}",
+    info
+  );
+
+  let metrics = sample.prometheus();
+  assert!(metrics.contains("redis_key_ttl_seconds_bucket{le=\"3600\"} 2\n"));
+  assert!(metrics.contains("redis_key_ttl_seconds_bucket{le=\"+Inf\"} 4\n"));
+  assert!(metrics.contains("redis_key_ttl_seconds_count 4\n"));
+  assert!(metrics.contains("redis_key_ttl_persistent_keys 3\n"));
+}
+
+#[tokio::test]
+async fn negative_lookup_cache() {
+  let server = TestServer::with_config(&[("negative-lookup-cache", "yes")]).await;
+  let mut connection = server.connection().await;
+  for i in 0..100 {
+    let _: () = connection.set(format!("key:{}", i), i).await.unwrap();
+  }
+  let _: () = connection.del("key:0").await.unwrap();
+
+  for i in 0..1000 {
+    let value: Option<String> = connection.get(format!("missing:{}", i)).await.unwrap();
+    assert_eq!(value, None);
+  }
+  let value: Option<String> = connection.get("key:0").await.unwrap();
+  assert_eq!(value, None);
+  for i in 1..100 {
+    let value: i64 = connection.get(format!("key:{}", i)).await.unwrap();
+    assert_eq!(value, i);
+  }
+
+  let counter = |info: &str, name: &str| -> u64 {
+    info
+      .lines()
+      .find_map(|line| line.strip_prefix(&format!("{}:", name)))
+      .unwrap()
+      .parse()
+      .unwrap()
+  };
+  let info: String = redis::cmd("INFO")
+    .arg("stats")
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  let hits = counter(&info, "negative_lookup_cache_hits");
+  let false_positives = counter(&info, "negative_lookup_cache_false_positives");
+  assert_eq!(hits + false_positives, 1001);
+  assert!(hits > 950, "{}", info);
+}
+
+#[tokio::test]
+async fn dump_and_restore() {
+  assert_eq!(dump::crc64(b"123456789"), 0xe9c6d914c4b8d9ca);
+
+  let server = TestServer::start().await;
+  let mut connection = server.connection().await;
+  let _: () = connection.set("source", "hello\r\nworld").await.unwrap();
+  let payload: Vec<u8> = redis::cmd("DUMP")
+    .arg("source")
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  assert_eq!(payload, dump::serialize(b"hello\r\nworld"));
+
+  let restore = |key: &str, payload: &[u8]| {
+    let mut command = redis::cmd("RESTORE");
+    command.arg(key).arg(0).arg(payload);
+    command
+  };
+  let _: () = restore("copy", &payload)
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  let copy: String = connection.get("copy").await.unwrap();
+  assert_eq!(copy, "hello\r\nworld");
+  let error = restore("copy", &payload)
+    .query_async::<_, ()>(&mut connection)
+    .await
+    .unwrap_err();
+  assert_eq!(error.code(), Some("BUSYKEY"));
+
+  let mut corrupted = payload.clone();
+  corrupted[3] ^= 1;
+  let error = restore("corrupted", &corrupted)
+    .query_async::<_, ()>(&mut connection)
+    .await
+    .unwrap_err();
+  assert!(error.to_string().contains("checksum"), "{}", error);
+
+  // A valid checksum over a value followed by a stray byte: only deep
+  // sanitization refuses it
+  let mut trailing = payload[..payload.len() - 10].to_vec();
+  trailing.push(b'!');
+  trailing.extend_from_slice(&11u16.to_le_bytes());
+  let crc = dump::crc64(&trailing);
+  trailing.extend_from_slice(&crc.to_le_bytes());
+  let _: () = restore("lenient", &trailing)
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  let _: () = redis::cmd("CONFIG")
+    .arg("SET")
+    .arg("sanitize-dump-payload")
+    .arg("yes")
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  let error = restore("strict", &trailing)
+    .query_async::<_, ()>(&mut connection)
+    .await
+    .unwrap_err();
+  assert!(error.to_string().contains("Bad data format"), "{}", error);
+  let _: () = restore("strict", &payload)
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn diagnostics_report() {
+  let server = TestServer::start().await;
+  let mut connection = server.connection().await;
+  for i in 0..50 {
+    let _: () = connection.set(format!("key:{}", i), i).await.unwrap();
+  }
+  let _: () = connection.set_ex("volatile", "1", 100).await.unwrap();
+
+  let report = server.server.diagnostics().await;
+  assert_eq!(report[0], "Diagnostics report");
+  let line = |prefix: &str| {
+    report
+      .iter()
+      .find(|line| line.starts_with(prefix))
+      .unwrap_or_else(|| panic!("no {} line in {:?}", prefix, report))
+      .clone()
+  };
+  assert!(line("tasks:").contains("connections=1"));
+  assert!(line("keyspace:").starts_with("keyspace: keys=51 expires=1 "));
+  let shard_keys: usize = report
+    .iter()
+    .filter(|line| line.starts_with("keyspace shard "))
+    .map(|line| {
+      let keys = line.split("keys=").nth(1).unwrap();
+      keys.split(' ').next().unwrap().parse::<usize>().unwrap()
+    })
+    .sum();
+  assert_eq!(shard_keys, 51);
+  assert!(line("clients:").starts_with("clients: connected=1 "));
+  assert!(line("replication:").starts_with("replication: role:master "));
+  line("memory:");
+  line("slowlog:");
+}
+
+/// Runs SET and GET until the metrics received on `receiver` include all of
+/// `expected`: the first flush only takes a baseline
+async fn receive_statsd(
+  receiver: &tokio::net::UdpSocket,
+  connection: &mut redis::aio::MultiplexedConnection,
+  expected: &[&str],
+) {
+  let mut buffer = vec![0; statsd::MAX_PACKET];
+  let mut received = String::new();
+  while !expected.iter().all(|metric| received.contains(metric)) {
+    let _: () = connection.set("key", "value").await.unwrap();
+    let _: String = connection.get("key").await.unwrap();
+    let (length, _) = tokio::time::timeout(Duration::from_secs(5), receiver.recv_from(&mut buffer))
+      .await
+      .unwrap_or_else(|_| panic!("no {:?} in {}", expected, received))
+      .unwrap();
+    assert!(length <= statsd::MAX_PACKET);
+    received.push_str(std::str::from_utf8(&buffer[..length]).unwrap());
+    received.push('\n');
+  }
+}
+
+#[tokio::test]
+async fn statsd_exporter() {
+  let receiver = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+  let address = receiver.local_addr().unwrap().to_string();
+  let server =
+    TestServer::with_config(&[("statsd-address", &address), ("statsd-flush-interval", "1")]).await;
+  let mut connection = server.connection().await;
+  receive_statsd(
+    &receiver,
+    &mut connection,
+    &[
+      "redis.commands.total:",
+      "redis.commands.set.calls:",
+      "redis.commands.get.latency:",
+      "redis.memory.used:",
+      "redis.keys:1|g",
+    ],
+  )
+  .await;
+
+  let _: () = redis::cmd("CONFIG")
+    .arg("SET")
+    .arg("statsd-datadog-tags")
+    .arg("yes")
+    .arg("statsd-prefix")
+    .arg("cache")
+    .query_async(&mut connection)
+    .await
+    .unwrap();
+  receive_statsd(
+    &receiver,
+    &mut connection,
+    &[
+      "cache.commands.calls:",
+      "|#command:get",
+      "cache.clients.connected:1|g",
+    ],
+  )
+  .await;
+}
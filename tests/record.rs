@@ -0,0 +1,64 @@
+/**
+ * Recording what clients send and replaying it, see `record`.
+ */
+mod common;
+
+use common::TestServer;
+use redis::aio::MultiplexedConnection;
+use redis::AsyncCommands;
+use redis_starter_rust::record;
+use std::collections::HashSet;
+
+async fn digest(connection: &mut MultiplexedConnection) -> String {
+  redis::cmd("DEBUG")
+    .arg("DIGEST")
+    .query_async(connection)
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn recordings_replay_to_the_same_state() {
+  let dir = common::scratch_dir();
+  let file = dir.join("session.rec");
+  let server = TestServer::with_config(&[("record-file", file.to_str().unwrap())]).await;
+  let mut first = server.connection().await;
+  let mut second = server.connection().await;
+
+  // Interleaved clients, where replaying out of order would diverge
+  let _: () = first.set("counter", 10).await.unwrap();
+  let _: i64 = second.incr("counter", 5).await.unwrap();
+  let _: () = first.set("counter", 1).await.unwrap();
+  let _: i64 = second.incr("counter", 2).await.unwrap();
+  let _: () = first.set_ex("session", "token", 1000).await.unwrap();
+  let _: () = second.del("missing").await.unwrap();
+  let _: Result<i64, _> = first.incr("session", 1).await;
+  let expected = digest(&mut first).await;
+  let _: () = redis::cmd("CONFIG")
+    .arg("SET")
+    .arg("record-file")
+    .arg("")
+    .query_async(&mut first)
+    .await
+    .unwrap();
+  let _: () = first.set("after", "stopped").await.unwrap();
+
+  let recording = std::fs::read(&file).unwrap();
+  let entries = record::parse(&recording).unwrap();
+  assert!(entries
+    .windows(2)
+    .all(|pair| pair[0].micros <= pair[1].micros));
+  let clients: HashSet<u64> = entries.iter().map(|entry| entry.client_id).collect();
+  assert!(clients.len() >= 2, "{:?}", clients);
+
+  let replayed = TestServer::with_config(&[("replay-file", file.to_str().unwrap())]).await;
+  let mut connection = replayed.connection().await;
+  assert_eq!(digest(&mut connection).await, expected);
+  let counter: i64 = connection.get("counter").await.unwrap();
+  assert_eq!(counter, 3);
+  let after: Option<String> = connection.get("after").await.unwrap();
+  assert_eq!(after, None);
+
+  assert!(record::parse(b"not a recording\r\n").is_err());
+  std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,43 @@
+/**
+ * Restarting from the snapshot SHUTDOWN saved. The servers run without
+ * `dir`, so in the working directory, which this test binary owns.
+ */
+mod common;
+
+use redis::AsyncCommands;
+use redis_starter_rust::RedisServer;
+
+async fn start() -> (RedisServer, redis::aio::MultiplexedConnection) {
+  let port = common::free_port();
+  let mut server = RedisServer::builder()
+    .bind(&format!("127.0.0.1:{}", port))
+    .build();
+  server.start().await.expect("failed to start the server");
+  let connection = redis::Client::open(format!("redis://127.0.0.1:{}/", port))
+    .unwrap()
+    .get_multiplexed_async_connection()
+    .await
+    .unwrap();
+  (server, connection)
+}
+
+#[tokio::test]
+async fn restart_without_dir() {
+  let dir = common::scratch_dir();
+  std::env::set_current_dir(&dir).unwrap();
+
+  let (mut server, mut connection) = start().await;
+  let _: () = connection.set("survives", "restarts").await.unwrap();
+  let _: redis::RedisResult<()> = redis::cmd("SHUTDOWN")
+    .arg("SAVE")
+    .query_async(&mut connection)
+    .await;
+  server.stopped().await;
+  assert!(dir.join("dump.rdb").exists());
+
+  let (mut server, mut connection) = start().await;
+  let value: Option<String> = connection.get("survives").await.unwrap();
+  assert_eq!(value.as_deref(), Some("restarts"));
+  server.shutdown();
+  let _ = std::fs::remove_dir_all(&dir);
+}